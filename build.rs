@@ -0,0 +1,20 @@
+// sqlx::migrate!() embeds migrations/ (and migrations_sqlite/) at compile
+// time; without this a new migration file wouldn't trigger a rebuild.
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=migrations_sqlite");
+    #[cfg(feature = "grpc")]
+    grpc();
+}
+
+// The gRPC service and client from proto/, with the protoc that comes with
+// protoc-bin-vendored so the build needs none installed.
+#[cfg(feature = "grpc")]
+fn grpc() {
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("No bundled protoc for this platform");
+    let mut config = tonic_prost_build::Config::new();
+    config.protoc_executable(protoc);
+    tonic_prost_build::configure()
+        .compile_with_config(config, &["proto/blog.proto"], &["proto"])
+        .expect("Failed to compile proto/blog.proto");
+}
@@ -0,0 +1,9 @@
+-- Translated copies of a post, one row per language
+CREATE TABLE IF NOT EXISTS translations(
+	id SERIAL PRIMARY KEY,
+	post_id INTEGER NOT NULL REFERENCES blog_posts(id) ON DELETE CASCADE,
+	lang TEXT NOT NULL,
+	title TEXT NOT NULL,
+	content TEXT NOT NULL,
+	UNIQUE (post_id, lang)
+);
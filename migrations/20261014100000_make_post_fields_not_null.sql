@@ -0,0 +1,6 @@
+-- BlogPost requires author and content, so the columns should too
+UPDATE blog_posts SET author = '' WHERE author IS NULL;
+UPDATE blog_posts SET content = '' WHERE content IS NULL;
+ALTER TABLE blog_posts
+	ALTER COLUMN author SET NOT NULL,
+	ALTER COLUMN content SET NOT NULL;
@@ -0,0 +1,8 @@
+-- Background work such as async imports, polled via GET /admin/jobs/{id}
+CREATE TABLE IF NOT EXISTS jobs(
+	id SERIAL PRIMARY KEY,
+	kind TEXT NOT NULL,
+	status TEXT NOT NULL DEFAULT 'pending',
+	result JSONB,
+	error TEXT
+);
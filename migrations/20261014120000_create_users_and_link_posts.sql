@@ -0,0 +1,25 @@
+-- Accounts, and posts owned by a user instead of a free-text author
+CREATE TABLE IF NOT EXISTS users(
+	id SERIAL PRIMARY KEY,
+	username TEXT NOT NULL UNIQUE,
+	password_hash TEXT NOT NULL
+);
+
+-- Existing authors become users that can't log in yet ('!' is not a valid
+-- password hash). Posts without an author go to a shared placeholder.
+INSERT INTO users (username, password_hash)
+SELECT DISTINCT COALESCE(NULLIF(author, ''), 'unknown'), '!'
+FROM blog_posts
+ON CONFLICT (username) DO NOTHING;
+
+ALTER TABLE blog_posts ADD COLUMN user_id INTEGER REFERENCES users(id);
+
+UPDATE blog_posts p
+SET user_id = u.id
+FROM users u
+WHERE u.username = COALESCE(NULLIF(p.author, ''), 'unknown');
+
+ALTER TABLE blog_posts ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE blog_posts DROP COLUMN author;
+
+CREATE INDEX IF NOT EXISTS blog_posts_user_id_idx ON blog_posts(user_id);
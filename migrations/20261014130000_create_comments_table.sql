@@ -0,0 +1,9 @@
+-- Reader comments, removed together with their post
+CREATE TABLE IF NOT EXISTS comments(
+	id SERIAL PRIMARY KEY,
+	post_id INTEGER NOT NULL REFERENCES blog_posts(id) ON DELETE CASCADE,
+	user_id INTEGER NOT NULL REFERENCES users(id),
+	body TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS comments_post_id_idx ON comments(post_id);
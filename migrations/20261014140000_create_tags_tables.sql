@@ -0,0 +1,13 @@
+-- Tags shared between posts; names are stored lowercased
+CREATE TABLE IF NOT EXISTS tags(
+	id SERIAL PRIMARY KEY,
+	name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS post_tags(
+	post_id INTEGER NOT NULL REFERENCES blog_posts(id) ON DELETE CASCADE,
+	tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+	PRIMARY KEY (post_id, tag_id)
+);
+
+CREATE INDEX IF NOT EXISTS post_tags_tag_id_idx ON post_tags(tag_id);
@@ -0,0 +1,11 @@
+-- Weighted full-text index: title matches are labelled A and content B so
+-- ts_rank can rank title hits higher. Being a generated column, existing
+-- rows are populated when this runs and new rows keep it up to date.
+ALTER TABLE blog_posts
+	ADD COLUMN IF NOT EXISTS search_vector tsvector
+	GENERATED ALWAYS AS (
+		setweight(to_tsvector('english', title), 'A') ||
+		setweight(to_tsvector('english', content), 'B')
+	) STORED;
+
+CREATE INDEX IF NOT EXISTS blog_posts_search_vector_idx ON blog_posts USING GIN (search_vector);
@@ -0,0 +1,6 @@
+-- Soft delete: DELETE /blog/{id} sets deleted_at and every normal read
+-- filters those rows out. DELETE /blog/{id}/purge removes them for good.
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS blog_posts_deleted_at_idx ON blog_posts(deleted_at)
+	WHERE deleted_at IS NOT NULL;
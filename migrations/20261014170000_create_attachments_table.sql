@@ -0,0 +1,14 @@
+-- Images uploaded to a post. The file itself lives in the attachment store
+-- under storage_key; the row goes with its post.
+CREATE TABLE IF NOT EXISTS attachments(
+	id SERIAL PRIMARY KEY,
+	post_id INTEGER NOT NULL REFERENCES blog_posts(id) ON DELETE CASCADE,
+	user_id INTEGER NOT NULL REFERENCES users(id),
+	filename TEXT NOT NULL,
+	content_type TEXT NOT NULL,
+	size_bytes BIGINT NOT NULL,
+	storage_key TEXT NOT NULL UNIQUE,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS attachments_post_id_idx ON attachments(post_id);
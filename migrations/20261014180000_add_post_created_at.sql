@@ -0,0 +1,5 @@
+-- When a post was created, for sorting the listing newest first. Rows that
+-- predate the column get the time of the migration.
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE INDEX IF NOT EXISTS blog_posts_created_at_idx ON blog_posts(created_at);
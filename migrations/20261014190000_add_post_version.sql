@@ -0,0 +1,3 @@
+-- Optimistic locking: every update must name the version it started from
+-- and bumps it, so a concurrent edit is refused instead of overwritten.
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
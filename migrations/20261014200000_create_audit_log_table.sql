@@ -0,0 +1,16 @@
+-- One row per create/update/delete, written in the same transaction as the
+-- change itself. old_value/new_value hold the entity as the API returns it;
+-- one of them is NULL for creates and deletes.
+CREATE TABLE IF NOT EXISTS audit_log(
+	id BIGSERIAL PRIMARY KEY,
+	user_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+	entity TEXT NOT NULL,
+	entity_id INTEGER NOT NULL,
+	action TEXT NOT NULL,
+	old_value JSONB,
+	new_value JSONB,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_entity_idx ON audit_log(entity, entity_id);
+CREATE INDEX IF NOT EXISTS audit_log_created_at_idx ON audit_log(created_at);
@@ -0,0 +1,4 @@
+-- Roles: admins can change anything, editors write their own posts,
+-- readers only read and comment. Existing accounts keep writing as editors.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'editor'
+	CHECK (role IN ('admin', 'editor', 'reader'));
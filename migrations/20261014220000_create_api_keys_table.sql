@@ -0,0 +1,13 @@
+-- Keys for machine clients. Only a SHA-256 of the key is kept; prefix is
+-- its first characters, so a key can be recognised in listings and logs.
+CREATE TABLE IF NOT EXISTS api_keys(
+	id SERIAL PRIMARY KEY,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	name TEXT NOT NULL,
+	prefix TEXT NOT NULL,
+	key_hash TEXT NOT NULL UNIQUE,
+	scope TEXT NOT NULL CHECK (scope IN ('read', 'read_write')),
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	last_used_at TIMESTAMPTZ,
+	revoked_at TIMESTAMPTZ
+);
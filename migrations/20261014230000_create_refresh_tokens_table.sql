@@ -0,0 +1,12 @@
+-- One row per refresh token handed out; like API keys only the SHA-256 is
+-- kept. A refresh revokes the token it used and inserts its replacement.
+CREATE TABLE IF NOT EXISTS refresh_tokens(
+	id SERIAL PRIMARY KEY,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	token_hash TEXT NOT NULL UNIQUE,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	expires_at TIMESTAMPTZ NOT NULL,
+	revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_id_idx ON refresh_tokens(user_id);
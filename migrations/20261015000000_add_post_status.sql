@@ -0,0 +1,6 @@
+-- draft and archived posts are only shown to their author (and admins).
+-- Everything written before this was public, so it starts out published.
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'published'
+	CHECK (status IN ('draft', 'published', 'archived'));
+
+CREATE INDEX IF NOT EXISTS blog_posts_status_idx ON blog_posts(status);
@@ -0,0 +1,6 @@
+-- When a post last changed: set with every update that bumps the version.
+-- Existing posts count as unchanged since they were created.
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+UPDATE blog_posts SET updated_at = created_at;
+
+CREATE INDEX IF NOT EXISTS blog_posts_updated_at_idx ON blog_posts(updated_at);
@@ -0,0 +1,19 @@
+-- URL slugs, made from the title when a post is created and kept when it
+-- is renamed. Existing posts get one the same way as new ones: a repeated
+-- slug takes -2, -3, ... in id order.
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS slug TEXT;
+
+WITH base AS (
+	SELECT id, COALESCE(NULLIF(LEFT(TRIM(BOTH '-' FROM
+		regexp_replace(lower(title), '[^a-z0-9]+', '-', 'g')), 80), ''), 'post') AS slug
+	FROM blog_posts
+), numbered AS (
+	SELECT id, slug, row_number() OVER (PARTITION BY slug ORDER BY id) AS n FROM base
+)
+UPDATE blog_posts p
+SET slug = CASE WHEN numbered.n = 1 THEN numbered.slug ELSE numbered.slug || '-' || numbered.n END
+FROM numbered
+WHERE p.id = numbered.id AND p.slug IS NULL;
+
+ALTER TABLE blog_posts ALTER COLUMN slug SET NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS blog_posts_slug_idx ON blog_posts(slug);
@@ -0,0 +1,17 @@
+-- Turns jobs into a queue that workers poll: what to run, when it may next
+-- run, and how many tries it has had. Rows from before this already ran in
+-- the process that queued them, so unfinished ones were lost on a restart.
+ALTER TABLE jobs
+	ADD COLUMN IF NOT EXISTS payload JSONB NOT NULL DEFAULT 'null',
+	ADD COLUMN IF NOT EXISTS attempts INT NOT NULL DEFAULT 0,
+	ADD COLUMN IF NOT EXISTS max_attempts INT NOT NULL DEFAULT 1,
+	ADD COLUMN IF NOT EXISTS run_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	ADD COLUMN IF NOT EXISTS locked_at TIMESTAMPTZ,
+	ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+UPDATE jobs SET status = 'failed', error = 'Interrupted by a restart'
+WHERE status IN ('pending', 'running');
+
+CREATE INDEX IF NOT EXISTS jobs_run_at_idx ON jobs (run_at) WHERE status IN ('pending', 'running');
+CREATE INDEX IF NOT EXISTS jobs_created_at_idx ON jobs (created_at);
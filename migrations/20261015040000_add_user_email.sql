@@ -0,0 +1,3 @@
+-- Where to send a user's notifications, such as new comments on their
+-- posts. Optional: accounts without one get none.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email TEXT;
@@ -0,0 +1,31 @@
+-- URLs that get a signed POST when one of their owner's posts changes (any
+-- post, for admins). The secret signs the payloads, so it is kept as is.
+CREATE TABLE IF NOT EXISTS webhooks(
+	id SERIAL PRIMARY KEY,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	url TEXT NOT NULL,
+	secret TEXT NOT NULL,
+	-- post.created, post.updated and/or post.deleted
+	events TEXT[] NOT NULL,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS webhooks_user_id_idx ON webhooks(user_id);
+
+-- One row per event sent to a webhook. Tries and backoff are the job's;
+-- the row keeps what the last try got back.
+CREATE TABLE IF NOT EXISTS webhook_deliveries(
+	id SERIAL PRIMARY KEY,
+	webhook_id INTEGER NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+	job_id INTEGER REFERENCES jobs(id) ON DELETE SET NULL,
+	event TEXT NOT NULL,
+	payload JSONB NOT NULL,
+	attempts INT NOT NULL DEFAULT 0,
+	response_status INT,
+	error TEXT,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_id_idx
+	ON webhook_deliveries(webhook_id, created_at DESC);
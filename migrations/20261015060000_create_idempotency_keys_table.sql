@@ -0,0 +1,15 @@
+-- Idempotency-Key values sent with POST /blog, per user, and the response
+-- each got, replayed to repeats until the row expires. Status and response
+-- are NULL while the first request is still being handled.
+CREATE TABLE IF NOT EXISTS idempotency_keys(
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	key TEXT NOT NULL,
+	-- SHA-256 of the request body, so a key reused for a different request
+	-- is caught.
+	request_hash TEXT NOT NULL,
+	status_code INT,
+	response JSONB,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	expires_at TIMESTAMPTZ NOT NULL,
+	PRIMARY KEY (user_id, key)
+);
@@ -0,0 +1,29 @@
+-- Tenants: separate blogs served by one deployment, each with its own
+-- posts and accounts. Everything that existed before belongs to the
+-- default tenant, which is also what requests naming no tenant get.
+CREATE TABLE IF NOT EXISTS tenants(
+	id SERIAL PRIMARY KEY,
+	-- The subdomain, and what X-Tenant-Id may name instead of the id.
+	slug TEXT NOT NULL UNIQUE,
+	name TEXT NOT NULL,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+INSERT INTO tenants (id, slug, name) VALUES (1, 'default', 'Default')
+ON CONFLICT (id) DO NOTHING;
+SELECT setval('tenants_id_seq', (SELECT MAX(id) FROM tenants));
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS tenant_id INTEGER NOT NULL DEFAULT 1
+	REFERENCES tenants(id);
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS tenant_id INTEGER NOT NULL DEFAULT 1
+	REFERENCES tenants(id);
+-- So an admin only sees the jobs of their own tenant.
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS tenant_id INTEGER NOT NULL DEFAULT 1
+	REFERENCES tenants(id);
+
+-- Usernames and slugs only have to be unique within a tenant.
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_username_key;
+CREATE UNIQUE INDEX IF NOT EXISTS users_tenant_username_idx ON users(tenant_id, username);
+DROP INDEX IF EXISTS blog_posts_slug_idx;
+CREATE UNIQUE INDEX IF NOT EXISTS blog_posts_tenant_slug_idx ON blog_posts(tenant_id, slug);
+CREATE INDEX IF NOT EXISTS jobs_tenant_id_idx ON jobs(tenant_id);
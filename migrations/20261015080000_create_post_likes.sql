@@ -0,0 +1,10 @@
+-- One like per reader and post. like_count is kept alongside so post
+-- queries don't have to count.
+CREATE TABLE IF NOT EXISTS post_likes(
+	post_id INTEGER NOT NULL REFERENCES blog_posts(id) ON DELETE CASCADE,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	PRIMARY KEY (post_id, user_id)
+);
+
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS like_count INTEGER NOT NULL DEFAULT 0;
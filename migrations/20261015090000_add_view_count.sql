@@ -0,0 +1,5 @@
+-- Views of GET /blog/{id}, added in batches by the server rather than on
+-- every read.
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS view_count BIGINT NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS blog_posts_view_count_idx ON blog_posts(tenant_id, view_count DESC);
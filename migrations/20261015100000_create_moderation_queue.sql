@@ -0,0 +1,17 @@
+-- Posts and comments a moderator flagged, waiting for an admin. The
+-- content itself is saved as usual; resolving an item only takes it off
+-- the queue.
+CREATE TABLE IF NOT EXISTS moderation_queue(
+	id SERIAL PRIMARY KEY,
+	tenant_id INTEGER NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+	content_kind TEXT NOT NULL CHECK (content_kind IN ('post', 'comment')),
+	content_id INTEGER NOT NULL,
+	user_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+	reason TEXT NOT NULL,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	resolved_at TIMESTAMPTZ,
+	resolved_by INTEGER REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS moderation_queue_open
+	ON moderation_queue (tenant_id, created_at DESC) WHERE resolved_at IS NULL;
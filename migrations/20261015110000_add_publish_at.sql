@@ -0,0 +1,8 @@
+-- When the scheduler is to publish a draft. Only drafts are scheduled, so
+-- a post with a publish_at is never public yet.
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS publish_at TIMESTAMPTZ;
+ALTER TABLE blog_posts ADD CONSTRAINT blog_posts_scheduled_drafts
+	CHECK (publish_at IS NULL OR status = 'draft');
+
+CREATE INDEX IF NOT EXISTS blog_posts_publish_at
+	ON blog_posts (publish_at) WHERE publish_at IS NOT NULL;
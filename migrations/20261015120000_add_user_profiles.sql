@@ -0,0 +1,5 @@
+-- What GET /authors shows of an account besides its username. All
+-- optional; the username stands in for a missing display name.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS display_name TEXT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS bio TEXT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar_url TEXT;
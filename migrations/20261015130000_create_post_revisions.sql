@@ -0,0 +1,17 @@
+-- What a post looked like before each update: one row per version it
+-- left behind, so older versions can be looked at and restored. The
+-- current version is the post itself.
+CREATE TABLE IF NOT EXISTS post_revisions(
+	post_id INTEGER NOT NULL REFERENCES blog_posts(id) ON DELETE CASCADE,
+	-- The post's version at the time.
+	rev INTEGER NOT NULL,
+	title TEXT NOT NULL,
+	content TEXT NOT NULL,
+	tags TEXT[] NOT NULL,
+	status TEXT NOT NULL,
+	-- When this version was written, and when and by whom it was replaced.
+	edited_at TIMESTAMPTZ NOT NULL,
+	replaced_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	replaced_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+	PRIMARY KEY (post_id, rev)
+);
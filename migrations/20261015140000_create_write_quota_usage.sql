@@ -0,0 +1,11 @@
+-- One row per post or comment a user created, for the write quotas. Rows
+-- older than the quota window are pruned as new ones come in; deleting a
+-- post doesn't give its row back.
+CREATE TABLE IF NOT EXISTS write_quota_usage(
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	kind TEXT NOT NULL CHECK (kind IN ('post', 'comment')),
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS write_quota_usage_user
+	ON write_quota_usage (user_id, kind, created_at);
@@ -0,0 +1,13 @@
+-- Accounts at OAuth providers (GitHub, Google) that log in as a local
+-- user. `subject` is the provider's stable id for the account, not its
+-- username or email, which can change.
+CREATE TABLE IF NOT EXISTS user_identities(
+	tenant_id INTEGER NOT NULL,
+	provider TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	PRIMARY KEY (tenant_id, provider, subject)
+);
+
+CREATE INDEX IF NOT EXISTS user_identities_user_id_idx ON user_identities(user_id);
@@ -0,0 +1,22 @@
+-- A second, public key for posts: a UUID v7, so its leading 48 bits are
+-- the creation time in milliseconds and new keys still sort after old
+-- ones. POST_IDS=uuid routes by it alone. The integer id stays what
+-- comments, likes, revisions and the rest refer to.
+CREATE OR REPLACE FUNCTION uuid_v7(at TIMESTAMPTZ) RETURNS UUID AS $$
+	-- A random (v4) UUID with the time written over its first six bytes
+	-- and the version nibble turned from 4 into 7.
+	SELECT encode(
+		set_bit(set_bit(
+			overlay(uuid_send(gen_random_uuid()) PLACING
+				substring(int8send(floor(extract(epoch FROM at) * 1000)::BIGINT) FROM 3)
+				FROM 1 FOR 6),
+			52, 1), 53, 1),
+		'hex')::UUID;
+$$ LANGUAGE SQL VOLATILE;
+
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS uuid UUID;
+UPDATE blog_posts SET uuid = uuid_v7(created_at) WHERE uuid IS NULL;
+ALTER TABLE blog_posts ALTER COLUMN uuid SET DEFAULT uuid_v7(clock_timestamp());
+ALTER TABLE blog_posts ALTER COLUMN uuid SET NOT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS blog_posts_uuid_key ON blog_posts(uuid);
@@ -0,0 +1,7 @@
+-- Snowflake ids, made by the server (see post_ids.rs) for every new post,
+-- for ID_STRATEGY=snowflake. Posts from before this are not given one:
+-- the ids stand for their creation time and machine, which can't be made
+-- up after the fact. Those posts stay reachable by UUID.
+ALTER TABLE blog_posts ADD COLUMN IF NOT EXISTS snowflake_id BIGINT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS blog_posts_snowflake_id_key ON blog_posts(snowflake_id);
@@ -0,0 +1,21 @@
+-- Posts for POST_STORE=sqlite. Accounts stay in Postgres, so the author's
+-- name is stored with the post instead of joined from users, and tags are
+-- a sorted JSON array rather than their own tables. Timestamps are
+-- RFC 3339 text in UTC with microseconds, which sorts chronologically.
+CREATE TABLE IF NOT EXISTS blog_posts (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	title TEXT NOT NULL,
+	slug TEXT NOT NULL UNIQUE,
+	content TEXT NOT NULL,
+	user_id INTEGER NOT NULL,
+	author TEXT NOT NULL,
+	tags TEXT NOT NULL DEFAULT '[]',
+	version INTEGER NOT NULL DEFAULT 1,
+	status TEXT NOT NULL DEFAULT 'published'
+		CHECK (status IN ('draft', 'published', 'archived')),
+	created_at TEXT NOT NULL,
+	updated_at TEXT NOT NULL,
+	deleted_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS blog_posts_created_at_idx ON blog_posts(created_at);
@@ -0,0 +1,32 @@
+-- Posts belong to a tenant, as in Postgres, and slugs only have to be
+-- unique within one. SQLite can't drop a column's UNIQUE, so the table is
+-- rebuilt; existing posts go to the default tenant.
+CREATE TABLE blog_posts_new (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	tenant_id INTEGER NOT NULL DEFAULT 1,
+	title TEXT NOT NULL,
+	slug TEXT NOT NULL,
+	content TEXT NOT NULL,
+	user_id INTEGER NOT NULL,
+	author TEXT NOT NULL,
+	tags TEXT NOT NULL DEFAULT '[]',
+	version INTEGER NOT NULL DEFAULT 1,
+	status TEXT NOT NULL DEFAULT 'published'
+		CHECK (status IN ('draft', 'published', 'archived')),
+	created_at TEXT NOT NULL,
+	updated_at TEXT NOT NULL,
+	deleted_at TEXT,
+	UNIQUE (tenant_id, slug)
+);
+
+INSERT INTO blog_posts_new
+	(id, title, slug, content, user_id, author, tags, version, status, created_at,
+	updated_at, deleted_at)
+SELECT id, title, slug, content, user_id, author, tags, version, status, created_at,
+	updated_at, deleted_at
+FROM blog_posts;
+
+DROP TABLE blog_posts;
+ALTER TABLE blog_posts_new RENAME TO blog_posts;
+
+CREATE INDEX IF NOT EXISTS blog_posts_created_at_idx ON blog_posts(created_at);
@@ -0,0 +1,12 @@
+-- The public post key, as hyphenated text. New posts get a UUID v7 from
+-- the server; SQLite can't make those, so posts from before this get a
+-- random (v4) one, which sorts anywhere.
+ALTER TABLE blog_posts ADD COLUMN uuid TEXT;
+
+UPDATE blog_posts SET uuid = lower(
+	hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2)
+	|| '-' || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2)
+	|| '-' || hex(randomblob(6))
+) WHERE uuid IS NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS blog_posts_uuid_key ON blog_posts(uuid);
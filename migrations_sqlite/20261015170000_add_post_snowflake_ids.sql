@@ -0,0 +1,5 @@
+-- Snowflake ids, as in Postgres: new posts get one from the server and
+-- older ones have none.
+ALTER TABLE blog_posts ADD COLUMN snowflake_id INTEGER;
+
+CREATE UNIQUE INDEX IF NOT EXISTS blog_posts_snowflake_id_key ON blog_posts(snowflake_id);
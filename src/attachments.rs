@@ -0,0 +1,205 @@
+// Image uploads and where they are stored.
+
+use crate::*;
+
+// Where uploaded images are kept, selected at startup via ATTACHMENT_STORE.
+// Keys are generated by the server (random hex plus an extension), never
+// taken from the client.
+#[async_trait]
+pub trait AttachmentStore: Send + Sync {
+    async fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()>;
+    async fn get(&self, key: &str) -> std::io::Result<Vec<u8>>;
+    async fn delete(&self, key: &str) -> std::io::Result<()>;
+
+    // A URL the client can download the file from itself, for stores that
+    // can hand one out; the API serves the bytes otherwise.
+    fn download_url(&self, _key: &str, _content_type: &str, _filename: &str) -> Option<String> {
+        None
+    }
+
+    // The request that lets the client upload `size` bytes of
+    // `content_type` to `key` itself, for stores that can sign one.
+    fn upload_url(&self, _key: &str, _content_type: &str, _size: i64) -> Option<PresignedUpload> {
+        None
+    }
+}
+
+// A pre-signed request for uploading one file straight to the store.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PresignedUpload {
+    pub method: &'static str,
+    pub url: String,
+    // To be sent with exactly these values; they are part of the signature.
+    pub headers: BTreeMap<String, String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+// One file per attachment under UPLOAD_DIR.
+pub struct DiskStore {
+    root: PathBuf,
+}
+
+impl DiskStore {
+    pub fn new(root: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(DiskStore { root })
+    }
+}
+
+#[async_trait]
+impl AttachmentStore for DiskStore {
+    async fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        tokio::fs::write(self.root.join(key), bytes).await
+    }
+
+    async fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        tokio::fs::read(self.root.join(key)).await
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        match tokio::fs::remove_file(self.root.join(key)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub(crate) const MAX_IMAGES_PER_UPLOAD: usize = 10;
+
+pub struct Uploads {
+    pub store: Arc<dyn AttachmentStore>,
+    // Per file. IMAGE_MAX_BYTES, default 5 MiB.
+    pub max_bytes: usize,
+}
+
+impl Uploads {
+    pub fn new(store: Arc<dyn AttachmentStore>, max_bytes: usize) -> Self {
+        Uploads { store, max_bytes }
+    }
+
+    pub fn from_env() -> Self {
+        let store: Arc<dyn AttachmentStore> = match env::var("ATTACHMENT_STORE").as_deref() {
+            Ok("disk") | Err(_) => {
+                let dir = env::var("UPLOAD_DIR").unwrap_or_else(|_| "uploads".to_string());
+                let store = DiskStore::new(&dir)
+                    .unwrap_or_else(|err| panic!("Cannot use UPLOAD_DIR {}: {}", dir, err));
+                Arc::new(store)
+            }
+            Ok("s3") => {
+                let store = S3Config::from_env().and_then(S3Store::new);
+                Arc::new(store.unwrap_or_else(|err| panic!("{}", err)))
+            }
+            Ok(other) => panic!("Unknown ATTACHMENT_STORE `{}`", other),
+        };
+        let max_bytes = env::var("IMAGE_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5 * 1024 * 1024);
+        Uploads::new(store, max_bytes)
+    }
+}
+
+// The image types accepted, as (MIME type, extension).
+pub(crate) const IMAGE_TYPES: [(&str, &str); 4] = [
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+];
+
+// A type the client names, as (MIME type, extension), if it is one of
+// those.
+pub fn named_image_type(content_type: &str) -> Option<(&'static str, &'static str)> {
+    let content_type = content_type.trim().to_ascii_lowercase();
+    IMAGE_TYPES.iter().copied().find(|(mime, _)| *mime == content_type)
+}
+
+// The image type going by the file's first bytes, as (MIME type,
+// extension). The type the client claims is ignored.
+pub fn sniff_image_type(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(("image/png", "png"))
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(("image/jpeg", "jpg"))
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some(("image/gif", "gif"))
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some(("image/webp", "webp"))
+    } else {
+        None
+    }
+}
+
+// Drops any directories a browser put in the name and keeps it printable.
+pub(crate) fn clean_filename(name: Option<&str>, extension: &str) -> String {
+    let name = name
+        .and_then(|name| name.rsplit(['/', '\\']).next())
+        .map(|name| name.chars().filter(|c| !c.is_control() && *c != '"').take(255))
+        .map(String::from_iter)
+        .unwrap_or_default();
+    if name.trim().is_empty() {
+        format!("image.{}", extension)
+    } else {
+        name
+    }
+}
+
+// An `image` file part read into memory, refusing to read past max_bytes.
+pub struct UploadedImage {
+    pub filename: String,
+    pub content_type: &'static str,
+    pub extension: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+pub async fn read_images(
+    mut payload: Multipart,
+    max_bytes: usize,
+) -> Result<Vec<UploadedImage>, ApiError> {
+    let mut images = Vec::new();
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|err| ApiError::UnprocessableEntity(err.to_string()))?;
+        if field.name() != Some("image") {
+            continue;
+        }
+        if images.len() == MAX_IMAGES_PER_UPLOAD {
+            return Err(ApiError::UnprocessableEntity(format!(
+                "At most {} images per upload",
+                MAX_IMAGES_PER_UPLOAD
+            )));
+        }
+        let filename = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_filename())
+            .map(String::from);
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|err| ApiError::UnprocessableEntity(err.to_string()))?;
+            if bytes.len() + chunk.len() > max_bytes {
+                return Err(ApiError::PayloadTooLarge(format!(
+                    "Images can be at most {} bytes",
+                    max_bytes
+                )));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        let (content_type, extension) = sniff_image_type(&bytes).ok_or_else(|| {
+            ApiError::UnprocessableEntity(
+                "Only PNG, JPEG, GIF and WebP images are accepted".to_string(),
+            )
+        })?;
+        images.push(UploadedImage {
+            filename: clean_filename(filename.as_deref(), extension),
+            content_type,
+            extension,
+            bytes,
+        });
+    }
+    if images.is_empty() {
+        return Err(ApiError::UnprocessableEntity(
+            "Send the files as multipart `image` fields".to_string(),
+        ));
+    }
+    Ok(images)
+}
@@ -0,0 +1,344 @@
+// Accounts, tokens, roles and API keys.
+
+use crate::*;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Claims {
+    // The user id.
+    pub sub: String,
+    pub username: String,
+    // Fixed when the token is issued, so a role change applies from the
+    // next login. Tokens from before roles existed count as readers.
+    #[serde(default)]
+    pub role: Role,
+    // The tenant the user belongs to; tokens from before tenants existed
+    // are for the default one.
+    #[serde(default = "default_tenant")]
+    pub tenant: i32,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: u64,
+    // Good for one POST /auth/refresh, within refresh_expires_in seconds.
+    pub refresh_token: String,
+    pub refresh_expires_in: u64,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+// JWT_SECRET signs tokens (HS256). Without it a random secret is generated
+// at startup, which is fine for development but logs everyone out on
+// restart and can't be shared between instances.
+pub struct AuthConfig {
+    secret: Vec<u8>,
+    pub ttl_secs: u64,
+    pub refresh_ttl_secs: u64,
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Self {
+        let secret = match env::var("JWT_SECRET") {
+            Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+            _ => {
+                tracing::warn!("JWT_SECRET is not set; using a random secret for this process");
+                rand::random::<[u8; 32]>().to_vec()
+            }
+        };
+        AuthConfig {
+            secret,
+            ttl_secs: env::var("JWT_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3600),
+            refresh_ttl_secs: env::var("REFRESH_TOKEN_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30 * 24 * 3600),
+        }
+    }
+
+    pub fn issue_token(&self, user: &User) -> Result<String, ApiError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let claims = Claims {
+            sub: user.id.to_string(),
+            username: user.username.clone(),
+            role: Role::from_db(&user.role),
+            tenant: user.tenant_id,
+            iat: now,
+            exp: now + self.ttl_secs,
+        };
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(&self.secret),
+        )
+        .map_err(|err| ApiError::DatabaseError(format!("Could not issue token: {}", err)))
+    }
+
+    // What login and refresh answer with; `refresh_token` is already stored.
+    pub fn token_response(
+        &self,
+        user: &User,
+        refresh_token: String,
+    ) -> Result<TokenResponse, ApiError> {
+        Ok(TokenResponse {
+            access_token: self.issue_token(user)?,
+            token_type: "Bearer",
+            expires_in: self.ttl_secs,
+            refresh_token,
+            refresh_expires_in: self.refresh_ttl_secs,
+        })
+    }
+
+    // For other short-lived values the server hands out and must get back
+    // unchanged, such as the OAuth `state`. `T` needs an `exp` and must not
+    // parse as Claims, or it would work as an access token.
+    pub(crate) fn sign<T: Serialize>(&self, claims: &T) -> Result<String, ApiError> {
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            claims,
+            &jsonwebtoken::EncodingKey::from_secret(&self.secret),
+        )
+        .map_err(|err| ApiError::DatabaseError(format!("Could not sign: {}", err)))
+    }
+
+    // None if it wasn't signed here, has expired or isn't a `T`.
+    pub(crate) fn verify<T: DeserializeOwned>(&self, token: &str) -> Option<T> {
+        jsonwebtoken::decode::<T>(
+            token,
+            &jsonwebtoken::DecodingKey::from_secret(&self.secret),
+            &jsonwebtoken::Validation::default(),
+        )
+        .ok()
+        .map(|data| data.claims)
+    }
+
+    pub fn verify_token(&self, token: &str) -> Result<Claims, ApiError> {
+        jsonwebtoken::decode::<Claims>(
+            token,
+            &jsonwebtoken::DecodingKey::from_secret(&self.secret),
+            &jsonwebtoken::Validation::default(),
+        )
+        .map(|data| data.claims)
+        .map_err(|_| ApiError::Unauthorized("Invalid or expired token".to_string()))
+    }
+}
+
+// Argon2id with a random salt, in PHC string format.
+pub fn hash_password(password: &str) -> Result<String, ApiError> {
+    use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+    let salt = SaltString::generate(&mut OsRng);
+    argon2::Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| ApiError::DatabaseError(format!("Could not hash password: {}", err)))
+}
+
+// False for malformed hashes too, such as the '!' placeholder given to
+// accounts migrated from free-text authors.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    use argon2::password_hash::{PasswordHash, PasswordVerifier};
+    PasswordHash::new(hash)
+        .map(|parsed| {
+            argon2::Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+pub fn validate_new_user(user: &NewUser) -> Result<(), ApiError> {
+    let username_ok = (3..=32).contains(&user.username.len())
+        && user
+            .username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if !username_ok {
+        return Err(ApiError::UnprocessableEntity(
+            "Username must be 3-32 characters of letters, digits, `_`, `-` or `.`".to_string(),
+        ));
+    }
+    if !(8..=128).contains(&user.password.chars().count()) {
+        return Err(ApiError::UnprocessableEntity(
+            "Password must be 8-128 characters".to_string(),
+        ));
+    }
+    if let Some(email) = &user.email {
+        validate_email(email)?;
+    }
+    Ok(())
+}
+
+// Only the shape; whether mail gets there is up to the mail server.
+pub fn validate_email(email: &str) -> Result<(), ApiError> {
+    if email.len() > 254 || email.parse::<lettre::Address>().is_err() {
+        return Err(ApiError::UnprocessableEntity(format!(
+            "`{}` is not an email address",
+            email
+        )));
+    }
+    Ok(())
+}
+
+// The authenticated caller, taken from an `Authorization: Bearer` token or
+// an `X-Api-Key`. Adding it to a handler's arguments makes the route answer
+// 401 without valid credentials or with those of another tenant, and 403 to
+// a read-only key unless the method is GET or HEAD.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: i32,
+    pub username: String,
+    pub role: Role,
+    pub tenant_id: i32,
+    // Signed in with a read-scoped API key.
+    pub read_only: bool,
+}
+
+impl AuthUser {
+    pub fn require_role(&self, role: Role) -> Result<(), ApiError> {
+        if self.role >= role {
+            return Ok(());
+        }
+        let required = match role {
+            Role::Admin => Requirement::Admin,
+            _ => Requirement::Editor,
+        };
+        Err(ApiError::Forbidden(PermissionDenied {
+            message: format!("This needs the {} role", role.as_str()),
+            required,
+            role: self.role,
+        }))
+    }
+
+    // For changes to something `owner_id` wrote.
+    pub fn require_owner(&self, owner_id: i32) -> Result<(), ApiError> {
+        if self.id == owner_id || self.role == Role::Admin {
+            return Ok(());
+        }
+        Err(ApiError::Forbidden(PermissionDenied {
+            message: "Only the author or an admin can change this".to_string(),
+            required: Requirement::AuthorOrAdmin,
+            role: self.role,
+        }))
+    }
+
+    pub fn require_write(&self) -> Result<(), ApiError> {
+        if !self.read_only {
+            return Ok(());
+        }
+        Err(ApiError::Forbidden(PermissionDenied {
+            message: "This API key is read-only".to_string(),
+            required: Requirement::ReadWrite,
+            role: self.role,
+        }))
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let user = authenticate(&req).await?;
+            if !req.method().is_safe() {
+                user.require_write()?;
+            }
+            Ok(user)
+        })
+    }
+}
+
+// A bearer token wins over an API key when both are sent. Either only
+// works for the tenant its user belongs to.
+pub(crate) async fn authenticate(req: &HttpRequest) -> Result<AuthUser, ApiError> {
+    let user = authenticate_credentials(req).await?;
+    if user.tenant_id != Tenant::extract(req).await?.id {
+        return Err(ApiError::Unauthorized(
+            "These credentials are for another tenant".to_string(),
+        ));
+    }
+    Ok(user)
+}
+
+async fn authenticate_credentials(req: &HttpRequest) -> Result<AuthUser, ApiError> {
+    if !req.headers().contains_key("Authorization")
+        && let Some(key) = req.headers().get("X-Api-Key")
+    {
+        let key = key
+            .to_str()
+            .map_err(|_| ApiError::Unauthorized("Invalid API key".to_string()))?;
+        let pool = req
+            .app_data::<web::Data<PgPool>>()
+            .ok_or_else(|| ApiError::DatabaseError("No database pool".to_string()))?;
+        return authenticate_api_key(pool, &hash_secret(key.trim()))
+            .await?
+            .ok_or_else(|| ApiError::Unauthorized("Invalid or revoked API key".to_string()));
+    }
+    authenticate_token(req)
+}
+
+pub(crate) fn authenticate_token(req: &HttpRequest) -> Result<AuthUser, ApiError> {
+    let config = req
+        .app_data::<web::Data<AuthConfig>>()
+        .ok_or_else(|| ApiError::DatabaseError("Auth is not configured".to_string()))?;
+    let header = req.headers().get("Authorization").and_then(|value| value.to_str().ok());
+    config.bearer_user(header)
+}
+
+impl AuthConfig {
+    // The user of an `Authorization: Bearer <token>` header value.
+    pub(crate) fn bearer_user(&self, header: Option<&str>) -> Result<AuthUser, ApiError> {
+        let token = header
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| ApiError::Unauthorized("Missing bearer token".to_string()))?;
+        self.token_user(token.trim())
+    }
+
+    fn token_user(&self, token: &str) -> Result<AuthUser, ApiError> {
+        let claims = self.verify_token(token)?;
+        let id = claims
+            .sub
+            .parse()
+            .map_err(|_| ApiError::Unauthorized("Invalid or expired token".to_string()))?;
+        Ok(AuthUser {
+            id,
+            username: claims.username,
+            role: claims.role,
+            tenant_id: claims.tenant,
+            read_only: false,
+        })
+    }
+}
+
+// For API keys and refresh tokens. They're long random strings, so a fast
+// unsalted hash is enough to keep them out of the database.
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+pub fn generate_api_key() -> String {
+    format!("cra_{}", hex::encode(rand::random::<[u8; 32]>()))
+}
+
+pub fn generate_refresh_token() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
@@ -0,0 +1,235 @@
+// The post and listing cache, in process or in Redis.
+
+use crate::*;
+
+// Optional cache for post reads, enabled by CACHE_TTL_SECS > 0. By default
+// it lives in each server process: replicas don't share it and a write on
+// one instance doesn't invalidate the others, so readers behind a load
+// balancer can see stale data for up to the TTL. With CACHE_REDIS_URL set
+// the entries live in Redis instead, shared by every instance, and writes
+// invalidate them for all readers. Entries are per tenant.
+pub(crate) type PostEntries = Cache<(i32, i32), BlogPost>;
+pub(crate) type ListEntries = Cache<String, Arc<Page<BlogPost>>>;
+
+pub(crate) enum CacheBackend {
+    Memory(PostEntries, ListEntries),
+    Redis(RedisPostCache),
+}
+
+pub struct PostCache {
+    backend: Option<CacheBackend>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CacheStats {
+    pub enabled: bool,
+    // "memory" or "redis"; absent when the cache is off.
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub backend: Option<&'static str>,
+    pub hits: u64,
+    pub misses: u64,
+    // In-process entries only; Redis entries aren't counted.
+    pub entries: u64,
+}
+
+// For every Redis user (cache, rate limiting). The connection manager
+// reconnects on its own, but the first connect is bounded so a wrong URL
+// fails startup instead of hanging it.
+pub async fn connect_redis(url: &str) -> Result<redis::aio::ConnectionManager, String> {
+    let client = redis::Client::open(url).map_err(|err| err.to_string())?;
+    let connect = client.get_connection_manager();
+    tokio::time::timeout(Duration::from_secs(5), connect)
+        .await
+        .map_err(|_| "timed out".to_string())?
+        .map_err(|err| err.to_string())
+}
+
+// Entries are JSON with the cache TTL. Lists are namespaced by a
+// generation number so invalidating them all is a single INCR; old
+// generations simply expire.
+pub(crate) struct RedisPostCache {
+    connection: redis::aio::ConnectionManager,
+    ttl_secs: u64,
+}
+
+pub(crate) const REDIS_LIST_GENERATION: &str = "cache:lists:generation";
+
+impl RedisPostCache {
+    // Errors are logged and treated as a miss: the database is always
+    // there to fall back on.
+    async fn get<T: serde::de::DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let mut connection = self.connection.clone();
+        let raw: Option<String> = redis::AsyncCommands::get(&mut connection, key)
+            .await
+            .map_err(|err| tracing::warn!("Redis cache read failed: {}", err))
+            .ok()?;
+        serde_json::from_str(&raw?).ok()
+    }
+
+    async fn put<T: Serialize>(&self, key: &str, value: &T) {
+        let Ok(raw) = serde_json::to_string(value) else {
+            return;
+        };
+        let mut connection = self.connection.clone();
+        let result: redis::RedisResult<()> =
+            redis::AsyncCommands::set_ex(&mut connection, key, raw, self.ttl_secs).await;
+        if let Err(err) = result {
+            tracing::warn!("Redis cache write failed: {}", err);
+        }
+    }
+
+    async fn list_key(&self, tenant: i32, query: &str) -> Option<String> {
+        let mut connection = self.connection.clone();
+        let generation: Option<i64> =
+            redis::AsyncCommands::get(&mut connection, REDIS_LIST_GENERATION)
+                .await
+                .map_err(|err| tracing::warn!("Redis cache read failed: {}", err))
+                .ok()?;
+        Some(format!("cache:list:{}:{}:{}", generation.unwrap_or(0), tenant, query))
+    }
+
+    async fn invalidate(&self, tenant: i32, id: Option<i32>) {
+        let mut connection = self.connection.clone();
+        let mut pipe = redis::pipe();
+        if let Some(id) = id {
+            pipe.del(format!("cache:post:{}:{}", tenant, id)).ignore();
+        }
+        pipe.incr(REDIS_LIST_GENERATION, 1).ignore();
+        let result: redis::RedisResult<()> = pipe.query_async(&mut connection).await;
+        if let Err(err) = result {
+            tracing::warn!("Redis cache invalidation failed, entries may be stale: {}", err);
+        }
+    }
+}
+
+impl PostCache {
+    pub async fn from_env() -> Result<Self, String> {
+        let ttl: u64 = env::var("CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let max_entries: u64 = env::var("CACHE_MAX_ENTRIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1000);
+
+        let backend = match (ttl, env::var("CACHE_REDIS_URL")) {
+            (0, _) => None,
+            (_, Ok(url)) if !url.is_empty() => {
+                let connection = connect_redis(&url)
+                    .await
+                    .map_err(|err| format!("Cannot connect to CACHE_REDIS_URL: {}", err))?;
+                Some(CacheBackend::Redis(RedisPostCache { connection, ttl_secs: ttl }))
+            }
+            _ => {
+                let ttl = Duration::from_secs(ttl);
+                Some(CacheBackend::Memory(
+                    Cache::builder().max_capacity(max_entries).time_to_live(ttl).build(),
+                    Cache::builder().max_capacity(max_entries).time_to_live(ttl).build(),
+                ))
+            }
+        };
+
+        Ok(PostCache {
+            backend,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    fn record<T>(&self, value: Option<T>) -> Option<T> {
+        let counter = if value.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    pub async fn get_post(&self, tenant: i32, id: i32) -> Option<BlogPost> {
+        let post = match self.backend.as_ref()? {
+            CacheBackend::Memory(posts, _) => posts.get(&(tenant, id)),
+            CacheBackend::Redis(redis) => {
+                redis.get(&format!("cache:post:{}:{}", tenant, id)).await
+            }
+        };
+        self.record(post)
+    }
+
+    pub async fn put_post(&self, tenant: i32, post: &BlogPost) {
+        match &self.backend {
+            Some(CacheBackend::Memory(posts, _)) => posts.insert((tenant, post.id), post.clone()),
+            Some(CacheBackend::Redis(redis)) => {
+                redis.put(&format!("cache:post:{}:{}", tenant, post.id), post).await
+            }
+            None => {}
+        }
+    }
+
+    // List entries are keyed by the tenant and the raw query string.
+    pub async fn get_list(&self, tenant: i32, key: &str) -> Option<Arc<Page<BlogPost>>> {
+        let page = match self.backend.as_ref()? {
+            CacheBackend::Memory(_, lists) => lists.get(&format!("{}:{}", tenant, key)),
+            CacheBackend::Redis(redis) => match redis.list_key(tenant, key).await {
+                Some(key) => redis.get(&key).await.map(Arc::new),
+                None => None,
+            },
+        };
+        self.record(page)
+    }
+
+    pub async fn put_list(&self, tenant: i32, key: &str, page: Arc<Page<BlogPost>>) {
+        match &self.backend {
+            Some(CacheBackend::Memory(_, lists)) => {
+                lists.insert(format!("{}:{}", tenant, key), page)
+            }
+            Some(CacheBackend::Redis(redis)) => {
+                if let Some(key) = redis.list_key(tenant, key).await {
+                    redis.put(&key, &*page).await;
+                }
+            }
+            None => {}
+        }
+    }
+
+    // Any write can change any of the tenant's lists, so lists are dropped
+    // wholesale, every tenant's at once.
+    pub async fn invalidate(&self, tenant: i32, id: Option<i32>) {
+        match &self.backend {
+            Some(CacheBackend::Memory(posts, lists)) => {
+                if let Some(id) = id {
+                    posts.invalidate(&(tenant, id));
+                }
+                lists.invalidate_all();
+            }
+            Some(CacheBackend::Redis(redis)) => redis.invalidate(tenant, id).await,
+            None => {}
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (backend, entries) = match &self.backend {
+            Some(CacheBackend::Memory(posts, lists)) => {
+                // Counts are maintained lazily; flush pending bookkeeping first.
+                posts.run_pending_tasks();
+                lists.run_pending_tasks();
+                (Some("memory"), posts.entry_count() + lists.entry_count())
+            }
+            Some(CacheBackend::Redis(_)) => (Some("redis"), 0),
+            None => (None, 0),
+        };
+        CacheStats {
+            enabled: self.backend.is_some(),
+            backend,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+    pub cache: CacheStats,
+}
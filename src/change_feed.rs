@@ -0,0 +1,223 @@
+// In-process fan-out of post changes to WebSocket and SSE clients.
+
+use crate::*;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostEventKind {
+    #[serde(rename = "post.created")]
+    Created,
+    #[serde(rename = "post.updated")]
+    Updated,
+    #[serde(rename = "post.deleted")]
+    Deleted,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PostEvent {
+    pub kind: PostEventKind,
+    // Subscribers only get their own tenant's events.
+    #[serde(skip)]
+    pub tenant: i32,
+    pub id: i32,
+    pub post: Option<BlogPost>,
+    // Set when the write took a published post back to draft.
+    #[serde(skip)]
+    pub unpublished: bool,
+}
+
+impl PostEvent {
+    // The event as `viewer` gets it, if at all. Writes to posts they can't
+    // see are left out, except that a post they could see going back to
+    // draft reaches them as a deletion, without the content.
+    pub fn seen_by(&self, viewer: Option<&AuthUser>) -> Option<PostEvent> {
+        match &self.post {
+            Some(post) if !post_visible(post, viewer) => self.unpublished.then(|| PostEvent {
+                kind: PostEventKind::Deleted,
+                post: None,
+                unpublished: false,
+                ..self.clone()
+            }),
+            _ => Some(self.clone()),
+        }
+    }
+}
+
+// In-process fan-out of post writes. Handlers publish after a successful
+// write and every subscriber gets its own copy. Only writes made through
+// this instance are seen.
+pub struct ChangeFeed {
+    // None once closed for shutdown.
+    sender: std::sync::RwLock<Option<broadcast::Sender<PostEvent>>>,
+}
+
+impl ChangeFeed {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        ChangeFeed {
+            sender: std::sync::RwLock::new(Some(sender)),
+        }
+    }
+
+    // Ends every subscription: WebSockets are closed as going away and
+    // event streams finish, so they don't hold the shutdown drain open
+    // for the whole timeout. Later subscribers get an already closed feed.
+    pub fn close(&self) {
+        self.sender.write().unwrap_or_else(|e| e.into_inner()).take();
+    }
+
+    pub fn publish(&self, kind: PostEventKind, tenant: i32, id: i32, post: Option<BlogPost>) {
+        self.send(PostEvent { kind, tenant, id, post, unpublished: false });
+    }
+
+    // An update; `unpublishing` when the post was published before it and
+    // so leaves the view of those who can't see drafts.
+    pub fn publish_update(&self, tenant: i32, post: BlogPost, unpublishing: bool) {
+        self.send(PostEvent {
+            kind: PostEventKind::Updated,
+            tenant,
+            id: post.id,
+            unpublished: unpublishing && post.status != PostStatus::Published.as_str(),
+            post: Some(post),
+        });
+    }
+
+    fn send(&self, event: PostEvent) {
+        // Sending only fails when nobody is subscribed, which is fine.
+        if let Some(sender) = self.sender.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            let _ = sender.send(event);
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<PostEvent> {
+        match self.sender.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            Some(sender) => sender.subscribe(),
+            None => broadcast::channel(1).1,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ContentUpdate<'a> {
+    pub id: i32,
+    pub title: &'a str,
+    pub content: &'a str,
+}
+
+pub(crate) fn sse_event<T: Serialize>(event: &str, data: &T) -> web::Bytes {
+    let data = serde_json::to_string(data).unwrap_or_else(|_| "null".to_string());
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+pub(crate) fn content_event(post: &BlogPost) -> web::Bytes {
+    sse_event(
+        "content",
+        &ContentUpdate {
+            id: post.id,
+            title: &post.title,
+            content: &post.content,
+        },
+    )
+}
+
+pub(crate) const SSE_KEEPALIVE: Duration = Duration::from_secs(15);
+
+pub(crate) const WS_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+// One task per /ws/blog connection: forwards the tenant's post events the
+// viewer may see as JSON text messages and answers pings. A client that
+// falls too far behind gets a `feed.lagged` message with the number of events it missed, so it can
+// refetch instead of trusting its copy. The server pings every 30s so
+// idle connections survive proxies.
+pub async fn websocket_feed(
+    mut session: actix_ws::Session,
+    mut messages: actix_ws::MessageStream,
+    mut receiver: broadcast::Receiver<PostEvent>,
+    tenant: i32,
+    viewer: Option<AuthUser>,
+) {
+    let mut ping = tokio::time::interval(WS_PING_INTERVAL);
+    ping.tick().await;
+    let reason = loop {
+        tokio::select! {
+            event = receiver.recv() => {
+                let text = match event {
+                    Ok(event) if event.tenant != tenant => continue,
+                    Ok(event) => match event.seen_by(viewer.as_ref()) {
+                        Some(event) => serde_json::to_string(&event),
+                        None => continue,
+                    },
+                    Err(broadcast::error::RecvError::Lagged(missed)) => serde_json::to_string(
+                        &serde_json::json!({ "kind": "feed.lagged", "missed": missed }),
+                    ),
+                    Err(broadcast::error::RecvError::Closed) => {
+                        break Some(actix_ws::CloseCode::Away.into());
+                    }
+                };
+                let Ok(text) = text else { continue };
+                if session.text(text).await.is_err() {
+                    return;
+                }
+            }
+            message = messages.next() => match message {
+                Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Ok(actix_ws::Message::Close(reason))) => break reason,
+                // The feed is one way; anything else from the client is ignored.
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => return,
+            },
+            _ = ping.tick() => {
+                if session.ping(b"").await.is_err() {
+                    return;
+                }
+            }
+        }
+    };
+    let _ = session.close(reason).await;
+}
+
+// Current content first, then one `content` event per update to the post
+// that `viewer` may see. A `deleted` event ends the stream. Comment lines
+// are sent while idle so proxies don't close the connection.
+pub fn content_stream(
+    post: BlogPost,
+    receiver: broadcast::Receiver<PostEvent>,
+    viewer: Option<AuthUser>,
+) -> impl Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    let id = post.id;
+    let first = stream::once(async move { Ok(content_event(&post)) });
+
+    let updates = stream::unfold(Some((receiver, viewer)), move |state| async move {
+        let (mut receiver, viewer) = state?;
+        loop {
+            tokio::select! {
+                received = receiver.recv() => match received {
+                    Ok(event) if event.id != id => continue,
+                    Ok(event) => match event.seen_by(viewer.as_ref()) {
+                        Some(event) if event.kind == PostEventKind::Deleted => {
+                            let bytes = sse_event("deleted", &serde_json::json!({ "id": id }));
+                            return Some((Ok(bytes), None));
+                        }
+                        Some(PostEvent { post: Some(post), .. }) => {
+                            return Some((Ok(content_event(&post)), Some((receiver, viewer))));
+                        }
+                        _ => continue,
+                    },
+                    // Skipped events are fine: the next update carries the
+                    // full content again.
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+                _ = tokio::time::sleep(SSE_KEEPALIVE) => {
+                    let bytes = web::Bytes::from_static(b": keep-alive\n\n");
+                    return Some((Ok(bytes), Some((receiver, viewer))));
+                }
+            }
+        }
+    });
+
+    first.chain(updates)
+}
@@ -0,0 +1,202 @@
+// The command line: serving, plus the ops tasks that would otherwise need
+// psql. Each task opens its own pool and uses the same query functions as
+// the handlers.
+
+use clap::{Parser, Subcommand};
+use fake::faker::lorem::en::{Paragraphs, Sentence, Words};
+use fake::Fake;
+
+use crate::*;
+
+#[derive(Parser)]
+#[command(about = "A blog post API", version)]
+pub struct Cli {
+    // `rest_api --migrate-only`, from before there were subcommands.
+    #[arg(long, hide = true)]
+    migrate_only: bool,
+    /// Load accounts and posts from this YAML or JSON file, after the
+    /// command (for `serve`, before it starts listening)
+    #[arg(long, value_name = "PATH", global = true)]
+    load_fixtures: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the server (the default)
+    Serve,
+    /// Apply pending migrations and exit
+    Migrate,
+    /// Add generated posts, for trying things out locally
+    Seed {
+        /// How many posts to add
+        #[arg(long, default_value_t = 20)]
+        count: u32,
+        /// Whose posts they are; created, unable to log in, if missing
+        #[arg(long, default_value = "seed")]
+        author: String,
+        /// Slug of the tenant to add them to
+        #[arg(long, default_value = "default")]
+        tenant: String,
+    },
+    /// Create an admin account, or make an existing account admin
+    CreateAdmin {
+        #[arg(long)]
+        username: String,
+        /// Only used for a new account
+        #[arg(long, env = "ADMIN_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+        /// Slug of the tenant the account is in
+        #[arg(long, default_value = "default")]
+        tenant: String,
+    },
+    /// Add a tenant, a blog of its own with its own accounts
+    CreateTenant {
+        /// Used in X-Tenant-Id and as the subdomain
+        #[arg(long)]
+        slug: String,
+        #[arg(long)]
+        name: String,
+    },
+}
+
+impl Cli {
+    pub async fn run(self) -> std::io::Result<()> {
+        let command = match self.command {
+            Some(command) => command,
+            None if self.migrate_only => Command::Migrate,
+            None => Command::Serve,
+        };
+        if let Command::Serve = command {
+            return serve(self.load_fixtures).await;
+        }
+
+        dotenv().ok();
+        let config = Config::load().unwrap_or_else(|err| panic!("{}", err));
+        let _telemetry = init_tracing(&config);
+        let pool = establish_connection(&config)
+            .await
+            .expect("Failed to connect to database");
+        let result = match command {
+            Command::Serve => unreachable!(),
+            Command::Migrate => MIGRATOR
+                .run(&pool)
+                .await
+                .map(|()| "Database schema is up to date".to_string())
+                .map_err(|err| format!("Failed to run migrations: {}", err)),
+            Command::Seed { count, author, tenant } => async {
+                let tenant = find_tenant(&pool, &tenant).await?;
+                seed_posts(&pool, &tenant, &author, count).await
+            }
+            .await
+            .map(|posts| format!("Added {} post(s) by {}", posts.len(), author))
+            .map_err(|err| err.to_string()),
+            Command::CreateAdmin { username, password, tenant } => async {
+                let tenant = find_tenant(&pool, &tenant).await?;
+                create_admin(&pool, &tenant, &username, password.as_deref()).await
+            }
+            .await
+            .map(|user| format!("{} (user {}) is an admin", user.username, user.id))
+            .map_err(|err| err.to_string()),
+            Command::CreateTenant { slug, name } => async {
+                validate_tenant_slug(&slug)?;
+                create_tenant(&pool, &slug, name.trim()).await
+            }
+            .await
+            .map(|tenant| format!("Created tenant {} ({})", tenant.slug, tenant.id))
+            .map_err(|err| err.to_string()),
+        };
+        let result = match (result, self.load_fixtures) {
+            (Ok(message), Some(path)) => async {
+                let report = load_fixtures(&pool, &Fixtures::from_file(&path)?).await?;
+                Ok(format!("{}\n{}", message, report))
+            }
+            .await,
+            (result, _) => result,
+        };
+        pool.close().await;
+        match result {
+            Ok(message) => {
+                println!("{}", message);
+                Ok(())
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+pub async fn find_tenant(pool: &PgPool, slug: &str) -> Result<Tenant, ApiError> {
+    get_tenant_by_slug(pool, slug)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("No tenant `{}`", slug)))
+}
+
+// Lorem ipsum posts with a few tags each, added in one transaction with
+// their author when that is new.
+pub async fn seed_posts(
+    pool: &PgPool,
+    tenant: &Tenant,
+    author: &str,
+    count: u32,
+) -> Result<Vec<BlogPost>, ApiError> {
+    let mut tx = pool.begin().await?;
+    let author = match get_user_by_username(&mut *tx, tenant.id, author).await? {
+        Some(user) => user,
+        None => create_user(&mut *tx, tenant.id, author, "!", None).await?,
+    };
+    let mut posts = Vec::new();
+    for _ in 0..count {
+        let title: String = Sentence(3..8).fake();
+        let paragraphs: Vec<String> = Paragraphs(2..6).fake();
+        let tags: Vec<String> = Words(1..4).fake();
+        let post = NewBlogPost {
+            title: title.trim_end_matches('.').to_string(),
+            content: paragraphs.join("\n\n"),
+            tags: Some(tags),
+            version: None,
+            status: None,
+            publish_at: None,
+            unknown_fields: BTreeMap::new(),
+        };
+        posts.push(create_post(&mut tx, tenant.id, &post, author.id).await?);
+    }
+    tx.commit().await?;
+    Ok(posts)
+}
+
+// Without a password an existing account is required; the audit log
+// records the promotion as the account's own. A new account is only kept
+// if the promotion succeeds.
+pub async fn create_admin(
+    pool: &PgPool,
+    tenant: &Tenant,
+    username: &str,
+    password: Option<&str>,
+) -> Result<User, ApiError> {
+    let mut tx = pool.begin().await?;
+    let user = match (get_user_by_username(&mut *tx, tenant.id, username).await?, password) {
+        (Some(user), _) => user,
+        (None, Some(password)) => {
+            let new_user = NewUser {
+                username: username.to_string(),
+                password: password.to_string(),
+                email: None,
+            };
+            validate_new_user(&new_user)?;
+            create_user(&mut *tx, tenant.id, username, &hash_password(password)?, None).await?
+        }
+        (None, None) => {
+            return Err(ApiError::NotFound(format!(
+                "No user `{}`; pass --password or set ADMIN_PASSWORD to create one",
+                username
+            )))
+        }
+    };
+    let admin = set_user_role(&mut tx, tenant.id, user.id, Role::Admin, user.id).await?;
+    tx.commit().await?;
+    Ok(admin)
+}
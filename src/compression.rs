@@ -0,0 +1,110 @@
+// gzip/brotli response compression: actix's Compress, limited to the
+// configured encodings, content types and minimum size.
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::ServiceFactory;
+use actix_web::http::header::{AcceptEncoding, Encoding, CONTENT_ENCODING, CONTENT_TYPE};
+use actix_web::middleware::{Compress, Condition};
+
+use crate::*;
+
+impl CompressionConfig {
+    // Narrows the request's Accept-Encoding to the one encoding it will be
+    // answered with, or drops it for an uncompressed answer. A client that
+    // takes none of ours gets identity rather than Compress's 406.
+    pub(crate) fn negotiate(&self, req: &mut ServiceRequest) {
+        let Some(accepted) = req.get_header::<AcceptEncoding>() else {
+            return;
+        };
+        let offered: Vec<Encoding> = self
+            .encodings
+            .iter()
+            .filter_map(|encoding| encoding.parse().ok())
+            .chain([Encoding::identity()])
+            .collect();
+        let chosen = accepted
+            .negotiate(offered.iter())
+            .filter(|encoding| *encoding != Encoding::identity())
+            .and_then(|encoding| HeaderValue::from_str(&encoding.to_string()).ok());
+        match chosen {
+            Some(encoding) => req.headers_mut().insert(AcceptEncoding::name(), encoding),
+            None => req.headers_mut().remove(AcceptEncoding::name()),
+        };
+    }
+
+    // Whether a response of this type and size is worth compressing.
+    pub fn compresses(&self, content_type: Option<&HeaderValue>, size: BodySize) -> bool {
+        if matches!(size, BodySize::Sized(len) if len < self.min_size as u64) {
+            return false;
+        }
+        let Some(content_type) = content_type.and_then(|value| value.to_str().ok()) else {
+            return false;
+        };
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        let kind = essence.split('/').next().unwrap_or_default();
+        self.content_types.iter().any(|allowed| match allowed.strip_suffix("/*") {
+            Some(allowed_kind) => kind.eq_ignore_ascii_case(allowed_kind),
+            None => essence.eq_ignore_ascii_case(allowed),
+        })
+    }
+}
+
+// Compress leaves alone any response that already has a Content-Encoding,
+// so the ones that should go out as they are carry `identity` through it,
+// which is taken off again on the way out.
+pub fn with_compression<T, B>(
+    app: App<T>,
+    config: CompressionConfig,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+>
+where
+    T: ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<B>,
+        Error = actix_web::Error,
+        InitError = (),
+    > + 'static,
+    B: MessageBody + 'static,
+{
+    const IDENTITY: HeaderValue = HeaderValue::from_static("identity");
+    let enabled = config.enabled;
+    let inner = Arc::new(config);
+    let outer = inner.clone();
+    app.wrap_fn(move |req, srv| {
+        let config = inner.clone();
+        let fut = srv.call(req);
+        async move {
+            let mut res = fut.await?;
+            let compresses = config.compresses(
+                res.headers().get(CONTENT_TYPE),
+                res.response().body().size(),
+            );
+            if enabled && !compresses && !res.headers().contains_key(CONTENT_ENCODING) {
+                res.headers_mut().insert(CONTENT_ENCODING, IDENTITY);
+            }
+            Ok(res)
+        }
+    })
+    .wrap(Condition::new(enabled, Compress::default()))
+    .wrap_fn(move |mut req, srv| {
+        if enabled {
+            outer.negotiate(&mut req);
+        }
+        let fut = srv.call(req);
+        async move {
+            let mut res = fut.await?;
+            if enabled && res.headers().get(CONTENT_ENCODING) == Some(&IDENTITY) {
+                res.headers_mut().remove(CONTENT_ENCODING);
+            }
+            Ok(res)
+        }
+    })
+}
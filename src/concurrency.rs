@@ -0,0 +1,41 @@
+// Limits how many expensive queries run at once.
+
+use crate::*;
+
+// Caps how many expensive requests (full listings, imports, and later
+// search/export) hit the database at once. SEARCH_MAX_CONCURRENCY (default
+// 8) requests run; others wait up to SEARCH_QUEUE_WAIT_MS (default 500) for
+// a slot and then get 503 with Retry-After. Cheap single-row reads don't
+// go through this.
+pub struct HeavyQueryLimiter {
+    permits: Arc<Semaphore>,
+    wait: Duration,
+}
+
+impl HeavyQueryLimiter {
+    pub fn from_env() -> Self {
+        let max: usize = env::var("SEARCH_MAX_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(8);
+        let wait_ms: u64 = env::var("SEARCH_QUEUE_WAIT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(500);
+        HeavyQueryLimiter {
+            permits: Arc::new(Semaphore::new(max.max(1))),
+            wait: Duration::from_millis(wait_ms),
+        }
+    }
+
+    // The slot is held until the returned permit is dropped.
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, ApiError> {
+        let acquire = self.permits.clone().acquire_owned();
+        match tokio::time::timeout(self.wait, acquire).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(ApiError::ServiceUnavailable(
+                "Too many expensive requests in flight, try again shortly".to_string(),
+            )),
+        }
+    }
+}
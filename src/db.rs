@@ -0,0 +1,3290 @@
+// The database layer: pool setup, the embedded migrations and the query
+// functions every store and handler is built on.
+
+use crate::*;
+
+// Everything in migrations/, embedded at compile time. build.rs makes
+// cargo rebuild when a migration is added. Applied migrations are recorded
+// in _sqlx_migrations with a checksum, so never edit one that has shipped;
+// add a new file instead.
+pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
+
+// -------------------- DB --------------------
+
+
+// DB_TEST_BEFORE_ACQUIRE (default true) pings every connection before it is
+// handed to a handler, so connections killed by a failover or an idle
+// timeout on the server side are discarded and replaced instead of failing
+// the request. The cost is one extra round trip per acquire; turn it off
+// when the database is local and latency matters more than resilience.
+fn pool_options(config: &Config) -> PgPoolOptions {
+    let idle_timeout = (config.idle_timeout_secs > 0)
+        .then(|| Duration::from_secs(config.idle_timeout_secs));
+    PgPoolOptions::new()
+        .max_connections(config.pool_size)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+        .idle_timeout(idle_timeout)
+        .test_before_acquire(env_flag("DB_TEST_BEFORE_ACQUIRE", true))
+}
+
+// The statement timeout is a startup parameter of every connection, so
+// it holds for each statement without a round trip to set it.
+fn connect_options(config: &Config, url: &str) -> Result<PgConnectOptions, sqlx::Error> {
+    let options: PgConnectOptions = url.parse()?;
+    Ok(match config.statement_timeout_ms {
+        0 => options,
+        ms => options.options([("statement_timeout", ms.to_string())]),
+    })
+}
+
+pub async fn establish_connection(config: &Config) -> Result<PgPool, sqlx::Error> {
+    pool_options(config).connect_with(connect_options(config, &config.database_url)?).await
+}
+
+// For the stores that keep posts elsewhere: routes that still need Postgres
+// connect on first use, so the server can start without it.
+pub fn establish_lazy_connection(config: &Config) -> Result<PgPool, sqlx::Error> {
+    Ok(pool_options(config).connect_lazy_with(connect_options(config, &config.database_url)?))
+}
+
+// One pool per replica in `replica_urls`, connecting on first use so a
+// replica that is down doesn't keep the server from starting. A replica
+// gets REPLICA_ACQUIRE_TIMEOUT to hand out a connection, so one that is
+// down holds up a read only that long before it goes to the primary.
+pub fn establish_replica_connections(config: &Config) -> Result<Vec<PgPool>, sqlx::Error> {
+    let acquire_timeout =
+        Duration::from_secs(config.acquire_timeout_secs).min(REPLICA_ACQUIRE_TIMEOUT);
+    config
+        .replica_urls
+        .iter()
+        .map(|url| {
+            let options = pool_options(config).acquire_timeout(acquire_timeout);
+            Ok(options.connect_lazy_with(connect_options(config, url)?))
+        })
+        .collect()
+}
+
+// -------------------- SQLX --------------------
+
+// Writes that take several statements, or that record an audit entry,
+// take a `&mut PgConnection` and leave the transaction to the caller: it
+// begins one, runs as many of them as belong together, and commits. An
+// error anywhere drops the transaction and so rolls all of them back. On
+// a bare connection their statements would commit one by one.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %user_id))]
+pub async fn create_post(
+    conn: &mut PgConnection,
+    tenant: i32,
+    post: &NewBlogPost,
+    user_id: i32,
+) -> Result<BlogPost, ApiError> {
+    let tags = post.tags.as_deref().map(normalize_tags).transpose()?;
+    let content = sanitize_content(&post.content);
+    let slug = unique_slug(&mut *conn, tenant, &post.title).await?;
+    let id = sqlx::query_scalar!(
+        "INSERT INTO blog_posts \
+         (tenant_id, title, slug, content, user_id, status, publish_at, snowflake_id) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+        tenant,
+        post.title,
+        slug,
+        &*content,
+        user_id,
+        post.initial_status()?.as_str(),
+        post.publish_at,
+        next_snowflake_id(),
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if let Some(tags) = tags {
+        set_post_tags(conn, id, &tags).await?;
+    }
+    let created = get_post(&mut *conn, tenant, id).await?;
+    record_audit(conn, user_id, AuditEntity::Post, id, AuditAction::Create, None, Some(&created))
+        .await?;
+    Ok(created)
+}
+
+// The live post `user_id` wrote under `title`, if there is one.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %user_id))]
+pub async fn find_post_by_title(
+    conn: &mut PgConnection,
+    tenant: i32,
+    user_id: i32,
+    title: &str,
+) -> Result<Option<i32>, ApiError> {
+    sqlx::query_scalar!(
+        "SELECT id FROM blog_posts \
+         WHERE tenant_id = $1 AND user_id = $2 AND title = $3 AND deleted_at IS NULL \
+         ORDER BY id LIMIT 1",
+        tenant,
+        user_id,
+        title,
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(ApiError::from)
+}
+
+// Lowercase ASCII letters and digits, with everything else in between
+// collapsed to single dashes, e.g. "Hello, World!" -> "hello-world".
+// Titles without any of those get "post".
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(MAX_SLUG_CHARS);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "post".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+pub(crate) const MAX_SLUG_CHARS: usize = 80;
+
+// The title's slug, or the first of slug-2, slug-3, ... not taken yet by
+// any post of the tenant, trashed ones included. Must run in the inserting
+// transaction: the advisory lock keeps two posts with the same title from
+// both getting the same free slug.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant))]
+pub async fn unique_slug(
+    conn: &mut PgConnection,
+    tenant: i32,
+    title: &str,
+) -> Result<String, ApiError> {
+    let base = slugify(title);
+    sqlx::query!("SELECT pg_advisory_xact_lock($2, hashtext($1))", &base, tenant)
+        .execute(&mut *conn)
+        .await?;
+    let taken = sqlx::query_scalar!(
+        "SELECT slug FROM blog_posts \
+         WHERE tenant_id = $2 AND (slug = $1 OR slug LIKE $1 || '-%')",
+        &base,
+        tenant,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(first_free_slug(&base, &taken))
+}
+
+// `base` itself, or the first of `base-2`, `base-3`, ... not in `taken`.
+pub fn first_free_slug(base: &str, taken: &[String]) -> String {
+    if !taken.iter().any(|slug| slug == base) {
+        return base.to_string();
+    }
+    (2..)
+        .map(|n| format!("{}-{}", base, n))
+        .find(|candidate| !taken.contains(candidate))
+        .expect("some suffix is free")
+}
+
+pub(crate) const SLUG_REBUILD_BATCH: i64 = 500;
+
+// Gives the tenant's posts, trashed ones included, fresh slugs from their
+// titles in id order; with `missing_only`, just the posts whose slug is
+// empty. Batches of SLUG_REBUILD_BATCH posts each run in a transaction of
+// their own, so a big tenant isn't locked all at once. Returns the ids
+// whose slug changed.
+#[tracing::instrument(level = "info", skip_all, fields(%tenant, missing_only))]
+pub async fn rebuild_slugs(
+    pool: &PgPool,
+    tenant: i32,
+    missing_only: bool,
+) -> Result<Vec<i32>, ApiError> {
+    let mut changed = Vec::new();
+    let mut done = 0;
+    let mut after = 0;
+    loop {
+        let mut tx = pool.begin().await?;
+        let posts = sqlx::query!(
+            "SELECT id, title, slug FROM blog_posts \
+             WHERE tenant_id = $1 AND (NOT $2 OR slug = '') AND id > $3 \
+             ORDER BY id LIMIT $4 FOR UPDATE",
+            tenant,
+            missing_only,
+            after,
+            SLUG_REBUILD_BATCH,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let Some(last) = posts.last() else {
+            break;
+        };
+        after = last.id;
+        // Parked out of the way first, so a post can take a slug that
+        // another post of the batch is giving up. No slug has a '#' in it.
+        let ids: Vec<i32> = posts.iter().map(|post| post.id).collect();
+        sqlx::query!("UPDATE blog_posts SET slug = '#' || id WHERE id = ANY($1)", &ids)
+            .execute(&mut *tx)
+            .await?;
+        for post in &posts {
+            let slug = unique_slug(&mut tx, tenant, &post.title).await?;
+            sqlx::query!("UPDATE blog_posts SET slug = $1 WHERE id = $2", &slug, post.id)
+                .execute(&mut *tx)
+                .await?;
+            if slug != post.slug {
+                changed.push(post.id);
+            }
+        }
+        tx.commit().await?;
+        done += posts.len();
+        tracing::info!(done, changed = changed.len(), "Rebuilding slugs");
+    }
+    Ok(changed)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%post_id))]
+pub async fn set_post_tags(
+    conn: &mut PgConnection,
+    post_id: i32,
+    tags: &[String],
+) -> Result<(), ApiError> {
+    sqlx::query!("DELETE FROM post_tags WHERE post_id = $1", post_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO tags (name) SELECT * FROM UNNEST($1::text[]) ON CONFLICT (name) DO NOTHING",
+        tags,
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO post_tags (post_id, tag_id) SELECT $1, id FROM tags WHERE name = ANY($2)",
+        post_id,
+        tags,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+// Each post is validated and reviewed by the moderator like a single one:
+// a rejected post is a failed item, a flagged one is created and queued
+// for review in the same transaction.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, mode = ?mode, %user_id))]
+pub async fn create_posts_bulk(
+    pool: &PgPool,
+    moderator: &dyn Moderator,
+    tenant: i32,
+    posts: &[NewBlogPost],
+    mode: BulkMode,
+    user_id: i32,
+) -> Result<BulkResult, ApiError> {
+    let mut tx = pool.begin().await?;
+    let mut result = BulkResult::default();
+
+    for (index, post) in posts.iter().enumerate() {
+        let flag = match check_new_post(post) {
+            Ok(()) => moderate(moderator, ContentKind::Post, &post.moderated_text()).await,
+            Err(err) => Err(err),
+        };
+        match mode {
+            BulkMode::AllOrNothing => {
+                // Dropping `tx` on the early return rolls everything back.
+                let created = match flag {
+                    Ok(flag) => create_flagged_post(&mut tx, tenant, post, user_id, flag).await,
+                    Err(err) => Err(err),
+                };
+                let created = created.map_err(|err| err.at_index(index))?;
+                result.created.push(created);
+            }
+            BulkMode::BestEffort => {
+                let flag = match flag {
+                    Ok(flag) => flag,
+                    Err(err) => {
+                        result.failed.push(BulkFailure { index, error: err.to_string() });
+                        continue;
+                    }
+                };
+                // A failed statement aborts the surrounding transaction in
+                // Postgres, so each row runs in a savepoint we can roll back.
+                let mut savepoint = tx.begin().await?;
+                match create_flagged_post(&mut savepoint, tenant, post, user_id, flag).await {
+                    Ok(created) => {
+                        savepoint.commit().await?;
+                        result.created.push(created);
+                    }
+                    Err(err) => {
+                        savepoint.rollback().await?;
+                        result.failed.push(BulkFailure { index, error: err.to_string() });
+                    }
+                }
+            }
+        }
+    }
+
+    tx.commit().await?;
+    Ok(result)
+}
+
+// A post of a bulk write, queued for review when the moderator flagged it.
+async fn create_flagged_post(
+    conn: &mut PgConnection,
+    tenant: i32,
+    post: &NewBlogPost,
+    user_id: i32,
+    flag: Option<String>,
+) -> Result<BlogPost, ApiError> {
+    let created = create_post(&mut *conn, tenant, post, user_id).await?;
+    if let Some(reason) = flag {
+        queue_for_moderation(&mut *conn, tenant, ContentKind::Post, created.id, user_id, &reason)
+            .await?;
+    }
+    Ok(created)
+}
+
+// Moves the posts to the trash in one statement. An id that isn't a live
+// post of the actor's tenant (or repeats an earlier one), or belongs to
+// someone else, is reported, and under all_or_nothing fails the whole batch.
+#[tracing::instrument(level = "debug", skip_all, fields(mode = ?mode, count = ids.len()))]
+pub async fn delete_posts_bulk(
+    pool: &PgPool,
+    ids: &[i32],
+    mode: BulkMode,
+    actor: &AuthUser,
+) -> Result<BulkDeleteResult, ApiError> {
+    let mut tx = pool.begin().await?;
+    // Locked and read first so ownership can be checked and the audit log
+    // gets what was trashed.
+    let mut live: HashMap<i32, BlogPost> = sqlx::query_as!(
+        BlogPost,
+        r#"
+        SELECT p.id, p.uuid, p.snowflake_id, p.title, p.slug, p.content, p.user_id,
+            u.username AS author,
+            ARRAY(
+                SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+                WHERE pt.post_id = p.id ORDER BY t.name
+            ) AS "tags!",
+            p.version, p.status, p.like_count, p.view_count, p.publish_at,
+            p.created_at, p.updated_at
+        FROM blog_posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.id = ANY($1) AND p.tenant_id = $2 AND p.deleted_at IS NULL
+        FOR UPDATE OF p
+        "#,
+        ids,
+        actor.tenant_id,
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|post| (post.id, post))
+    .collect();
+
+    let mut result = BulkDeleteResult::default();
+    for &id in ids {
+        let checked = match live.remove(&id) {
+            Some(old) => actor.require_owner(old.user_id).map(|()| old),
+            None => Err(ApiError::NotFound(format!("Post {} not found", id))),
+        };
+        let err = match checked {
+            Ok(old) => {
+                record_audit(
+                    &mut tx,
+                    actor.id,
+                    AuditEntity::Post,
+                    id,
+                    AuditAction::Delete,
+                    Some(&old),
+                    None,
+                )
+                .await?;
+                result.deleted.push(id);
+                continue;
+            }
+            Err(err) => err,
+        };
+        if mode == BulkMode::AllOrNothing {
+            // Dropping `tx` rolls the whole batch back.
+            return Err(err);
+        }
+        result.failed.push(BulkDeleteFailure { id, error: err.to_string() });
+    }
+
+    sqlx::query!(
+        "UPDATE blog_posts SET deleted_at = now() WHERE id = ANY($1)",
+        &result.deleted,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+// $1 to $5 are the PostFilter tag, author, status, viewer and see_all,
+// $6 to $9 its date bounds and $10 its tenant.
+pub(crate) const LIST_POSTS_SQL: &str = "SELECT p.id, p.uuid, p.snowflake_id, p.title, p.slug, \
+     p.content, p.user_id, u.username AS author, \
+     ARRAY(SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id \
+           WHERE pt.post_id = p.id ORDER BY t.name) AS tags, p.version, p.status, \
+     p.like_count, p.view_count, p.publish_at, p.created_at, p.updated_at \
+     FROM blog_posts p JOIN users u ON u.id = p.user_id \
+     WHERE p.deleted_at IS NULL \
+     AND ($1::text IS NULL OR EXISTS (SELECT 1 FROM post_tags pt JOIN tags t ON t.id = pt.tag_id \
+           WHERE pt.post_id = p.id AND t.name = $1)) \
+     AND ($2::text IS NULL OR u.username = $2) \
+     AND ($3::text IS NULL OR p.status = $3) \
+     AND (p.status = 'published' OR p.user_id = $4 OR $5) \
+     AND ($6::timestamptz IS NULL OR p.created_at >= $6) \
+     AND ($7::timestamptz IS NULL OR p.created_at <= $7) \
+     AND ($8::timestamptz IS NULL OR p.updated_at >= $8) \
+     AND ($9::timestamptz IS NULL OR p.updated_at <= $9) \
+     AND p.tenant_id = $10";
+
+// Built at runtime because the ORDER BY varies; the column and direction
+// come from enums, never from client text. $13 and $14 are the keyset
+// cursor, if any.
+#[tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(filter = ?filter, sort = ?sort, order = ?order, %limit, %offset)
+)]
+pub async fn get_all_posts(
+    pool: &PgPool,
+    filter: PostFilter<'_>,
+    sort: SortColumn,
+    order: SortOrder,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<BlogPost>, ApiError> {
+    let sql = format!(
+        "{} AND ($13::timestamptz IS NULL OR p.created_at < $13 \
+         OR (p.created_at = $13 AND p.id > $14)) \
+         ORDER BY {} {}, p.id LIMIT $11 OFFSET $12",
+        LIST_POSTS_SQL,
+        sort.as_sql(),
+        order.as_sql()
+    );
+    sqlx::query_as::<_, BlogPost>(&sql)
+        .bind(filter.tag)
+        .bind(filter.author)
+        .bind(filter.status.map(PostStatus::as_str))
+        .bind(filter.viewer)
+        .bind(filter.see_all)
+        .bind(filter.created_from)
+        .bind(filter.created_to)
+        .bind(filter.updated_from)
+        .bind(filter.updated_to)
+        .bind(filter.tenant)
+        .bind(limit)
+        .bind(offset)
+        .bind(filter.after.map(|cursor| cursor.created_at))
+        .bind(filter.after.map(|cursor| cursor.id))
+        .fetch_all(pool)
+        .await
+        .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(filter = ?filter))]
+pub async fn count_posts(pool: &PgPool, filter: PostFilter<'_>) -> Result<i64, ApiError> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM blog_posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.deleted_at IS NULL AND ($1::text IS NULL OR EXISTS (
+            SELECT 1 FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+            WHERE pt.post_id = p.id AND t.name = $1
+        ))
+        AND ($2::text IS NULL OR u.username = $2)
+        AND ($3::text IS NULL OR p.status = $3)
+        AND (p.status = 'published' OR p.user_id = $4 OR $5)
+        AND ($6::timestamptz IS NULL OR p.created_at >= $6)
+        AND ($7::timestamptz IS NULL OR p.created_at <= $7)
+        AND ($8::timestamptz IS NULL OR p.updated_at >= $8)
+        AND ($9::timestamptz IS NULL OR p.updated_at <= $9)
+        AND p.tenant_id = $10
+        "#,
+        filter.tag,
+        filter.author,
+        filter.status.map(PostStatus::as_str),
+        filter.viewer,
+        filter.see_all,
+        filter.created_from,
+        filter.created_to,
+        filter.updated_from,
+        filter.updated_to,
+        filter.tenant,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// Asks the planner how many rows `sql` would return instead of running it.
+// This is cheap on any table size but only as good as the statistics
+// gathered by ANALYZE/autovacuum, so it can be well off right after bulk
+// writes or for selective filters the planner can't model.
+#[tracing::instrument(level = "debug", skip_all, fields(filter = ?filter))]
+pub async fn estimate_count(
+    pool: &PgPool,
+    sql: &str,
+    filter: PostFilter<'_>,
+) -> Result<i64, ApiError> {
+    let plan: serde_json::Value =
+        sqlx::query_scalar(&format!("EXPLAIN (FORMAT JSON) {}", sql))
+            .bind(filter.tag)
+            .bind(filter.author)
+            .bind(filter.status.map(PostStatus::as_str))
+            .bind(filter.viewer)
+            .bind(filter.see_all)
+            .bind(filter.created_from)
+            .bind(filter.created_to)
+            .bind(filter.updated_from)
+            .bind(filter.updated_to)
+            .bind(filter.tenant)
+            .fetch_one(pool)
+            .await
+            .map_err(ApiError::from)?;
+
+    plan[0]["Plan"]["Plan Rows"]
+        .as_f64()
+        .map(|rows| rows as i64)
+        .ok_or_else(|| {
+            ApiError::DatabaseError("Query plan has no row estimate".to_string())
+        })
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%limit, %offset))]
+pub async fn search_posts(
+    pool: &PgPool,
+    tenant: i32,
+    query: &str,
+    weights: &[f32],
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<SearchHit>, ApiError> {
+    sqlx::query_as!(
+        SearchHit,
+        r#"
+        SELECT p.id, p.uuid, p.title, p.slug, p.user_id, u.username AS author,
+            ARRAY(
+                SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+                WHERE pt.post_id = p.id ORDER BY t.name
+            ) AS "tags!",
+            p.version,
+            ts_rank($2::float4[], p.search_vector, q) AS "rank!",
+            ts_headline(
+                'english', p.content, q,
+                'StartSel=<mark>, StopSel=</mark>, MaxFragments=2, MaxWords=30, MinWords=10'
+            ) AS "snippet!"
+        FROM blog_posts p
+        JOIN users u ON u.id = p.user_id,
+            websearch_to_tsquery('english', $1) q
+        WHERE p.search_vector @@ q AND p.deleted_at IS NULL AND p.status = 'published'
+            AND p.tenant_id = $5
+        ORDER BY "rank!" DESC, p.id
+        LIMIT $3 OFFSET $4
+        "#,
+        query,
+        weights,
+        limit,
+        offset,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn count_search_results(
+    pool: &PgPool,
+    tenant: i32,
+    query: &str,
+) -> Result<i64, ApiError> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM blog_posts
+        WHERE search_vector @@ websearch_to_tsquery('english', $1)
+            AND deleted_at IS NULL AND status = 'published' AND tenant_id = $2
+        "#,
+        query,
+        tenant,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id))]
+pub async fn get_post<'e, E: PgExecutor<'e>>(
+    executor: E,
+    tenant: i32,
+    id: i32,
+) -> Result<BlogPost, ApiError> {
+    sqlx::query_as!(
+        BlogPost,
+        r#"
+        SELECT p.id, p.uuid, p.snowflake_id, p.title, p.slug, p.content, p.user_id,
+            u.username AS author,
+            ARRAY(
+                SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+                WHERE pt.post_id = p.id ORDER BY t.name
+            ) AS "tags!",
+            p.version, p.status, p.like_count, p.view_count, p.publish_at,
+            p.created_at, p.updated_at
+        FROM blog_posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.id = $1 AND p.tenant_id = $2 AND p.deleted_at IS NULL
+        "#,
+        id,
+        tenant,
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(ApiError::from)
+}
+
+// The live posts of `tenant` among `ids`, in id order, in one query. Ids
+// without one are left out.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, count = ids.len()))]
+pub async fn get_posts_by_ids(
+    pool: &PgPool,
+    tenant: i32,
+    ids: &[i32],
+) -> Result<Vec<BlogPost>, ApiError> {
+    sqlx::query_as!(
+        BlogPost,
+        r#"
+        SELECT p.id, p.uuid, p.snowflake_id, p.title, p.slug, p.content, p.user_id,
+            u.username AS author,
+            ARRAY(
+                SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+                WHERE pt.post_id = p.id ORDER BY t.name
+            ) AS "tags!",
+            p.version, p.status, p.like_count, p.view_count, p.publish_at,
+            p.created_at, p.updated_at
+        FROM blog_posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.id = ANY($1) AND p.tenant_id = $2 AND p.deleted_at IS NULL
+        ORDER BY p.id
+        "#,
+        ids,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// The slugs and update times of the published posts of `tenant`, most
+// recently updated first, without loading any content.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %limit))]
+pub async fn get_sitemap_entries(
+    pool: &PgPool,
+    tenant: i32,
+    limit: i64,
+) -> Result<Vec<SitemapEntry>, ApiError> {
+    sqlx::query_as!(
+        SitemapEntry,
+        "SELECT slug, updated_at FROM blog_posts \
+         WHERE tenant_id = $1 AND status = 'published' AND deleted_at IS NULL \
+         ORDER BY updated_at DESC, id DESC LIMIT $2",
+        tenant,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// The live posts among `slugs`, in no particular order, leaving out the
+// slugs no post has.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, count = slugs.len()))]
+pub async fn get_posts_by_slugs(
+    pool: &PgPool,
+    tenant: i32,
+    slugs: &[String],
+) -> Result<Vec<BlogPost>, ApiError> {
+    sqlx::query_as!(
+        BlogPost,
+        r#"
+        SELECT p.id, p.uuid, p.snowflake_id, p.title, p.slug, p.content, p.user_id,
+            u.username AS author,
+            ARRAY(
+                SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+                WHERE pt.post_id = p.id ORDER BY t.name
+            ) AS "tags!",
+            p.version, p.status, p.like_count, p.view_count, p.publish_at,
+            p.created_at, p.updated_at
+        FROM blog_posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.slug = ANY($1) AND p.tenant_id = $2 AND p.deleted_at IS NULL
+        "#,
+        slugs,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant))]
+pub async fn get_post_by_slug(
+    pool: &PgPool,
+    tenant: i32,
+    slug: &str,
+) -> Result<BlogPost, ApiError> {
+    sqlx::query_as!(
+        BlogPost,
+        r#"
+        SELECT p.id, p.uuid, p.snowflake_id, p.title, p.slug, p.content, p.user_id,
+            u.username AS author,
+            ARRAY(
+                SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+                WHERE pt.post_id = p.id ORDER BY t.name
+            ) AS "tags!",
+            p.version, p.status, p.like_count, p.view_count, p.publish_at,
+            p.created_at, p.updated_at
+        FROM blog_posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.slug = $1 AND p.tenant_id = $2 AND p.deleted_at IS NULL
+        "#,
+        slug,
+        tenant,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("No post with slug `{}`", slug)))
+}
+
+// Every published post of the tenant, oldest first, read row by row so an
+// export never holds the whole table in memory.
+pub fn stream_all_posts(
+    pool: &PgPool,
+    tenant: i32,
+) -> impl Stream<Item = Result<BlogPost, sqlx::Error>> + '_ {
+    sqlx::query_as!(
+        BlogPost,
+        r#"
+        SELECT p.id, p.uuid, p.snowflake_id, p.title, p.slug, p.content, p.user_id,
+            u.username AS author,
+            ARRAY(
+                SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+                WHERE pt.post_id = p.id ORDER BY t.name
+            ) AS "tags!",
+            p.version, p.status, p.like_count, p.view_count, p.publish_at,
+            p.created_at, p.updated_at
+        FROM blog_posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.deleted_at IS NULL AND p.status = 'published' AND p.tenant_id = $1
+        ORDER BY p.id
+        "#,
+        tenant,
+    )
+    .fetch(pool)
+}
+
+// The query behind `stream_posts`: LIST_POSTS_SQL in the given order, with
+// no page to cut it short.
+pub fn stream_posts_sql(sort: SortColumn, order: SortOrder) -> String {
+    format!("{} ORDER BY {} {}, p.id", LIST_POSTS_SQL, sort.as_sql(), order.as_sql())
+}
+
+// Every post `filter` matches, read row by row like `stream_all_posts`.
+// `sql` comes from `stream_posts_sql`.
+pub fn stream_posts<'a>(
+    pool: &'a PgPool,
+    sql: &'a str,
+    filter: PostFilter<'a>,
+) -> impl Stream<Item = Result<BlogPost, sqlx::Error>> + 'a {
+    sqlx::query_as::<_, BlogPost>(sql)
+        .bind(filter.tag)
+        .bind(filter.author)
+        .bind(filter.status.map(PostStatus::as_str))
+        .bind(filter.viewer)
+        .bind(filter.see_all)
+        .bind(filter.created_from)
+        .bind(filter.created_to)
+        .bind(filter.updated_from)
+        .bind(filter.updated_to)
+        .bind(filter.tenant)
+        .fetch(pool)
+}
+
+// Locks the live post until the transaction ends and checks it is still
+// the version the client last saw, so a stale write can't slip in between
+// the check and the update.
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn check_if_match(
+    conn: &mut PgConnection,
+    tenant: i32,
+    id: i32,
+    if_match: &IfMatch,
+) -> Result<(), ApiError> {
+    sqlx::query_scalar!(
+        "SELECT id FROM blog_posts \
+         WHERE id = $1 AND tenant_id = $2 AND deleted_at IS NULL FOR UPDATE",
+        id,
+        tenant,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", id)))?;
+
+    require_etag(&get_post(&mut *conn, tenant, id).await?, if_match)
+}
+
+// If-Match uses the strong comparison, unlike If-None-Match.
+pub fn require_etag(post: &BlogPost, if_match: &IfMatch) -> Result<(), ApiError> {
+    let current = post_etag(post);
+    let matched = match if_match {
+        IfMatch::Any => true,
+        IfMatch::Items(tags) => tags.iter().any(|tag| tag.strong_eq(&current)),
+    };
+    if !matched {
+        return Err(ApiError::PreconditionFailed(format!(
+            "Post {} has changed; fetch it again for the current ETag",
+            post.id
+        )));
+    }
+    Ok(())
+}
+
+// PUT and PATCH have to say which version of the post they change.
+pub fn expected_version(version: Option<i32>) -> Result<i32, ApiError> {
+    version.ok_or_else(|| {
+        ApiError::Validation(ValidationFailure {
+            message: "Validation failed".to_string(),
+            errors: BTreeMap::from([(
+                "version".to_string(),
+                vec!["is required; send the version you last read".to_string()],
+            )]),
+        })
+    })
+}
+
+// Why a version-guarded UPDATE matched no row: the post is gone, or
+// someone else updated it first.
+pub(crate) async fn version_conflict(conn: &mut PgConnection, id: i32, expected: i32) -> ApiError {
+    let current = sqlx::query_scalar!(
+        "SELECT version FROM blog_posts WHERE id = $1 AND deleted_at IS NULL",
+        id,
+    )
+    .fetch_optional(conn)
+    .await;
+    match current {
+        Ok(current) => stale_version(id, expected, current),
+        Err(err) => err.into(),
+    }
+}
+
+// For the stores that keep no schedules: asking for one is refused rather
+// than quietly publishing the post never.
+pub fn no_schedule(publish_at: Option<DateTime<Utc>>) -> Result<(), ApiError> {
+    match publish_at {
+        Some(_) => Err(ApiError::UnprocessableEntity(
+            "Scheduled publishing needs POST_STORE=postgres".to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+// `current` is None when the post is gone.
+pub fn stale_version(id: i32, expected: i32, current: Option<i32>) -> ApiError {
+    match current {
+        Some(current) => ApiError::Conflict(format!(
+            "Post {} was modified concurrently: expected version {}, it is at {}",
+            id, expected, current
+        )),
+        None => ApiError::NotFound(format!("Post {} not found", id)),
+    }
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn update_post(
+    conn: &mut PgConnection,
+    tenant: i32,
+    id: i32,
+    post: &NewBlogPost,
+    if_match: Option<&IfMatch>,
+    user_id: i32,
+) -> Result<BlogPost, ApiError> {
+    let tags = post.tags.as_deref().map(normalize_tags).transpose()?;
+    let content = sanitize_content(&post.content);
+    let version = expected_version(post.version)?;
+    if let Some(if_match) = if_match {
+        check_if_match(&mut *conn, tenant, id, if_match).await?;
+    }
+    let old = get_post(&mut *conn, tenant, id).await?;
+    let updated = sqlx::query_scalar!(
+        "UPDATE blog_posts SET title = $1, content = $2, version = version + 1, \
+         updated_at = now() \
+         WHERE id = $3 AND version = $4 AND deleted_at IS NULL RETURNING id",
+        post.title,
+        &*content,
+        id,
+        version,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if updated.is_none() {
+        return Err(version_conflict(&mut *conn, id, version).await);
+    }
+
+    if let Some(tags) = tags {
+        set_post_tags(&mut *conn, id, &tags).await?;
+    }
+    save_revision(&mut *conn, &old, user_id).await?;
+    let updated = get_post(&mut *conn, tenant, id).await?;
+    record_audit(
+        &mut *conn,
+        user_id,
+        AuditEntity::Post,
+        id,
+        AuditAction::Update,
+        Some(&old),
+        Some(&updated),
+    )
+    .await?;
+    Ok(updated)
+}
+
+// An id from the posts' sequence, for a post created under another key.
+async fn next_post_id(conn: &mut PgConnection) -> Result<i32, ApiError> {
+    let id = sqlx::query_scalar!(r#"SELECT nextval('blog_posts_id_seq')::INTEGER AS "id!""#)
+        .fetch_one(conn)
+        .await?;
+    Ok(id)
+}
+
+// PUT with `?upsert=true`: creates the post under the id the client chose,
+// or updates it like update_post if it is there. It's one INSERT ... ON
+// CONFLICT, so two clients putting the same new id can't both create it.
+// `owner` is who the caller found owning the post (the caller, for a new
+// one); if that has changed since, nothing is written. The bool is true
+// when the post was created.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, key = ?key))]
+pub async fn upsert_post(
+    conn: &mut PgConnection,
+    tenant: i32,
+    key: PostKey,
+    post: &NewBlogPost,
+    if_match: Option<&IfMatch>,
+    owner: i32,
+    user_id: i32,
+) -> Result<(BlogPost, bool), ApiError> {
+    // A new post under a UUID or snowflake id gets the next id; one that
+    // is there, of any tenant, keeps its own.
+    let (id, uuid, snowflake) = match key {
+        PostKey::Id(id) if id < 1 => {
+            return Err(ApiError::UnprocessableEntity("Post ids start at 1".to_string()));
+        }
+        PostKey::Id(id) => (id, None, None),
+        PostKey::Uuid(uuid) => {
+            let found = sqlx::query_scalar!("SELECT id FROM blog_posts WHERE uuid = $1", uuid)
+                .fetch_optional(&mut *conn)
+                .await?;
+            match found {
+                Some(id) => (id, None, None),
+                None => (next_post_id(&mut *conn).await?, Some(uuid), None),
+            }
+        }
+        PostKey::Snowflake(snowflake) if snowflake < 1 => {
+            return Err(ApiError::UnprocessableEntity("Snowflake ids start at 1".to_string()));
+        }
+        PostKey::Snowflake(snowflake) => {
+            let found = sqlx::query_scalar!(
+                "SELECT id FROM blog_posts WHERE snowflake_id = $1",
+                snowflake,
+            )
+            .fetch_optional(&mut *conn)
+            .await?;
+            match found {
+                Some(id) => (id, None, None),
+                None => (next_post_id(&mut *conn).await?, None, Some(snowflake)),
+            }
+        }
+    };
+    let tags = post.tags.as_deref().map(normalize_tags).transpose()?;
+    let content = sanitize_content(&post.content);
+    let old = match get_post(&mut *conn, tenant, id).await {
+        Ok(old) => Some(old),
+        Err(ApiError::NotFound(_)) => None,
+        Err(err) => return Err(err),
+    };
+    let slug = match &old {
+        Some(old) => {
+            expected_version(post.version)?;
+            if let Some(if_match) = if_match {
+                check_if_match(&mut *conn, tenant, id, if_match).await?;
+            }
+            old.slug.clone()
+        }
+        None => unique_slug(&mut *conn, tenant, &post.title).await?,
+    };
+    // xmax is 0 on a row this statement inserted.
+    let row = sqlx::query!(
+        r#"INSERT INTO blog_posts
+            (id, tenant_id, title, slug, content, user_id, status, publish_at, uuid, snowflake_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, COALESCE($10, uuid_v7(clock_timestamp())), $11)
+        ON CONFLICT (id) DO UPDATE SET title = EXCLUDED.title, content = EXCLUDED.content,
+            version = blog_posts.version + 1, updated_at = now()
+        WHERE blog_posts.tenant_id = EXCLUDED.tenant_id AND blog_posts.user_id = EXCLUDED.user_id
+            AND blog_posts.deleted_at IS NULL AND blog_posts.version = $9
+        RETURNING (xmax = 0) AS "created!""#,
+        id,
+        tenant,
+        post.title,
+        slug,
+        &*content,
+        owner,
+        post.initial_status()?.as_str(),
+        post.publish_at,
+        post.version,
+        uuid,
+        snowflake.unwrap_or_else(next_snowflake_id),
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let created = match (row, &old, post.version) {
+        (Some(row), _, _) => row.created,
+        (None, Some(_), Some(version)) => {
+            return Err(version_conflict(&mut *conn, id, version).await);
+        }
+        // Another tenant's post, one in the trash, or one made in between.
+        _ => return Err(ApiError::Conflict(format!("Post {} is taken", key))),
+    };
+    if created {
+        // So the posts created without an id don't run into this one.
+        sqlx::query!(
+            "SELECT setval('blog_posts_id_seq', GREATEST(last_value, $1)) FROM blog_posts_id_seq",
+            i64::from(id),
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+    }
+
+    if let Some(tags) = tags {
+        set_post_tags(&mut *conn, id, &tags).await?;
+    }
+    if let Some(old) = old.as_ref().filter(|_| !created) {
+        save_revision(&mut *conn, old, user_id).await?;
+    }
+    let saved = get_post(&mut *conn, tenant, id).await?;
+    let (action, old) = if created {
+        (AuditAction::Create, None)
+    } else {
+        (AuditAction::Update, old.as_ref())
+    };
+    record_audit(&mut *conn, user_id, AuditEntity::Post, id, action, old, Some(&saved)).await?;
+    Ok((saved, created))
+}
+
+// Built at runtime because the SET list depends on which fields the
+// client sent; values are always bound, never spliced into the SQL.
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn patch_post(
+    conn: &mut PgConnection,
+    tenant: i32,
+    id: i32,
+    patch: &UpdateBlogPost,
+    if_match: Option<&IfMatch>,
+    user_id: i32,
+) -> Result<BlogPost, ApiError> {
+    let tags = patch.tags.as_deref().map(normalize_tags).transpose()?;
+    let version = expected_version(patch.version)?;
+    if let Some(if_match) = if_match {
+        check_if_match(&mut *conn, tenant, id, if_match).await?;
+    }
+    let old = get_post(&mut *conn, tenant, id).await?;
+
+    // The version goes up even when only the tags change, so there is
+    // always something to SET.
+    let mut builder = QueryBuilder::<Postgres>::new("UPDATE blog_posts SET ");
+    let mut fields = builder.separated(", ");
+    fields.push("version = version + 1");
+    fields.push("updated_at = now()");
+    if let Some(title) = &patch.title {
+        fields.push("title = ").push_bind_unseparated(title);
+    }
+    if let Some(content) = &patch.content {
+        fields.push("content = ").push_bind_unseparated(sanitize_content(content).into_owned());
+    }
+    if let Some(status) = patch.status {
+        fields.push("status = ").push_bind_unseparated(status.as_str());
+    }
+    let publish_at = patch.scheduled(&old.status, old.publish_at)?;
+    if publish_at != old.publish_at {
+        fields.push("publish_at = ").push_bind_unseparated(publish_at);
+    }
+    builder
+        .push(" WHERE deleted_at IS NULL AND id = ")
+        .push_bind(id)
+        .push(" AND version = ")
+        .push_bind(version)
+        .push(" RETURNING id");
+    let updated = builder
+        .build_query_scalar::<i32>()
+        .fetch_optional(&mut *conn)
+        .await?;
+    if updated.is_none() {
+        return Err(version_conflict(&mut *conn, id, version).await);
+    }
+
+    if let Some(tags) = tags {
+        set_post_tags(&mut *conn, id, &tags).await?;
+    }
+    save_revision(&mut *conn, &old, user_id).await?;
+    let updated = get_post(&mut *conn, tenant, id).await?;
+    record_audit(
+        &mut *conn,
+        user_id,
+        AuditEntity::Post,
+        id,
+        AuditAction::Update,
+        Some(&old),
+        Some(&updated),
+    )
+    .await?;
+    Ok(updated)
+}
+
+// Publishes every scheduled draft that is due, as its author, and returns
+// them with their tenants. Rows another instance is publishing are skipped.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn publish_due_posts(pool: &PgPool) -> Result<Vec<(i32, BlogPost)>, ApiError> {
+    let mut tx = pool.begin().await?;
+    let due = sqlx::query!(
+        "SELECT id, tenant_id FROM blog_posts \
+         WHERE status = 'draft' AND publish_at <= now() AND deleted_at IS NULL \
+         ORDER BY publish_at FOR UPDATE SKIP LOCKED"
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut published = Vec::with_capacity(due.len());
+    for row in due {
+        let old = get_post(&mut *tx, row.tenant_id, row.id).await?;
+        sqlx::query!(
+            "UPDATE blog_posts SET status = 'published', publish_at = NULL, \
+             version = version + 1, updated_at = now() WHERE id = $1",
+            row.id,
+        )
+        .execute(&mut *tx)
+        .await?;
+        save_revision(&mut tx, &old, old.user_id).await?;
+        let updated = get_post(&mut *tx, row.tenant_id, row.id).await?;
+        record_audit(
+            &mut tx,
+            old.user_id,
+            AuditEntity::Post,
+            row.id,
+            AuditAction::Update,
+            Some(&old),
+            Some(&updated),
+        )
+        .await?;
+        published.push((row.tenant_id, updated));
+    }
+    tx.commit().await?;
+    Ok(published)
+}
+
+// What publish and unpublish do. Like any update this bumps the version,
+// unless the post already has that status.
+#[tracing::instrument(level = "debug", skip_all, fields(%id, status = ?status))]
+pub async fn set_post_status(
+    conn: &mut PgConnection,
+    tenant: i32,
+    id: i32,
+    status: PostStatus,
+    user_id: i32,
+) -> Result<BlogPost, ApiError> {
+    let old = get_post(&mut *conn, tenant, id).await.map_err(|err| match err {
+        ApiError::NotFound(_) => ApiError::NotFound(format!("Post {} not found", id)),
+        err => err,
+    })?;
+    if old.status == status.as_str() {
+        return Ok(old);
+    }
+    // Only drafts are scheduled, and this one is leaving draft or was never
+    // in it, so it comes off the schedule.
+    sqlx::query!(
+        "UPDATE blog_posts SET status = $1, version = version + 1, updated_at = now(), \
+         publish_at = NULL WHERE id = $2",
+        status.as_str(),
+        id,
+    )
+    .execute(&mut *conn)
+    .await?;
+    save_revision(&mut *conn, &old, user_id).await?;
+    let updated = get_post(&mut *conn, tenant, id).await?;
+    record_audit(
+        &mut *conn,
+        user_id,
+        AuditEntity::Post,
+        id,
+        AuditAction::Update,
+        Some(&old),
+        Some(&updated),
+    )
+    .await?;
+    Ok(updated)
+}
+
+// Moves the post to the trash; see purge_post for removing it.
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn delete_post(
+    conn: &mut PgConnection,
+    tenant: i32,
+    id: i32,
+    if_match: Option<&IfMatch>,
+    user_id: i32,
+) -> Result<(), ApiError> {
+    if let Some(if_match) = if_match {
+        check_if_match(&mut *conn, tenant, id, if_match).await?;
+    }
+    let old = get_post(&mut *conn, tenant, id).await.map_err(|err| match err {
+        ApiError::NotFound(_) => ApiError::NotFound(format!("Post {} not found", id)),
+        err => err,
+    })?;
+    let result = sqlx::query!(
+        "UPDATE blog_posts SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL",
+        id,
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(ApiError::from)?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound(format!("Post {} not found", id)));
+    }
+    record_audit(
+        &mut *conn,
+        user_id,
+        AuditEntity::Post,
+        id,
+        AuditAction::Delete,
+        Some(&old),
+        None,
+    )
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%limit, %offset))]
+pub async fn list_trash(
+    pool: &PgPool,
+    tenant: i32,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<TrashedPost>, ApiError> {
+    sqlx::query_as!(
+        TrashedPost,
+        r#"
+        SELECT p.id, p.uuid, p.title, p.user_id, u.username AS author,
+            p.deleted_at AS "deleted_at!"
+        FROM blog_posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.deleted_at IS NOT NULL AND p.tenant_id = $3
+        ORDER BY p.deleted_at DESC, p.id
+        LIMIT $1 OFFSET $2
+        "#,
+        limit,
+        offset,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn count_trash(pool: &PgPool, tenant: i32) -> Result<i64, ApiError> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM blog_posts
+        WHERE deleted_at IS NOT NULL AND tenant_id = $1
+        "#,
+        tenant,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn restore_post(
+    conn: &mut PgConnection,
+    tenant: i32,
+    id: i32,
+    user_id: i32,
+) -> Result<BlogPost, ApiError> {
+    let restored = sqlx::query_scalar!(
+        "UPDATE blog_posts SET deleted_at = NULL \
+         WHERE id = $1 AND tenant_id = $2 AND deleted_at IS NOT NULL RETURNING id",
+        id,
+        tenant,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if restored.is_none() {
+        return Err(ApiError::NotFound(format!("Post {} is not in the trash", id)));
+    }
+    let post = get_post(&mut *conn, tenant, id).await?;
+    record_audit(
+        &mut *conn,
+        user_id,
+        AuditEntity::Post,
+        id,
+        AuditAction::Restore,
+        None,
+        Some(&post),
+    )
+    .await?;
+    Ok(post)
+}
+
+// Only posts already in the trash can be purged, so a single mistaken
+// request can't destroy a live post.
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn purge_post(
+    conn: &mut PgConnection,
+    tenant: i32,
+    id: i32,
+    user_id: i32,
+) -> Result<(), ApiError> {
+    let result = sqlx::query!(
+        "DELETE FROM blog_posts WHERE id = $1 AND tenant_id = $2 AND deleted_at IS NOT NULL",
+        id,
+        tenant,
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(ApiError::from)?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound(format!("Post {} is not in the trash", id)));
+    }
+    record_audit::<BlogPost>(
+        &mut *conn,
+        user_id,
+        AuditEntity::Post,
+        id,
+        AuditAction::Purge,
+        None,
+        None,
+    )
+    .await?;
+    Ok(())
+}
+
+// Keeps `old`, the post as it was before an update by `user_id`, as the
+// revision for its version. Part of the update's transaction.
+#[tracing::instrument(level = "debug", skip_all, fields(id = old.id, rev = old.version))]
+pub async fn save_revision(
+    conn: &mut PgConnection,
+    old: &BlogPost,
+    user_id: i32,
+) -> Result<(), ApiError> {
+    sqlx::query!(
+        "INSERT INTO post_revisions \
+         (post_id, rev, title, content, tags, status, edited_at, replaced_by) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        old.id,
+        old.version,
+        old.title,
+        old.content,
+        &old.tags,
+        old.status,
+        old.updated_at,
+        user_id,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// Newest first, each next to the version that replaced it: the next
+// revision, or the post itself for the newest.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id, %limit, %offset))]
+pub async fn list_revisions(
+    pool: &PgPool,
+    tenant: i32,
+    id: i32,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<RevisionRow>, ApiError> {
+    sqlx::query_as!(
+        RevisionRow,
+        r#"
+        SELECT r.rev, r.title, r.content, r.tags, r.status, r.edited_at, r.replaced_at,
+            r.replaced_by,
+            COALESCE(LAG(r.title) OVER w, p.title) AS "next_title!",
+            COALESCE(LAG(r.content) OVER w, p.content) AS "next_content!",
+            COALESCE(LAG(r.tags) OVER w, ARRAY(
+                SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+                WHERE pt.post_id = p.id ORDER BY t.name
+            )) AS "next_tags!",
+            COALESCE(LAG(r.status) OVER w, p.status) AS "next_status!"
+        FROM post_revisions r
+        JOIN blog_posts p ON p.id = r.post_id
+        WHERE r.post_id = $1 AND p.tenant_id = $2
+        WINDOW w AS (ORDER BY r.rev DESC)
+        ORDER BY r.rev DESC
+        LIMIT $3 OFFSET $4
+        "#,
+        id,
+        tenant,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id))]
+pub async fn count_revisions(pool: &PgPool, tenant: i32, id: i32) -> Result<i64, ApiError> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM post_revisions r
+        JOIN blog_posts p ON p.id = r.post_id
+        WHERE r.post_id = $1 AND p.tenant_id = $2
+        "#,
+        id,
+        tenant,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id, %rev))]
+pub async fn get_revision<'e, E: PgExecutor<'e>>(
+    executor: E,
+    tenant: i32,
+    id: i32,
+    rev: i32,
+) -> Result<PostRevision, ApiError> {
+    sqlx::query_as!(
+        PostRevision,
+        "SELECT r.post_id, r.rev, r.title, r.content, r.tags, r.status, r.edited_at, \
+         r.replaced_at, r.replaced_by \
+         FROM post_revisions r JOIN blog_posts p ON p.id = r.post_id \
+         WHERE r.post_id = $1 AND r.rev = $2 AND p.tenant_id = $3",
+        id,
+        rev,
+        tenant,
+    )
+    .fetch_optional(executor)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Post {} has no revision {}", id, rev)))
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(slug = ?slug))]
+pub async fn create_tenant(pool: &PgPool, slug: &str, name: &str) -> Result<Tenant, ApiError> {
+    sqlx::query_as!(
+        Tenant,
+        "INSERT INTO tenants (slug, name) VALUES ($1, $2) RETURNING id, slug, name",
+        slug,
+        name,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn get_tenant(pool: &PgPool, id: i32) -> Result<Option<Tenant>, ApiError> {
+    sqlx::query_as!(Tenant, "SELECT id, slug, name FROM tenants WHERE id = $1", id)
+        .fetch_optional(pool)
+        .await
+        .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(slug = ?slug))]
+pub async fn get_tenant_by_slug(pool: &PgPool, slug: &str) -> Result<Option<Tenant>, ApiError> {
+    sqlx::query_as!(Tenant, "SELECT id, slug, name FROM tenants WHERE slug = $1", slug)
+        .fetch_optional(pool)
+        .await
+        .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, username = ?username))]
+pub async fn create_user<'e, E: PgExecutor<'e>>(
+    executor: E,
+    tenant: i32,
+    username: &str,
+    password_hash: &str,
+    email: Option<&str>,
+) -> Result<User, ApiError> {
+    sqlx::query_as!(
+        User,
+        "INSERT INTO users (tenant_id, username, password_hash, email) \
+         VALUES ($1, $2, $3, $4) RETURNING *",
+        tenant,
+        username,
+        password_hash,
+        email,
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, username = ?username))]
+pub async fn get_user_by_username<'e, E: PgExecutor<'e>>(
+    executor: E,
+    tenant: i32,
+    username: &str,
+) -> Result<Option<User>, ApiError> {
+    sqlx::query_as!(
+        User,
+        "SELECT * FROM users WHERE tenant_id = $1 AND username = $2",
+        tenant,
+        username,
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn get_user(pool: &PgPool, id: i32) -> Result<User, ApiError> {
+    sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("User {} not found", id)))
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn set_user_email(pool: &PgPool, id: i32, email: Option<&str>) -> Result<User, ApiError> {
+    sqlx::query_as!(
+        User,
+        "UPDATE users SET email = $1 WHERE id = $2 RETURNING *",
+        email,
+        id,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// The user a provider account logs in as, if it has been linked.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, provider = ?provider))]
+pub async fn get_identity_user(
+    pool: &PgPool,
+    tenant: i32,
+    provider: &str,
+    subject: &str,
+) -> Result<Option<User>, ApiError> {
+    sqlx::query_as!(
+        User,
+        "SELECT u.* FROM users u JOIN user_identities i ON i.user_id = u.id \
+         WHERE i.tenant_id = $1 AND i.provider = $2 AND i.subject = $3",
+        tenant,
+        provider,
+        subject,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// 409 if the provider account is already linked, to this user or another.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, provider = ?provider, %user_id))]
+pub async fn link_identity<'e, E: PgExecutor<'e>>(
+    executor: E,
+    tenant: i32,
+    provider: &str,
+    subject: &str,
+    user_id: i32,
+) -> Result<(), ApiError> {
+    sqlx::query!(
+        "INSERT INTO user_identities (tenant_id, provider, subject, user_id) \
+         VALUES ($1, $2, $3, $4)",
+        tenant,
+        provider,
+        subject,
+        user_id,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn set_user_profile(
+    pool: &PgPool,
+    id: i32,
+    profile: &UserProfile,
+) -> Result<User, ApiError> {
+    sqlx::query_as!(
+        User,
+        "UPDATE users SET display_name = $1, bio = $2, avatar_url = $3 WHERE id = $4 RETURNING *",
+        profile.display_name.as_deref().map(str::trim),
+        profile.bio.as_deref(),
+        profile.avatar_url.as_deref(),
+        id,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// Accounts with at least one published post, by username.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %limit, %offset))]
+pub async fn list_authors(
+    pool: &PgPool,
+    tenant: i32,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Author>, ApiError> {
+    sqlx::query_as!(
+        Author,
+        r#"
+        SELECT u.id, u.username, COALESCE(u.display_name, u.username) AS "display_name!",
+            u.bio, u.avatar_url, COUNT(p.id) AS "post_count!"
+        FROM users u
+        JOIN blog_posts p ON p.user_id = u.id
+            AND p.status = 'published' AND p.deleted_at IS NULL
+        WHERE u.tenant_id = $1
+        GROUP BY u.id
+        ORDER BY u.username
+        LIMIT $2 OFFSET $3
+        "#,
+        tenant,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant))]
+pub async fn count_authors(pool: &PgPool, tenant: i32) -> Result<i64, ApiError> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(DISTINCT p.user_id) AS "count!" FROM blog_posts p
+        WHERE p.status = 'published' AND p.deleted_at IS NULL AND p.tenant_id = $1
+        "#,
+        tenant,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// Any account of the tenant, including ones that haven't published yet.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id))]
+pub async fn get_author(pool: &PgPool, tenant: i32, id: i32) -> Result<Author, ApiError> {
+    sqlx::query_as!(
+        Author,
+        r#"
+        SELECT u.id, u.username, COALESCE(u.display_name, u.username) AS "display_name!",
+            u.bio, u.avatar_url, COUNT(p.id) AS "post_count!"
+        FROM users u
+        LEFT JOIN blog_posts p ON p.user_id = u.id
+            AND p.status = 'published' AND p.deleted_at IS NULL
+        WHERE u.id = $1 AND u.tenant_id = $2
+        GROUP BY u.id
+        "#,
+        id,
+        tenant,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Author {} not found", id)))
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id, role = ?role))]
+pub async fn set_user_role(
+    conn: &mut PgConnection,
+    tenant: i32,
+    id: i32,
+    role: Role,
+    actor_id: i32,
+) -> Result<User, ApiError> {
+    let old = sqlx::query_as!(
+        User,
+        "SELECT * FROM users WHERE id = $1 AND tenant_id = $2 FOR UPDATE",
+        id,
+        tenant,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("User {} not found", id)))?;
+    let user = sqlx::query_as!(
+        User,
+        "UPDATE users SET role = $1 WHERE id = $2 RETURNING *",
+        role.as_str(),
+        id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    record_audit(
+        &mut *conn,
+        actor_id,
+        AuditEntity::User,
+        id,
+        AuditAction::Update,
+        Some(&old),
+        Some(&user),
+    )
+    .await?;
+    Ok(user)
+}
+
+// The author of a post, trashed or not.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id))]
+pub async fn post_owner(pool: &PgPool, tenant: i32, id: i32) -> Result<i32, ApiError> {
+    sqlx::query_scalar!(
+        "SELECT user_id FROM blog_posts WHERE id = $1 AND tenant_id = $2",
+        id,
+        tenant,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", id)))
+}
+
+// The id of the post with this UUID, trashed or not.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %uuid))]
+pub async fn post_id_by_uuid(pool: &PgPool, tenant: i32, uuid: Uuid) -> Result<i32, ApiError> {
+    sqlx::query_scalar!(
+        "SELECT id FROM blog_posts WHERE uuid = $1 AND tenant_id = $2",
+        uuid,
+        tenant,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", uuid)))
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %snowflake))]
+pub async fn post_id_by_snowflake(
+    pool: &PgPool,
+    tenant: i32,
+    snowflake: i64,
+) -> Result<i32, ApiError> {
+    sqlx::query_scalar!(
+        "SELECT id FROM blog_posts WHERE snowflake_id = $1 AND tenant_id = $2",
+        snowflake,
+        tenant,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", snowflake)))
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id))]
+pub async fn comment_owner(pool: &PgPool, tenant: i32, id: i32) -> Result<i32, ApiError> {
+    sqlx::query_scalar!(
+        "SELECT c.user_id FROM comments c JOIN blog_posts p ON p.id = c.post_id \
+         WHERE c.id = $1 AND p.tenant_id = $2",
+        id,
+        tenant,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Comment {} not found", id)))
+}
+
+// Stores the hash of a newly generated key for `user_id`, who has to be
+// in `tenant`.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %user_id, scope = ?scope))]
+pub async fn create_api_key(
+    pool: &PgPool,
+    tenant: i32,
+    user_id: i32,
+    name: &str,
+    scope: KeyScope,
+    key: &str,
+) -> Result<ApiKey, ApiError> {
+    sqlx::query_as!(
+        ApiKey,
+        r#"
+        WITH k AS (
+            INSERT INTO api_keys (user_id, name, prefix, key_hash, scope)
+            SELECT id, $2, $3, $4, $5 FROM users WHERE id = $1 AND tenant_id = $6
+            RETURNING *
+        )
+        SELECT k.id, k.user_id, u.username, k.name, k.prefix, k.scope,
+            k.created_at, k.last_used_at, k.revoked_at
+        FROM k
+        JOIN users u ON u.id = k.user_id
+        "#,
+        user_id,
+        name,
+        &key[..12],
+        hash_secret(key),
+        scope.as_str(),
+        tenant,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("User {} not found", user_id)))
+}
+
+// Every key of the tenant's users, revoked ones included, newest first.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant))]
+pub async fn list_api_keys(pool: &PgPool, tenant: i32) -> Result<Vec<ApiKey>, ApiError> {
+    sqlx::query_as!(
+        ApiKey,
+        r#"
+        SELECT k.id, k.user_id, u.username, k.name, k.prefix, k.scope,
+            k.created_at, k.last_used_at, k.revoked_at
+        FROM api_keys k
+        JOIN users u ON u.id = k.user_id
+        WHERE u.tenant_id = $1
+        ORDER BY k.id DESC
+        "#,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id))]
+pub async fn revoke_api_key(pool: &PgPool, tenant: i32, id: i32) -> Result<ApiKey, ApiError> {
+    sqlx::query_as!(
+        ApiKey,
+        r#"
+        WITH k AS (
+            UPDATE api_keys SET revoked_at = now()
+            WHERE id = $1 AND revoked_at IS NULL
+                AND user_id IN (SELECT id FROM users WHERE tenant_id = $2)
+            RETURNING *
+        )
+        SELECT k.id, k.user_id, u.username, k.name, k.prefix, k.scope,
+            k.created_at, k.last_used_at, k.revoked_at
+        FROM k
+        JOIN users u ON u.id = k.user_id
+        "#,
+        id,
+        tenant,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("API key {} not found or already revoked", id)))
+}
+
+// The user behind a live key, noting when it was last used.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn authenticate_api_key(
+    pool: &PgPool,
+    key_hash: &str,
+) -> Result<Option<AuthUser>, ApiError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_keys k SET last_used_at = now()
+        FROM users u
+        WHERE k.key_hash = $1 AND k.revoked_at IS NULL AND u.id = k.user_id
+        RETURNING u.id, u.username, u.role, u.tenant_id, k.scope
+        "#,
+        key_hash,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| AuthUser {
+        id: row.id,
+        username: row.username,
+        role: Role::from_db(&row.role),
+        tenant_id: row.tenant_id,
+        read_only: row.scope != KeyScope::ReadWrite.as_str(),
+    }))
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%user_id))]
+pub async fn create_refresh_token<'e>(
+    executor: impl PgExecutor<'e>,
+    user_id: i32,
+    token: &str,
+    ttl_secs: u64,
+) -> Result<(), ApiError> {
+    sqlx::query!(
+        "INSERT INTO refresh_tokens (user_id, token_hash, expires_at) \
+         VALUES ($1, $2, now() + make_interval(secs => $3))",
+        user_id,
+        hash_secret(token),
+        ttl_secs as f64,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// Swaps `token` for `new_token` and returns its user. Presenting a token
+// that was already used or logged out revokes all of the user's sessions,
+// since someone other than the user may have it. Tokens of another
+// tenant's users are unknown here.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant))]
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    tenant: i32,
+    token: &str,
+    new_token: &str,
+    ttl_secs: u64,
+) -> Result<User, ApiError> {
+    let invalid = || ApiError::Unauthorized("Invalid or expired refresh token".to_string());
+    let mut tx = pool.begin().await?;
+    let row = sqlx::query!(
+        r#"
+        SELECT id, user_id, revoked_at, expires_at < now() AS "expired!"
+        FROM refresh_tokens
+        WHERE token_hash = $1 AND user_id IN (SELECT id FROM users WHERE tenant_id = $2)
+        FOR UPDATE
+        "#,
+        hash_secret(token),
+        tenant,
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(invalid)?;
+    if row.revoked_at.is_some() {
+        sqlx::query!(
+            "UPDATE refresh_tokens SET revoked_at = now() \
+             WHERE user_id = $1 AND revoked_at IS NULL",
+            row.user_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        tracing::warn!(
+            "Revoked refresh token {} was reused; ending user {}'s sessions",
+            row.id,
+            row.user_id
+        );
+        return Err(invalid());
+    }
+    if row.expired {
+        return Err(invalid());
+    }
+
+    sqlx::query!("UPDATE refresh_tokens SET revoked_at = now() WHERE id = $1", row.id)
+        .execute(&mut *tx)
+        .await?;
+    create_refresh_token(&mut *tx, row.user_id, new_token, ttl_secs).await?;
+    let user = sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", row.user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(user)
+}
+
+// Unknown and already revoked tokens are fine; logging out twice is a no-op.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn revoke_refresh_token(pool: &PgPool, token: &str) -> Result<(), ApiError> {
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked_at = now() \
+         WHERE token_hash = $1 AND revoked_at IS NULL",
+        hash_secret(token),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id))]
+pub async fn post_exists(pool: &PgPool, tenant: i32, id: i32) -> Result<bool, ApiError> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS(
+            SELECT 1 FROM blog_posts WHERE id = $1 AND tenant_id = $2 AND deleted_at IS NULL
+        ) AS "exists!""#,
+        id,
+        tenant,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// Like create_comment, answers NotFound when the post doesn't exist.
+#[tracing::instrument(level = "debug", skip_all, fields(%post_id, %user_id))]
+pub async fn create_attachment(
+    pool: &PgPool,
+    post_id: i32,
+    user_id: i32,
+    filename: &str,
+    content_type: &str,
+    size_bytes: i64,
+    storage_key: &str,
+) -> Result<Attachment, ApiError> {
+    sqlx::query_as!(
+        Attachment,
+        r#"
+        INSERT INTO attachments
+            (post_id, user_id, filename, content_type, size_bytes, storage_key)
+        SELECT $1, $2, $3, $4, $5, $6
+        WHERE EXISTS (SELECT 1 FROM blog_posts WHERE id = $1 AND deleted_at IS NULL)
+        RETURNING id, post_id, user_id, filename, content_type, size_bytes, storage_key,
+            created_at
+        "#,
+        post_id,
+        user_id,
+        filename,
+        content_type,
+        size_bytes,
+        storage_key,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%post_id))]
+pub async fn list_attachments(pool: &PgPool, post_id: i32) -> Result<Vec<Attachment>, ApiError> {
+    sqlx::query_as!(
+        Attachment,
+        r#"
+        SELECT id, post_id, user_id, filename, content_type, size_bytes, storage_key,
+            created_at
+        FROM attachments
+        WHERE post_id = $1
+        ORDER BY id
+        "#,
+        post_id,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %post_id, %id))]
+pub async fn get_attachment(
+    pool: &PgPool,
+    tenant: i32,
+    post_id: i32,
+    id: i32,
+) -> Result<Attachment, ApiError> {
+    sqlx::query_as!(
+        Attachment,
+        r#"
+        SELECT a.id, a.post_id, a.user_id, a.filename, a.content_type, a.size_bytes,
+            a.storage_key, a.created_at
+        FROM attachments a
+        JOIN blog_posts p ON p.id = a.post_id
+        WHERE a.post_id = $1 AND a.id = $2 AND p.tenant_id = $3 AND p.deleted_at IS NULL
+        "#,
+        post_id,
+        id,
+        tenant,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// Inserts nothing, and so answers NotFound, when the post doesn't exist in
+// the tenant.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %post_id, %user_id))]
+pub async fn create_comment(
+    conn: &mut PgConnection,
+    tenant: i32,
+    post_id: i32,
+    user_id: i32,
+    comment: &NewComment,
+) -> Result<Comment, ApiError> {
+    let created = sqlx::query_as!(
+        Comment,
+        r#"
+        WITH inserted AS (
+            INSERT INTO comments (post_id, user_id, body)
+            SELECT $1, $2, $3
+            WHERE EXISTS (
+                SELECT 1 FROM blog_posts WHERE id = $1 AND tenant_id = $4 AND deleted_at IS NULL
+            )
+            RETURNING *
+        )
+        SELECT i.id, i.post_id, i.user_id, u.username AS author, i.body
+        FROM inserted i
+        JOIN users u ON u.id = i.user_id
+        "#,
+        post_id,
+        user_id,
+        comment.body,
+        tenant,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let id = created.id;
+    record_audit(
+        &mut *conn,
+        user_id,
+        AuditEntity::Comment,
+        id,
+        AuditAction::Create,
+        None,
+        Some(&created),
+    )
+    .await?;
+    Ok(created)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%post_id))]
+pub async fn list_comments(pool: &PgPool, post_id: i32) -> Result<Vec<Comment>, ApiError> {
+    sqlx::query_as!(
+        Comment,
+        r#"
+        SELECT c.id, c.post_id, c.user_id, u.username AS author, c.body
+        FROM comments c
+        JOIN users u ON u.id = c.user_id
+        WHERE c.post_id = $1
+        ORDER BY c.id
+        "#,
+        post_id,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// One statement for a whole batch of counts. Posts deleted since are
+// skipped.
+#[tracing::instrument(level = "debug", skip_all, fields(posts = ids.len()))]
+pub async fn add_post_views(pool: &PgPool, ids: &[i32], counts: &[i64]) -> Result<(), ApiError> {
+    sqlx::query!(
+        "UPDATE blog_posts p SET view_count = p.view_count + v.n \
+         FROM UNNEST($1::int4[], $2::int8[]) AS v(id, n) WHERE p.id = v.id",
+        ids,
+        counts,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Published posts of the tenant, most viewed first.
+#[tracing::instrument(level = "debug", skip_all, fields(%limit))]
+pub async fn popular_posts(
+    pool: &PgPool,
+    tenant: i32,
+    limit: i64,
+) -> Result<Vec<BlogPost>, ApiError> {
+    sqlx::query_as!(
+        BlogPost,
+        r#"
+        SELECT p.id, p.uuid, p.snowflake_id, p.title, p.slug, p.content, p.user_id,
+            u.username AS author,
+            ARRAY(
+                SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+                WHERE pt.post_id = p.id ORDER BY t.name
+            ) AS "tags!",
+            p.version, p.status, p.like_count, p.view_count, p.publish_at,
+            p.created_at, p.updated_at
+        FROM blog_posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.tenant_id = $1 AND p.deleted_at IS NULL AND p.status = 'published'
+        ORDER BY p.view_count DESC, p.id
+        LIMIT $2
+        "#,
+        tenant,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// The months with published posts, by creation date in UTC, newest first.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn archive_months(pool: &PgPool, tenant: i32) -> Result<Vec<ArchiveMonth>, ApiError> {
+    sqlx::query_as!(
+        ArchiveMonth,
+        r#"
+        SELECT EXTRACT(YEAR FROM m.month)::int4 AS "year!",
+            EXTRACT(MONTH FROM m.month)::int4 AS "month!",
+            COUNT(*) AS "posts!"
+        FROM (
+            SELECT date_trunc('month', created_at AT TIME ZONE 'UTC') AS month
+            FROM blog_posts
+            WHERE tenant_id = $1 AND deleted_at IS NULL AND status = 'published'
+        ) m
+        GROUP BY m.month
+        ORDER BY m.month DESC
+        "#,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// A month of the archive, newest first, with how many posts it has in all.
+// The range is bounded on created_at itself so its index can be used.
+#[tracing::instrument(level = "debug", skip_all, fields(%year, %month, %limit, %offset))]
+pub async fn archive_posts(
+    pool: &PgPool,
+    tenant: i32,
+    year: i32,
+    month: u32,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<BlogPost>, i64), ApiError> {
+    let month = month as i32;
+    let posts = sqlx::query_as!(
+        BlogPost,
+        r#"
+        SELECT p.id, p.uuid, p.snowflake_id, p.title, p.slug, p.content, p.user_id,
+            u.username AS author,
+            ARRAY(
+                SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+                WHERE pt.post_id = p.id ORDER BY t.name
+            ) AS "tags!",
+            p.version, p.status, p.like_count, p.view_count, p.publish_at,
+            p.created_at, p.updated_at
+        FROM blog_posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.tenant_id = $1 AND p.deleted_at IS NULL AND p.status = 'published'
+            AND p.created_at >= make_timestamptz($2, $3, 1, 0, 0, 0, 'UTC')
+            AND p.created_at < make_timestamptz($2, $3, 1, 0, 0, 0, 'UTC')
+                + interval '1 month'
+        ORDER BY p.created_at DESC, p.id DESC
+        LIMIT $4 OFFSET $5
+        "#,
+        tenant,
+        year,
+        month,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM blog_posts
+        WHERE tenant_id = $1 AND deleted_at IS NULL AND status = 'published'
+            AND created_at >= make_timestamptz($2, $3, 1, 0, 0, 0, 'UTC')
+            AND created_at < make_timestamptz($2, $3, 1, 0, 0, 0, 'UTC') + interval '1 month'
+        "#,
+        tenant,
+        year,
+        month,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((posts, total))
+}
+
+// Published posts to read after post `id`: the ones sharing the most tags
+// with it first, then the ones whose text best matches any word of its
+// title. Posts with neither in common aren't related.
+#[tracing::instrument(level = "debug", skip_all, fields(%id, %limit))]
+pub async fn related_posts(
+    pool: &PgPool,
+    tenant: i32,
+    id: i32,
+    limit: i64,
+) -> Result<Vec<BlogPost>, ApiError> {
+    sqlx::query_as!(
+        BlogPost,
+        r#"
+        WITH source AS (
+            SELECT s.id, s.tenant_id,
+                NULLIF(array_to_string(ARRAY(
+                    SELECT quote_literal(lexeme)
+                    FROM unnest(tsvector_to_array(to_tsvector('english', s.title))) lexeme
+                ), ' | '), '')::tsquery AS terms
+            FROM blog_posts s
+            WHERE s.id = $1 AND s.tenant_id = $2
+        ), scored AS (
+            SELECT p.id,
+                (
+                    SELECT COUNT(*) FROM post_tags a
+                    JOIN post_tags b ON b.tag_id = a.tag_id AND b.post_id = source.id
+                    WHERE a.post_id = p.id
+                ) + COALESCE(ts_rank(p.search_vector, source.terms), 0) AS score
+            FROM blog_posts p, source
+            WHERE p.tenant_id = source.tenant_id AND p.id <> source.id
+                AND p.deleted_at IS NULL AND p.status = 'published'
+                AND (
+                    p.search_vector @@ source.terms
+                    OR EXISTS (
+                        SELECT 1 FROM post_tags a
+                        JOIN post_tags b ON b.tag_id = a.tag_id AND b.post_id = source.id
+                        WHERE a.post_id = p.id
+                    )
+                )
+        )
+        SELECT p.id, p.uuid, p.snowflake_id, p.title, p.slug, p.content, p.user_id,
+            u.username AS author,
+            ARRAY(
+                SELECT t.name FROM post_tags pt JOIN tags t ON t.id = pt.tag_id
+                WHERE pt.post_id = p.id ORDER BY t.name
+            ) AS "tags!",
+            p.version, p.status, p.like_count, p.view_count, p.publish_at,
+            p.created_at, p.updated_at
+        FROM scored
+        JOIN blog_posts p ON p.id = scored.id
+        JOIN users u ON u.id = p.user_id
+        ORDER BY scored.score DESC, p.created_at DESC, p.id
+        LIMIT $3
+        "#,
+        id,
+        tenant,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// The row lock on the post keeps like_count in step with post_likes when
+// several readers like it at once. Liking twice is a conflict.
+#[tracing::instrument(level = "debug", skip_all, fields(%post_id, %user_id))]
+pub async fn like_post(
+    conn: &mut PgConnection,
+    tenant: i32,
+    post_id: i32,
+    user_id: i32,
+) -> Result<LikeCount, ApiError> {
+    let like_count = sqlx::query_scalar!(
+        "UPDATE blog_posts SET like_count = like_count + 1 \
+         WHERE id = $1 AND tenant_id = $2 AND deleted_at IS NULL RETURNING like_count",
+        post_id,
+        tenant,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", post_id)))?;
+    let inserted = sqlx::query!(
+        "INSERT INTO post_likes (post_id, user_id) VALUES ($1, $2)",
+        post_id,
+        user_id,
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(ApiError::from);
+    match inserted {
+        Err(ApiError::Conflict(_)) => {
+            return Err(ApiError::Conflict(format!("You already like post {}", post_id)))
+        }
+        other => other?,
+    };
+    Ok(LikeCount { post_id, like_count })
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%post_id, %user_id))]
+pub async fn unlike_post(
+    conn: &mut PgConnection,
+    tenant: i32,
+    post_id: i32,
+    user_id: i32,
+) -> Result<LikeCount, ApiError> {
+    let like_count = sqlx::query_scalar!(
+        "UPDATE blog_posts SET like_count = like_count - 1 \
+         WHERE id = $1 AND tenant_id = $2 AND deleted_at IS NULL RETURNING like_count",
+        post_id,
+        tenant,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", post_id)))?;
+    let deleted = sqlx::query!(
+        "DELETE FROM post_likes WHERE post_id = $1 AND user_id = $2",
+        post_id,
+        user_id,
+    )
+    .execute(&mut *conn)
+    .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(ApiError::NotFound(format!("You don't like post {}", post_id)));
+    }
+    Ok(LikeCount { post_id, like_count })
+}
+
+// Most recent first.
+#[tracing::instrument(level = "debug", skip_all, fields(%post_id, %limit, %offset))]
+pub async fn list_likes(
+    pool: &PgPool,
+    post_id: i32,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Like>, ApiError> {
+    sqlx::query_as!(
+        Like,
+        r#"
+        SELECT l.user_id, u.username, l.created_at
+        FROM post_likes l
+        JOIN users u ON u.id = l.user_id
+        WHERE l.post_id = $1
+        ORDER BY l.created_at DESC, l.user_id
+        LIMIT $2 OFFSET $3
+        "#,
+        post_id,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn get_comment(pool: &PgPool, id: i32) -> Result<Option<Comment>, ApiError> {
+    sqlx::query_as!(
+        Comment,
+        r#"
+        SELECT c.id, c.post_id, c.user_id, u.username AS author, c.body
+        FROM comments c
+        JOIN users u ON u.id = c.user_id
+        WHERE c.id = $1
+        "#,
+        id,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn delete_comment(
+    conn: &mut PgConnection,
+    id: i32,
+    user_id: i32,
+) -> Result<(), ApiError> {
+    let old = sqlx::query_as!(
+        Comment,
+        r#"
+        SELECT c.id, c.post_id, c.user_id, u.username AS author, c.body
+        FROM comments c
+        JOIN users u ON u.id = c.user_id
+        WHERE c.id = $1
+        FOR UPDATE OF c
+        "#,
+        id,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Comment {} not found", id)))?;
+    let result = sqlx::query!("DELETE FROM comments WHERE id = $1", id)
+        .execute(&mut *conn)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound(format!("Comment {} not found", id)));
+    }
+    record_audit(
+        &mut *conn,
+        user_id,
+        AuditEntity::Comment,
+        id,
+        AuditAction::Delete,
+        Some(&old),
+        None,
+    )
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%post_id, lang = ?lang))]
+pub async fn upsert_translation(
+    pool: &PgPool,
+    post_id: i32,
+    lang: &str,
+    title: &str,
+    content: &str,
+) -> Result<Translation, ApiError> {
+    sqlx::query_as::<_, Translation>(
+        r#"
+        INSERT INTO translations (post_id, lang, title, content)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (post_id, lang)
+        DO UPDATE SET title = EXCLUDED.title, content = EXCLUDED.content
+        RETURNING *
+        "#,
+    )
+    .bind(post_id)
+    .bind(lang)
+    .bind(title)
+    .bind(content)
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%post_id))]
+pub async fn list_translation_langs(
+    pool: &PgPool,
+    post_id: i32,
+) -> Result<Vec<String>, ApiError> {
+    sqlx::query_scalar::<_, String>(
+        "SELECT lang FROM translations WHERE post_id = $1 ORDER BY lang",
+    )
+    .bind(post_id)
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%post_id, lang = ?lang))]
+pub async fn get_translation(
+    pool: &PgPool,
+    post_id: i32,
+    lang: &str,
+) -> Result<Option<Translation>, ApiError> {
+    sqlx::query_as::<_, Translation>(
+        "SELECT * FROM translations WHERE post_id = $1 AND lang = $2",
+    )
+    .bind(post_id)
+    .bind(lang)
+    .fetch_optional(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, kind = ?kind))]
+pub async fn create_job<'e, E: PgExecutor<'e>>(
+    executor: E,
+    tenant: i32,
+    kind: &str,
+    payload: &serde_json::Value,
+    max_attempts: i32,
+) -> Result<Job, ApiError> {
+    sqlx::query_as::<_, Job>(
+        "INSERT INTO jobs (tenant_id, kind, status, payload, max_attempts) \
+         VALUES ($1, $2, $3, $4, $5) RETURNING *",
+    )
+    .bind(tenant)
+    .bind(kind)
+    .bind(JobStatus::Pending.as_str())
+    .bind(payload)
+    .bind(max_attempts)
+    .fetch_one(executor)
+    .await
+    .map_err(ApiError::from)
+}
+
+// Marks the next due job running and hands it to this worker. SKIP LOCKED
+// lets each worker, in any instance, take a different one. A job left
+// running for longer than `timeout` lost its worker and is taken again.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn claim_job(pool: &PgPool, timeout: Duration) -> Result<Option<ClaimedJob>, ApiError> {
+    sqlx::query_as::<_, ClaimedJob>(
+        "UPDATE jobs SET status = 'running', attempts = attempts + 1, locked_at = now(), \
+             updated_at = now() \
+         WHERE id = ( \
+             SELECT id FROM jobs \
+             WHERE (status = 'pending' AND run_at <= now()) \
+                 OR (status = 'running' AND locked_at < now() - make_interval(secs => $1)) \
+             ORDER BY run_at, id \
+             LIMIT 1 \
+             FOR UPDATE SKIP LOCKED \
+         ) \
+         RETURNING id, kind, payload, attempts, max_attempts",
+    )
+    .bind(timeout.as_secs_f64())
+    .fetch_optional(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn complete_job(
+    pool: &PgPool,
+    id: i32,
+    result: Option<serde_json::Value>,
+) -> Result<(), ApiError> {
+    sqlx::query(
+        "UPDATE jobs SET status = 'done', result = $1, error = NULL, locked_at = NULL, \
+             updated_at = now() \
+         WHERE id = $2",
+    )
+    .bind(result)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Back to pending until `retry_at`, or failed for good without one.
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn fail_job(
+    pool: &PgPool,
+    id: i32,
+    error: &str,
+    retry_at: Option<DateTime<Utc>>,
+) -> Result<(), ApiError> {
+    let status = match retry_at {
+        Some(_) => JobStatus::Pending,
+        None => JobStatus::Failed,
+    };
+    sqlx::query(
+        "UPDATE jobs SET status = $1, error = $2, run_at = COALESCE($3, run_at), \
+             locked_at = NULL, updated_at = now() \
+         WHERE id = $4",
+    )
+    .bind(status.as_str())
+    .bind(error)
+    .bind(retry_at)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id))]
+pub async fn get_job(pool: &PgPool, tenant: i32, id: i32) -> Result<Job, ApiError> {
+    sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE id = $1 AND tenant_id = $2")
+        .bind(id)
+        .bind(tenant)
+        .fetch_one(pool)
+        .await
+        .map_err(ApiError::from)
+}
+
+// Who queued the job, for imports, which carry the user they create posts
+// for; None for the other kinds.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id))]
+pub async fn job_owner(pool: &PgPool, tenant: i32, id: i32) -> Result<Option<i32>, ApiError> {
+    sqlx::query_scalar::<_, Option<i32>>(
+        "SELECT CASE WHEN kind = 'import' THEN (payload->>'user_id')::int END \
+         FROM jobs WHERE id = $1 AND tenant_id = $2",
+    )
+    .bind(id)
+    .bind(tenant)
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+// The tenant's jobs, newest first, with how many jobs of the kind asked
+// for are in each status.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant))]
+pub async fn list_jobs(
+    pool: &PgPool,
+    tenant: i32,
+    query: &JobsQuery,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<Job>, i64, JobCounts), ApiError> {
+    let status = query.status.map(|status| status.as_str());
+    let jobs = sqlx::query_as::<_, Job>(
+        "SELECT * FROM jobs \
+         WHERE ($1::text IS NULL OR status = $1) AND ($2::text IS NULL OR kind = $2) \
+             AND tenant_id = $5 \
+         ORDER BY created_at DESC, id DESC \
+         LIMIT $3 OFFSET $4",
+    )
+    .bind(status)
+    .bind(&query.kind)
+    .bind(limit)
+    .bind(offset)
+    .bind(tenant)
+    .fetch_all(pool)
+    .await?;
+    let by_status = sqlx::query_as::<_, (String, i64)>(
+        "SELECT status, COUNT(*) FROM jobs \
+         WHERE ($1::text IS NULL OR kind = $1) AND tenant_id = $2 GROUP BY status",
+    )
+    .bind(&query.kind)
+    .bind(tenant)
+    .fetch_all(pool)
+    .await?;
+
+    let mut counts = JobCounts::default();
+    for (name, count) in by_status {
+        match name.as_str() {
+            "pending" => counts.pending = count,
+            "running" => counts.running = count,
+            "done" => counts.done = count,
+            _ => counts.failed = count,
+        }
+    }
+    let total = match query.status {
+        None => counts.pending + counts.running + counts.done + counts.failed,
+        Some(JobStatus::Pending) => counts.pending,
+        Some(JobStatus::Running) => counts.running,
+        Some(JobStatus::Done) => counts.done,
+        Some(JobStatus::Failed) => counts.failed,
+    };
+    Ok((jobs, total, counts))
+}
+
+// Takes the caller's connection so the entry commits or rolls back with the
+// change it describes.
+#[tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(entity = ?entity, %entity_id, action = ?action)
+)]
+pub async fn record_audit<T: Serialize>(
+    conn: &mut PgConnection,
+    user_id: i32,
+    entity: AuditEntity,
+    entity_id: i32,
+    action: AuditAction,
+    old: Option<&T>,
+    new: Option<&T>,
+) -> Result<(), ApiError> {
+    let to_json = |value: Option<&T>| {
+        value
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(|err| ApiError::DatabaseError(err.to_string()))
+    };
+    sqlx::query!(
+        "INSERT INTO audit_log (user_id, entity, entity_id, action, old_value, new_value) \
+         VALUES ($1, $2, $3, $4, $5, $6)",
+        user_id,
+        entity.as_str(),
+        entity_id,
+        action.as_str(),
+        to_json(old)?,
+        to_json(new)?,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// Newest first. Users only act within their own tenant, so its entries are
+// the ones made by its users.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %limit, %offset))]
+pub async fn list_audit(
+    pool: &PgPool,
+    tenant: i32,
+    query: &AuditQuery,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<AuditEntry>, i64), ApiError> {
+    let entity = query.entity.map(|entity| entity.as_str());
+    let entries = sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT a.id, a.user_id, u.username AS "username?", a.entity, a.entity_id, a.action,
+            a.old_value, a.new_value, a.created_at
+        FROM audit_log a
+        JOIN users u ON u.id = a.user_id
+        WHERE u.tenant_id = $7
+            AND ($1::text IS NULL OR a.entity = $1)
+            AND ($2::int IS NULL OR a.entity_id = $2)
+            AND ($3::timestamptz IS NULL OR a.created_at >= $3)
+            AND ($4::timestamptz IS NULL OR a.created_at <= $4)
+        ORDER BY a.created_at DESC, a.id DESC
+        LIMIT $5 OFFSET $6
+        "#,
+        entity,
+        query.entity_id,
+        query.from,
+        query.to,
+        limit,
+        offset,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM audit_log a
+        JOIN users u ON u.id = a.user_id
+        WHERE u.tenant_id = $5
+            AND ($1::text IS NULL OR a.entity = $1)
+            AND ($2::int IS NULL OR a.entity_id = $2)
+            AND ($3::timestamptz IS NULL OR a.created_at >= $3)
+            AND ($4::timestamptz IS NULL OR a.created_at <= $4)
+        "#,
+        entity,
+        query.entity_id,
+        query.from,
+        query.to,
+        tenant,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((entries, total))
+}
+
+pub(crate) fn publish_created(feed: &ChangeFeed, tenant: i32, posts: &[BlogPost]) {
+    for post in posts {
+        feed.publish(PostEventKind::Created, tenant, post.id, Some(post.clone()));
+    }
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%user_id))]
+pub async fn create_webhook(
+    pool: &PgPool,
+    user_id: i32,
+    url: &str,
+    secret: &str,
+    events: &[String],
+) -> Result<Webhook, ApiError> {
+    sqlx::query_as!(
+        Webhook,
+        "INSERT INTO webhooks (user_id, url, secret, events) VALUES ($1, $2, $3, $4) \
+         RETURNING id, user_id, url, events, created_at",
+        user_id,
+        url,
+        secret,
+        events,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%user_id))]
+pub async fn list_webhooks(pool: &PgPool, user_id: i32) -> Result<Vec<Webhook>, ApiError> {
+    sqlx::query_as!(
+        Webhook,
+        "SELECT id, user_id, url, events, created_at FROM webhooks WHERE user_id = $1 \
+         ORDER BY id",
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id))]
+pub async fn webhook_owner(pool: &PgPool, tenant: i32, id: i32) -> Result<i32, ApiError> {
+    sqlx::query_scalar!(
+        "SELECT w.user_id FROM webhooks w JOIN users u ON u.id = w.user_id \
+         WHERE w.id = $1 AND u.tenant_id = $2",
+        id,
+        tenant,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Webhook {} not found", id)))
+}
+
+// Its delivery log goes with it.
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn delete_webhook(pool: &PgPool, id: i32) -> Result<Webhook, ApiError> {
+    sqlx::query_as!(
+        Webhook,
+        "DELETE FROM webhooks WHERE id = $1 RETURNING id, user_id, url, events, created_at",
+        id,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Webhook {} not found", id)))
+}
+
+// Webhooks that want `event` for a post by `owner`: the owner's own, and
+// those of every admin of the post's tenant. Posts purged since have no
+// owner and only go to admins.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %event))]
+pub async fn webhooks_for_event(
+    pool: &PgPool,
+    tenant: i32,
+    owner: Option<i32>,
+    event: &str,
+) -> Result<Vec<i32>, ApiError> {
+    sqlx::query_scalar!(
+        "SELECT w.id FROM webhooks w JOIN users u ON u.id = w.user_id \
+         WHERE $2 = ANY(w.events) AND u.tenant_id = $3 \
+             AND (w.user_id = $1 OR u.role = 'admin') \
+         ORDER BY w.id",
+        owner,
+        event,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%webhook_id))]
+pub async fn create_webhook_delivery(
+    conn: &mut PgConnection,
+    webhook_id: i32,
+    event: &str,
+    payload: &serde_json::Value,
+) -> Result<i32, ApiError> {
+    sqlx::query_scalar!(
+        "INSERT INTO webhook_deliveries (webhook_id, event, payload) VALUES ($1, $2, $3) \
+         RETURNING id",
+        webhook_id,
+        event,
+        payload,
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id, %job_id))]
+pub async fn set_delivery_job(
+    conn: &mut PgConnection,
+    id: i32,
+    job_id: i32,
+) -> Result<(), ApiError> {
+    sqlx::query!("UPDATE webhook_deliveries SET job_id = $1 WHERE id = $2", job_id, id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn delivery_target(pool: &PgPool, id: i32) -> Result<Option<DeliveryTarget>, ApiError> {
+    sqlx::query_as!(
+        DeliveryTarget,
+        "SELECT w.url, w.secret, d.event, d.payload FROM webhook_deliveries d \
+         JOIN webhooks w ON w.id = d.webhook_id WHERE d.id = $1",
+        id,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(ApiError::from)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%id))]
+pub async fn record_delivery_attempt(
+    pool: &PgPool,
+    id: i32,
+    response_status: Option<i32>,
+    error: Option<&str>,
+) -> Result<(), ApiError> {
+    sqlx::query!(
+        "UPDATE webhook_deliveries SET attempts = attempts + 1, response_status = $1, \
+             error = $2, delivered_at = CASE WHEN $2::text IS NULL THEN now() END \
+         WHERE id = $3",
+        response_status,
+        error,
+        id,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Newest first. The status comes from the delivery's job: failed once that
+// has given up.
+#[tracing::instrument(level = "debug", skip_all, fields(%webhook_id))]
+pub async fn list_deliveries(
+    pool: &PgPool,
+    webhook_id: i32,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<WebhookDelivery>, i64), ApiError> {
+    let deliveries = sqlx::query_as!(
+        WebhookDelivery,
+        r#"
+        SELECT d.id, d.webhook_id, d.event,
+            CASE
+                WHEN d.delivered_at IS NOT NULL THEN 'delivered'
+                WHEN j.status = 'failed' THEN 'failed'
+                ELSE 'pending'
+            END AS "status!",
+            d.attempts, d.response_status, d.error, d.payload, d.created_at, d.delivered_at
+        FROM webhook_deliveries d
+        LEFT JOIN jobs j ON j.id = d.job_id
+        WHERE d.webhook_id = $1
+        ORDER BY d.created_at DESC, d.id DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        webhook_id,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM webhook_deliveries WHERE webhook_id = $1"#,
+        webhook_id,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((deliveries, total))
+}
+
+// Takes `key` for a request by `user_id`, unless a live row has it
+// already, which is then returned as it is. The user's expired keys are
+// cleared first, so they don't pile up and can be reused.
+#[tracing::instrument(level = "debug", skip_all, fields(%user_id))]
+pub async fn claim_idempotency_key(
+    pool: &PgPool,
+    user_id: i32,
+    key: &str,
+    request_hash: &str,
+    ttl_secs: u64,
+) -> Result<Option<IdempotencyRecord>, ApiError> {
+    sqlx::query!(
+        "DELETE FROM idempotency_keys WHERE user_id = $1 AND expires_at < now()",
+        user_id,
+    )
+    .execute(pool)
+    .await?;
+    let claimed = sqlx::query_scalar!(
+        "INSERT INTO idempotency_keys (user_id, key, request_hash, expires_at) \
+         VALUES ($1, $2, $3, now() + make_interval(secs => $4)) \
+         ON CONFLICT (user_id, key) DO NOTHING RETURNING user_id",
+        user_id,
+        key,
+        request_hash,
+        ttl_secs as f64,
+    )
+    .fetch_optional(pool)
+    .await?;
+    if claimed.is_some() {
+        return Ok(None);
+    }
+    // Gone again if the first request failed in the meantime; it counts as
+    // still in progress then, and the client's next retry gets the key.
+    let record = sqlx::query_as!(
+        IdempotencyRecord,
+        "SELECT request_hash, status_code, response FROM idempotency_keys \
+         WHERE user_id = $1 AND key = $2",
+        user_id,
+        key,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(Some(record.unwrap_or(IdempotencyRecord {
+        request_hash: request_hash.to_string(),
+        status_code: None,
+        response: None,
+    })))
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%user_id, %status_code))]
+pub async fn store_idempotent_response(
+    pool: &PgPool,
+    user_id: i32,
+    key: &str,
+    status_code: i32,
+    response: &serde_json::Value,
+) -> Result<(), ApiError> {
+    sqlx::query!(
+        "UPDATE idempotency_keys SET status_code = $3, response = $4 \
+         WHERE user_id = $1 AND key = $2",
+        user_id,
+        key,
+        status_code,
+        response,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Frees a key whose request failed, so it can be sent again.
+#[tracing::instrument(level = "debug", skip_all, fields(%user_id))]
+pub async fn release_idempotency_key(
+    pool: &PgPool,
+    user_id: i32,
+    key: &str,
+) -> Result<(), ApiError> {
+    sqlx::query!(
+        "DELETE FROM idempotency_keys WHERE user_id = $1 AND key = $2 AND response IS NULL",
+        user_id,
+        key,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %content_id))]
+pub async fn queue_for_moderation<'e, E: PgExecutor<'e>>(
+    executor: E,
+    tenant: i32,
+    kind: ContentKind,
+    content_id: i32,
+    user_id: i32,
+    reason: &str,
+) -> Result<(), ApiError> {
+    sqlx::query!(
+        "INSERT INTO moderation_queue (tenant_id, content_kind, content_id, user_id, reason) \
+         VALUES ($1, $2, $3, $4, $5)",
+        tenant,
+        kind.as_str(),
+        content_id,
+        user_id,
+        reason,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// Open items oldest first, so the queue is worked in order; resolved ones
+// most recently resolved first.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %limit, %offset))]
+pub async fn list_moderation_queue(
+    pool: &PgPool,
+    tenant: i32,
+    resolved: bool,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<ModerationItem>, i64), ApiError> {
+    let items = sqlx::query_as!(
+        ModerationItem,
+        r#"
+        SELECT id, content_kind, content_id, user_id, reason, created_at, resolved_at, resolved_by
+        FROM moderation_queue
+        WHERE tenant_id = $1 AND (resolved_at IS NOT NULL) = $2
+        ORDER BY
+            CASE WHEN $2 THEN resolved_at END DESC,
+            CASE WHEN NOT $2 THEN created_at END ASC,
+            id
+        LIMIT $3 OFFSET $4
+        "#,
+        tenant,
+        resolved,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM moderation_queue
+        WHERE tenant_id = $1 AND (resolved_at IS NOT NULL) = $2
+        "#,
+        tenant,
+        resolved,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((items, total))
+}
+
+// Takes an item off the queue. Resolving it again changes nothing.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant, %id))]
+pub async fn resolve_moderation_item(
+    pool: &PgPool,
+    tenant: i32,
+    id: i32,
+    admin_id: i32,
+) -> Result<ModerationItem, ApiError> {
+    sqlx::query_as!(
+        ModerationItem,
+        r#"
+        UPDATE moderation_queue
+        SET resolved_at = COALESCE(resolved_at, now()),
+            resolved_by = COALESCE(resolved_by, $3)
+        WHERE id = $1 AND tenant_id = $2
+        RETURNING id, content_kind, content_id, user_id, reason, created_at, resolved_at,
+            resolved_by
+        "#,
+        id,
+        tenant,
+        admin_id,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Moderation item {} not found", id)))
+}
+
+// How many of `kind` the user created within `window`, and when the oldest
+// of those was.
+#[tracing::instrument(level = "debug", skip_all, fields(%user_id, kind = kind.as_str()))]
+pub async fn quota_usage(
+    pool: &PgPool,
+    user_id: i32,
+    kind: ContentKind,
+    window: Duration,
+) -> Result<(i64, Option<DateTime<Utc>>), ApiError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!", MIN(created_at) AS oldest FROM write_quota_usage
+        WHERE user_id = $1 AND kind = $2 AND created_at > now() - make_interval(secs => $3)
+        "#,
+        user_id,
+        kind.as_str(),
+        window.as_secs_f64(),
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((row.count, row.oldest))
+}
+
+// Counts `count` new writes of `kind`, dropping the user's ones that have
+// left the window.
+#[tracing::instrument(level = "debug", skip_all, fields(%user_id, kind = kind.as_str(), %count))]
+pub async fn record_quota_usage(
+    pool: &PgPool,
+    user_id: i32,
+    kind: ContentKind,
+    count: usize,
+    window: Duration,
+) -> Result<(), ApiError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "DELETE FROM write_quota_usage \
+         WHERE user_id = $1 AND kind = $2 AND created_at <= now() - make_interval(secs => $3)",
+        user_id,
+        kind.as_str(),
+        window.as_secs_f64(),
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO write_quota_usage (user_id, kind) \
+         SELECT $1, $2 FROM generate_series(1, $3)",
+        user_id,
+        kind.as_str(),
+        count as i32,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// Drafts and archived posts count too; trashed ones don't.
+#[tracing::instrument(level = "debug", skip_all, fields(%tenant))]
+pub async fn admin_stats(pool: &PgPool, tenant: i32) -> Result<AdminStats, ApiError> {
+    let posts_per_author = sqlx::query_as!(
+        AuthorPostCount,
+        r#"
+        SELECT u.id AS user_id, u.username, COUNT(*) AS "posts!"
+        FROM blog_posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.tenant_id = $1 AND p.deleted_at IS NULL
+        GROUP BY u.id, u.username
+        ORDER BY 3 DESC, u.username
+        "#,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await?;
+    let posts_per_day = sqlx::query_as!(
+        DailyPostCount,
+        r#"
+        SELECT d.day::date AS "day!", COUNT(p.id) AS "posts!"
+        FROM generate_series(
+            (now() AT TIME ZONE 'UTC')::date - 29,
+            (now() AT TIME ZONE 'UTC')::date,
+            interval '1 day'
+        ) AS d(day)
+        LEFT JOIN blog_posts p ON p.tenant_id = $1 AND p.deleted_at IS NULL
+            AND (p.created_at AT TIME ZONE 'UTC')::date = d.day::date
+        GROUP BY d.day
+        ORDER BY d.day
+        "#,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await?;
+    let total_comments = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM comments c
+        JOIN blog_posts p ON p.id = c.post_id
+        WHERE p.tenant_id = $1 AND p.deleted_at IS NULL
+        "#,
+        tenant,
+    )
+    .fetch_one(pool)
+    .await?;
+    let most_viewed = sqlx::query_as!(
+        ViewedPost,
+        r#"
+        SELECT id, title, slug, view_count FROM blog_posts
+        WHERE tenant_id = $1 AND deleted_at IS NULL AND view_count > 0
+        ORDER BY view_count DESC, id
+        LIMIT 10
+        "#,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(AdminStats {
+        total_posts: posts_per_author.iter().map(|author| author.posts).sum(),
+        total_comments,
+        posts_per_author,
+        posts_per_day,
+        most_viewed,
+    })
+}
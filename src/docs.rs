@@ -0,0 +1,186 @@
+// OpenAPI description of the HTTP API, served as JSON at
+// /api-docs/openapi.json and browsable through Swagger UI at /docs.
+// Every new route needs adding to `paths` here (ApiV1 for the ones in
+// `api_v1`), or it won't show up.
+
+use utoipa::openapi::security::{self, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{Content, RefOr};
+use utoipa::{Modify, OpenApi};
+
+use actix_web::HttpResponse;
+
+use crate::*;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Crud Rust API", description = "Blog posts, comments, tags and accounts."),
+    paths(
+        index_page,
+        websocket_blog_feed,
+        health,
+        healthz,
+        readyz,
+        pool_stats,
+        get_admin_db_pool,
+        get_metrics,
+        get_admin_jobs,
+        get_admin_job,
+        get_admin_audit,
+        get_admin_stats,
+        rebuild_admin_slugs,
+        get_admin_moderation,
+        resolve_admin_moderation,
+        rss_feed,
+        atom_feed,
+        get_sitemap,
+    ),
+    nest((path = "/api/v1", api = ApiV1)),
+    components(schemas(AnchoredBlogPost, Paragraph)),
+    modifiers(&BearerAuth, &ProblemResponses),
+    tags(
+        (name = "posts", description = "Blog posts"),
+        (name = "comments", description = "Comments on posts"),
+        (name = "likes", description = "Readers liking posts"),
+        (name = "translations", description = "Translated copies of posts"),
+        (name = "attachments", description = "Images uploaded to posts"),
+        (name = "users", description = "Accounts, login, roles and API keys"),
+        (name = "authors", description = "Public profiles of the people who write posts"),
+        (name = "webhooks", description = "Notifying other services of post changes"),
+        (name = "feeds", description = "RSS and Atom feeds of published posts"),
+        (name = "admin", description = "Operational endpoints"),
+        (name = "moderation", description = "Reviewing flagged posts and comments"),
+    )
+)]
+pub struct ApiDoc;
+
+// Everything registered by `api_v1`, documented under /api/v1. The
+// deprecated unversioned copies are left out.
+#[derive(OpenApi)]
+#[openapi(paths(
+    register_user,
+    login,
+    refresh_session,
+    logout,
+    oauth_login,
+    oauth_callback,
+    update_user_role,
+    update_own_email,
+    update_own_profile,
+    get_authors,
+    get_author_profile,
+    get_author_posts,
+    create_user_api_key,
+    get_api_keys,
+    delete_api_key,
+    create_user_webhook,
+    get_webhooks,
+    delete_user_webhook,
+    get_webhook_deliveries,
+    create_blogpost,
+    create_blogposts_bulk,
+    delete_blogposts_bulk,
+    get_blogposts_batch,
+    get_blogposts_by_slugs,
+    preview_blogpost,
+    import_blogposts,
+    get_blogposts,
+    search_blogposts,
+    get_popular_blogposts,
+    get_archive,
+    get_archive_month,
+    export_blogposts,
+    stream_blogposts,
+    get_trash,
+    get_blogpost,
+    get_blogpost_by_slug,
+    get_blogpost_html,
+    get_related_blogposts,
+    update_blogpost,
+    patch_blogpost,
+    delete_blogpost,
+    publish_blogpost,
+    unpublish_blogpost,
+    restore_blogpost,
+    purge_blogpost,
+    get_blogpost_revisions,
+    get_blogpost_revision,
+    restore_blogpost_revision,
+    stream_blogpost_content,
+    translate_blogpost,
+    create_blogpost_comment,
+    get_blogpost_comments,
+    delete_blogpost_comment,
+    like_blogpost,
+    unlike_blogpost,
+    get_blogpost_likes,
+    upload_blogpost_images,
+    presign_blogpost_image,
+    get_blogpost_images,
+    download_blogpost_image,
+))]
+struct ApiV1;
+
+// Write endpoints refer to this scheme with `security(("bearer_auth" = []))`.
+// `api_key` is listed once rather than on every route; anything that takes
+// a bearer token takes a key too.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_auth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(security::ApiKey::Header(security::ApiKeyValue::new(
+                "X-Api-Key",
+            ))),
+        );
+    }
+}
+
+// Responses documented with `body = Problem` are listed by utoipa as
+// application/json; this gives them the content type they are sent with.
+struct ProblemResponses;
+
+impl Modify for ProblemResponses {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for item in openapi.paths.paths.values_mut() {
+            let operations = [
+                &mut item.get,
+                &mut item.put,
+                &mut item.post,
+                &mut item.delete,
+                &mut item.patch,
+            ];
+            for operation in operations.into_iter().flatten() {
+                for response in operation.responses.responses.values_mut() {
+                    let RefOr::T(response) = response else { continue };
+                    let is_problem = matches!(
+                        response.content.get("application/json"),
+                        Some(RefOr::T(Content { schema: Some(RefOr::Ref(schema)), .. }))
+                            if schema.ref_location.ends_with("/Problem")
+                    );
+                    if is_problem {
+                        let content = response.content.shift_remove("application/json");
+                        response.content.extend(content.map(|c| (PROBLEM_JSON.to_string(), c)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Swagger UI lives under /docs/; send the bare path there.
+pub async fn redirect_to_docs() -> HttpResponse {
+    HttpResponse::Found()
+        .insert_header(("Location", "/docs/"))
+        .finish()
+}
@@ -0,0 +1,411 @@
+// ApiError, the one error type handlers return, and how it is sent: as an
+// RFC 7807 application/problem+json body.
+
+use crate::*;
+
+#[derive(Debug)]
+pub enum ApiError {
+    DatabaseError(String),
+    NotFound(String),
+    UnprocessableEntity(String),
+    InsufficientStorage(String),
+    ServiceUnavailable(String),
+    Unauthorized(String),
+    Conflict(String),
+    Validation(ValidationFailure),
+    PayloadTooLarge(String),
+    // A body over the route's limit, in bytes.
+    BodyTooLarge(usize),
+    PreconditionFailed(String),
+    Forbidden(PermissionDenied),
+    // A malformed query string or body actix couldn't parse.
+    BadRequest(String),
+    // Seconds until the client may try again.
+    TooManyRequests(u64),
+    // A user's write quota is used up.
+    QuotaExceeded(QuotaUsage),
+    // The database can't be reached, or the circuit breaker is open;
+    // seconds until it is worth trying again.
+    DatabaseUnavailable(u64),
+}
+
+impl ApiError {
+    // Prefixes the message with the position of the offending item in a
+    // batch request, keeping the original status.
+    pub fn at_index(self, index: usize) -> Self {
+        match self {
+            ApiError::DatabaseError(msg) => {
+                ApiError::DatabaseError(format!("Item {}: {}", index, msg))
+            }
+            ApiError::NotFound(msg) => {
+                ApiError::NotFound(format!("Item {}: {}", index, msg))
+            }
+            ApiError::UnprocessableEntity(msg) => {
+                ApiError::UnprocessableEntity(format!("Item {}: {}", index, msg))
+            }
+            ApiError::InsufficientStorage(msg) => {
+                ApiError::InsufficientStorage(format!("Item {}: {}", index, msg))
+            }
+            ApiError::ServiceUnavailable(msg) => {
+                ApiError::ServiceUnavailable(format!("Item {}: {}", index, msg))
+            }
+            ApiError::Unauthorized(msg) => {
+                ApiError::Unauthorized(format!("Item {}: {}", index, msg))
+            }
+            ApiError::Conflict(msg) => ApiError::Conflict(format!("Item {}: {}", index, msg)),
+            ApiError::Validation(failure) => ApiError::Validation(ValidationFailure {
+                message: format!("Item {}: {}", index, failure.message),
+                errors: failure.errors,
+            }),
+            ApiError::PayloadTooLarge(msg) => {
+                ApiError::PayloadTooLarge(format!("Item {}: {}", index, msg))
+            }
+            ApiError::BodyTooLarge(limit) => ApiError::BodyTooLarge(limit),
+            ApiError::PreconditionFailed(msg) => {
+                ApiError::PreconditionFailed(format!("Item {}: {}", index, msg))
+            }
+            ApiError::Forbidden(denied) => ApiError::Forbidden(PermissionDenied {
+                message: format!("Item {}: {}", index, denied.message),
+                ..denied
+            }),
+            ApiError::BadRequest(msg) => {
+                ApiError::BadRequest(format!("Item {}: {}", index, msg))
+            }
+            ApiError::TooManyRequests(secs) => ApiError::TooManyRequests(secs),
+            ApiError::QuotaExceeded(usage) => ApiError::QuotaExceeded(usage),
+            ApiError::DatabaseUnavailable(secs) => ApiError::DatabaseUnavailable(secs),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ApiError::DatabaseError(_) => ErrorCode::InternalError,
+            ApiError::NotFound(_) => ErrorCode::NotFound,
+            ApiError::UnprocessableEntity(_) => ErrorCode::UnprocessableEntity,
+            ApiError::InsufficientStorage(_) => ErrorCode::StorageFull,
+            ApiError::ServiceUnavailable(_) => ErrorCode::ServiceUnavailable,
+            ApiError::Unauthorized(_) => ErrorCode::Unauthorized,
+            ApiError::Conflict(_) => ErrorCode::Conflict,
+            ApiError::Validation(_) => ErrorCode::ValidationFailed,
+            ApiError::PayloadTooLarge(_) | ApiError::BodyTooLarge(_) => ErrorCode::PayloadTooLarge,
+            ApiError::PreconditionFailed(_) => ErrorCode::PreconditionFailed,
+            ApiError::Forbidden(_) => ErrorCode::Forbidden,
+            ApiError::BadRequest(_) => ErrorCode::BadRequest,
+            ApiError::TooManyRequests(_) => ErrorCode::RateLimited,
+            ApiError::QuotaExceeded(_) => ErrorCode::QuotaExceeded,
+            ApiError::DatabaseUnavailable(_) => ErrorCode::DatabaseUnavailable,
+        }
+    }
+
+    // The message without the `Not Found: ` style prefix Display adds.
+    pub fn detail(&self) -> String {
+        match self {
+            ApiError::DatabaseError(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::UnprocessableEntity(msg)
+            | ApiError::InsufficientStorage(msg)
+            | ApiError::ServiceUnavailable(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::Conflict(msg)
+            | ApiError::PayloadTooLarge(msg)
+            | ApiError::PreconditionFailed(msg)
+            | ApiError::BadRequest(msg) => msg.clone(),
+            ApiError::Validation(failure) => failure.message.clone(),
+            ApiError::Forbidden(denied) => denied.message.clone(),
+            ApiError::BodyTooLarge(limit) => {
+                format!("Request body is over this route's limit of {} bytes", limit)
+            }
+            ApiError::TooManyRequests(secs) => {
+                format!("Too many requests; try again in {}s", secs)
+            }
+            ApiError::QuotaExceeded(usage) => format!(
+                "Quota of {} {}s used up; try again in {}s",
+                usage.limit,
+                usage.kind.as_str(),
+                usage.reset_secs
+            ),
+            ApiError::DatabaseUnavailable(secs) => {
+                format!("The database is unavailable; try again in {}s", secs)
+            }
+        }
+    }
+
+    pub fn problem(&self) -> Problem {
+        let code = self.code();
+        let mut problem = Problem {
+            problem_type: code.type_uri(),
+            title: code.title().to_string(),
+            status: self.status_code().as_u16(),
+            detail: self.detail(),
+            code,
+            errors: None,
+            required: None,
+            role: None,
+            limit: None,
+        };
+        match self {
+            ApiError::Validation(failure) => problem.errors = Some(failure.errors.clone()),
+            ApiError::Forbidden(denied) => {
+                problem.required = Some(denied.required);
+                problem.role = Some(denied.role);
+            }
+            ApiError::BodyTooLarge(limit) => problem.limit = Some(*limit),
+            _ => {}
+        }
+        problem
+    }
+}
+
+// Which problem an error is, stable for clients to match on. Sent as
+// `code` and, in kebab case, as the last segment of `type`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    PreconditionFailed,
+    PayloadTooLarge,
+    // A field failed its rules; `errors` says which and why.
+    ValidationFailed,
+    // Well-formed but refused otherwise, e.g. an unknown tag or field.
+    UnprocessableEntity,
+    RateLimited,
+    // A user's quota of posts or comments is used up until X-Quota-Reset.
+    QuotaExceeded,
+    InternalError,
+    ServiceUnavailable,
+    DatabaseUnavailable,
+    StorageFull,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::PreconditionFailed => "precondition_failed",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::ValidationFailed => "validation_failed",
+            ErrorCode::UnprocessableEntity => "unprocessable_entity",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::QuotaExceeded => "quota_exceeded",
+            ErrorCode::InternalError => "internal_error",
+            ErrorCode::ServiceUnavailable => "service_unavailable",
+            ErrorCode::DatabaseUnavailable => "database_unavailable",
+            ErrorCode::StorageFull => "storage_full",
+        }
+    }
+
+    // The same for every occurrence of the problem; `detail` is what is
+    // specific to this one.
+    pub fn title(self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "Malformed request",
+            ErrorCode::Unauthorized => "Authentication required",
+            ErrorCode::Forbidden => "Permission denied",
+            ErrorCode::NotFound => "Not found",
+            ErrorCode::Conflict => "Conflict with the current state",
+            ErrorCode::PreconditionFailed => "Precondition failed",
+            ErrorCode::PayloadTooLarge => "Request too large",
+            ErrorCode::ValidationFailed => "Validation failed",
+            ErrorCode::UnprocessableEntity => "Request refused",
+            ErrorCode::RateLimited => "Rate limit exceeded",
+            ErrorCode::QuotaExceeded => "Write quota exceeded",
+            ErrorCode::InternalError => "Internal error",
+            ErrorCode::ServiceUnavailable => "Temporarily unavailable",
+            ErrorCode::DatabaseUnavailable => "Database unavailable",
+            ErrorCode::StorageFull => "Storage full",
+        }
+    }
+
+    // Relative, so it resolves against whichever host served the error.
+    pub fn type_uri(self) -> String {
+        format!("/problems/{}", self.as_str().replace('_', "-"))
+    }
+}
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+// An error body (RFC 7807). `errors` comes with validation_failed,
+// `required` and `role` with forbidden, `limit` with payload_too_large
+// for a body over the route's limit.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    pub code: ErrorCode,
+    // Field name to what is wrong with it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<BTreeMap<String, Vec<String>>>,
+    // What the request would have needed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required: Option<Requirement>,
+    // The caller's role.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    // The largest body the route takes, in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+impl ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        let mut res = HttpResponse::build(self.status_code());
+        res.content_type(PROBLEM_JSON);
+        match self {
+            ApiError::ServiceUnavailable(_) => {
+                res.insert_header(("Retry-After", "1"));
+            }
+            ApiError::TooManyRequests(secs) | ApiError::DatabaseUnavailable(secs) => {
+                res.insert_header(("Retry-After", secs.to_string()));
+            }
+            ApiError::QuotaExceeded(usage) => {
+                res.insert_header(("Retry-After", usage.reset_secs.to_string()))
+                    .quota(Some(*usage));
+            }
+            ApiError::Unauthorized(_) => {
+                res.insert_header(("WWW-Authenticate", "Bearer"));
+            }
+            _ => {}
+        }
+        let problem = self.problem();
+        let mut response = res.json(&problem);
+        // For localize_problem, which translates it.
+        response.extensions_mut().insert(problem);
+        response
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::UnprocessableEntity(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::InsufficientStorage(_) => StatusCode::INSUFFICIENT_STORAGE,
+            ApiError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::PayloadTooLarge(_) | ApiError::BodyTooLarge(_) => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::TooManyRequests(_) | ApiError::QuotaExceeded(_) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ApiError::DatabaseUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::DatabaseError(msg) => write!(f, "Database Error: {}", msg),
+            ApiError::NotFound(msg) => write!(f, "Not Found: {}", msg),
+            ApiError::UnprocessableEntity(msg) => {
+                write!(f, "Unprocessable Entity: {}", msg)
+            }
+            ApiError::InsufficientStorage(msg) => {
+                write!(f, "Insufficient Storage: {}", msg)
+            }
+            ApiError::ServiceUnavailable(msg) => {
+                write!(f, "Service Unavailable: {}", msg)
+            }
+            ApiError::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            ApiError::Conflict(msg) => write!(f, "Conflict: {}", msg),
+            ApiError::Validation(failure) => {
+                write!(f, "Unprocessable Entity: {}", failure)
+            }
+            ApiError::PayloadTooLarge(msg) => write!(f, "Payload Too Large: {}", msg),
+            ApiError::BodyTooLarge(_) => write!(f, "Payload Too Large: {}", self.detail()),
+            ApiError::PreconditionFailed(msg) => write!(f, "Precondition Failed: {}", msg),
+            ApiError::Forbidden(denied) => write!(f, "Forbidden: {}", denied),
+            ApiError::BadRequest(msg) => write!(f, "Bad Request: {}", msg),
+            ApiError::TooManyRequests(_) | ApiError::QuotaExceeded(_) => {
+                write!(f, "Too Many Requests: {}", self.detail())
+            }
+            ApiError::DatabaseUnavailable(_) => {
+                write!(f, "Database Unavailable: {}", self.detail())
+            }
+        }
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => {
+                ApiError::NotFound("Record not found".to_string())
+            }
+            sqlx::Error::Database(ref db) if db.is_unique_violation() => {
+                ApiError::Conflict("Record already exists".to_string())
+            }
+            sqlx::Error::PoolTimedOut => {
+                metrics().observe_pool_timeout();
+                tracing::warn!("Database unavailable: {}", err);
+                ApiError::DatabaseUnavailable(1)
+            }
+            // query_canceled: the statement ran past DB_STATEMENT_TIMEOUT_MS.
+            // Not retried, as it would most likely take as long again.
+            sqlx::Error::Database(ref db) if db.code().as_deref() == Some("57014") => {
+                tracing::warn!("Query timed out: {}", err);
+                ApiError::ServiceUnavailable("The query took too long".to_string())
+            }
+            _ if is_transient(&err) => {
+                tracing::warn!("Database unavailable: {}", err);
+                ApiError::DatabaseUnavailable(1)
+            }
+            _ => ApiError::DatabaseError(err.to_string()),
+        }
+    }
+}
+
+// Failures that say nothing about the query, only that the database can't
+// be reached right now: a dropped or refused connection, a server that is
+// shutting down or out of connections. Worth retrying; anything else isn't.
+pub fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(db) => db.code().is_some_and(|code| {
+            // connection_exception, admin/crash/cannot_connect_now and
+            // too_many_connections.
+            code.starts_with("08") || matches!(&*code, "57P01" | "57P02" | "57P03" | "53300")
+        }),
+        _ => false,
+    }
+}
+
+// -------------------- Rejections outside handlers --------------------
+
+// Bodies, query strings and paths actix can't parse are answered with
+// problems too, keeping the status actix would have used, as are paths
+// no route matches. JSON bodies are held to `limits.json_bytes`.
+pub fn configure_problems(cfg: &mut web::ServiceConfig, limits: &LimitsConfig) {
+    cfg.default_service(web::to(|req: HttpRequest| async move {
+        Err::<HttpResponse, _>(ApiError::NotFound(format!("No route for {}", req.path())))
+    }))
+    .app_data(json_config(limits.json_bytes))
+    .app_data(
+        web::QueryConfig::default()
+            .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+    )
+    .app_data(
+        web::PathConfig::default()
+            .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+    );
+}
@@ -0,0 +1,37 @@
+// ETags for posts and the conditional request headers.
+
+use crate::*;
+
+// Derived from everything a client sees of the post, version included, so
+// any change to it gives a new tag. Likes and views are left out: they
+// aren't edits.
+pub fn post_etag(post: &BlogPost) -> EntityTag {
+    let content = BlogPost {
+        like_count: 0,
+        view_count: 0,
+        ..post.clone()
+    };
+    let digest = Sha256::digest(serde_json::to_vec(&content).unwrap_or_default());
+    EntityTag::new_strong(hex::encode(&digest[..8]))
+}
+
+// If-None-Match uses the weak comparison (RFC 9110 13.1.2).
+pub fn matches_if_none_match(req: &HttpRequest, etag: &EntityTag) -> bool {
+    if !req.headers().contains_key(IfNoneMatch::name()) {
+        return false;
+    }
+    match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        Err(_) => false,
+    }
+}
+
+// None when the request has no If-Match header. Unparsable tags are
+// dropped, so a garbled header matches nothing rather than everything.
+pub fn if_match(req: &HttpRequest) -> Option<IfMatch> {
+    if !req.headers().contains_key(IfMatch::name()) {
+        return None;
+    }
+    Some(IfMatch::parse(req).unwrap_or(IfMatch::Items(Vec::new())))
+}
@@ -0,0 +1,176 @@
+// Known accounts and posts for demo environments and integration tests,
+// loaded from a YAML or JSON file by `--load-fixtures` or FIXTURES.
+
+use std::path::Path;
+
+use crate::*;
+
+// A fixtures file:
+//
+//     tenant: default            # slug; `default` when left out
+//     users:
+//       - username: alice
+//         password: correct horse  # left out: the account can't log in
+//         role: admin              # `editor` when left out
+//     posts:
+//       - title: Hello
+//         content: The first post.
+//         author: alice
+//         tags: [intro]
+//         status: draft            # published when left out
+//
+// Users are known by username and posts by author and title; ones that
+// are already there are left as they are, so loading a file again only
+// adds what is new in it.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Fixtures {
+    #[serde(default = "default_tenant")]
+    pub tenant: String,
+    #[serde(default)]
+    pub users: Vec<UserFixture>,
+    #[serde(default)]
+    pub posts: Vec<PostFixture>,
+}
+
+fn default_tenant() -> String {
+    "default".to_string()
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct UserFixture {
+    pub username: String,
+    pub password: Option<String>,
+    pub email: Option<String>,
+    pub role: Option<Role>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PostFixture {
+    pub title: String,
+    pub content: String,
+    // Username of the post's author, from `users` or already there.
+    pub author: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub status: Option<PostStatus>,
+    pub publish_at: Option<DateTime<Utc>>,
+}
+
+// What loading fixtures added, and what was already there.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FixtureReport {
+    pub users_added: usize,
+    pub users_existing: usize,
+    pub posts_added: usize,
+    pub posts_existing: usize,
+}
+
+impl fmt::Display for FixtureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Added {} user(s) and {} post(s) from fixtures; {} and {} were already there",
+            self.users_added, self.posts_added, self.users_existing, self.posts_existing
+        )
+    }
+}
+
+impl Fixtures {
+    // JSON for a `.json` file, YAML for anything else.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read fixtures {}: {}", path.display(), err))?;
+        let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&source).map_err(|err| err.to_string()),
+            _ => serde_yaml::from_str(&source).map_err(|err| err.to_string()),
+        };
+        parsed.map_err(|err| format!("Bad fixtures {}: {}", path.display(), err))
+    }
+}
+
+// Adds the fixtures' users and posts in one transaction, so a file with a
+// bad entry adds nothing. Posts skip the response cache, as `seed` does.
+pub async fn load_fixtures(pool: &PgPool, fixtures: &Fixtures) -> Result<FixtureReport, String> {
+    let tenant = find_tenant(pool, &fixtures.tenant).await.map_err(|err| err.to_string())?;
+    let mut report = FixtureReport::default();
+    let mut tx = pool.begin().await.map_err(|err| err.to_string())?;
+    for user in &fixtures.users {
+        let added = load_user(&mut tx, tenant.id, user)
+            .await
+            .map_err(|err| format!("User `{}`: {}", user.username, err))?;
+        if added {
+            report.users_added += 1;
+        } else {
+            report.users_existing += 1;
+        }
+    }
+    for post in &fixtures.posts {
+        let added = load_post(&mut tx, tenant.id, post)
+            .await
+            .map_err(|err| format!("Post `{}`: {}", post.title, err))?;
+        if added {
+            report.posts_added += 1;
+        } else {
+            report.posts_existing += 1;
+        }
+    }
+    tx.commit().await.map_err(|err| err.to_string())?;
+    Ok(report)
+}
+
+// False when there already is an account with this username.
+async fn load_user(
+    conn: &mut PgConnection,
+    tenant: i32,
+    user: &UserFixture,
+) -> Result<bool, ApiError> {
+    if get_user_by_username(&mut *conn, tenant, &user.username).await?.is_some() {
+        return Ok(false);
+    }
+    let password_hash = match &user.password {
+        Some(password) => {
+            validate_new_user(&NewUser {
+                username: user.username.clone(),
+                password: password.clone(),
+                email: user.email.clone(),
+            })?;
+            hash_password(password)?
+        }
+        None => "!".to_string(),
+    };
+    let email = user.email.as_deref();
+    let created = create_user(&mut *conn, tenant, &user.username, &password_hash, email).await?;
+    if let Some(role) = user.role.filter(|role| role.as_str() != created.role) {
+        set_user_role(conn, tenant, created.id, role, created.id).await?;
+    }
+    Ok(true)
+}
+
+// False when the author already has a post with this title.
+async fn load_post(
+    conn: &mut PgConnection,
+    tenant: i32,
+    post: &PostFixture,
+) -> Result<bool, ApiError> {
+    let author = get_user_by_username(&mut *conn, tenant, &post.author)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("No user `{}`", post.author)))?;
+    if find_post_by_title(conn, tenant, author.id, &post.title).await?.is_some() {
+        return Ok(false);
+    }
+    let new_post = NewBlogPost {
+        title: post.title.clone(),
+        content: post.content.clone(),
+        tags: Some(post.tags.clone()),
+        version: None,
+        status: post.status,
+        publish_at: post.publish_at,
+        unknown_fields: BTreeMap::new(),
+    };
+    new_post.validate()?;
+    create_post(conn, tenant, &new_post, author.id).await?;
+    Ok(true)
+}
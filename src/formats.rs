@@ -0,0 +1,86 @@
+// JSON or XML response bodies, by the request's Accept header. JSON is the
+// default; XML is for older clients that can't take JSON.
+
+use actix_web::http::header::{Accept, Quality, CONTENT_TYPE, VARY};
+use actix_web::mime;
+use actix_web::HttpResponseBuilder;
+
+use crate::*;
+
+pub const APPLICATION_XML: &str = "application/xml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Xml,
+}
+
+impl Format {
+    // The format the client likes best. `application/xml` or `text/xml`
+    // above JSON picks XML; no Accept, `*/*`, or only types we don't have
+    // get JSON rather than a 406.
+    pub fn of(req: &HttpRequest) -> Format {
+        let Some(accept) = req.get_header::<Accept>() else {
+            return Format::Json;
+        };
+        let mut ranked: Vec<_> =
+            accept.iter().filter(|item| item.quality > Quality::ZERO).collect();
+        // Stable, so the listed order breaks ties.
+        ranked.sort_by_key(|item| std::cmp::Reverse(item.quality));
+        for item in ranked {
+            let mime = &item.item;
+            match (mime.type_(), mime.subtype()) {
+                (mime::APPLICATION | mime::TEXT, mime::XML) => return Format::Xml,
+                (mime::APPLICATION, mime::JSON | mime::STAR) | (mime::STAR, mime::STAR) => {
+                    return Format::Json
+                }
+                _ => {}
+            }
+        }
+        Format::Json
+    }
+}
+
+// Anything Serialize as XML: the type's name is the root element and each
+// field an element in it, a list field one element per item.
+//
+//     <BlogPost><id>1</id><title>Hi</title><tags>a</tags><tags>b</tags>...
+//
+// None is an empty element.
+pub fn to_xml<T: Serialize>(value: &T) -> Result<String, String> {
+    let body = quick_xml::se::to_string(value).map_err(|err| err.to_string())?;
+    Ok(format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}", body))
+}
+
+// Finishes a response with `value` in the request's format, like `.json()`
+// does in JSON. The shared way handlers answer in either; `Negotiated` is
+// the same as a Responder.
+pub trait NegotiatedBody {
+    fn negotiated<T: Serialize>(&mut self, req: &HttpRequest, value: &T) -> HttpResponse;
+}
+
+impl NegotiatedBody for HttpResponseBuilder {
+    fn negotiated<T: Serialize>(&mut self, req: &HttpRequest, value: &T) -> HttpResponse {
+        self.append_header((VARY, "Accept"));
+        if Format::of(req) == Format::Xml {
+            // Only values without a name for their root, like a bare
+            // list, fail; they go out as JSON.
+            match to_xml(value) {
+                Ok(xml) => return self.insert_header((CONTENT_TYPE, APPLICATION_XML)).body(xml),
+                Err(err) => tracing::warn!("Cannot send this response as XML: {}", err),
+            }
+        }
+        self.json(value)
+    }
+}
+
+// `value` with 200 in the request's format.
+pub struct Negotiated<T>(pub T);
+
+impl<T: Serialize> Responder for Negotiated<T> {
+    type Body = actix_web::body::BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse {
+        HttpResponse::Ok().negotiated(req, &self.0)
+    }
+}
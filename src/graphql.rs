@@ -0,0 +1,310 @@
+// The GraphQL schema served at /graphql.
+
+use crate::*;
+
+// Posts over GraphQL at /graphql, next to REST. Resolvers call the same
+// query functions and touch the same cache, change feed and storage guard
+// as the REST handlers, so both views always agree.
+pub type BlogSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+pub fn build_schema(
+    pool: PgPool,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    storage: web::Data<StorageGuard>,
+    profile: web::Data<ListProfile>,
+    moderator: web::Data<dyn Moderator>,
+    quotas: web::Data<WriteQuotas>,
+) -> BlogSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(pool)
+        .data(cache)
+        .data(feed)
+        .data(storage)
+        .data(profile)
+        .data(moderator)
+        .data(quotas)
+        .limit_depth(8)
+        .finish()
+}
+
+// Errors carry the REST API's error code, upper-cased as is usual for
+// GraphQL, in `extensions.code`, field problems in `extensions.fields` and
+// a missing permission in `extensions.required`.
+impl ErrorExtensions for ApiError {
+    fn extend(&self) -> async_graphql::Error {
+        async_graphql::Error::new(self.to_string()).extend_with(|_, extensions| {
+            extensions.set("code", self.code().as_str().to_ascii_uppercase());
+            if let ApiError::Validation(failure) = self {
+                let fields = serde_json::to_value(&failure.errors).unwrap_or_default();
+                if let Ok(fields) = async_graphql::Value::from_json(fields) {
+                    extensions.set("fields", fields);
+                }
+            }
+            if let ApiError::Forbidden(denied) = self {
+                extensions.set("required", denied.required.as_str());
+            }
+        })
+    }
+}
+
+// Only mutations ask for the user, so read-only keys are refused here.
+pub(crate) fn graphql_user<'a>(ctx: &Context<'a>) -> async_graphql::Result<&'a AuthUser> {
+    let user = ctx.data_opt::<AuthUser>().ok_or_else(|| {
+        ApiError::Unauthorized("Missing or invalid token".to_string()).extend()
+    })?;
+    user.require_write().map_err(|err| err.extend())?;
+    Ok(user)
+}
+
+// Set on every request by `graphql`.
+pub(crate) fn graphql_tenant(ctx: &Context<'_>) -> async_graphql::Result<i32> {
+    Ok(ctx.data::<Tenant>()?.id)
+}
+
+#[derive(InputObject)]
+pub struct PostInput {
+    pub title: String,
+    pub content: String,
+    pub tags: Option<Vec<String>>,
+    // PUBLISHED when left out, or DRAFT with `publish_at`.
+    pub status: Option<PostStatus>,
+    // Publishes the draft at this time.
+    pub publish_at: Option<DateTime<Utc>>,
+}
+
+impl From<PostInput> for NewBlogPost {
+    fn from(input: PostInput) -> Self {
+        NewBlogPost {
+            title: input.title,
+            content: input.content,
+            tags: input.tags,
+            version: None,
+            status: input.status,
+            publish_at: input.publish_at,
+            unknown_fields: BTreeMap::new(),
+        }
+    }
+}
+
+// Only the fields given are changed, like PATCH /blog/{id}.
+#[derive(InputObject)]
+pub struct PostPatch {
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub tags: Option<Vec<String>>,
+    // The version being changed; a stale one is a CONFLICT.
+    pub version: i32,
+    pub status: Option<PostStatus>,
+}
+
+impl From<PostPatch> for UpdateBlogPost {
+    fn from(patch: PostPatch) -> Self {
+        UpdateBlogPost {
+            title: patch.title,
+            content: patch.content,
+            tags: patch.tags,
+            version: Some(patch.version),
+            status: patch.status,
+            publish_at: None,
+            unknown_fields: BTreeMap::new(),
+        }
+    }
+}
+
+// Page<BlogPost> for GraphQL, which can't derive objects for generic types.
+#[derive(SimpleObject)]
+pub struct PostPage {
+    pub data: Vec<BlogPost>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    pub total_pages: i64,
+}
+
+impl From<Page<BlogPost>> for PostPage {
+    fn from(page: Page<BlogPost>) -> Self {
+        PostPage {
+            data: page.data,
+            page: page.page,
+            per_page: page.per_page,
+            total: page.total,
+            total_pages: page.total_pages,
+        }
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    // Null when the post doesn't exist or is in the trash.
+    async fn post(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<Option<BlogPost>> {
+        match get_post(ctx.data::<PgPool>()?, graphql_tenant(ctx)?, id).await {
+            Ok(post) if post_visible(&post, ctx.data_opt::<AuthUser>()) => Ok(Some(post)),
+            Ok(_) => Ok(None),
+            Err(ApiError::NotFound(_)) => Ok(None),
+            Err(err) => Err(err.extend()),
+        }
+    }
+
+    async fn post_by_slug(
+        &self,
+        ctx: &Context<'_>,
+        slug: String,
+    ) -> async_graphql::Result<Option<BlogPost>> {
+        match get_post_by_slug(ctx.data::<PgPool>()?, graphql_tenant(ctx)?, &slug).await {
+            Ok(post) if post_visible(&post, ctx.data_opt::<AuthUser>()) => Ok(Some(post)),
+            Ok(_) => Ok(None),
+            Err(ApiError::NotFound(_)) => Ok(None),
+            Err(err) => Err(err.extend()),
+        }
+    }
+
+    // Each argument is a GraphQL field argument.
+    #[allow(clippy::too_many_arguments)]
+    async fn posts(
+        &self,
+        ctx: &Context<'_>,
+        tag: Option<String>,
+        author: Option<String>,
+        status: Option<PostStatus>,
+        sort: Option<SortColumn>,
+        order: Option<SortOrder>,
+        page: Option<i64>,
+        per_page: Option<i64>,
+    ) -> async_graphql::Result<PostPage> {
+        let pool = ctx.data::<PgPool>()?;
+        let (sort, order) = ctx.data::<web::Data<ListProfile>>()?.resolve_with(sort, order);
+        let (page, per_page) = clamp_paging(page, per_page);
+        let tag = tag.as_deref().map(normalize_tag);
+        let filter = PostFilter {
+            tag: tag.as_deref(),
+            author: author.as_deref(),
+            status,
+            tenant: graphql_tenant(ctx)?,
+            ..Default::default()
+        }
+        .viewed_by(ctx.data_opt::<AuthUser>());
+        let offset = (page - 1) * per_page;
+        let data = get_all_posts(pool, filter, sort, order, per_page, offset)
+            .await
+            .map_err(|err| err.extend())?;
+        let total = count_posts(pool, filter).await.map_err(|err| err.extend())?;
+        Ok(Page::new(data, page, per_page, total, false).into())
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    async fn create_post(
+        &self,
+        ctx: &Context<'_>,
+        input: PostInput,
+    ) -> async_graphql::Result<BlogPost> {
+        let user = graphql_user(ctx)?;
+        let pool = ctx.data::<PgPool>()?;
+        let storage = ctx.data::<web::Data<StorageGuard>>()?;
+        let moderator = ctx.data::<web::Data<dyn Moderator>>()?;
+        let quotas = ctx.data::<web::Data<WriteQuotas>>()?;
+        let post = NewBlogPost::from(input);
+        let created = async {
+            user.require_role(Role::Editor)?;
+            storage.check_writable()?;
+            post.validate()?;
+            quotas.check(pool, user, ContentKind::Post, 1).await?;
+            let flag = moderate(moderator.get_ref(), ContentKind::Post, &post.moderated_text())
+                .await?;
+            // The post is only saved together with its place in the queue.
+            let mut tx = pool.begin().await?;
+            let created = create_post(&mut tx, user.tenant_id, &post, user.id).await?;
+            if let Some(reason) = flag {
+                let (tenant, kind) = (user.tenant_id, ContentKind::Post);
+                queue_for_moderation(&mut *tx, tenant, kind, created.id, user.id, &reason).await?;
+            }
+            tx.commit().await?;
+            Ok::<_, ApiError>(created)
+        }
+        .await
+        .map_err(|err| err.extend())?;
+        quotas.record(pool, user, ContentKind::Post, 1).await;
+        ctx.data::<web::Data<PostCache>>()?.invalidate(user.tenant_id, None).await;
+        let feed = ctx.data::<web::Data<ChangeFeed>>()?;
+        feed.publish(PostEventKind::Created, user.tenant_id, created.id, Some(created.clone()));
+        Ok(created)
+    }
+
+    async fn update_post(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        patch: PostPatch,
+    ) -> async_graphql::Result<BlogPost> {
+        let user = graphql_user(ctx)?;
+        let pool = ctx.data::<PgPool>()?;
+        let storage = ctx.data::<web::Data<StorageGuard>>()?;
+        let patch = UpdateBlogPost::from(patch);
+        let post = async {
+            storage.check_writable()?;
+            patch.validate()?;
+            user.require_owner(post_owner(pool, user.tenant_id, id).await?)?;
+            let before = get_post(pool, user.tenant_id, id).await;
+            let was_published =
+                before.is_ok_and(|post| post.status == PostStatus::Published.as_str());
+            let mut tx = pool.begin().await?;
+            let post = patch_post(&mut tx, user.tenant_id, id, &patch, None, user.id).await?;
+            tx.commit().await?;
+            Ok::<_, ApiError>((post, was_published))
+        }
+        .await
+        .map_err(|err| err.extend())?;
+        let (post, was_published) = post;
+        ctx.data::<web::Data<PostCache>>()?.invalidate(user.tenant_id, Some(id)).await;
+        let feed = ctx.data::<web::Data<ChangeFeed>>()?;
+        feed.publish_update(user.tenant_id, post.clone(), was_published);
+        Ok(post)
+    }
+
+    // Moves the post to the trash, like DELETE /blog/{id}.
+    async fn delete_post(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<bool> {
+        let user = graphql_user(ctx)?;
+        let pool = ctx.data::<PgPool>()?;
+        async {
+            user.require_owner(post_owner(pool, user.tenant_id, id).await?)?;
+            let mut tx = pool.begin().await?;
+            delete_post(&mut tx, user.tenant_id, id, None, user.id).await?;
+            tx.commit().await?;
+            Ok::<_, ApiError>(())
+        }
+        .await
+        .map_err(|err| err.extend())?;
+        ctx.data::<web::Data<PostCache>>()?.invalidate(user.tenant_id, Some(id)).await;
+        let feed = ctx.data::<web::Data<ChangeFeed>>()?;
+        feed.publish(PostEventKind::Deleted, user.tenant_id, id, None);
+        Ok(true)
+    }
+}
+
+// A valid bearer token or API key is passed on to the resolvers; queries
+// work without one, mutations answer UNAUTHORIZED. An unknown tenant is a
+// plain 404, as on the REST routes.
+pub(crate) async fn graphql(
+    req: HttpRequest,
+    tenant: Tenant,
+    schema: web::Data<BlogSchema>,
+    request: GraphQLRequest,
+) -> Result<GraphQLResponse, ApiError> {
+    let mut request = request.into_inner().data(tenant);
+    if let Ok(user) = authenticate(&req).await {
+        request = request.data(user);
+    }
+    Ok(schema.execute(request).await.into())
+}
+
+pub(crate) async fn graphiql() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(GraphiQLSource::build().endpoint("/graphql").finish())
+}
@@ -0,0 +1,377 @@
+// The post CRUD over gRPC (proto/blog.proto), served on GRPC_PORT next to
+// the HTTP server in a build with `--features grpc`. Calls go through the
+// same PostRepository, cache, change feed and checks as the REST handlers.
+
+use tonic::metadata::MetadataMap;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Code, Request, Response, Status};
+
+use crate::*;
+
+pub mod proto {
+    tonic::include_proto!("blog.v1");
+}
+
+use proto::posts_server::{Posts, PostsServer};
+
+// GRPC_PORT, when set; the gRPC server listens on the HTTP server's host.
+pub fn grpc_port() -> Result<Option<u16>, String> {
+    match env::var("GRPC_PORT") {
+        Ok(port) if !port.trim().is_empty() => port
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("Invalid GRPC_PORT `{}`", port)),
+        _ => Ok(None),
+    }
+}
+
+pub struct GrpcPosts {
+    pool: PgPool,
+    repo: web::Data<dyn PostRepository>,
+    auth: web::Data<AuthConfig>,
+    tenants: web::Data<Tenants>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    storage: web::Data<StorageGuard>,
+    moderator: web::Data<dyn Moderator>,
+    profile: web::Data<ListProfile>,
+    limiter: web::Data<HeavyQueryLimiter>,
+    quotas: web::Data<WriteQuotas>,
+}
+
+impl GrpcPosts {
+    pub fn new(state: &AppState) -> Self {
+        GrpcPosts {
+            pool: state.pool.clone(),
+            repo: state.repo.clone(),
+            auth: state.auth.clone(),
+            tenants: state.tenants.clone(),
+            cache: state.cache.clone(),
+            feed: state.feed.clone(),
+            storage: state.storage.clone(),
+            moderator: state.moderator.clone(),
+            profile: state.profile.clone(),
+            limiter: state.limiter.clone(),
+            quotas: state.quotas.clone(),
+        }
+    }
+
+    // `x-tenant-id` as X-Tenant-Id is for HTTP; the default tenant without.
+    async fn tenant(&self, metadata: &MetadataMap) -> Result<Tenant, ApiError> {
+        let Some(value) = metadata.get("x-tenant-id") else {
+            return Ok(Tenant::default());
+        };
+        let value = value
+            .to_str()
+            .map_err(|_| ApiError::BadRequest("Invalid x-tenant-id metadata".to_string()))?;
+        self.tenants.lookup(value.trim().to_ascii_lowercase()).await
+    }
+
+    // The caller, if they sent credentials: a bearer token in
+    // `authorization` wins over `x-api-key`, as with the headers. Like
+    // there, they only work for their user's tenant.
+    async fn viewer(
+        &self,
+        metadata: &MetadataMap,
+        tenant: &Tenant,
+    ) -> Result<Option<AuthUser>, ApiError> {
+        let user = if let Some(value) = metadata.get("authorization") {
+            self.auth.bearer_user(value.to_str().ok())?
+        } else if let Some(key) = metadata.get("x-api-key") {
+            let key = key
+                .to_str()
+                .map_err(|_| ApiError::Unauthorized("Invalid API key".to_string()))?;
+            authenticate_api_key(&self.pool, &hash_secret(key.trim()))
+                .await?
+                .ok_or_else(|| ApiError::Unauthorized("Invalid or revoked API key".to_string()))?
+        } else {
+            return Ok(None);
+        };
+        if user.tenant_id != tenant.id {
+            return Err(ApiError::Unauthorized(
+                "These credentials are for another tenant".to_string(),
+            ));
+        }
+        Ok(Some(user))
+    }
+
+    // For the calls that change posts, which read-only keys can't make.
+    async fn writer(&self, metadata: &MetadataMap) -> Result<AuthUser, ApiError> {
+        let tenant = self.tenant(metadata).await?;
+        let user = self
+            .viewer(metadata, &tenant)
+            .await?
+            .ok_or_else(|| ApiError::Unauthorized("Missing bearer token".to_string()))?;
+        user.require_write()?;
+        Ok(user)
+    }
+
+    async fn get_post(&self, metadata: &MetadataMap, id: i32) -> Result<BlogPost, ApiError> {
+        let tenant = self.tenant(metadata).await?;
+        let viewer = self.viewer(metadata, &tenant).await?;
+        let post = self.repo.get(tenant.id, id).await?;
+        if !post_visible(&post, viewer.as_ref()) {
+            return Err(ApiError::NotFound(format!("Post {} not found", id)));
+        }
+        Ok(post)
+    }
+
+    async fn get_post_by_slug(
+        &self,
+        metadata: &MetadataMap,
+        slug: &str,
+    ) -> Result<BlogPost, ApiError> {
+        let tenant = self.tenant(metadata).await?;
+        let viewer = self.viewer(metadata, &tenant).await?;
+        let post = self.repo.get_by_slug(tenant.id, slug).await?;
+        if !post_visible(&post, viewer.as_ref()) {
+            return Err(ApiError::NotFound(format!("No post with slug `{}`", slug)));
+        }
+        Ok(post)
+    }
+
+    async fn list_posts(
+        &self,
+        metadata: &MetadataMap,
+        request: proto::ListPostsRequest,
+    ) -> Result<proto::ListPostsResponse, ApiError> {
+        let tenant = self.tenant(metadata).await?;
+        let viewer = self.viewer(metadata, &tenant).await?;
+        let (page, per_page) = clamp_paging(request.page, request.per_page);
+        let tag = request.tag.as_deref().map(normalize_tag);
+        let filter = PostFilter {
+            tag: tag.as_deref(),
+            author: request.author.as_deref(),
+            status: request.status().into(),
+            tenant: tenant.id,
+            ..Default::default()
+        }
+        .viewed_by(viewer.as_ref());
+        let (sort, order) = self.profile.resolve_with(None, None);
+        let _permit = self.limiter.acquire().await?;
+        let posts = self.repo.list(filter, sort, order, per_page, (page - 1) * per_page).await?;
+        let total = self.repo.count(filter, CountMode::Exact).await?;
+        Ok(proto::ListPostsResponse {
+            posts: posts.into_iter().map(proto::Post::from).collect(),
+            page,
+            per_page,
+            total,
+        })
+    }
+
+    // As POST /blog does it, without the idempotency keys.
+    async fn create_post(
+        &self,
+        metadata: &MetadataMap,
+        request: proto::CreatePostRequest,
+    ) -> Result<BlogPost, ApiError> {
+        let user = self.writer(metadata).await?;
+        user.require_role(Role::Editor)?;
+        self.storage.check_writable()?;
+        let status = request.status().into();
+        let new_post = NewBlogPost {
+            title: request.title,
+            content: request.content,
+            tags: Some(request.tags),
+            version: None,
+            status,
+            publish_at: None,
+            unknown_fields: BTreeMap::new(),
+        };
+        new_post.validate()?;
+        self.quotas.check(&self.pool, &user, ContentKind::Post, 1).await?;
+        let text = new_post.moderated_text();
+        let flag = moderate(self.moderator.get_ref(), ContentKind::Post, &text).await?;
+        let post = self.repo.create(&new_post, &user).await?;
+        self.quotas.record(&self.pool, &user, ContentKind::Post, 1).await;
+        flag_for_review(&self.pool, user.tenant_id, ContentKind::Post, post.id, user.id, flag)
+            .await;
+        self.cache.invalidate(user.tenant_id, None).await;
+        self.feed.publish(PostEventKind::Created, user.tenant_id, post.id, Some(post.clone()));
+        Ok(post)
+    }
+
+    // As PUT /blog/{id} does it; the version stands in for If-Match.
+    async fn update_post(
+        &self,
+        metadata: &MetadataMap,
+        request: proto::UpdatePostRequest,
+    ) -> Result<BlogPost, ApiError> {
+        let user = self.writer(metadata).await?;
+        self.storage.check_writable()?;
+        let id = request.id;
+        let updated_post = NewBlogPost {
+            title: request.title,
+            content: request.content,
+            tags: request.tags.map(|tags| tags.names),
+            version: Some(request.version),
+            status: None,
+            publish_at: None,
+            unknown_fields: BTreeMap::new(),
+        };
+        updated_post.validate()?;
+        user.require_owner(self.repo.owner(user.tenant_id, id).await?)?;
+        let post = self.repo.update(user.tenant_id, id, &updated_post, None, user.id).await?;
+        self.cache.invalidate(user.tenant_id, Some(id)).await;
+        self.feed.publish(PostEventKind::Updated, user.tenant_id, id, Some(post.clone()));
+        Ok(post)
+    }
+
+    async fn delete_post(&self, metadata: &MetadataMap, id: i32) -> Result<(), ApiError> {
+        let user = self.writer(metadata).await?;
+        user.require_owner(self.repo.owner(user.tenant_id, id).await?)?;
+        self.repo.delete(user.tenant_id, id, None, user.id).await?;
+        self.cache.invalidate(user.tenant_id, Some(id)).await;
+        self.feed.publish(PostEventKind::Deleted, user.tenant_id, id, None);
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Posts for GrpcPosts {
+    async fn get_post(
+        &self,
+        request: Request<proto::GetPostRequest>,
+    ) -> Result<Response<proto::Post>, Status> {
+        let post = self.get_post(request.metadata(), request.get_ref().id).await?;
+        Ok(Response::new(post.into()))
+    }
+
+    async fn get_post_by_slug(
+        &self,
+        request: Request<proto::GetPostBySlugRequest>,
+    ) -> Result<Response<proto::Post>, Status> {
+        let post = self.get_post_by_slug(request.metadata(), &request.get_ref().slug).await?;
+        Ok(Response::new(post.into()))
+    }
+
+    async fn list_posts(
+        &self,
+        request: Request<proto::ListPostsRequest>,
+    ) -> Result<Response<proto::ListPostsResponse>, Status> {
+        let (metadata, _, request) = request.into_parts();
+        Ok(Response::new(self.list_posts(&metadata, request).await?))
+    }
+
+    async fn create_post(
+        &self,
+        request: Request<proto::CreatePostRequest>,
+    ) -> Result<Response<proto::Post>, Status> {
+        let (metadata, _, request) = request.into_parts();
+        Ok(Response::new(self.create_post(&metadata, request).await?.into()))
+    }
+
+    async fn update_post(
+        &self,
+        request: Request<proto::UpdatePostRequest>,
+    ) -> Result<Response<proto::Post>, Status> {
+        let (metadata, _, request) = request.into_parts();
+        Ok(Response::new(self.update_post(&metadata, request).await?.into()))
+    }
+
+    async fn delete_post(
+        &self,
+        request: Request<proto::DeletePostRequest>,
+    ) -> Result<Response<proto::DeletePostResponse>, Status> {
+        self.delete_post(request.metadata(), request.get_ref().id).await?;
+        Ok(Response::new(proto::DeletePostResponse {}))
+    }
+}
+
+impl From<BlogPost> for proto::Post {
+    fn from(post: BlogPost) -> Self {
+        let status = match post.status.as_str() {
+            "draft" => proto::PostStatus::Draft,
+            "archived" => proto::PostStatus::Archived,
+            _ => proto::PostStatus::Published,
+        };
+        proto::Post {
+            id: post.id,
+            uuid: post.uuid.to_string(),
+            title: post.title,
+            slug: post.slug,
+            content: post.content,
+            user_id: post.user_id,
+            author: post.author,
+            tags: post.tags,
+            version: post.version,
+            status: status.into(),
+            like_count: post.like_count,
+            view_count: post.view_count,
+            publish_at: post.publish_at.map(|at| at.to_rfc3339()),
+            created_at: post.created_at.to_rfc3339(),
+            updated_at: post.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+// Unspecified is no status: every one for a listing, published for a new
+// post.
+impl From<proto::PostStatus> for Option<PostStatus> {
+    fn from(status: proto::PostStatus) -> Self {
+        match status {
+            proto::PostStatus::Unspecified => None,
+            proto::PostStatus::Draft => Some(PostStatus::Draft),
+            proto::PostStatus::Published => Some(PostStatus::Published),
+            proto::PostStatus::Archived => Some(PostStatus::Archived),
+        }
+    }
+}
+
+// The closest gRPC code, with the REST API's error code in the
+// `x-error-code` metadata and, where HTTP sends Retry-After, `retry-after`.
+impl From<ApiError> for Status {
+    fn from(err: ApiError) -> Self {
+        let code = match &err {
+            ApiError::NotFound(_) => Code::NotFound,
+            ApiError::Unauthorized(_) => Code::Unauthenticated,
+            ApiError::Forbidden(_) => Code::PermissionDenied,
+            ApiError::Validation(_)
+            | ApiError::UnprocessableEntity(_)
+            | ApiError::BadRequest(_)
+            | ApiError::PayloadTooLarge(_)
+            | ApiError::BodyTooLarge(_) => Code::InvalidArgument,
+            ApiError::Conflict(_) => Code::Aborted,
+            ApiError::PreconditionFailed(_) => Code::FailedPrecondition,
+            ApiError::TooManyRequests(_)
+            | ApiError::QuotaExceeded(_)
+            | ApiError::InsufficientStorage(_) => Code::ResourceExhausted,
+            ApiError::ServiceUnavailable(_) | ApiError::DatabaseUnavailable(_) => Code::Unavailable,
+            ApiError::DatabaseError(_) => Code::Internal,
+        };
+        // Validation failures name their fields, as GraphQL errors do.
+        let message = match &err {
+            ApiError::Validation(failure) => failure.to_string(),
+            _ => err.detail(),
+        };
+        let mut status = Status::new(code, message);
+        let metadata = status.metadata_mut();
+        if let Ok(value) = err.code().as_str().parse() {
+            metadata.insert("x-error-code", value);
+        }
+        let retry_after = match &err {
+            ApiError::ServiceUnavailable(_) => Some(1),
+            ApiError::TooManyRequests(secs) | ApiError::DatabaseUnavailable(secs) => Some(*secs),
+            ApiError::QuotaExceeded(usage) => Some(usage.reset_secs),
+            _ => None,
+        };
+        if let Some(secs) = retry_after {
+            metadata.insert("retry-after", secs.into());
+        }
+        status
+    }
+}
+
+// Serves until `shutdown` resolves, then lets calls in flight finish.
+pub async fn serve_grpc(
+    state: &AppState,
+    incoming: TcpIncoming,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), String> {
+    tonic::transport::Server::builder()
+        .add_service(PostsServer::new(GrpcPosts::new(state)))
+        .serve_with_incoming_shutdown(incoming, shutdown)
+        .await
+        .map_err(|err| format!("gRPC server failed: {}", err))
+}
@@ -0,0 +1,2929 @@
+// Every HTTP handler, and the route tables that register them.
+
+use crate::*;
+
+// -------------------- Routes --------------------
+
+#[utoipa::path(
+    get,
+    path = "/",
+    responses((status = 200, description = "Greeting", body = String)),
+)]
+pub(crate) async fn index_page() -> &'static str {
+    "Hello Crud API"
+}
+
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 201, description = "Account created", body = User),
+        (status = 409, description = "Username is taken", body = Problem),
+        (status = 422, description = "Invalid username or password", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/users/register")]
+pub(crate) async fn register_user(
+    req: HttpRequest,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    new_user: web::Json<NewUser>,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    validate_new_user(&new_user)?;
+    let new_user = new_user.into_inner();
+    // Argon2 is deliberately slow; keep it off the async workers.
+    let password = new_user.password;
+    let hash = web::block(move || hash_password(&password))
+        .await
+        .map_err(|err| ApiError::DatabaseError(err.to_string()))??;
+    let email = new_user.email.as_deref();
+    let created = create_user(pool.get_ref(), tenant.id, &new_user.username, &hash, email).await;
+    let user = match created {
+        Err(ApiError::Conflict(_)) => {
+            return Err(ApiError::Conflict(format!(
+                "Username `{}` is already taken",
+                new_user.username
+            )))
+        }
+        other => other?,
+    };
+    Ok(HttpResponse::Created().location(&req, &format!("/authors/{}", user.id)).json(user))
+}
+
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 200, description = "Bearer token", body = TokenResponse),
+        (status = 401, description = "Invalid username or password", body = Problem),
+    ),
+)]
+#[post("/auth/login")]
+pub(crate) async fn login(
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    auth: web::Data<AuthConfig>,
+    body: web::Json<LoginRequest>,
+) -> Result<impl Responder, ApiError> {
+    let body = body.into_inner();
+    let user = get_user_by_username(pool.get_ref(), tenant.id, &body.username).await?;
+    let stored = user.as_ref().map(|user| user.password_hash.clone());
+    let verified = web::block(move || {
+        stored.is_some_and(|hash| verify_password(&body.password, &hash))
+    })
+    .await
+    .map_err(|err| ApiError::DatabaseError(err.to_string()))?;
+    let user = match user {
+        Some(user) if verified => user,
+        _ => return Err(ApiError::Unauthorized("Invalid username or password".to_string())),
+    };
+    let refresh_token = generate_refresh_token();
+    create_refresh_token(pool.get_ref(), user.id, &refresh_token, auth.refresh_ttl_secs).await?;
+    Ok(HttpResponse::Ok().json(auth.token_response(&user, refresh_token)?))
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "New access and refresh tokens", body = TokenResponse),
+        (status = 401, description = "Unknown, expired or already used refresh token",
+            body = Problem),
+    ),
+)]
+// The new access token carries the user's current role.
+#[post("/auth/refresh")]
+pub(crate) async fn refresh_session(
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    auth: web::Data<AuthConfig>,
+    body: web::Json<RefreshRequest>,
+) -> Result<impl Responder, ApiError> {
+    let refresh_token = generate_refresh_token();
+    let ttl = auth.refresh_ttl_secs;
+    let user =
+        rotate_refresh_token(&pool, tenant.id, &body.refresh_token, &refresh_token, ttl).await?;
+    Ok(HttpResponse::Ok().json(auth.token_response(&user, refresh_token)?))
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = RefreshRequest,
+    responses(
+        (status = 204, description = "The refresh token no longer works"),
+    ),
+)]
+// Access tokens already issued stay valid until they expire.
+#[post("/auth/logout")]
+pub(crate) async fn logout(
+    pool: web::Data<PgPool>,
+    body: web::Json<RefreshRequest>,
+) -> Result<impl Responder, ApiError> {
+    revoke_refresh_token(&pool, &body.refresh_token).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    tag = "users",
+    params(("provider" = String, Path, description = "`github` or `google`")),
+    responses(
+        (status = 302, description = "To the provider's login page"),
+        (status = 404, description = "The provider isn't set up", body = Problem),
+    ),
+)]
+// With a bearer token or API key, the provider account gets linked to
+// that user instead.
+#[get("/auth/{provider}/login")]
+pub(crate) async fn oauth_login(
+    req: HttpRequest,
+    tenant: Tenant,
+    user: Option<AuthUser>,
+    auth: web::Data<AuthConfig>,
+    oauth: web::Data<OAuthConfig>,
+    path: web::Path<String>,
+) -> Result<impl Responder, ApiError> {
+    let provider = oauth.provider(&path)?;
+    let callback = format!("/auth/{}/callback", provider.name());
+    let redirect_uri = resource_url(&req, &callback);
+    let link = user.map(|user| user.id);
+    let (url, nonce) = oauth.authorize_url(&auth, provider, &redirect_uri, tenant.id, link)?;
+    let cookie_path = format!("{}{}", req.path().trim_end_matches("/login"), "/callback");
+    Ok(HttpResponse::Found()
+        .insert_header(("Location", url))
+        .insert_header(("Cache-Control", "no-store"))
+        .cookie(oauth_cookie(&req, cookie_path, Some(nonce)))
+        .finish())
+}
+
+#[utoipa::path(
+    tag = "users",
+    params(
+        ("provider" = String, Path, description = "`github` or `google`"),
+        OAuthCallbackQuery,
+    ),
+    responses(
+        (status = 200, description = "Bearer token", body = TokenResponse),
+        (status = 400, description = "Missing or invalid state or code", body = Problem),
+        (status = 401, description = "The login was declined or the code rejected",
+            body = Problem),
+        (status = 404, description = "The provider isn't set up", body = Problem),
+        (status = 409, description = "The provider account is linked to another user",
+            body = Problem),
+        (status = 503, description = "The provider could not be reached", body = Problem),
+    ),
+)]
+// Where the provider sends the browser back to. Logs in as the user linked
+// to the provider account, linking or creating one first if need be.
+#[get("/auth/{provider}/callback")]
+pub(crate) async fn oauth_callback(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    auth: web::Data<AuthConfig>,
+    oauth: web::Data<OAuthConfig>,
+    path: web::Path<String>,
+    query: web::Query<OAuthCallbackQuery>,
+) -> Result<impl Responder, ApiError> {
+    let provider = oauth.provider(&path)?;
+    if let Some(error) = &query.error {
+        return Err(ApiError::Unauthorized(format!(
+            "The {} login was not completed: {}",
+            provider.name(),
+            error
+        )));
+    }
+    let nonce = req.cookie(OAUTH_COOKIE).map(|cookie| cookie.value().to_string());
+    let state = oauth.check_state(&auth, provider, query.state.as_deref(), nonce.as_deref())?;
+    let code = query
+        .code
+        .as_deref()
+        .ok_or_else(|| ApiError::BadRequest("Missing `code`".to_string()))?;
+    storage.check_writable()?;
+    let callback = format!("/auth/{}/callback", provider.name());
+    let account = oauth.account(provider, code, &resource_url(&req, &callback)).await?;
+    let user = oauth_user(&pool, state.tenant, provider.name(), &account, state.link).await?;
+    let refresh_token = generate_refresh_token();
+    create_refresh_token(pool.get_ref(), user.id, &refresh_token, auth.refresh_ttl_secs).await?;
+    Ok(HttpResponse::Ok()
+        .cookie(oauth_cookie(&req, req.path().to_string(), None))
+        .json(auth.token_response(&user, refresh_token)?))
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = RoleUpdate,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The user with the new role", body = User),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+        (status = 404, description = "No such user", body = Problem),
+    ),
+)]
+// Takes effect the next time the user logs in; tokens carry the role.
+#[put("/users/{id}/role")]
+pub(crate) async fn update_user_role(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    path: web::Path<i32>,
+    body: web::Json<RoleUpdate>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    let id = path.into_inner();
+    let mut tx = pool.begin().await?;
+    let updated = set_user_role(&mut tx, user.tenant_id, id, body.role, user.id).await?;
+    tx.commit().await?;
+    Ok(HttpResponse::Ok().json(updated))
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = UserEmail,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The caller's account", body = User),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 422, description = "Not an email address", body = Problem),
+    ),
+)]
+#[put("/users/me/email")]
+pub(crate) async fn update_own_email(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    body: web::Json<UserEmail>,
+) -> Result<impl Responder, ApiError> {
+    if let Some(email) = &body.email {
+        validate_email(email)?;
+    }
+    let updated = set_user_email(&pool, user.id, body.email.as_deref()).await?;
+    Ok(HttpResponse::Ok().json(updated))
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = UserProfile,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The caller's account", body = User),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 422, description = "Too long a name or bio, or not an http(s) URL",
+            body = Problem),
+    ),
+)]
+#[put("/users/me/profile")]
+pub(crate) async fn update_own_profile(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    body: ValidatedJson<UserProfile>,
+) -> Result<impl Responder, ApiError> {
+    let updated = set_user_profile(&pool, user.id, &body).await?;
+    Ok(HttpResponse::Ok().json(updated))
+}
+
+#[utoipa::path(
+    tag = "authors",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "Everyone with a published post, by username",
+            body = Page<Author>),
+    ),
+)]
+#[get("/authors")]
+pub(crate) async fn get_authors(
+    req: HttpRequest,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    query: web::Query<PageQuery>,
+) -> Result<impl Responder, ApiError> {
+    let (page, per_page) = clamp_paging(query.page, query.per_page);
+    let authors = list_authors(&pool, tenant.id, per_page, (page - 1) * per_page).await?;
+    let total = count_authors(&pool, tenant.id).await?;
+    let authors = Page::new(authors, page, per_page, total, false);
+    Ok(HttpResponse::Ok().page_links(&req, &authors).json(authors))
+}
+
+#[utoipa::path(
+    tag = "authors",
+    responses(
+        (status = 200, description = "The account's public profile", body = Author),
+        (status = 404, description = "No such account", body = Problem),
+    ),
+)]
+#[get("/authors/{id}")]
+pub(crate) async fn get_author_profile(
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    path: web::Path<i32>,
+) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(get_author(&pool, tenant.id, path.into_inner()).await?))
+}
+
+#[utoipa::path(
+    tag = "authors",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "The author's published posts, newest first",
+            content((Page<BlogPost> = "application/json"), (Page<BlogPost> = "application/xml"))),
+        (status = 404, description = "No such account", body = Problem),
+    ),
+)]
+#[get("/authors/{id}/posts")]
+pub(crate) async fn get_author_posts(
+    req: HttpRequest,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    repo: web::Data<dyn PostRepository>,
+    path: web::Path<i32>,
+    query: web::Query<PageQuery>,
+) -> Result<impl Responder, ApiError> {
+    let author = get_author(&pool, tenant.id, path.into_inner()).await?;
+    let (page, per_page) = clamp_paging(query.page, query.per_page);
+    let filter = PostFilter {
+        author: Some(&author.username),
+        status: Some(PostStatus::Published),
+        tenant: tenant.id,
+        ..Default::default()
+    };
+    let (sort, order) = (SortColumn::CreatedAt, SortOrder::Desc);
+    let posts = repo.list(filter, sort, order, per_page, (page - 1) * per_page).await?;
+    let total = repo.count(filter, CountMode::Exact).await?;
+    let posts = Page::new(posts, page, per_page, total, false);
+    Ok(HttpResponse::Ok().page_links(&req, &posts).negotiated(&req, &posts))
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = NewApiKey,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 201, description = "The new key, shown only this once", body = CreatedApiKey),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+        (status = 404, description = "No such user", body = Problem),
+        (status = 422, description = "Empty name", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/api-keys")]
+pub(crate) async fn create_user_api_key(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    body: web::Json<NewApiKey>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    storage.check_writable()?;
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::UnprocessableEntity("API key name must not be empty".to_string()));
+    }
+    let key = generate_api_key();
+    let owner = body.user_id.unwrap_or(user.id);
+    let api_key = create_api_key(&pool, user.tenant_id, owner, name, body.scope, &key).await?;
+    let location = format!("/api-keys/{}", api_key.id);
+    Ok(HttpResponse::Created().location(&req, &location).json(CreatedApiKey { key, api_key }))
+}
+
+#[utoipa::path(
+    tag = "users",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Every key, newest first, without the keys themselves",
+            body = Vec<ApiKey>),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+    ),
+)]
+#[get("/api-keys")]
+pub(crate) async fn get_api_keys(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    Ok(HttpResponse::Ok().json(list_api_keys(&pool, user.tenant_id).await?))
+}
+
+#[utoipa::path(
+    tag = "users",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The revoked key", body = ApiKey),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+        (status = 404, description = "No such key, or already revoked", body = Problem),
+    ),
+)]
+#[delete("/api-keys/{id}")]
+pub(crate) async fn delete_api_key(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    path: web::Path<i32>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    Ok(HttpResponse::Ok().json(revoke_api_key(&pool, user.tenant_id, path.into_inner()).await?))
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    request_body = NewWebhook,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 201, description = "The webhook, with its secret shown only this once",
+            body = CreatedWebhook),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is a viewer", body = Problem),
+        (status = 422, description = "Bad URL or unknown event", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+// Fires for the caller's posts; an admin's fire for everyone's.
+#[post("/webhooks")]
+pub(crate) async fn create_user_webhook(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    body: web::Json<NewWebhook>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Editor)?;
+    storage.check_writable()?;
+    let events = validate_webhook(&body)?;
+    let secret = generate_webhook_secret();
+    let webhook = create_webhook(&pool, user.id, &body.url, &secret, &events).await?;
+    let location = format!("/webhooks/{}", webhook.id);
+    Ok(HttpResponse::Created().location(&req, &location).json(CreatedWebhook { secret, webhook }))
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The caller's webhooks, without their secrets",
+            body = Vec<Webhook>),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+    ),
+)]
+#[get("/webhooks")]
+pub(crate) async fn get_webhooks(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(list_webhooks(&pool, user.id).await?))
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The deleted webhook", body = Webhook),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the caller's webhook", body = Problem),
+        (status = 404, description = "No such webhook", body = Problem),
+    ),
+)]
+#[delete("/webhooks/{id}")]
+pub(crate) async fn delete_user_webhook(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    path: web::Path<i32>,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    user.require_owner(webhook_owner(&pool, user.tenant_id, id).await?)?;
+    Ok(HttpResponse::Ok().json(delete_webhook(&pool, id).await?))
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    params(DeliveriesQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Deliveries, newest first", body = Page<WebhookDelivery>),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the caller's webhook", body = Problem),
+        (status = 404, description = "No such webhook", body = Problem),
+    ),
+)]
+#[get("/webhooks/{id}/deliveries")]
+pub(crate) async fn get_webhook_deliveries(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    path: web::Path<i32>,
+    query: web::Query<DeliveriesQuery>,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    user.require_owner(webhook_owner(&pool, user.tenant_id, id).await?)?;
+    let (page, per_page) = clamp_paging(query.page, query.per_page);
+    let (deliveries, total) = list_deliveries(&pool, id, per_page, (page - 1) * per_page).await?;
+    let deliveries = Page::new(deliveries, page, per_page, total, false);
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Total-Count", total.to_string()))
+        .page_links(&req, &deliveries)
+        .json(deliveries))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    request_body = NewBlogPost,
+    params(
+        ("Idempotency-Key" = Option<String>, Header,
+            description = "Sending the same key again replays the first response"),
+    ),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 201, description = "The created post, or the replayed one", body = BlogPost),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not allowed for this role", body = Problem),
+        (status = 409, description = "Same Idempotency-Key still in progress", body = Problem),
+        (status = 422, description = "Invalid fields, tags or unknown fields, rejected by \
+            moderation, or an Idempotency-Key reused for a different body", body = Problem),
+        (status = 429, description = "Write quota used up", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/blog")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn create_blogpost(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    repo: web::Data<dyn PostRepository>,
+    storage: web::Data<StorageGuard>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    moderator: web::Data<dyn Moderator>,
+    quotas: web::Data<WriteQuotas>,
+    key: IdempotencyKey,
+    new_post: ValidatedJson<NewBlogPost>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Editor)?;
+    storage.check_writable()?;
+    check_unknown_fields(&new_post.unknown_fields)?;
+    let request = match start_idempotent(&pool, user.id, key, &*new_post).await? {
+        Idempotent::Replay(res) => return Ok(res),
+        Idempotent::Proceed(request) => request,
+    };
+    let text = new_post.moderated_text();
+    // Replays above don't count against the quota.
+    let (created, flag, usage) = match quotas.check(&pool, &user, ContentKind::Post, 1).await {
+        Ok(usage) => match moderate(moderator.get_ref(), ContentKind::Post, &text).await {
+            Ok(flag) => (repo.create(&new_post, &user).await, flag, usage),
+            Err(err) => (Err(err), None, usage),
+        },
+        Err(err) => (Err(err), None, None),
+    };
+    let post = request.finish(&pool, StatusCode::CREATED, created).await?;
+    quotas.record(&pool, &user, ContentKind::Post, 1).await;
+    flag_for_review(&pool, user.tenant_id, ContentKind::Post, post.id, user.id, flag).await;
+    cache.invalidate(user.tenant_id, None).await;
+    feed.publish(PostEventKind::Created, user.tenant_id, post.id, Some(post.clone()));
+    Ok(HttpResponse::Created()
+        .location(&req, &post_path(&req, &post))
+        .quota(usage)
+        .json(post))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(BulkQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Created posts and per-item failures", body = BulkResult),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not allowed for this role", body = Problem),
+        (status = 413, description = "Body over BULK_JSON_LIMIT_BYTES", body = Problem),
+        (status = 422, description = "An item was rejected (all_or_nothing)", body = Problem),
+        (status = 429, description = "Write quota used up", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/blog/bulk", wrap = "from_fn(bulk_json_limit)")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn create_blogposts_bulk(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    moderator: web::Data<dyn Moderator>,
+    quotas: web::Data<WriteQuotas>,
+    query: web::Query<BulkQuery>,
+    new_posts: web::Json<Vec<NewBlogPost>>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Editor)?;
+    storage.check_writable()?;
+    let usage = quotas.check(&pool, &user, ContentKind::Post, new_posts.len()).await?;
+    let moderator = moderator.get_ref();
+    let result =
+        create_posts_bulk(&pool, moderator, user.tenant_id, &new_posts, query.mode, user.id)
+            .await?;
+    quotas.record(&pool, &user, ContentKind::Post, result.created.len()).await;
+    cache.invalidate(user.tenant_id, None).await;
+    publish_created(&feed, user.tenant_id, &result.created);
+    Ok(HttpResponse::Ok().quota(usage).json(result))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(BulkQuery),
+    request_body = Vec<i32>,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Trashed ids and per-item failures", body = BulkDeleteResult),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "A post isn't the caller's (all_or_nothing)",
+            body = Problem),
+        (status = 404, description = "An id was not a live post (all_or_nothing)", body = Problem),
+    ),
+)]
+// Registered ahead of /blog/{id} so "bulk" isn't taken for an id.
+#[delete("/blog/bulk")]
+pub(crate) async fn delete_blogposts_bulk(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    query: web::Query<BulkQuery>,
+    ids: web::Json<Vec<i32>>,
+) -> Result<impl Responder, ApiError> {
+    let result = delete_posts_bulk(&pool, &ids, query.mode, &user).await?;
+    for &id in &result.deleted {
+        cache.invalidate(user.tenant_id, Some(id)).await;
+        feed.publish(PostEventKind::Deleted, user.tenant_id, id, None);
+    }
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    request_body = Vec<i32>,
+    responses(
+        (status = 200, description = "The posts found, by id, and the ids that weren't",
+            body = BatchGetResult),
+        (status = 422, description = "Too many ids", body = Problem),
+    ),
+)]
+// Registered ahead of /blog/{id} so "batch-get" isn't taken for an id.
+#[post("/blog/batch-get")]
+pub(crate) async fn get_blogposts_batch(
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    repo: web::Data<dyn PostRepository>,
+    ids: web::Json<Vec<i32>>,
+) -> Result<impl Responder, ApiError> {
+    if ids.len() > MAX_BATCH_IDS {
+        return Err(ApiError::UnprocessableEntity(format!(
+            "At most {} ids per batch",
+            MAX_BATCH_IDS
+        )));
+    }
+    let mut result = BatchGetResult::default();
+    for post in repo.get_many(tenant.id, &ids).await? {
+        if post_visible(&post, viewer.as_ref()) {
+            result.posts.insert(post.id, post);
+        }
+    }
+    for &id in ids.iter() {
+        if !result.posts.contains_key(&id) && !result.not_found.contains(&id) {
+            result.not_found.push(id);
+        }
+    }
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(BySlugsQuery),
+    responses(
+        (status = 200, description = "The posts found, in the order of `slugs`",
+            body = Vec<BlogPost>),
+        (status = 400, description = "No slugs", body = Problem),
+        (status = 422, description = "Too many slugs", body = Problem),
+    ),
+)]
+// Registered ahead of /blog/{id} so "by-slugs" isn't taken for an id.
+#[get("/blog/by-slugs")]
+pub(crate) async fn get_blogposts_by_slugs(
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    repo: web::Data<dyn PostRepository>,
+    query: web::Query<BySlugsQuery>,
+) -> Result<impl Responder, ApiError> {
+    let slugs = query.slugs();
+    if slugs.is_empty() {
+        return Err(ApiError::BadRequest("`slugs` must name at least one slug".to_string()));
+    }
+    if slugs.len() > MAX_BATCH_SLUGS {
+        return Err(ApiError::UnprocessableEntity(format!(
+            "At most {} slugs per batch",
+            MAX_BATCH_SLUGS
+        )));
+    }
+    let mut found = repo.get_many_by_slug(tenant.id, &slugs).await?;
+    found.retain(|post| post_visible(post, viewer.as_ref()));
+    let posts: Vec<BlogPost> = slugs
+        .iter()
+        .filter_map(|slug| found.iter().position(|post| &post.slug == slug))
+        .map(|index| found[index].clone())
+        .collect();
+    Ok(HttpResponse::Ok().json(posts))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    request_body = NewBlogPost,
+    responses(
+        (status = 200, description = "Rendered preview; nothing is stored", body = PostPreview),
+        (status = 422, description = "Invalid or unknown fields", body = Problem),
+    ),
+)]
+#[post("/blog/preview")]
+pub(crate) async fn preview_blogpost(
+    new_post: ValidatedJson<NewBlogPost>,
+) -> Result<impl Responder, ApiError> {
+    check_unknown_fields(&new_post.unknown_fields)?;
+    Ok(HttpResponse::Ok().json(preview_post(&new_post)))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(ImportQuery),
+    request_body(content((Vec<NewBlogPost> = "application/json"), (String = "text/csv")),
+        description = "A JSON array of posts, or CSV with a header row of field names"),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Import summary", body = ImportSummary),
+        (status = 202, description = "Queued import job (?async=true)", body = Job),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not allowed for this role", body = Problem),
+        (status = 413, description = "Body over BULK_JSON_LIMIT_BYTES", body = Problem),
+        (status = 422, description = "A bad CSV, or a row was rejected (all_or_nothing)",
+            body = Problem),
+        (status = 429, description = "Write quota used up", body = Problem),
+        (status = 503, description = "Too many heavy requests", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/blog/import", wrap = "from_fn(bulk_json_limit)")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn import_blogposts(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    limiter: web::Data<HeavyQueryLimiter>,
+    jobs: web::Data<JobQueue>,
+    moderator: web::Data<dyn Moderator>,
+    quotas: web::Data<WriteQuotas>,
+    query: web::Query<ImportQuery>,
+    imported: ImportedPosts,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Editor)?;
+    storage.check_writable()?;
+    imported.check(query.mode)?;
+    let new_posts = &imported.posts;
+    let usage = quotas.check(&pool, &user, ContentKind::Post, new_posts.len()).await?;
+    if query.run_async {
+        // The worker takes a heavy query slot when it runs the job. Every
+        // post counts against the quota now, whether or not it is created.
+        let payload = ImportPayload::new(&imported, query.mode, &user);
+        let job = jobs.enqueue(user.tenant_id, "import", &payload).await?;
+        quotas.record(&pool, &user, ContentKind::Post, new_posts.len()).await;
+        return Ok(HttpResponse::Accepted().quota(usage).json(job));
+    }
+
+    let _permit = limiter.acquire().await?;
+    let moderator = moderator.get_ref();
+    let result =
+        create_posts_bulk(&pool, moderator, user.tenant_id, new_posts, query.mode, user.id)
+            .await?;
+    quotas.record(&pool, &user, ContentKind::Post, result.created.len()).await;
+    cache.invalidate(user.tenant_id, None).await;
+    publish_created(&feed, user.tenant_id, &result.created);
+    Ok(HttpResponse::Ok().quota(usage).json(ImportSummary::from(imported.merge(result))))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "A page of posts; with ?excerpt=true each also has \
+            an `excerpt`. With `after` or `limit` the page is a CursorPage instead",
+            content((Page<BlogPost> = "application/json"), (Page<BlogPost> = "application/xml"))),
+        (status = 400, description = "Keyset parameters mixed with page or sort ones, or an \
+            invalid cursor", body = Problem),
+        (status = 422, description = "`author` or `tag` longer than QUERY_LIMIT_CHARS",
+            body = Problem),
+        (status = 503, description = "Too many heavy requests", body = Problem),
+    ),
+)]
+#[get("/blog")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn get_blogposts(
+    req: HttpRequest,
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    repo: web::Data<dyn PostRepository>,
+    cache: web::Data<PostCache>,
+    profile: web::Data<ListProfile>,
+    limiter: web::Data<HeavyQueryLimiter>,
+    limits: web::Data<LimitsConfig>,
+    query: web::Query<ListQuery>,
+) -> Result<impl Responder, ApiError> {
+    limits.check_query("author", query.author.as_deref())?;
+    limits.check_query("tag", query.tag.as_deref())?;
+    if let Some((after, limit)) = query.keyset()? {
+        let tag = query.tag.as_deref().map(normalize_tag);
+        let filter = PostFilter {
+            tag: tag.as_deref(),
+            author: query.author.as_deref(),
+            status: query.status,
+            created_from: query.created_from,
+            created_to: query.created_to,
+            updated_from: query.updated_from,
+            updated_to: query.updated_to,
+            tenant: tenant.id,
+            after,
+            ..Default::default()
+        }
+        .viewed_by(viewer.as_ref());
+        let page = keyset_page(repo.get_ref(), &limiter, filter, limit).await?;
+        let mut response = HttpResponse::Ok();
+        response.cursor_links(&req, &page);
+        if query.excerpt {
+            let page = page.map(|post| PostWithExcerpt {
+                post: post.clone(),
+                excerpt: render_excerpt(&post.content),
+            });
+            return Ok(response.negotiated(&req, &page));
+        }
+        return Ok(response.negotiated(&req, &page));
+    }
+
+    let (sort, order) = profile.resolve(&query);
+    let (page, per_page) = (query.page(), query.per_page());
+    let count_mode = query.count.unwrap_or(CountMode::Exact);
+
+    // Lists with someone's drafts in them aren't shared through the cache.
+    let cached = match viewer {
+        None => cache.get_list(tenant.id, req.query_string()).await,
+        Some(_) => None,
+    };
+    let posts = match cached {
+        Some(posts) => posts,
+        None => {
+            let _permit = limiter.acquire().await?;
+            let offset = (page - 1) * per_page;
+            let tag = query.tag.as_deref().map(normalize_tag);
+            let filter = PostFilter {
+                tag: tag.as_deref(),
+                author: query.author.as_deref(),
+                status: query.status,
+                created_from: query.created_from,
+                created_to: query.created_to,
+                updated_from: query.updated_from,
+                updated_to: query.updated_to,
+                tenant: tenant.id,
+                ..Default::default()
+            }
+            .viewed_by(viewer.as_ref());
+            let data = repo.list(filter, sort, order, per_page, offset).await?;
+            let total = repo.count(filter, count_mode).await?;
+            let estimated = count_mode == CountMode::Estimate;
+            let posts = Arc::new(Page::new(data, page, per_page, total, estimated));
+            if viewer.is_none() {
+                cache.put_list(tenant.id, req.query_string(), posts.clone()).await;
+            }
+            posts
+        }
+    };
+
+    let header = match count_mode {
+        CountMode::Exact => "X-Total-Count",
+        CountMode::Estimate => "X-Estimated-Count",
+    };
+    let mut response = HttpResponse::Ok();
+    response.insert_header((header, posts.total.to_string())).page_links(&req, &posts);
+    // Rendered per request; the cache holds the posts only.
+    if query.excerpt {
+        let posts = posts.map(|post| PostWithExcerpt {
+            post: post.clone(),
+            excerpt: render_excerpt(&post.content),
+        });
+        return Ok(response.negotiated(&req, &posts));
+    }
+    Ok(response.negotiated(&req, &*posts))
+}
+
+// One more post than asked for tells whether there is a next page, with
+// no count. Not cached: the cache holds numbered pages.
+async fn keyset_page(
+    repo: &dyn PostRepository,
+    limiter: &HeavyQueryLimiter,
+    filter: PostFilter<'_>,
+    limit: i64,
+) -> Result<CursorPage<BlogPost>, ApiError> {
+    let _permit = limiter.acquire().await?;
+    let (sort, order) = (SortColumn::CreatedAt, SortOrder::Desc);
+    let mut data = repo.list(filter, sort, order, limit + 1, 0).await?;
+    let next_cursor = if data.len() as i64 > limit {
+        data.truncate(limit as usize);
+        data.last().map(|post| PostCursor::of(post).encode())
+    } else {
+        None
+    };
+    Ok(CursorPage {
+        data,
+        limit,
+        next_cursor,
+    })
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PageQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Deleted posts, most recent first",
+            body = Page<TrashedPost>),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+    ),
+)]
+// Registered ahead of /blog/{id} like /blog/search.
+#[get("/blog/trash")]
+pub(crate) async fn get_trash(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    query: web::Query<PageQuery>,
+) -> Result<impl Responder, ApiError> {
+    let (page, per_page) = clamp_paging(query.page, query.per_page);
+    let posts = list_trash(&pool, user.tenant_id, per_page, (page - 1) * per_page).await?;
+    let total = count_trash(&pool, user.tenant_id).await?;
+    let posts = Page::new(posts, page, per_page, total, false);
+    Ok(HttpResponse::Ok().page_links(&req, &posts).json(posts))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostPath),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The post, now published", body = BlogPost),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such post", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/blog/{id}/publish")]
+pub(crate) async fn publish_blogpost(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    path: PostId,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    let id = path.into_inner();
+    user.require_owner(post_owner(&pool, user.tenant_id, id).await?)?;
+    let mut tx = pool.begin().await?;
+    let post = set_post_status(&mut tx, user.tenant_id, id, PostStatus::Published, user.id).await?;
+    tx.commit().await?;
+    cache.invalidate(user.tenant_id, Some(id)).await;
+    // To subscribers the post appears only now.
+    feed.publish(PostEventKind::Created, user.tenant_id, id, Some(post.clone()));
+    Ok(HttpResponse::Ok().json(post))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostPath),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The post, back to a draft", body = BlogPost),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such post", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/blog/{id}/unpublish")]
+pub(crate) async fn unpublish_blogpost(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    path: PostId,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    let id = path.into_inner();
+    user.require_owner(post_owner(&pool, user.tenant_id, id).await?)?;
+    let before = get_post(pool.get_ref(), user.tenant_id, id).await;
+    let mut tx = pool.begin().await?;
+    let post = set_post_status(&mut tx, user.tenant_id, id, PostStatus::Draft, user.id).await?;
+    tx.commit().await?;
+    cache.invalidate(user.tenant_id, Some(id)).await;
+    let unpublishing = before.is_ok_and(|post| post.status == PostStatus::Published.as_str());
+    feed.publish_update(user.tenant_id, post.clone(), unpublishing);
+    Ok(HttpResponse::Ok().json(post))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostPath),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The restored post", body = BlogPost),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "Post is not in the trash", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/blog/{id}/restore")]
+pub(crate) async fn restore_blogpost(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    path: PostId,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    let id = path.into_inner();
+    user.require_owner(post_owner(&pool, user.tenant_id, id).await?)?;
+    let mut tx = pool.begin().await?;
+    let post = restore_post(&mut tx, user.tenant_id, id, user.id).await?;
+    tx.commit().await?;
+    cache.invalidate(user.tenant_id, Some(id)).await;
+    // To subscribers the post simply reappears.
+    feed.publish(PostEventKind::Created, user.tenant_id, id, Some(post.clone()));
+    Ok(HttpResponse::Ok().json(post))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostPath),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 204, description = "Post permanently removed"),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "Post is not in the trash", body = Problem),
+    ),
+)]
+#[delete("/blog/{id}/purge")]
+pub(crate) async fn purge_blogpost(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    uploads: web::Data<Uploads>,
+    path: PostId,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    user.require_owner(post_owner(&pool, user.tenant_id, id).await?)?;
+    let attachments = list_attachments(&pool, id).await?;
+    let mut tx = pool.begin().await?;
+    purge_post(&mut tx, user.tenant_id, id, user.id).await?;
+    tx.commit().await?;
+    // The rows went with the post; the files have to be removed by hand.
+    for attachment in attachments {
+        if let Err(err) = uploads.store.delete(&attachment.storage_key).await {
+            tracing::warn!("Cannot remove image {} of post {}: {}", attachment.id, id, err);
+        }
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostPath, PageQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Earlier versions of the post, newest first, each with \
+            what the update that replaced it changed", body = Page<RevisionSummary>),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such post", body = Problem),
+    ),
+)]
+#[get("/blog/{id}/revisions")]
+pub(crate) async fn get_blogpost_revisions(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    path: PostId,
+    query: web::Query<PageQuery>,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    user.require_owner(post_owner(&pool, user.tenant_id, id).await?)?;
+    let (page, per_page) = clamp_paging(query.page, query.per_page);
+    let rows = list_revisions(&pool, user.tenant_id, id, per_page, (page - 1) * per_page).await?;
+    let revisions: Vec<_> = rows.into_iter().map(RevisionSummary::from).collect();
+    let total = count_revisions(&pool, user.tenant_id, id).await?;
+    let revisions = Page::new(revisions, page, per_page, total, false);
+    Ok(HttpResponse::Ok().page_links(&req, &revisions).json(revisions))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The post as it was at that version", body = PostRevision),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such post or revision", body = Problem),
+    ),
+)]
+#[get("/blog/{id}/revisions/{rev}")]
+pub(crate) async fn get_blogpost_revision(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    post: PostId,
+    path: web::Path<(String, i32)>,
+) -> Result<impl Responder, ApiError> {
+    let (id, rev) = (post.into_inner(), path.into_inner().1);
+    user.require_owner(post_owner(&pool, user.tenant_id, id).await?)?;
+    Ok(HttpResponse::Ok().json(get_revision(pool.get_ref(), user.tenant_id, id, rev).await?))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The post with the revision's title, content and tags, as \
+            a new version", body = BlogPost),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such post or revision, or the post is in the trash",
+            body = Problem),
+        (status = 409, description = "The post was updated at the same time", body = Problem),
+        (status = 412, description = "If-Match doesn't match the current ETag", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+// An update like any other, so what the post was before is kept as a
+// revision too. Status and schedule stay as they are.
+#[post("/blog/{id}/revisions/{rev}/restore")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn restore_blogpost_revision(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    post: PostId,
+    path: web::Path<(String, i32)>,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    let (id, rev) = (post.into_inner(), path.into_inner().1);
+    user.require_owner(post_owner(&pool, user.tenant_id, id).await?)?;
+    let mut tx = pool.begin().await?;
+    let revision = get_revision(&mut *tx, user.tenant_id, id, rev).await?;
+    let current = get_post(&mut *tx, user.tenant_id, id).await?;
+    let restored = NewBlogPost {
+        title: revision.title,
+        content: revision.content,
+        tags: Some(revision.tags),
+        version: Some(current.version),
+        status: None,
+        publish_at: None,
+        unknown_fields: BTreeMap::new(),
+    };
+    let if_match = if_match(&req);
+    let post = update_post(&mut tx, user.tenant_id, id, &restored, if_match.as_ref(), user.id)
+        .await?;
+    tx.commit().await?;
+    cache.invalidate(user.tenant_id, Some(id)).await;
+    feed.publish(PostEventKind::Updated, user.tenant_id, id, Some(post.clone()));
+    Ok(HttpResponse::Ok()
+        .insert_header(("ETag", post_etag(&post).to_string()))
+        .json(post))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Every post, streamed as CSV or NDJSON",
+            content((String = "text/csv"), (String = "application/x-ndjson"))),
+        (status = 400, description = "Missing or unknown format", body = Problem),
+        (status = 503, description = "Too many heavy requests", body = Problem),
+    ),
+)]
+// Registered ahead of /blog/{id} like /blog/search.
+#[get("/blog/export")]
+pub(crate) async fn export_blogposts(
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    limiter: web::Data<HeavyQueryLimiter>,
+    query: web::Query<ExportQuery>,
+) -> Result<impl Responder, ApiError> {
+    // Held by the task for as long as rows are being streamed.
+    let permit = limiter.acquire().await?;
+    let format = query.format;
+    let pool = pool.get_ref().clone();
+    let (sender, body) = row_channel();
+
+    actix_web::rt::spawn(async move {
+        let _permit = permit;
+        if let Some(header) = format.header()
+            && sender.send(Ok(web::Bytes::from(header))).await.is_err()
+        {
+            return;
+        }
+        let posts = stream_all_posts(&pool, tenant.id);
+        send_rows(&sender, posts, "Export", |post| format.encode(post)).await;
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"posts.{}\"", format.extension()),
+        ))
+        .streaming(body))
+}
+
+type RowSender = mpsc::Sender<Result<web::Bytes, actix_web::Error>>;
+
+// A response body fed by a task reading rows. Bounded, so a slow client
+// slows the query down instead of rows piling up in memory.
+fn row_channel() -> (RowSender, impl Stream<Item = Result<web::Bytes, actix_web::Error>>) {
+    let (sender, receiver) = mpsc::channel(16);
+    let body = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    (sender, body)
+}
+
+// Sends each post as `encode` writes it until the rows run out or the
+// client goes away.
+async fn send_rows(
+    sender: &RowSender,
+    posts: impl Stream<Item = Result<BlogPost, sqlx::Error>>,
+    what: &str,
+    encode: impl Fn(&BlogPost) -> String,
+) {
+    let mut posts = std::pin::pin!(posts);
+    while let Some(post) = posts.next().await {
+        let chunk = match post {
+            Ok(post) => Ok(web::Bytes::from(encode(&post))),
+            Err(err) => {
+                // The status is already sent; cutting the body short is
+                // all that's left to signal the failure.
+                tracing::error!("{} failed part way: {}", what, err);
+                Err(actix_web::error::ErrorInternalServerError(err))
+            }
+        };
+        let failed = chunk.is_err();
+        // A failed send means the client went away.
+        if sender.send(chunk).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(StreamQuery),
+    responses(
+        (status = 200, description = "Every matching post, one JSON object per line",
+            content((String = "application/x-ndjson"))),
+        (status = 422, description = "`author` or `tag` longer than QUERY_LIMIT_CHARS",
+            body = Problem),
+        (status = 503, description = "Too many heavy requests", body = Problem),
+    ),
+)]
+// Registered ahead of /blog/{id} like /blog/export.
+#[get("/blog/stream")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn stream_blogposts(
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    pool: web::Data<PgPool>,
+    limiter: web::Data<HeavyQueryLimiter>,
+    profile: web::Data<ListProfile>,
+    limits: web::Data<LimitsConfig>,
+    query: web::Query<StreamQuery>,
+) -> Result<impl Responder, ApiError> {
+    limits.check_query("author", query.author.as_deref())?;
+    limits.check_query("tag", query.tag.as_deref())?;
+    let permit = limiter.acquire().await?;
+    let (sort, order) = profile.resolve_with(query.sort, query.order);
+    let sql = stream_posts_sql(sort, order);
+    let query = query.into_inner();
+    let pool = pool.get_ref().clone();
+    let (sender, body) = row_channel();
+
+    actix_web::rt::spawn(async move {
+        let _permit = permit;
+        let tag = query.tag.as_deref().map(normalize_tag);
+        let filter = PostFilter {
+            tag: tag.as_deref(),
+            author: query.author.as_deref(),
+            status: query.status,
+            created_from: query.created_from,
+            created_to: query.created_to,
+            updated_from: query.updated_from,
+            updated_to: query.updated_to,
+            tenant: tenant.id,
+            ..Default::default()
+        }
+        .viewed_by(viewer.as_ref());
+        let posts = stream_posts(&pool, &sql, filter);
+        send_rows(&sender, posts, "Stream", |post| ExportFormat::Ndjson.encode(post)).await;
+    });
+
+    Ok(HttpResponse::Ok().content_type("application/x-ndjson").streaming(body))
+}
+
+// Registered ahead of /blog/{id} so "search" isn't taken for an id.
+#[utoipa::path(
+    tag = "posts",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "Ranked search hits", body = Page<SearchHit>),
+        (status = 422, description = "Empty or too long query", body = Problem),
+        (status = 503, description = "Too many heavy requests", body = Problem),
+    ),
+)]
+#[get("/blog/search")]
+pub(crate) async fn search_blogposts(
+    req: HttpRequest,
+    tenant: Tenant,
+    reads: web::Data<ReadPools>,
+    limiter: web::Data<HeavyQueryLimiter>,
+    limits: web::Data<LimitsConfig>,
+    query: web::Query<SearchQuery>,
+) -> Result<impl Responder, ApiError> {
+    let q = query.q.trim();
+    limits.check_query("q", Some(q))?;
+    if q.is_empty() {
+        return Err(ApiError::UnprocessableEntity(
+            "Query parameter `q` must not be empty".to_string(),
+        ));
+    }
+    let (page, per_page) = clamp_paging(query.page, query.per_page);
+    let _permit = limiter.acquire().await?;
+    let weights = query.rank_weights();
+    let offset = (page - 1) * per_page;
+    let hits = reads
+        .read(|pool| search_posts(pool, tenant.id, q, &weights, per_page, offset))
+        .await?;
+    let total = reads.read(|pool| count_search_results(pool, tenant.id, q)).await?;
+    let hits = Page::new(hits, page, per_page, total, false);
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Total-Count", total.to_string()))
+        .page_links(&req, &hits)
+        .json(hits))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PopularQuery),
+    responses(
+        (status = 200, description = "Published posts, most viewed first", body = Vec<BlogPost>),
+    ),
+)]
+// Registered ahead of /blog/{id} like /blog/search.
+#[get("/blog/popular")]
+pub(crate) async fn get_popular_blogposts(
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    query: web::Query<PopularQuery>,
+) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(popular_posts(&pool, tenant.id, query.limit()).await?))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    responses(
+        (status = 200, description = "Years and months with published posts, newest first",
+            body = Vec<ArchiveYear>),
+    ),
+)]
+// Registered ahead of /blog/{id} like /blog/search.
+#[get("/blog/archive")]
+pub(crate) async fn get_archive(
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+) -> Result<impl Responder, ApiError> {
+    let months = archive_months(&pool, tenant.id).await?;
+    Ok(HttpResponse::Ok().json(ArchiveYear::group(months)))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(
+        ("year" = i32, Path, description = "A year from 1 to 9999"),
+        ("month" = u32, Path, description = "1 to 12"),
+        PageQuery,
+    ),
+    responses(
+        (status = 200, description = "The month's published posts, newest first",
+            content((Page<BlogPost> = "application/json"), (Page<BlogPost> = "application/xml"))),
+        (status = 404, description = "Not a month", body = Problem),
+    ),
+)]
+#[get("/blog/archive/{year}/{month}")]
+pub(crate) async fn get_archive_month(
+    req: HttpRequest,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    path: web::Path<(i32, u32)>,
+    query: web::Query<PageQuery>,
+) -> Result<impl Responder, ApiError> {
+    let (year, month) = path.into_inner();
+    if !(1..=9999).contains(&year) || !(1..=12).contains(&month) {
+        return Err(ApiError::NotFound(format!("No month {}/{}", year, month)));
+    }
+    let (page, per_page) = clamp_paging(query.page, query.per_page);
+    let offset = (page - 1) * per_page;
+    let (posts, total) = archive_posts(&pool, tenant.id, year, month, per_page, offset).await?;
+    let posts = Page::new(posts, page, per_page, total, false);
+    Ok(HttpResponse::Ok().page_links(&req, &posts).negotiated(&req, &posts))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostPath, PostQuery),
+    responses(
+        (status = 200, description = "The post, or its paragraphs with ?anchors=true",
+            content((BlogPost = "application/json"), (BlogPost = "application/xml"))),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match \
+            or the date in If-Modified-Since"),
+        (status = 404, description = "No such post", body = Problem),
+        (status = 422, description = "Invalid language tag", body = Problem),
+    ),
+)]
+#[get("/blog/{id}")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn get_blogpost(
+    req: HttpRequest,
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    repo: web::Data<dyn PostRepository>,
+    cache: web::Data<PostCache>,
+    views: web::Data<ViewCounter>,
+    path: PostId,
+    query: web::Query<PostQuery>,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    let post = match cache.get_post(tenant.id, id).await {
+        Some(post) => post,
+        None => {
+            let post = repo.get(tenant.id, id).await?;
+            cache.put_post(tenant.id, &post).await;
+            post
+        }
+    };
+    // Drafts look like they don't exist to anyone else.
+    if !post_visible(&post, viewer.as_ref()) {
+        return Err(ApiError::NotFound(format!("Post {} not found", id)));
+    }
+    views.record(post.id);
+    serve_post(&req, repo.get_ref(), post, &query).await
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostQuery),
+    responses(
+        (status = 200, description = "The post, as GET /blog/{id} would return it",
+            content((BlogPost = "application/json"), (BlogPost = "application/xml"))),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match \
+            or the date in If-Modified-Since"),
+        (status = 404, description = "No post has this slug", body = Problem),
+        (status = 422, description = "Invalid language tag", body = Problem),
+    ),
+)]
+// Registered ahead of /blog/{id}/... so "slug" isn't taken for an id.
+#[get("/blog/slug/{slug}")]
+pub(crate) async fn get_blogpost_by_slug(
+    req: HttpRequest,
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    repo: web::Data<dyn PostRepository>,
+    views: web::Data<ViewCounter>,
+    path: web::Path<String>,
+    query: web::Query<PostQuery>,
+) -> Result<impl Responder, ApiError> {
+    let slug = path.into_inner();
+    let post = repo.get_by_slug(tenant.id, &slug).await?;
+    if !post_visible(&post, viewer.as_ref()) {
+        return Err(ApiError::NotFound(format!("No post with slug `{}`", slug)));
+    }
+    views.record(post.id);
+    serve_post(&req, repo.get_ref(), post, &query).await
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostPath, RelatedQuery),
+    responses(
+        (status = 200, description = "Published posts to read next, most related first",
+            body = Vec<BlogPost>),
+        (status = 404, description = "No such post", body = Problem),
+        (status = 503, description = "Too many heavy requests", body = Problem),
+    ),
+)]
+#[get("/blog/{id}/related")]
+pub(crate) async fn get_related_blogposts(
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    pool: web::Data<PgPool>,
+    limiter: web::Data<HeavyQueryLimiter>,
+    path: PostId,
+    query: web::Query<RelatedQuery>,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    let post = get_post(pool.get_ref(), tenant.id, id).await?;
+    if !post_visible(&post, viewer.as_ref()) {
+        return Err(ApiError::NotFound(format!("Post {} not found", id)));
+    }
+    // Scores every post sharing a tag or a title word.
+    let _permit = limiter.acquire().await?;
+    Ok(HttpResponse::Ok().json(related_posts(&pool, tenant.id, id, query.limit()).await?))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostPath),
+    responses(
+        (status = 200, description = "The content rendered from Markdown and sanitized",
+            content_type = "text/html"),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match \
+            or the date in If-Modified-Since"),
+        (status = 404, description = "No such post", body = Problem),
+    ),
+)]
+#[get("/blog/{id}/html")]
+pub(crate) async fn get_blogpost_html(
+    req: HttpRequest,
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    repo: web::Data<dyn PostRepository>,
+    cache: web::Data<PostCache>,
+    path: PostId,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    let post = match cache.get_post(tenant.id, id).await {
+        Some(post) => post,
+        None => {
+            let post = repo.get(tenant.id, id).await?;
+            cache.put_post(tenant.id, &post).await;
+            post
+        }
+    };
+    if !post_visible(&post, viewer.as_ref()) {
+        return Err(ApiError::NotFound(format!("Post {} not found", id)));
+    }
+    let etag = EntityTag::new_strong(format!("{}-html", post_etag(&post).tag()));
+    if matches_if_none_match(&req, &etag) || not_modified_since(&req, post.updated_at) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(("ETag", etag.to_string()))
+            .last_modified(post.updated_at)
+            .finish());
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header(("ETag", etag.to_string()))
+        .last_modified(post.updated_at)
+        .body(render_markdown(&post.content)))
+}
+
+#[utoipa::path(
+    tag = "feeds",
+    responses(
+        (status = 200, description = "The latest published posts as RSS 2.0",
+            content_type = "application/rss+xml"),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match"),
+    ),
+)]
+#[get("/feed.rss")]
+pub(crate) async fn rss_feed(
+    req: HttpRequest,
+    tenant: Tenant,
+    repo: web::Data<dyn PostRepository>,
+    site: web::Data<SiteConfig>,
+) -> Result<impl Responder, ApiError> {
+    let posts = latest_published_posts(repo.get_ref(), tenant.id, site.max_items).await?;
+    Ok(serve_feed(
+        &req,
+        site.max_age_secs,
+        "application/rss+xml; charset=utf-8",
+        render_rss(&site, &posts),
+    ))
+}
+
+#[utoipa::path(
+    tag = "feeds",
+    responses(
+        (status = 200, description = "The latest published posts as Atom 1.0",
+            content_type = "application/atom+xml"),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match"),
+    ),
+)]
+#[get("/feed.atom")]
+pub(crate) async fn atom_feed(
+    req: HttpRequest,
+    tenant: Tenant,
+    repo: web::Data<dyn PostRepository>,
+    site: web::Data<SiteConfig>,
+) -> Result<impl Responder, ApiError> {
+    let posts = latest_published_posts(repo.get_ref(), tenant.id, site.max_items).await?;
+    Ok(serve_feed(
+        &req,
+        site.max_age_secs,
+        "application/atom+xml; charset=utf-8",
+        render_atom(&site, &posts),
+    ))
+}
+
+#[utoipa::path(
+    tag = "feeds",
+    responses(
+        (status = 200, description = "The URLs of all published posts, as a sitemap",
+            content_type = "application/xml"),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match"),
+    ),
+)]
+#[get("/sitemap.xml")]
+pub(crate) async fn get_sitemap(
+    req: HttpRequest,
+    tenant: Tenant,
+    repo: web::Data<dyn PostRepository>,
+    site: web::Data<SiteConfig>,
+    sitemaps: web::Data<Sitemaps>,
+) -> Result<impl Responder, ApiError> {
+    let xml = sitemaps.get(repo.get_ref(), &site, tenant.id).await?;
+    Ok(serve_feed(
+        &req,
+        sitemaps.max_age_secs,
+        "application/xml; charset=utf-8",
+        xml.to_string(),
+    ))
+}
+
+pub(crate) async fn latest_published_posts(
+    repo: &dyn PostRepository,
+    tenant: i32,
+    limit: i64,
+) -> Result<Vec<BlogPost>, ApiError> {
+    let filter = PostFilter {
+        status: Some(PostStatus::Published),
+        tenant,
+        ..PostFilter::default()
+    };
+    repo.list(filter, SortColumn::CreatedAt, SortOrder::Desc, limit, 0).await
+}
+
+// Feeds are public and polled, so they may be cached anywhere for
+// FEED_MAX_AGE_SECS and revalidated by ETag after that.
+pub(crate) fn serve_feed(
+    req: &HttpRequest,
+    max_age_secs: u64,
+    content_type: &str,
+    xml: String,
+) -> HttpResponse {
+    let digest = Sha256::digest(xml.as_bytes());
+    let etag = EntityTag::new_strong(hex::encode(&digest[..8]));
+    let cache_control = format!("public, max-age={}", max_age_secs);
+    if matches_if_none_match(req, &etag) {
+        return HttpResponse::NotModified()
+            .insert_header(("ETag", etag.to_string()))
+            .insert_header(("Cache-Control", cache_control))
+            .finish();
+    }
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(("ETag", etag.to_string()))
+        .insert_header(("Cache-Control", cache_control))
+        .body(xml)
+}
+
+// The body of GET /blog/{id}: translation, anchors and ETag handling for a
+// post the caller may see.
+pub(crate) async fn serve_post(
+    req: &HttpRequest,
+    repo: &dyn PostRepository,
+    mut post: BlogPost,
+    query: &PostQuery,
+) -> Result<HttpResponse, ApiError> {
+    let mut response = HttpResponse::Ok();
+    let original = default_language();
+    let mut served = original.to_string();
+
+    // ?lang= is an explicit choice and beats the Accept-Language header.
+    let preferences = match &query.lang {
+        Some(lang) => Some(vec![normalize_lang(lang)?]),
+        None => req
+            .headers()
+            .get("Accept-Language")
+            .and_then(|value| value.to_str().ok())
+            .map(parse_accept_language),
+    };
+
+    if let Some(preferences) = preferences.filter(|p| !p.is_empty()) {
+        let available = repo.translation_langs(post.id).await?;
+        let chosen = negotiate_language(&preferences, original, &available);
+        let translation = match chosen.as_deref() {
+            Some(lang) if lang != original => repo.translation(post.id, lang).await?,
+            _ => None,
+        };
+
+        match translation {
+            Some(translation) => {
+                post.title = translation.title;
+                post.content = translation.content;
+                served = translation.lang;
+            }
+            None if chosen.is_none() => {
+                response.insert_header(("X-Translation-Fallback", "true"));
+            }
+            None => {}
+        }
+    }
+
+    // The anchored view is a different body for the same post, so it gets
+    // its own tag.
+    // The same in XML: it stands for the post's version, which If-Match
+    // checks, and Vary: Accept keeps caches from mixing the formats up.
+    let mut etag = post_etag(&post);
+    if query.anchors {
+        etag = EntityTag::new_strong(format!("{}-anchors", etag.tag()));
+    }
+    // A translation can change without the post's updated_at moving, so
+    // that only dates the original.
+    let dated = served == original;
+    response.insert_header(("Content-Language", served));
+    response.insert_header(("Vary", "Accept-Language"));
+    response.insert_header(("ETag", etag.to_string()));
+    if dated {
+        response.last_modified(post.updated_at);
+    }
+    if matches_if_none_match(req, &etag) || (dated && not_modified_since(req, post.updated_at)) {
+        return Ok(response.status(StatusCode::NOT_MODIFIED).finish());
+    }
+    if query.anchors {
+        return Ok(response.negotiated(req, &anchor_post(post)));
+    }
+    Ok(response.negotiated(req, &post))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    request_body = NewBlogPost,
+    params(PostPath, UpsertQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The updated post",
+            content((BlogPost = "application/json"), (BlogPost = "application/xml"))),
+        (status = 201, description = "The post, created under this id (`upsert=true`)",
+            content((BlogPost = "application/json"), (BlogPost = "application/xml"))),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such post", body = Problem),
+        (status = 409, description = "`version` is not the current version, or the id is \
+            taken by a post that can't be written (`upsert=true`)", body = Problem),
+        (status = 412, description = "If-Match doesn't match the current ETag", body = Problem),
+        (status = 422, description = "Invalid fields, tags or unknown fields", body = Problem),
+        (status = 429, description = "Write quota used up (`upsert=true`)", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[put("/blog/{id}")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn update_blogpost(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    repo: web::Data<dyn PostRepository>,
+    storage: web::Data<StorageGuard>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    moderator: web::Data<dyn Moderator>,
+    quotas: web::Data<WriteQuotas>,
+    key: PostKey,
+    query: web::Query<UpsertQuery>,
+    updated_post: ValidatedJson<NewBlogPost>,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    check_unknown_fields(&updated_post.unknown_fields)?;
+    let id = key.resolve(repo.get_ref(), user.tenant_id).await;
+    let unpublishing = match id {
+        Ok(id) => unpublishing(repo.get_ref(), user.tenant_id, id, updated_post.status).await,
+        Err(_) => false,
+    };
+    if query.upsert {
+        // A UUID or snowflake id no post has is as new as an id no post has.
+        let owner = match id {
+            Ok(id) => repo.owner(user.tenant_id, id).await,
+            err => err,
+        };
+        let owner = match owner {
+            Err(ApiError::NotFound(_)) => None,
+            owner => Some(owner?),
+        };
+        return upsert_blogpost(
+            &req, &user, &pool, &repo, &cache, &feed, &moderator, &quotas, key, owner,
+            unpublishing, &updated_post,
+        )
+        .await;
+    }
+    let id = id?;
+    user.require_owner(repo.owner(user.tenant_id, id).await?)?;
+    let if_match = if_match(&req);
+    let post =
+        repo.update(user.tenant_id, id, &updated_post, if_match.as_ref(), user.id).await?;
+    cache.invalidate(user.tenant_id, Some(id)).await;
+    feed.publish_update(user.tenant_id, post.clone(), unpublishing);
+    Ok(HttpResponse::Ok()
+        .insert_header(("ETag", post_etag(&post).to_string()))
+        .negotiated(&req, &post))
+}
+
+// The rest of PUT with `?upsert=true`. A post that is there is updated as
+// without it; a new one is checked like POST /blog, against the caller's
+// role, write quota and the moderator.
+#[allow(clippy::too_many_arguments)]
+async fn upsert_blogpost(
+    req: &HttpRequest,
+    user: &AuthUser,
+    pool: &PgPool,
+    repo: &web::Data<dyn PostRepository>,
+    cache: &PostCache,
+    feed: &ChangeFeed,
+    moderator: &web::Data<dyn Moderator>,
+    quotas: &WriteQuotas,
+    key: PostKey,
+    owner: Option<i32>,
+    unpublishing: bool,
+    post: &NewBlogPost,
+) -> Result<HttpResponse, ApiError> {
+    let (usage, flag) = match owner {
+        Some(owner) => {
+            user.require_owner(owner)?;
+            (None, None)
+        }
+        None => {
+            user.require_role(Role::Editor)?;
+            let usage = quotas.check(pool, user, ContentKind::Post, 1).await?;
+            let text = post.moderated_text();
+            (usage, moderate(moderator.get_ref(), ContentKind::Post, &text).await?)
+        }
+    };
+    let owner = owner.unwrap_or(user.id);
+    let if_match = if_match(req);
+    let (post, created) =
+        repo.upsert(user.tenant_id, key, post, if_match.as_ref(), owner, user.id).await?;
+    let id = post.id;
+    cache.invalidate(user.tenant_id, Some(id)).await;
+    if !created {
+        feed.publish_update(user.tenant_id, post.clone(), unpublishing);
+        return Ok(HttpResponse::Ok()
+            .insert_header(("ETag", post_etag(&post).to_string()))
+            .negotiated(req, &post));
+    }
+    quotas.record(pool, user, ContentKind::Post, 1).await;
+    flag_for_review(pool, user.tenant_id, ContentKind::Post, id, user.id, flag).await;
+    feed.publish(PostEventKind::Created, user.tenant_id, id, Some(post.clone()));
+    Ok(HttpResponse::Created()
+        .location(req, &post_path(req, &post))
+        .insert_header(("ETag", post_etag(&post).to_string()))
+        .quota(usage)
+        .negotiated(req, &post))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostPath),
+    request_body = UpdateBlogPost,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The updated post",
+            content((BlogPost = "application/json"), (BlogPost = "application/xml"))),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such post", body = Problem),
+        (status = 409, description = "`version` is not the current version", body = Problem),
+        (status = 412, description = "If-Match doesn't match the current ETag", body = Problem),
+        (status = 422, description = "Invalid fields, tags or unknown fields", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[patch("/blog/{id}")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn patch_blogpost(
+    req: HttpRequest,
+    user: AuthUser,
+    repo: web::Data<dyn PostRepository>,
+    storage: web::Data<StorageGuard>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    path: PostId,
+    patch: ValidatedJson<UpdateBlogPost>,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    check_unknown_fields(&patch.unknown_fields)?;
+    let id = path.into_inner();
+    user.require_owner(repo.owner(user.tenant_id, id).await?)?;
+    let unpublishing = unpublishing(repo.get_ref(), user.tenant_id, id, patch.status).await;
+    let post = repo.patch(user.tenant_id, id, &patch, if_match(&req).as_ref(), user.id).await?;
+    cache.invalidate(user.tenant_id, Some(id)).await;
+    feed.publish_update(user.tenant_id, post.clone(), unpublishing);
+    Ok(HttpResponse::Ok()
+        .insert_header(("ETag", post_etag(&post).to_string()))
+        .negotiated(&req, &post))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostPath),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 204, description = "Post moved to the trash"),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such post", body = Problem),
+        (status = 412, description = "If-Match doesn't match the current ETag", body = Problem),
+    ),
+)]
+#[delete("/blog/{id}")]
+pub(crate) async fn delete_blogpost(
+    req: HttpRequest,
+    user: AuthUser,
+    repo: web::Data<dyn PostRepository>,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+    path: PostId,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    user.require_owner(repo.owner(user.tenant_id, id).await?)?;
+    repo.delete(user.tenant_id, id, if_match(&req).as_ref(), user.id).await?;
+    cache.invalidate(user.tenant_id, Some(id)).await;
+    feed.publish(PostEventKind::Deleted, user.tenant_id, id, None);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    tag = "posts",
+    params(PostPath),
+    responses(
+        (status = 200, description = "Server-sent events with the post content",
+            content_type = "text/event-stream"),
+        (status = 404, description = "No such post", body = Problem),
+    ),
+)]
+#[get("/blog/{id}/content/stream")]
+pub(crate) async fn stream_blogpost_content(
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    pool: web::Data<PgPool>,
+    feed: web::Data<ChangeFeed>,
+    path: PostId,
+) -> Result<impl Responder, ApiError> {
+    // Subscribe before reading so no update between the two is lost.
+    let receiver = feed.subscribe();
+    let id = path.into_inner();
+    let post = get_post(pool.get_ref(), tenant.id, id).await?;
+    if !post_visible(&post, viewer.as_ref()) {
+        return Err(ApiError::NotFound(format!("Post {} not found", id)));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(content_stream(post, receiver, viewer)))
+}
+
+#[utoipa::path(
+    tag = "posts",
+    responses(
+        (status = 101, description = "WebSocket carrying post.created, post.updated and \
+            post.deleted events as JSON"),
+        (status = 400, description = "Not a WebSocket upgrade request", body = Problem),
+    ),
+)]
+#[get("/ws/blog")]
+pub(crate) async fn websocket_blog_feed(
+    req: HttpRequest,
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    body: web::Payload,
+    feed: web::Data<ChangeFeed>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, session, messages) =
+        actix_ws::handle(&req, body).map_err(|err| ApiError::BadRequest(err.to_string()))?;
+    let receiver = feed.subscribe();
+    actix_web::rt::spawn(websocket_feed(session, messages, receiver, tenant.id, viewer));
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "translations",
+    params(PostPath),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The stored translation", body = Translation),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such post", body = Problem),
+        (status = 422, description = "Invalid language tag", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/blog/{id}/translate")]
+pub(crate) async fn translate_blogpost(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    translator: web::Data<dyn Translator>,
+    path: PostId,
+    body: web::Json<TranslateRequest>,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    let post = get_post(pool.get_ref(), user.tenant_id, path.into_inner()).await?;
+    user.require_owner(post.user_id)?;
+    let lang = normalize_lang(&body.lang)?;
+    let title = translator.translate(&post.title, &lang).await?;
+    let content = translator.translate(&post.content, &lang).await?;
+
+    let translation =
+        upsert_translation(&pool, post.id, &lang, &title, &content).await?;
+    Ok(HttpResponse::Ok().json(translation))
+}
+
+// Post `id`, or NotFound when it's gone or a draft `viewer` can't see, so
+// routes under a post don't give away its drafts.
+async fn visible_post(
+    pool: &PgPool,
+    tenant: i32,
+    id: i32,
+    viewer: Option<&AuthUser>,
+) -> Result<BlogPost, ApiError> {
+    let not_found = || ApiError::NotFound(format!("Post {} not found", id));
+    match get_post(pool, tenant, id).await {
+        Ok(post) if post_visible(&post, viewer) => Ok(post),
+        Ok(_) | Err(ApiError::NotFound(_)) => Err(not_found()),
+        Err(err) => Err(err),
+    }
+}
+
+// Whether a write asking for `status` takes post `id` from published back
+// to draft. Read before the write, for the change feed.
+async fn unpublishing(
+    repo: &dyn PostRepository,
+    tenant: i32,
+    id: i32,
+    status: Option<PostStatus>,
+) -> bool {
+    if status != Some(PostStatus::Draft) {
+        return false;
+    }
+    let before = repo.get(tenant, id).await;
+    before.is_ok_and(|post| post.status == PostStatus::Published.as_str())
+}
+
+#[utoipa::path(
+    tag = "comments",
+    params(PostPath),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 201, description = "The created comment", body = Comment),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 404, description = "No such post", body = Problem),
+        (status = 422, description = "Empty comment, or rejected by moderation", body = Problem),
+        (status = 429, description = "Write quota used up", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/blog/{id}/comments")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn create_blogpost_comment(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    jobs: web::Data<JobQueue>,
+    moderator: web::Data<dyn Moderator>,
+    quotas: web::Data<WriteQuotas>,
+    path: PostId,
+    new_comment: web::Json<NewComment>,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    if new_comment.body.trim().is_empty() {
+        return Err(ApiError::UnprocessableEntity(
+            "Comment body must not be empty".to_string(),
+        ));
+    }
+    let post_id = path.into_inner();
+    visible_post(&pool, user.tenant_id, post_id, Some(&user)).await?;
+    let usage = quotas.check(&pool, &user, ContentKind::Comment, 1).await?;
+    let flag = moderate(moderator.get_ref(), ContentKind::Comment, &new_comment.body).await?;
+    // The comment, its place in the moderation queue and the job emailing
+    // the author are saved together or not at all.
+    let mut tx = pool.begin().await?;
+    let created = create_comment(&mut tx, user.tenant_id, post_id, user.id, &new_comment).await;
+    let comment = match created {
+        Err(ApiError::NotFound(_)) => {
+            return Err(ApiError::NotFound(format!("Post {} not found", post_id)))
+        }
+        other => other?,
+    };
+    if let Some(reason) = flag {
+        let (tenant, kind) = (user.tenant_id, ContentKind::Comment);
+        queue_for_moderation(&mut *tx, tenant, kind, comment.id, user.id, &reason).await?;
+    }
+    let payload = CommentEmailPayload {
+        comment_id: comment.id,
+        tenant_id: user.tenant_id,
+    };
+    jobs.enqueue_in(&mut tx, user.tenant_id, "comment_email", &payload).await?;
+    tx.commit().await?;
+    quotas.record(&pool, &user, ContentKind::Comment, 1).await;
+    jobs.wake();
+    Ok(HttpResponse::Created()
+        .quota(usage)
+        .location(&req, &format!("/comments/{}", comment.id))
+        .json(comment))
+}
+
+#[utoipa::path(
+    tag = "comments",
+    params(PostPath),
+    responses(
+        (status = 200, description = "The post's comments, oldest first", body = Vec<Comment>),
+        (status = 404, description = "No such post", body = Problem),
+    ),
+)]
+#[get("/blog/{id}/comments")]
+pub(crate) async fn get_blogpost_comments(
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    pool: web::Data<PgPool>,
+    path: PostId,
+) -> Result<impl Responder, ApiError> {
+    let post_id = path.into_inner();
+    visible_post(&pool, tenant.id, post_id, viewer.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(list_comments(&pool, post_id).await?))
+}
+
+#[utoipa::path(
+    tag = "comments",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 204, description = "Comment deleted"),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such comment", body = Problem),
+    ),
+)]
+#[delete("/comments/{id}")]
+pub(crate) async fn delete_blogpost_comment(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    path: web::Path<i32>,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    user.require_owner(comment_owner(&pool, user.tenant_id, id).await?)?;
+    let mut tx = pool.begin().await?;
+    delete_comment(&mut tx, id, user.id).await?;
+    tx.commit().await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    tag = "likes",
+    params(PostPath),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The post's new like count", body = LikeCount),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 404, description = "No such post, or a draft the caller can't see",
+            body = Problem),
+        (status = 409, description = "The caller already likes it", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/blog/{id}/like")]
+pub(crate) async fn like_blogpost(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    cache: web::Data<PostCache>,
+    path: PostId,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    let id = path.into_inner();
+    visible_post(&pool, user.tenant_id, id, Some(&user)).await?;
+    let mut tx = pool.begin().await?;
+    let count = like_post(&mut tx, user.tenant_id, id, user.id).await?;
+    tx.commit().await?;
+    cache.invalidate(user.tenant_id, Some(id)).await;
+    Ok(HttpResponse::Ok().json(count))
+}
+
+#[utoipa::path(
+    tag = "likes",
+    params(PostPath),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The post's new like count", body = LikeCount),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 404, description = "No such post, or the caller doesn't like it",
+            body = Problem),
+    ),
+)]
+#[delete("/blog/{id}/like")]
+pub(crate) async fn unlike_blogpost(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    cache: web::Data<PostCache>,
+    path: PostId,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    let mut tx = pool.begin().await?;
+    let count = unlike_post(&mut tx, user.tenant_id, id, user.id).await?;
+    tx.commit().await?;
+    cache.invalidate(user.tenant_id, Some(id)).await;
+    Ok(HttpResponse::Ok().json(count))
+}
+
+#[utoipa::path(
+    tag = "likes",
+    params(PostPath, PageQuery),
+    responses(
+        (status = 200, description = "Who likes the post, most recent first", body = Page<Like>),
+        (status = 404, description = "No such post", body = Problem),
+    ),
+)]
+#[get("/blog/{id}/likes")]
+pub(crate) async fn get_blogpost_likes(
+    req: HttpRequest,
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    pool: web::Data<PgPool>,
+    path: PostId,
+    query: web::Query<PageQuery>,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    let post = visible_post(&pool, tenant.id, id, viewer.as_ref()).await?;
+    let (page, per_page) = clamp_paging(query.page, query.per_page);
+    let likes = list_likes(&pool, id, per_page, (page - 1) * per_page).await?;
+    let total = i64::from(post.like_count);
+    let likes = Page::new(likes, page, per_page, total, false);
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Total-Count", total.to_string()))
+        .page_links(&req, &likes)
+        .json(likes))
+}
+
+#[utoipa::path(
+    tag = "attachments",
+    params(PostPath),
+    security(("bearer_auth" = [])),
+    request_body(content_type = "multipart/form-data",
+        description = "One or more files in `image` fields"),
+    responses(
+        (status = 201, description = "The stored images", body = Vec<Attachment>),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such post", body = Problem),
+        (status = 413, description = "An image is over IMAGE_MAX_BYTES", body = Problem),
+        (status = 422, description = "Not a PNG, JPEG, GIF or WebP image", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+#[post("/blog/{id}/images")]
+pub(crate) async fn upload_blogpost_images(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    uploads: web::Data<Uploads>,
+    path: PostId,
+    payload: Multipart,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    let post_id = path.into_inner();
+    if !post_exists(&pool, user.tenant_id, post_id).await? {
+        return Err(ApiError::NotFound(format!("Post {} not found", post_id)));
+    }
+    user.require_owner(post_owner(&pool, user.tenant_id, post_id).await?)?;
+    let images = read_images(payload, uploads.max_bytes).await?;
+
+    let mut attachments = Vec::with_capacity(images.len());
+    for image in &images {
+        let key = format!("{}.{}", hex::encode(rand::random::<[u8; 16]>()), image.extension);
+        uploads
+            .store
+            .put(&key, &image.bytes)
+            .await
+            .map_err(|err| ApiError::DatabaseError(format!("Cannot store image: {}", err)))?;
+            let (filename, content_type) = (&image.filename, image.content_type);
+        let size = image.bytes.len() as i64;
+        let created =
+            create_attachment(&pool, post_id, user.id, filename, content_type, size, &key).await;
+        match created {
+            Ok(attachment) => attachments.push(attachment),
+            Err(err) => {
+                // Don't leave a file behind that no row points to.
+                let _ = uploads.store.delete(&key).await;
+                return Err(err);
+            }
+        }
+    }
+    // The image, or the post's images when there are several, under the
+    // id or UUID the post was named by.
+    let post_key = req.match_info().query("id");
+    let location = match attachments.as_slice() {
+        [attachment] => format!("/blog/{}/images/{}", post_key, attachment.id),
+        _ => format!("/blog/{}/images", post_key),
+    };
+    Ok(HttpResponse::Created().location(&req, &location).json(attachments))
+}
+
+#[utoipa::path(
+    tag = "attachments",
+    params(PostPath),
+    security(("bearer_auth" = [])),
+    request_body = NewDirectUpload,
+    responses(
+        (status = 201, description = "The attachment, and the pre-signed request that uploads \
+            its file", body = DirectUpload),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Not the author or an admin", body = Problem),
+        (status = 404, description = "No such post", body = Problem),
+        (status = 409, description = "The attachment store can't sign uploads", body = Problem),
+        (status = 413, description = "`size_bytes` is over IMAGE_MAX_BYTES", body = Problem),
+        (status = 422, description = "Not a PNG, JPEG, GIF or WebP type", body = Problem),
+        (status = 507, description = "Database is full", body = Problem),
+    ),
+)]
+// The bytes never pass through the API: the client sends them to the URL
+// it gets back.
+#[post("/blog/{id}/images/presign")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn presign_blogpost_image(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    storage: web::Data<StorageGuard>,
+    uploads: web::Data<Uploads>,
+    path: PostId,
+    body: web::Json<NewDirectUpload>,
+) -> Result<impl Responder, ApiError> {
+    storage.check_writable()?;
+    let post_id = path.into_inner();
+    if !post_exists(&pool, user.tenant_id, post_id).await? {
+        return Err(ApiError::NotFound(format!("Post {} not found", post_id)));
+    }
+    user.require_owner(post_owner(&pool, user.tenant_id, post_id).await?)?;
+    let (content_type, extension) = named_image_type(&body.content_type).ok_or_else(|| {
+        ApiError::UnprocessableEntity(
+            "Only PNG, JPEG, GIF and WebP images are accepted".to_string(),
+        )
+    })?;
+    if body.size_bytes < 1 {
+        return Err(ApiError::UnprocessableEntity("`size_bytes` must be positive".to_string()));
+    }
+    if body.size_bytes as u64 > uploads.max_bytes as u64 {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "Images can be at most {} bytes",
+            uploads.max_bytes
+        )));
+    }
+    let key = format!("{}.{}", hex::encode(rand::random::<[u8; 16]>()), extension);
+    let upload = uploads.store.upload_url(&key, content_type, body.size_bytes).ok_or_else(|| {
+        ApiError::Conflict(
+            "This attachment store takes uploads through POST /blog/{id}/images only"
+                .to_string(),
+        )
+    })?;
+    let filename = clean_filename(body.filename.as_deref(), extension);
+    let attachment =
+        create_attachment(&pool, post_id, user.id, &filename, content_type, body.size_bytes, &key)
+            .await?;
+    let location = format!("/blog/{}/images/{}", req.match_info().query("id"), attachment.id);
+    Ok(HttpResponse::Created()
+        .location(&req, &location)
+        .json(DirectUpload { attachment, upload }))
+}
+
+#[utoipa::path(
+    tag = "attachments",
+    params(PostPath),
+    responses(
+        (status = 200, description = "Images of the post, oldest first", body = Vec<Attachment>),
+        (status = 404, description = "No such post", body = Problem),
+    ),
+)]
+#[get("/blog/{id}/images")]
+pub(crate) async fn get_blogpost_images(
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    pool: web::Data<PgPool>,
+    path: PostId,
+) -> Result<impl Responder, ApiError> {
+    let post_id = path.into_inner();
+    visible_post(&pool, tenant.id, post_id, viewer.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(list_attachments(&pool, post_id).await?))
+}
+
+#[utoipa::path(
+    tag = "attachments",
+    responses(
+        (status = 200, description = "The image file", content_type = "image/*"),
+        (status = 307, description = "A pre-signed URL of the file in the attachment store"),
+        (status = 404, description = "No such image", body = Problem),
+    ),
+)]
+#[get("/blog/{id}/images/{image_id}")]
+pub(crate) async fn download_blogpost_image(
+    tenant: Tenant,
+    viewer: Option<AuthUser>,
+    pool: web::Data<PgPool>,
+    uploads: web::Data<Uploads>,
+    post: PostId,
+    path: web::Path<(String, i32)>,
+) -> Result<impl Responder, ApiError> {
+    let (post_id, image_id) = (post.into_inner(), path.into_inner().1);
+    visible_post(&pool, tenant.id, post_id, viewer.as_ref()).await?;
+    let attachment = get_attachment(&pool, tenant.id, post_id, image_id).await?;
+    let (key, content_type) = (&attachment.storage_key, &attachment.content_type);
+    if let Some(url) = uploads.store.download_url(key, content_type, &attachment.filename) {
+        return Ok(HttpResponse::TemporaryRedirect()
+            .insert_header((actix_web::http::header::LOCATION, url))
+            .finish());
+    }
+    let bytes = uploads.store.get(&attachment.storage_key).await.map_err(|err| {
+        ApiError::DatabaseError(format!("Cannot read image {}: {}", image_id, err))
+    })?;
+    Ok(HttpResponse::Ok()
+        .content_type(attachment.content_type.as_str())
+        .insert_header((
+            "Content-Disposition",
+            format!("inline; filename=\"{}\"", attachment.filename),
+        ))
+        .insert_header(("X-Content-Type-Options", "nosniff"))
+        // Stored images never change; a new upload gets a new id.
+        .insert_header(("Cache-Control", "public, max-age=31536000, immutable"))
+        .body(bytes))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    responses((status = 200, description = "Service and storage status", body = Health)),
+)]
+#[get("/health")]
+pub(crate) async fn health(storage: web::Data<StorageGuard>) -> impl Responder {
+    HttpResponse::Ok().json(Health {
+        status: "ok",
+        storage: storage.status(),
+    })
+}
+
+#[utoipa::path(
+    tag = "admin",
+    responses((status = 200, description = "The process is up", body = Liveness)),
+)]
+// Liveness: answers as long as the process can serve HTTP, and never
+// touches the database, so a database outage doesn't get pods restarted.
+#[get("/healthz")]
+pub(crate) async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(Liveness { status: "ok" })
+}
+
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, description = "Ready for traffic", body = Readiness),
+        (status = 503, description = "The database can't be reached", body = Readiness),
+    ),
+)]
+#[get("/readyz")]
+pub(crate) async fn readyz(pool: web::Data<PgPool>) -> impl Responder {
+    let database = check_database(&pool).await;
+    let body = Readiness {
+        status: if database.is_ok() { "ready" } else { "unavailable" },
+        database: database.clone().err().unwrap_or_else(|| "ok".to_string()),
+        pool: PoolState {
+            size: pool.size(),
+            idle: pool.num_idle(),
+            max_connections: pool.options().get_max_connections(),
+        },
+    };
+    match database {
+        Ok(()) => HttpResponse::Ok().json(body),
+        Err(_) => HttpResponse::ServiceUnavailable().json(body),
+    }
+}
+
+#[utoipa::path(
+    tag = "admin",
+    responses((status = 200, description = "Prometheus text exposition format",
+        content_type = "text/plain")),
+)]
+// Unauthenticated like the other operational routes; keep it off the
+// public network at the proxy.
+#[get("/metrics")]
+pub(crate) async fn get_metrics(pool: web::Data<PgPool>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(metrics().render(&pool))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Connection pool and cache figures", body = PoolStats),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+    ),
+)]
+#[get("/admin/pool-stats")]
+pub(crate) async fn pool_stats(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    cache: web::Data<PostCache>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    Ok(HttpResponse::Ok().json(PoolStats {
+        size: pool.size(),
+        idle: pool.num_idle(),
+        cache: cache.stats(),
+    }))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Pool size, settings and recent waits for a connection",
+            body = PoolReport),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+    ),
+)]
+#[get("/admin/db-pool")]
+pub(crate) async fn get_admin_db_pool(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    monitor: web::Data<PoolMonitor>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    Ok(HttpResponse::Ok().json(monitor.report(&pool)))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    params(JobsQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Jobs, newest first, and counts by status", body = JobList),
+        (status = 400, description = "Unknown status", body = Problem),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+    ),
+)]
+#[get("/admin/jobs")]
+pub(crate) async fn get_admin_jobs(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    query: web::Query<JobsQuery>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    let (page, per_page) = clamp_paging(query.page, query.per_page);
+    let offset = (page - 1) * per_page;
+    let (jobs, total, counts) = list_jobs(&pool, user.tenant_id, &query, per_page, offset).await?;
+    let jobs = Page::new(jobs, page, per_page, total, false);
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Total-Count", total.to_string()))
+        .page_links(&req, &jobs)
+        .json(JobList { counts, jobs }))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The job", body = Job),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Neither the importer nor an admin", body = Problem),
+        (status = 404, description = "No such job", body = Problem),
+    ),
+)]
+// Whoever queued an import may poll it; every other job is for admins.
+#[get("/admin/jobs/{id}")]
+pub(crate) async fn get_admin_job(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    path: web::Path<i32>,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    if job_owner(&pool, user.tenant_id, id).await? != Some(user.id) {
+        user.require_role(Role::Admin)?;
+    }
+    Ok(HttpResponse::Ok().json(get_job(&pool, user.tenant_id, id).await?))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    params(AuditQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Audit entries, newest first", body = Page<AuditEntry>),
+        (status = 400, description = "Unknown entity or malformed date", body = Problem),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+    ),
+)]
+#[get("/admin/audit")]
+pub(crate) async fn get_admin_audit(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    query: web::Query<AuditQuery>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    let (page, per_page) = clamp_paging(query.page, query.per_page);
+    let offset = (page - 1) * per_page;
+    let (entries, total) = list_audit(&pool, user.tenant_id, &query, per_page, offset).await?;
+    let entries = Page::new(entries, page, per_page, total, false);
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Total-Count", total.to_string()))
+        .page_links(&req, &entries)
+        .json(entries))
+}
+
+#[utoipa::path(
+    tag = "moderation",
+    params(ModerationQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Flagged content; open items oldest first",
+            body = Page<ModerationItem>),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+    ),
+)]
+#[get("/admin/moderation")]
+pub(crate) async fn get_admin_moderation(
+    req: HttpRequest,
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    query: web::Query<ModerationQuery>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    let (page, per_page) = clamp_paging(query.page, query.per_page);
+    let offset = (page - 1) * per_page;
+    let tenant = user.tenant_id;
+    let (items, total) = list_moderation_queue(&pool, tenant, query.resolved, per_page, offset)
+        .await?;
+    let items = Page::new(items, page, per_page, total, false);
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Total-Count", total.to_string()))
+        .page_links(&req, &items)
+        .json(items))
+}
+
+#[utoipa::path(
+    tag = "moderation",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The item, now resolved", body = ModerationItem),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+        (status = 404, description = "No such item", body = Problem),
+    ),
+)]
+#[post("/admin/moderation/{id}/resolve")]
+pub(crate) async fn resolve_admin_moderation(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    path: web::Path<i32>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    let item = resolve_moderation_item(&pool, user.tenant_id, path.into_inner(), user.id).await?;
+    Ok(HttpResponse::Ok().json(item))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Post, comment and view figures", body = AdminStats),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+        (status = 503, description = "Too many heavy requests", body = Problem),
+    ),
+)]
+#[get("/admin/stats")]
+pub(crate) async fn get_admin_stats(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    limiter: web::Data<HeavyQueryLimiter>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    let _permit = limiter.acquire().await?;
+    Ok(HttpResponse::Ok().json(admin_stats(&pool, user.tenant_id).await?))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    params(RebuildSlugsQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "How many slugs changed", body = RebuildSlugsResult),
+        (status = 401, description = "Missing or invalid token", body = Problem),
+        (status = 403, description = "Caller is not an admin", body = Problem),
+    ),
+)]
+#[post("/admin/rebuild-slugs")]
+pub(crate) async fn rebuild_admin_slugs(
+    user: AuthUser,
+    pool: web::Data<PgPool>,
+    cache: web::Data<PostCache>,
+    query: web::Query<RebuildSlugsQuery>,
+) -> Result<impl Responder, ApiError> {
+    user.require_role(Role::Admin)?;
+    let changed = rebuild_slugs(&pool, user.tenant_id, query.missing_only).await?;
+    for id in &changed {
+        cache.invalidate(user.tenant_id, Some(*id)).await;
+    }
+    Ok(HttpResponse::Ok().json(RebuildSlugsResult { updated: changed.len() }))
+}
+
+// -------------------- API versions --------------------
+
+// Every route the server answers, in match order. The tests build their
+// App with this too.
+pub fn configure_routes(
+    cfg: &mut web::ServiceConfig,
+    openapi: &utoipa::openapi::OpenApi,
+    legacy: Option<&LegacyRoutes>,
+    limits: &LimitsConfig,
+) {
+    configure_problems(cfg, limits);
+    cfg.route("/", web::get().to(index_page))
+        .service(health)
+        .service(healthz)
+        .service(readyz)
+        .service(pool_stats)
+        .service(get_admin_db_pool)
+        .service(get_metrics)
+        .service(get_admin_jobs)
+        .service(get_admin_job)
+        .service(get_admin_audit)
+        .service(get_admin_stats)
+        .service(rebuild_admin_slugs)
+        .service(get_admin_moderation)
+        .service(resolve_admin_moderation)
+        .service(rss_feed)
+        .service(atom_feed)
+        .service(get_sitemap)
+        .route("/docs", web::get().to(docs::redirect_to_docs))
+        .service(websocket_blog_feed)
+        // Unversioned: the schema evolves by deprecating fields instead.
+        .service(
+            web::resource("/graphql")
+                .route(web::post().to(graphql))
+                .route(web::get().to(graphiql)),
+        )
+        .service(
+            SwaggerUi::new("/docs/{_:.*}").url("/api-docs/openapi.json", openapi.clone()),
+        )
+        .service(web::scope(API_V1).configure(api_v1));
+    // Matches every path, so it has to come last.
+    if let Some(legacy) = legacy {
+        cfg.service(web::scope("").wrap(legacy.headers()).configure(api_v1));
+    }
+}
+
+// Handler paths are relative to the version scope they are registered in.
+// A v2 gets its own scope and configure function; handlers that don't
+// change between versions can be registered in both.
+pub const API_V1: &str = "/api/v1";
+
+pub fn api_v1(cfg: &mut web::ServiceConfig) {
+    cfg.service(register_user)
+        .service(login)
+        .service(refresh_session)
+        .service(logout)
+        .service(oauth_login)
+        .service(oauth_callback)
+        .service(update_user_role)
+        .service(update_own_email)
+        .service(update_own_profile)
+        .service(get_authors)
+        .service(get_author_profile)
+        .service(get_author_posts)
+        .service(create_user_api_key)
+        .service(get_api_keys)
+        .service(delete_api_key)
+        .service(create_user_webhook)
+        .service(get_webhooks)
+        .service(delete_user_webhook)
+        .service(get_webhook_deliveries)
+        .service(create_blogposts_bulk)
+        .service(delete_blogposts_bulk)
+        .service(get_blogposts_batch)
+        .service(get_blogposts_by_slugs)
+        .service(import_blogposts)
+        .service(preview_blogpost)
+        .service(create_blogpost)
+        .service(get_blogposts)
+        .service(search_blogposts)
+        .service(get_popular_blogposts)
+        .service(get_archive)
+        .service(get_archive_month)
+        .service(export_blogposts)
+        .service(stream_blogposts)
+        .service(get_trash)
+        .service(get_blogpost_by_slug)
+        .service(get_blogpost)
+        .service(get_blogpost_html)
+        .service(get_related_blogposts)
+        .service(update_blogpost)
+        .service(patch_blogpost)
+        .service(delete_blogpost)
+        .service(publish_blogpost)
+        .service(unpublish_blogpost)
+        .service(restore_blogpost)
+        .service(purge_blogpost)
+        .service(get_blogpost_revisions)
+        .service(get_blogpost_revision)
+        .service(restore_blogpost_revision)
+        .service(stream_blogpost_content)
+        .service(translate_blogpost)
+        .service(create_blogpost_comment)
+        .service(get_blogpost_comments)
+        .service(delete_blogpost_comment)
+        .service(like_blogpost)
+        .service(unlike_blogpost)
+        .service(get_blogpost_likes)
+        .service(upload_blogpost_images)
+        .service(presign_blogpost_image)
+        .service(get_blogpost_images)
+        .service(download_blogpost_image);
+}
+
+// The unversioned paths (`/blog`, `/auth/login`, ...) clients used before
+// /api/v1, still served by the v1 handlers. LEGACY_ROUTES=0 turns them off;
+// LEGACY_ROUTES_SUNSET announces the date they go away.
+#[derive(Clone)]
+pub struct LegacyRoutes {
+    sunset: Option<String>,
+}
+
+impl LegacyRoutes {
+    pub fn from_env() -> Option<Self> {
+        if !env_flag("LEGACY_ROUTES", true) {
+            return None;
+        }
+        let sunset = env::var("LEGACY_ROUTES_SUNSET").ok().map(|sunset| {
+            DateTime::parse_from_rfc2822(&sunset).unwrap_or_else(|_| {
+                panic!(
+                    "LEGACY_ROUTES_SUNSET must be an HTTP date like \
+                     `Sat, 01 May 2027 00:00:00 GMT`, not `{}`",
+                    sunset
+                )
+            });
+            sunset
+        });
+        Some(LegacyRoutes { sunset })
+    }
+
+    pub fn headers(&self) -> DefaultHeaders {
+        deprecated_version(API_V1, self.sunset.as_deref())
+    }
+}
+
+// Added to every response of a superseded version: `Deprecation`, a `Link`
+// to its successor and, when a date is set, `Sunset` (RFC 8594).
+pub fn deprecated_version(successor: &str, sunset: Option<&str>) -> DefaultHeaders {
+    let mut headers = DefaultHeaders::new()
+        .add(("Deprecation", "true"))
+        .add(("Link", format!("<{}>; rel=\"successor-version\"", successor)));
+    if let Some(sunset) = sunset {
+        headers = headers.add(("Sunset", sunset));
+    }
+    headers
+}
@@ -0,0 +1,85 @@
+// HTTP caching of reads: the Cache-Control policies in CacheControlConfig,
+// and Last-Modified with If-Modified-Since for posts.
+
+use actix_web::http::header::{
+    HttpDate, IfModifiedSince, AUTHORIZATION, CACHE_CONTROL, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use actix_web::http::Method;
+use actix_web::HttpResponseBuilder;
+
+use crate::*;
+
+// Sets the policy configured for the route on its 200 and 304 answers to
+// GET and HEAD, unless the handler set one itself.
+pub fn apply_cache_control<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>> + use<S, B>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let fut = srv.call(req);
+    async move {
+        let mut res = fut.await?;
+        let policy = cache_policy(&res);
+        if let Some(value) = policy.and_then(|policy| HeaderValue::from_str(&policy).ok()) {
+            res.headers_mut().insert(CACHE_CONTROL, value);
+        }
+        Ok(res)
+    }
+}
+
+fn cache_policy<B>(res: &ServiceResponse<B>) -> Option<String> {
+    let req = res.request();
+    if !matches!(*req.method(), Method::GET | Method::HEAD)
+        || !matches!(res.status(), StatusCode::OK | StatusCode::NOT_MODIFIED)
+        || res.headers().contains_key(CACHE_CONTROL)
+    {
+        return None;
+    }
+    let config = req.app_data::<web::Data<CacheControlConfig>>()?;
+    let pattern = req.match_pattern()?;
+    let policy = config.routes.get(pattern.strip_prefix(API_V1).unwrap_or(&pattern))?;
+    // What a signed-in client sees may include drafts, so shared caches
+    // mustn't keep it.
+    Some(match req.headers().contains_key(AUTHORIZATION) {
+        true => private(policy),
+        false => policy.clone(),
+    })
+}
+
+// `policy` for a response only the client may keep: `private` in place of
+// `public` and without the directives meant for shared caches.
+fn private(policy: &str) -> String {
+    let directives = policy.split(',').map(str::trim).filter(|directive| {
+        let name = directive.split('=').next().unwrap_or_default().to_ascii_lowercase();
+        !matches!(name.as_str(), "public" | "private" | "s-maxage" | "proxy-revalidate")
+    });
+    std::iter::once("private").chain(directives).collect::<Vec<_>>().join(", ")
+}
+
+// Last-Modified for something last changed at `at`.
+pub trait LastModifiedHeader {
+    fn last_modified(&mut self, at: DateTime<Utc>) -> &mut Self;
+}
+
+impl LastModifiedHeader for HttpResponseBuilder {
+    fn last_modified(&mut self, at: DateTime<Utc>) -> &mut Self {
+        self.insert_header((LAST_MODIFIED, HttpDate::from(SystemTime::from(at))))
+    }
+}
+
+// Whether If-Modified-Since says the client has what changed last at
+// `at`. Ignored when the request has If-None-Match, the exact check
+// (RFC 9110 13.1.3). HTTP dates are in whole seconds.
+pub fn not_modified_since(req: &HttpRequest, at: DateTime<Utc>) -> bool {
+    if req.headers().contains_key(IF_NONE_MATCH) {
+        return false;
+    }
+    match req.get_header::<IfModifiedSince>() {
+        Some(IfModifiedSince(since)) => {
+            at.timestamp() <= DateTime::<Utc>::from(SystemTime::from(since)).timestamp()
+        }
+        None => false,
+    }
+}
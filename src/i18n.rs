@@ -0,0 +1,241 @@
+// Error messages in the client's language: catalogs of translations for
+// problem titles and details, picked by Accept-Language. The messages in
+// the code are English, which is what anything without a translation, or
+// a client asking for no language we have, gets.
+
+use std::path::Path;
+
+use actix_web::body::MessageBody;
+
+use crate::*;
+
+// One language's translations, from locales/<lang>.toml:
+//
+//     [titles]
+//     not_found = "Introuvable"
+//
+//     [messages]
+//     "Post {} not found" = "L'article {} est introuvable"
+//
+// Titles are keyed by error code. Message keys are the English text with
+// `{}` where it varies; the translation puts those parts back in order
+// with `{}`, or in any order with `{1}`, `{2}`... The varying parts are
+// themselves translated when they match a message, so "Item {}: {}"
+// carries the translation of whatever followed it.
+#[derive(Debug, Default)]
+pub struct Catalog {
+    titles: HashMap<ErrorCode, String>,
+    exact: HashMap<String, String>,
+    // The key's literal text between the `{}`s, and its translation.
+    patterns: Vec<(Vec<String>, String)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CatalogFile {
+    #[serde(default)]
+    titles: HashMap<ErrorCode, String>,
+    #[serde(default)]
+    messages: BTreeMap<String, String>,
+}
+
+impl Catalog {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let file: CatalogFile = toml::from_str(source).map_err(|err| err.to_string())?;
+        let mut catalog = Catalog {
+            titles: file.titles,
+            ..Default::default()
+        };
+        for (key, translation) in file.messages {
+            if !key.contains("{}") {
+                catalog.exact.insert(key, translation);
+                continue;
+            }
+            let segments: Vec<String> = key.split("{}").map(str::to_string).collect();
+            // Without text between them there is no telling where one part
+            // ends and the next begins.
+            let inner = &segments[1..segments.len() - 1];
+            if inner.iter().any(String::is_empty) || segments.concat().is_empty() {
+                return Err(format!("Message `{}` needs text between its `{{}}`s", key));
+            }
+            catalog.patterns.push((segments, translation));
+        }
+        // Longer literal text first, so the most specific key wins.
+        catalog.patterns.sort_by_key(|(segments, _)| {
+            std::cmp::Reverse(segments.iter().map(String::len).sum::<usize>())
+        });
+        Ok(catalog)
+    }
+
+    pub fn title(&self, code: ErrorCode) -> Option<&str> {
+        self.titles.get(&code).map(String::as_str)
+    }
+
+    // The message in this language, or as it is when there's no
+    // translation for it.
+    pub fn translate(&self, message: &str) -> String {
+        if let Some(translation) = self.exact.get(message) {
+            return translation.clone();
+        }
+        for (segments, translation) in &self.patterns {
+            if let Some(parts) = match_segments(segments, message) {
+                // Every part is shorter than the message, so this ends.
+                let parts: Vec<String> = parts.into_iter().map(|p| self.translate(p)).collect();
+                return fill_parts(translation, &parts);
+            }
+        }
+        message.to_string()
+    }
+}
+
+// The varying parts of `message` if it fits the key split into `segments`.
+fn match_segments<'a>(segments: &[String], message: &'a str) -> Option<Vec<&'a str>> {
+    let (first, rest) = segments.split_first()?;
+    let (last, inner) = rest.split_last()?;
+    let mut rest = message.strip_prefix(first.as_str())?.strip_suffix(last.as_str())?;
+    let mut parts = Vec::with_capacity(segments.len() - 1);
+    for segment in inner {
+        let at = rest.find(segment.as_str()).filter(|&at| at > 0)?;
+        parts.push(&rest[..at]);
+        rest = &rest[at + segment.len()..];
+    }
+    if rest.is_empty() {
+        return None;
+    }
+    parts.push(rest);
+    Some(parts)
+}
+
+fn fill_parts(translation: &str, parts: &[String]) -> String {
+    let mut out = String::with_capacity(translation.len());
+    let mut next = 0;
+    let mut rest = translation;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let part = after.find('}').and_then(|end| {
+            let index = match &after[..end] {
+                "" => {
+                    next += 1;
+                    next - 1
+                }
+                number => number.parse::<usize>().ok()?.checked_sub(1)?,
+            };
+            Some((parts.get(index)?, end))
+        });
+        match part {
+            Some((part, end)) => {
+                out.push_str(part);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// Every catalog, by language.
+#[derive(Debug, Default)]
+pub struct Catalogs {
+    catalogs: HashMap<String, Catalog>,
+}
+
+impl Catalogs {
+    pub fn new(catalogs: HashMap<String, Catalog>) -> Self {
+        Catalogs { catalogs }
+    }
+
+    // Each `<lang>.toml` in `dir`, named by its language code.
+    pub fn from_dir(dir: &Path) -> Result<Self, String> {
+        let entries = std::fs::read_dir(dir)
+            .map_err(|err| format!("Cannot read LOCALES_DIR {}: {}", dir.display(), err))?;
+        let mut catalogs = HashMap::new();
+        for entry in entries {
+            let path = entry.map_err(|err| err.to_string())?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+                continue;
+            }
+            let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+            let lang = normalize_lang(stem)
+                .map_err(|_| format!("{} isn't named after a language", path.display()))?;
+            let source = std::fs::read_to_string(&path)
+                .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+            let catalog = Catalog::parse(&source)
+                .map_err(|err| format!("Bad catalog {}: {}", path.display(), err))?;
+            catalogs.insert(lang, catalog);
+        }
+        Ok(Catalogs::new(catalogs))
+    }
+
+    // From LOCALES_DIR, by default `locales`, which may then be missing:
+    // that leaves messages in English only.
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("LOCALES_DIR") {
+            Ok(dir) => Catalogs::from_dir(Path::new(&dir)),
+            Err(_) if !Path::new("locales").is_dir() => Ok(Catalogs::default()),
+            Err(_) => Catalogs::from_dir(Path::new("locales")),
+        }
+    }
+
+    // The language the client prefers among English and the catalogs,
+    // with its catalog; None for English.
+    pub fn negotiate(&self, accept_language: &str) -> Option<(String, &Catalog)> {
+        let available: Vec<String> = self.catalogs.keys().cloned().collect();
+        let preferences = parse_accept_language(accept_language);
+        let lang = negotiate_language(&preferences, "en", &available)?;
+        let catalog = self.catalogs.get(&lang)?;
+        Some((lang, catalog))
+    }
+}
+
+impl Problem {
+    pub fn localize(&mut self, catalog: &Catalog) {
+        if let Some(title) = catalog.title(self.code) {
+            self.title = title.to_string();
+        }
+        self.detail = catalog.translate(&self.detail);
+        for messages in self.errors.iter_mut().flat_map(|errors| errors.values_mut()) {
+            for message in messages {
+                *message = catalog.translate(message);
+            }
+        }
+    }
+}
+
+// Rewrites problem responses in the language of the request's
+// Accept-Language, and says which in Content-Language. ApiError leaves
+// each one's Problem in the response extensions for this.
+pub fn localize_problem<B: MessageBody + 'static>(
+    mut res: ServiceResponse<B>,
+) -> ServiceResponse<EitherBody<B>> {
+    let Some(catalogs) = res.request().app_data::<web::Data<Catalogs>>().cloned() else {
+        return res.map_into_left_body();
+    };
+    let accept_language = res
+        .request()
+        .headers()
+        .get("Accept-Language")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let Some((lang, catalog)) = catalogs.negotiate(&accept_language) else {
+        return res.map_into_left_body();
+    };
+    let Some(mut problem) = res.response_mut().extensions_mut().remove::<Problem>() else {
+        return res.map_into_left_body();
+    };
+    problem.localize(catalog);
+    let Ok(body) = serde_json::to_string(&problem) else {
+        return res.map_into_left_body();
+    };
+    let (req, response) = res.into_parts();
+    let mut response = response.set_body(body);
+    if let Ok(lang) = HeaderValue::from_str(&lang) {
+        response.headers_mut().insert(HeaderName::from_static("content-language"), lang);
+    }
+    ServiceResponse::new(req, response.map_into_boxed_body()).map_into_right_body()
+}
@@ -0,0 +1,141 @@
+// Idempotency-Key on POST /blog: a retried request gets the response the
+// first one got rather than creating the post twice.
+
+use crate::*;
+
+pub(crate) static IDEMPOTENCY_TTL_SECS: OnceLock<u64> = OnceLock::new();
+
+// How long a key is remembered, from IDEMPOTENCY_TTL_SECS (default a day).
+pub fn idempotency_ttl_secs() -> u64 {
+    *IDEMPOTENCY_TTL_SECS.get_or_init(|| {
+        env::var("IDEMPOTENCY_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(86400)
+            .max(1)
+    })
+}
+
+pub(crate) const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+// The Idempotency-Key header, if sent. Keys are the client's choice (a
+// UUID is typical) and are scoped to the caller.
+#[derive(Debug, Clone)]
+pub struct IdempotencyKey(pub Option<String>);
+
+impl FromRequest for IdempotencyKey {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let Some(value) = req.headers().get("Idempotency-Key") else {
+            return ready(Ok(IdempotencyKey(None)));
+        };
+        let key = value.to_str().ok().filter(|key| {
+            (1..=MAX_IDEMPOTENCY_KEY_LEN).contains(&key.len())
+                && key.bytes().all(|b| b.is_ascii_graphic())
+        });
+        ready(match key {
+            Some(key) => Ok(IdempotencyKey(Some(key.to_string()))),
+            None => Err(ApiError::UnprocessableEntity(format!(
+                "Idempotency-Key must be 1 to {} visible ASCII characters",
+                MAX_IDEMPOTENCY_KEY_LEN
+            ))),
+        })
+    }
+}
+
+// What a key already has stored.
+pub struct IdempotencyRecord {
+    pub request_hash: String,
+    // None while the request that claimed the key is still being handled.
+    pub status_code: Option<i32>,
+    pub response: Option<serde_json::Value>,
+}
+
+pub enum Idempotent {
+    // Go ahead, then hand the outcome to `finish`.
+    Proceed(IdempotentRequest),
+    // Answered before: send this again.
+    Replay(HttpResponse),
+}
+
+pub struct IdempotentRequest {
+    // None when no key was sent.
+    claimed: Option<(i32, String)>,
+}
+
+// Claims the key for this request, or finds the response to replay. The
+// same key with a different body is a client bug, answered with 422; while
+// the first request is still running, repeats get 409.
+pub async fn start_idempotent<T: Serialize>(
+    pool: &PgPool,
+    user_id: i32,
+    key: IdempotencyKey,
+    body: &T,
+) -> Result<Idempotent, ApiError> {
+    let Some(key) = key.0 else {
+        return Ok(Idempotent::Proceed(IdempotentRequest { claimed: None }));
+    };
+    let request_hash = serde_json::to_vec(body)
+        .map(|bytes| hex::encode(Sha256::digest(bytes)))
+        .map_err(|err| ApiError::UnprocessableEntity(err.to_string()))?;
+    let ttl = idempotency_ttl_secs();
+    let Some(record) = claim_idempotency_key(pool, user_id, &key, &request_hash, ttl).await? else {
+        return Ok(Idempotent::Proceed(IdempotentRequest {
+            claimed: Some((user_id, key)),
+        }));
+    };
+    if record.request_hash != request_hash {
+        return Err(ApiError::UnprocessableEntity(
+            "Idempotency-Key was already used for a different request".to_string(),
+        ));
+    }
+    match (record.status_code, record.response) {
+        (Some(status), Some(response)) => {
+            let status = u16::try_from(status)
+                .ok()
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .unwrap_or(StatusCode::OK);
+            Ok(Idempotent::Replay(
+                HttpResponse::build(status)
+                    .insert_header(("Idempotent-Replayed", "true"))
+                    .json(response),
+            ))
+        }
+        _ => Err(ApiError::Conflict(
+            "A request with this Idempotency-Key is still in progress".to_string(),
+        )),
+    }
+}
+
+impl IdempotentRequest {
+    // Stores a successful response for replay. On an error the key is let
+    // go, so the client can retry with it once the problem is fixed.
+    pub async fn finish<T: Serialize>(
+        self,
+        pool: &PgPool,
+        status: StatusCode,
+        result: Result<T, ApiError>,
+    ) -> Result<T, ApiError> {
+        let Some((user_id, key)) = self.claimed else {
+            return result;
+        };
+        let stored = match &result {
+            Ok(value) => match serde_json::to_value(value) {
+                Ok(response) => {
+                    let status = i32::from(status.as_u16());
+                    store_idempotent_response(pool, user_id, &key, status, &response).await
+                }
+                Err(err) => Err(ApiError::DatabaseError(err.to_string())),
+            },
+            Err(_) => release_idempotency_key(pool, user_id, &key).await,
+        };
+        // The write itself went through either way; at worst a retry is
+        // refused with 409 until the key expires.
+        if let Err(err) = stored {
+            tracing::warn!("Could not record Idempotency-Key {}: {}", key, err);
+        }
+        result
+    }
+}
@@ -0,0 +1,177 @@
+// Bodies of POST /blog/import: a JSON array of posts, or CSV with a header
+// row naming the fields.
+
+use actix_web::error::PayloadError;
+
+use crate::*;
+
+// Columns of GET /blog/export?format=csv that aren't post fields. They are
+// skipped, so an export can be imported again.
+const EXPORT_ONLY_COLUMNS: [&str; 8] =
+    ["id", "uuid", "slug", "user_id", "author", "version", "created_at", "updated_at"];
+
+// The posts of an import. A CSV row that doesn't make a post is left out
+// and reported in `failed`; `rows` has the row each post came from.
+#[derive(Debug, Default)]
+pub struct ImportedPosts {
+    pub posts: Vec<NewBlogPost>,
+    pub rows: Vec<usize>,
+    pub failed: Vec<BulkFailure>,
+}
+
+impl From<Vec<NewBlogPost>> for ImportedPosts {
+    fn from(posts: Vec<NewBlogPost>) -> Self {
+        ImportedPosts {
+            rows: (0..posts.len()).collect(),
+            posts,
+            failed: Vec::new(),
+        }
+    }
+}
+
+impl ImportedPosts {
+    // One post per row after the header. `title`, `content`, `tags` (joined
+    // with `;`, as exported), `status` and `publish_at` are read; empty
+    // cells are left out. Other columns are unknown fields, as they would
+    // be in JSON. Rows are numbered from 0, not counting the header.
+    pub fn from_csv(text: &str) -> Result<Self, ApiError> {
+        let mut records = parse_csv(text)?.into_iter();
+        let Some(header) = records.next() else {
+            return Err(ApiError::UnprocessableEntity("The CSV has no header row".to_string()));
+        };
+        let header: Vec<String> = header.iter().map(|name| name.trim().to_lowercase()).collect();
+        for field in ["title", "content"] {
+            if !header.iter().any(|name| name == field) {
+                return Err(ApiError::UnprocessableEntity(format!(
+                    "The CSV header has no `{}` column",
+                    field
+                )));
+            }
+        }
+
+        let mut imported = ImportedPosts::default();
+        for (row, record) in records.enumerate() {
+            if record.len() != header.len() {
+                let error = format!("Row has {} fields, the header {}", record.len(), header.len());
+                imported.failed.push(BulkFailure { index: row, error });
+                continue;
+            }
+            let mut fields = serde_json::Map::new();
+            for (name, value) in header.iter().zip(record) {
+                if EXPORT_ONLY_COLUMNS.contains(&name.as_str())
+                    || (value.is_empty() && !matches!(name.as_str(), "title" | "content"))
+                {
+                    continue;
+                }
+                let value = match name.as_str() {
+                    "tags" => value.split(';').map(str::trim).collect(),
+                    _ => serde_json::Value::String(value),
+                };
+                fields.insert(name.clone(), value);
+            }
+            match serde_json::from_value(serde_json::Value::Object(fields)) {
+                Ok(post) => {
+                    imported.posts.push(post);
+                    imported.rows.push(row);
+                }
+                Err(err) => {
+                    imported.failed.push(BulkFailure { index: row, error: err.to_string() });
+                }
+            }
+        }
+        Ok(imported)
+    }
+
+    // `result` of creating `posts`, with its indexes turned back into rows
+    // and the rows that never made a post added.
+    pub fn merge(&self, mut result: BulkResult) -> BulkResult {
+        for failure in &mut result.failed {
+            failure.index = self.rows[failure.index];
+        }
+        result.failed.extend(self.failed.iter().cloned());
+        result.failed.sort_by_key(|failure| failure.index);
+        result
+    }
+
+    // Under all_or_nothing a bad row fails the whole import, as any other
+    // bad item does.
+    pub fn check(&self, mode: BulkMode) -> Result<(), ApiError> {
+        match self.failed.first() {
+            Some(failure) if mode == BulkMode::AllOrNothing => {
+                let error = ApiError::UnprocessableEntity(failure.error.clone());
+                Err(error.at_index(failure.index))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromRequest for ImportedPosts {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let csv = req.mime_type().ok().flatten().is_some_and(|mime| mime.subtype() == "csv");
+        if csv {
+            let limit = req
+                .app_data::<web::Data<LimitsConfig>>()
+                .map_or(LimitsConfig::default().bulk_json_bytes, |l| l.bulk_json_bytes);
+            let bytes = web::Bytes::from_request(req, payload);
+            return Box::pin(async move {
+                let bytes = bytes.await.map_err(|err| match err.as_error::<PayloadError>() {
+                    Some(PayloadError::Overflow) => ApiError::BodyTooLarge(limit).into(),
+                    _ => err,
+                })?;
+                let text = std::str::from_utf8(&bytes).map_err(|_| {
+                    ApiError::UnprocessableEntity("The CSV must be UTF-8".to_string())
+                })?;
+                Ok(ImportedPosts::from_csv(text)?)
+            });
+        }
+        let json = web::Json::<Vec<NewBlogPost>>::from_request(req, payload);
+        Box::pin(async move { Ok(ImportedPosts::from(json.await?.into_inner())) })
+    }
+}
+
+// RFC 4180 records: fields split by commas, quoted ones may hold commas,
+// line breaks and doubled quotes. Lines end in CRLF or LF; a blank line
+// is no record.
+pub fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, ApiError> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.strip_prefix('\u{feff}').unwrap_or(text).chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            _ if quoted => field.push(c),
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                if record != [""] {
+                    records.push(std::mem::take(&mut record));
+                }
+                record.clear();
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(ApiError::UnprocessableEntity("The CSV has an unclosed quote".to_string()));
+    }
+    record.push(field);
+    if record != [""] {
+        records.push(record);
+    }
+    Ok(records)
+}
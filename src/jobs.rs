@@ -0,0 +1,291 @@
+// The background job queue: work queued in the jobs table and run by
+// worker tasks, with retries and backoff, off the request path.
+
+use crate::*;
+
+// How a job went wrong: worth another try later, or not.
+#[derive(Debug)]
+pub enum JobError {
+    Retry(String),
+    Fail(String),
+}
+
+impl From<ApiError> for JobError {
+    // A busy or unreachable database may be fine by the next try; bad
+    // input won't be.
+    fn from(err: ApiError) -> Self {
+        match err {
+            ApiError::DatabaseError(_)
+            | ApiError::DatabaseUnavailable(_)
+            | ApiError::ServiceUnavailable(_)
+            | ApiError::InsufficientStorage(_) => JobError::Retry(err.to_string()),
+            _ => JobError::Fail(err.to_string()),
+        }
+    }
+}
+
+// Runs one kind of job. The payload is whatever was queued with it; the
+// value returned is kept as the job's result.
+#[async_trait]
+pub trait JobHandler: Send + Sync {
+    async fn run(&self, payload: serde_json::Value) -> Result<Option<serde_json::Value>, JobError>;
+}
+
+// What a worker needs of a job it claimed.
+#[derive(FromRow)]
+pub struct ClaimedJob {
+    pub id: i32,
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    pub max_attempts: i32,
+}
+
+// The longest wait between two tries, however many have failed.
+pub(crate) const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+// JOB_WORKERS: worker tasks per instance (0 runs none, leaving the jobs to
+// other instances). JOB_MAX_ATTEMPTS: tries before a job is failed.
+// JOB_RETRY_BASE_SECS: the wait after the first failure, doubled after
+// each one. JOB_TIMEOUT_SECS: how long a try may take. JOB_POLL_MS: how
+// often idle workers look for jobs queued by other instances.
+#[derive(Debug, Clone)]
+pub struct JobSettings {
+    pub workers: usize,
+    pub max_attempts: i32,
+    pub retry_base: Duration,
+    pub timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+impl JobSettings {
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+        }
+        JobSettings {
+            workers: var("JOB_WORKERS", 2),
+            max_attempts: var("JOB_MAX_ATTEMPTS", 5).max(1),
+            retry_base: Duration::from_secs(var("JOB_RETRY_BASE_SECS", 10)),
+            timeout: Duration::from_secs(var("JOB_TIMEOUT_SECS", 300).max(1)),
+            poll_interval: Duration::from_millis(var("JOB_POLL_MS", 1000).max(10)),
+        }
+    }
+
+    // retry_base, then twice that, four times, ... up to MAX_RETRY_DELAY.
+    pub fn retry_delay(&self, attempts: i32) -> Duration {
+        let doublings = attempts.saturating_sub(1).clamp(0, 20) as u32;
+        self.retry_base
+            .saturating_mul(2u32.pow(doublings))
+            .min(MAX_RETRY_DELAY)
+    }
+}
+
+pub struct JobQueue {
+    pool: PgPool,
+    settings: JobSettings,
+    handlers: HashMap<&'static str, Arc<dyn JobHandler>>,
+    wake: Notify,
+    closed: AtomicBool,
+}
+
+impl JobQueue {
+    pub fn new(pool: PgPool, settings: JobSettings) -> Self {
+        JobQueue {
+            pool,
+            settings,
+            handlers: HashMap::new(),
+            wake: Notify::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn register(&mut self, kind: &'static str, handler: Arc<dyn JobHandler>) {
+        self.handlers.insert(kind, handler);
+    }
+
+    pub fn settings(&self) -> &JobSettings {
+        &self.settings
+    }
+
+    // Queues inside the caller's transaction. Call `wake` once it commits.
+    // The job is listed to the tenant's admins.
+    pub async fn enqueue_in<T: Serialize>(
+        &self,
+        conn: &mut PgConnection,
+        tenant: i32,
+        kind: &str,
+        payload: &T,
+    ) -> Result<Job, ApiError> {
+        let payload = serde_json::to_value(payload)
+            .map_err(|err| ApiError::DatabaseError(format!("Could not queue job: {}", err)))?;
+        create_job(conn, tenant, kind, &payload, self.settings.max_attempts).await
+    }
+
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    pub async fn enqueue<T: Serialize>(
+        &self,
+        tenant: i32,
+        kind: &str,
+        payload: &T,
+    ) -> Result<Job, ApiError> {
+        let mut conn = self.pool.acquire().await?;
+        let job = self.enqueue_in(&mut conn, tenant, kind, payload).await?;
+        self.wake();
+        Ok(job)
+    }
+
+    // Claims and runs the next due job. False when there was none.
+    pub async fn run_next(&self) -> Result<bool, ApiError> {
+        let Some(job) = claim_job(&self.pool, self.settings.timeout).await? else {
+            return Ok(false);
+        };
+        let outcome = match self.handlers.get(job.kind.as_str()) {
+            Some(handler) => {
+                match tokio::time::timeout(self.settings.timeout, handler.run(job.payload)).await {
+                    Ok(outcome) => outcome,
+                    Err(_) => Err(JobError::Retry(format!(
+                        "Timed out after {}s",
+                        self.settings.timeout.as_secs()
+                    ))),
+                }
+            }
+            None => Err(JobError::Fail(format!("No handler for `{}` jobs", job.kind))),
+        };
+
+        match outcome {
+            Ok(result) => complete_job(&self.pool, job.id, result).await?,
+            Err(JobError::Retry(err)) if job.attempts < job.max_attempts => {
+                let delay = self.settings.retry_delay(job.attempts);
+                tracing::warn!(
+                    "Job {} ({}) failed on try {} of {}, retrying in {}s: {}",
+                    job.id,
+                    job.kind,
+                    job.attempts,
+                    job.max_attempts,
+                    delay.as_secs(),
+                    err
+                );
+                let retry_at = Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default();
+                fail_job(&self.pool, job.id, &err, Some(retry_at)).await?;
+            }
+            Err(JobError::Retry(err) | JobError::Fail(err)) => {
+                tracing::error!("Job {} ({}) failed: {}", job.id, job.kind, err);
+                fail_job(&self.pool, job.id, &err, None).await?;
+            }
+        }
+        Ok(true)
+    }
+
+    // Workers stop once their current job is done. Jobs still pending wait
+    // in the table for the next start.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.wake.notify_waiters();
+    }
+
+    async fn work(&self) {
+        while !self.closed.load(Ordering::SeqCst) {
+            match self.run_next().await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(err) => tracing::warn!("Job worker could not reach the queue: {}", err),
+            }
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(self.settings.poll_interval) => {}
+            }
+        }
+    }
+}
+
+pub fn spawn_job_workers(queue: web::Data<JobQueue>) {
+    for _ in 0..queue.settings.workers {
+        let queue = queue.clone();
+        actix_web::rt::spawn(async move { queue.work().await });
+    }
+}
+
+// Payload of an `import` job: the posts as sent, unknown fields included,
+// so STRICT_FIELDS is checked when the job runs. CSV rows that made no
+// post are in `failed`, and `rows` has the row of each post.
+#[derive(Serialize, Deserialize)]
+pub struct ImportPayload {
+    pub posts: Vec<serde_json::Value>,
+    pub mode: BulkMode,
+    pub user_id: i32,
+    // Jobs queued before tenants existed are for the default one.
+    #[serde(default = "default_tenant")]
+    pub tenant_id: i32,
+    // Left out by jobs queued before CSV imports; rows are then the posts.
+    #[serde(default)]
+    pub rows: Option<Vec<usize>>,
+    #[serde(default)]
+    pub failed: Vec<BulkFailure>,
+}
+
+impl ImportPayload {
+    pub fn new(imported: &ImportedPosts, mode: BulkMode, author: &AuthUser) -> Self {
+        let posts = imported
+            .posts
+            .iter()
+            .map(|post| {
+                let mut value = serde_json::to_value(post).unwrap_or_default();
+                if let serde_json::Value::Object(fields) = &mut value {
+                    fields.extend(post.unknown_fields.clone());
+                }
+                value
+            })
+            .collect();
+        ImportPayload {
+            posts,
+            mode,
+            user_id: author.id,
+            tenant_id: author.tenant_id,
+            rows: Some(imported.rows.clone()),
+            failed: imported.failed.clone(),
+        }
+    }
+}
+
+// POST /blog/import?async=true.
+pub struct ImportJob {
+    pub pool: PgPool,
+    pub cache: web::Data<PostCache>,
+    pub feed: web::Data<ChangeFeed>,
+    pub limiter: web::Data<HeavyQueryLimiter>,
+    pub moderator: web::Data<dyn Moderator>,
+}
+
+#[async_trait]
+impl JobHandler for ImportJob {
+    async fn run(&self, payload: serde_json::Value) -> Result<Option<serde_json::Value>, JobError> {
+        let payload: ImportPayload = serde_json::from_value(payload)
+            .map_err(|err| JobError::Fail(format!("Malformed import job: {}", err)))?;
+        let posts = payload
+            .posts
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<Result<Vec<NewBlogPost>, _>>()
+            .map_err(|err| JobError::Fail(format!("Malformed import job: {}", err)))?;
+        let imported = ImportedPosts {
+            rows: payload.rows.unwrap_or_else(|| (0..posts.len()).collect()),
+            posts,
+            failed: payload.failed,
+        };
+        let _permit = self.limiter.acquire().await?;
+        let tenant = payload.tenant_id;
+        let (moderator, posts) = (self.moderator.get_ref(), &imported.posts);
+        let result =
+            create_posts_bulk(&self.pool, moderator, tenant, posts, payload.mode, payload.user_id)
+                .await?;
+        self.cache.invalidate(tenant, None).await;
+        publish_created(&self.feed, tenant, &result.created);
+        let summary = serde_json::to_value(ImportSummary::from(imported.merge(result)))
+            .map_err(|err| JobError::Fail(err.to_string()))?;
+        Ok(Some(summary))
+    }
+}
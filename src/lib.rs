@@ -0,0 +1,165 @@
+// The blog API as a library: `app()` builds the configured actix App,
+// `run()` is the whole server and `Cli` the command line around it, so it
+// can be embedded or extended without touching main.rs. Items from every
+// module are re-exported here.
+
+pub(crate) use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    FromRequest,
+    http::header::{EntityTag, Header, HeaderName, HeaderValue, IfMatch, IfNoneMatch},
+    middleware::{from_fn, DefaultHeaders},
+    web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder,
+    post, get, put, patch, delete,
+    error::ResponseError,
+    http::StatusCode,
+};
+pub(crate) use actix_cors::Cors;
+pub(crate) use actix_multipart::Multipart;
+pub(crate) use async_graphql::http::GraphiQLSource;
+pub(crate) use async_graphql::{
+    Context, EmptySubscription, ErrorExtensions, InputObject, Object, Schema, SimpleObject,
+};
+pub(crate) use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+pub(crate) use async_trait::async_trait;
+pub(crate) use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+pub(crate) use chrono::{DateTime, SecondsFormat, Utc};
+pub(crate) use dotenv::dotenv;
+pub(crate) use futures_util::future::LocalBoxFuture;
+pub(crate) use futures_util::stream::{self, Stream, StreamExt};
+pub(crate) use serde::{de::DeserializeOwned, Deserialize, Serialize};
+pub(crate) use sha2::{Digest, Sha256};
+pub(crate) use tracing_actix_web::{RequestId, TracingLogger};
+pub(crate) use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions}, Acquire, FromRow, PgConnection, PgExecutor,
+    PgPool, Postgres, QueryBuilder,
+};
+pub(crate) use moka::sync::Cache;
+pub(crate) use prometheus::{
+    HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+pub(crate) use tracing_subscriber::prelude::*;
+pub(crate) use std::collections::{BTreeMap, HashMap};
+pub(crate) use std::env;
+pub(crate) use std::fmt;
+pub(crate) use std::path::PathBuf;
+pub(crate) use std::future::{ready, Future, Ready};
+pub(crate) use std::rc::Rc;
+pub(crate) use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+pub(crate) use std::sync::{Arc, OnceLock};
+pub(crate) use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+pub(crate) use tokio::sync::{broadcast, mpsc, Notify, OwnedSemaphorePermit, Semaphore};
+pub(crate) use validator::{Validate, ValidationError, ValidationErrors};
+pub(crate) use utoipa::{IntoParams, OpenApi, ToSchema};
+pub(crate) use utoipa_swagger_ui::SwaggerUi;
+pub(crate) use uuid::Uuid;
+
+mod db;
+mod config;
+mod logging;
+mod metrics;
+mod models;
+mod rendering;
+mod sanitize;
+mod syndication;
+mod sitemap;
+mod translation;
+mod etags;
+mod cache;
+mod auth;
+mod oauth;
+mod tenancy;
+mod change_feed;
+mod views;
+mod scheduling;
+mod pool;
+mod resilience;
+mod replicas;
+mod attachments;
+mod s3;
+mod storage;
+mod concurrency;
+mod rate_limit;
+mod quotas;
+mod limits;
+mod http_cache;
+mod shutdown;
+mod errors;
+mod i18n;
+mod repository;
+mod post_ids;
+mod jobs;
+mod imports;
+mod notifications;
+mod webhooks;
+mod moderation;
+mod revisions;
+mod idempotency;
+mod tls;
+mod compression;
+mod formats;
+mod links;
+mod graphql;
+mod handlers;
+mod server;
+mod cli;
+mod fixtures;
+mod docs;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(test)]
+mod tests;
+
+pub use db::*;
+pub use config::*;
+pub use logging::*;
+pub use metrics::*;
+pub use models::*;
+pub use rendering::*;
+pub use sanitize::*;
+pub use syndication::*;
+pub use sitemap::*;
+pub use translation::*;
+pub use etags::*;
+pub use cache::*;
+pub use auth::*;
+pub use oauth::*;
+pub use tenancy::*;
+pub use change_feed::*;
+pub use views::*;
+pub use scheduling::*;
+pub use pool::*;
+pub use resilience::*;
+pub use replicas::*;
+pub use attachments::*;
+pub use s3::*;
+pub use storage::*;
+pub use concurrency::*;
+pub use rate_limit::*;
+pub use quotas::*;
+pub use limits::*;
+pub use http_cache::*;
+pub use shutdown::*;
+pub use errors::*;
+pub use i18n::*;
+pub use repository::*;
+pub use post_ids::*;
+pub use jobs::*;
+pub use imports::*;
+pub use notifications::*;
+pub use webhooks::*;
+pub use moderation::*;
+pub use revisions::*;
+pub use idempotency::*;
+pub use tls::*;
+pub use compression::*;
+pub use formats::*;
+pub use links::*;
+pub use graphql::*;
+pub use handlers::*;
+pub use server::*;
+pub use cli::*;
+pub use fixtures::*;
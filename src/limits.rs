@@ -0,0 +1,52 @@
+// Request size limits from LimitsConfig: the JSON body limit, raised on
+// the routes that take many posts at once, and the query parameters that
+// are matched against the database.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::Extensions;
+use actix_web::error::JsonPayloadError;
+use actix_web::middleware::Next;
+
+use crate::*;
+
+// JSON bodies up to `limit` bytes, with parse failures and oversized
+// bodies answered as problems.
+pub fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default().limit(limit).error_handler(move |err, _| match err {
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            ApiError::BodyTooLarge(limit).into()
+        }
+        _ => ApiError::BadRequest(err.to_string()).into(),
+    })
+}
+
+// For `wrap` on a route: its JSON body, or any other, may be up to
+// `bulk_json_bytes`. The route's own JsonConfig comes before the app's.
+pub(crate) async fn bulk_json_limit(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let limits = req.app_data::<web::Data<LimitsConfig>>();
+    let limit = limits.map_or(LimitsConfig::default().bulk_json_bytes, |l| l.bulk_json_bytes);
+    let mut container = Extensions::new();
+    container.insert(json_config(limit));
+    container.insert(web::PayloadConfig::new(limit));
+    req.add_data_container(Rc::new(container));
+    next.call(req).await
+}
+
+impl LimitsConfig {
+    // 422 when the query parameter `name` is longer than `query_chars`,
+    // so the database never has to look for it.
+    pub fn check_query(&self, name: &str, value: Option<&str>) -> Result<(), ApiError> {
+        match value {
+            Some(value) if value.chars().count() > self.query_chars => {
+                Err(ApiError::UnprocessableEntity(format!(
+                    "Query parameter `{}` must be at most {} characters",
+                    name, self.query_chars
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
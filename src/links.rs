@@ -0,0 +1,100 @@
+// Absolute URLs to API resources, for the Link headers on paged listings
+// and the Location header on 201 responses.
+
+use actix_web::http::header::{LINK, LOCATION};
+use actix_web::HttpResponseBuilder;
+
+use crate::*;
+
+pub(crate) static API_BASE_URL: OnceLock<Option<String>> = OnceLock::new();
+
+// API_BASE_URL, e.g. `https://api.example.com`, is where clients reach the
+// server from outside. Unset, links use the scheme and host the request
+// came in on, which behind a proxy means its Forwarded or X-Forwarded-*
+// headers.
+pub fn api_base_url() -> Option<&'static str> {
+    API_BASE_URL
+        .get_or_init(|| {
+            let base = env::var("API_BASE_URL").ok()?;
+            Some(base.trim().trim_end_matches('/').to_string()).filter(|base| !base.is_empty())
+        })
+        .as_deref()
+}
+
+fn origin(req: &HttpRequest) -> String {
+    match api_base_url() {
+        Some(base) => base.to_string(),
+        None => {
+            let info = req.connection_info();
+            format!("{}://{}", info.scheme(), info.host())
+        }
+    }
+}
+
+// `path` under the API version the request came in on, so a request to
+// the unversioned routes gets unversioned links.
+pub fn resource_url(req: &HttpRequest, path: &str) -> String {
+    let scope = if req.path().starts_with(API_V1) { API_V1 } else { "" };
+    format!("{}{}{}", origin(req), scope, path)
+}
+
+// The request's own URL with `drop` left out of the query and `set`
+// appended, other parameters kept as they were sent.
+fn with_query(req: &HttpRequest, drop: &[&str], set: &[(&str, String)]) -> String {
+    let kept = req.query_string().split('&').filter(|pair| {
+        let name = pair.split('=').next().unwrap_or_default();
+        !pair.is_empty() && !drop.contains(&name)
+    });
+    let set = set.iter().map(|(name, value)| format!("{}={}", name, value));
+    let query: Vec<String> = kept.map(str::to_string).chain(set).collect();
+    format!("{}{}?{}", origin(req), req.path(), query.join("&"))
+}
+
+// `Link` (RFC 8288) with the `first`, `prev`, `next` and `last` pages of a
+// listing, and `Location` for what a request created.
+pub trait LinkHeaders {
+    fn page_links<T>(&mut self, req: &HttpRequest, page: &Page<T>) -> &mut Self;
+    fn cursor_links<T>(&mut self, req: &HttpRequest, page: &CursorPage<T>) -> &mut Self;
+    fn location(&mut self, req: &HttpRequest, path: &str) -> &mut Self;
+}
+
+fn link(url: String, rel: &str) -> String {
+    format!("<{}>; rel=\"{}\"", url, rel)
+}
+
+impl LinkHeaders for HttpResponseBuilder {
+    // `prev` and `next` only when there is such a page; past the end,
+    // `prev` is the last one. An estimated total makes `last` an estimate.
+    fn page_links<T>(&mut self, req: &HttpRequest, page: &Page<T>) -> &mut Self {
+        let last = page.total_pages.max(1);
+        let url = |n: i64| {
+            let set = [("page", n.to_string()), ("per_page", page.per_page.to_string())];
+            with_query(req, &["page", "per_page"], &set)
+        };
+        let mut links = vec![link(url(1), "first")];
+        if page.page > 1 {
+            links.push(link(url((page.page - 1).min(last)), "prev"));
+        }
+        if page.page < last {
+            links.push(link(url(page.page + 1), "next"));
+        }
+        links.push(link(url(last), "last"));
+        self.insert_header((LINK, links.join(", ")))
+    }
+
+    // Keyset pages only know the way forward.
+    fn cursor_links<T>(&mut self, req: &HttpRequest, page: &CursorPage<T>) -> &mut Self {
+        let limit = ("limit", page.limit.to_string());
+        let first = with_query(req, &["after", "limit"], std::slice::from_ref(&limit));
+        let mut links = vec![link(first, "first")];
+        if let Some(cursor) = &page.next_cursor {
+            let set = [("after", cursor.clone()), limit];
+            links.push(link(with_query(req, &["after", "limit"], &set), "next"));
+        }
+        self.insert_header((LINK, links.join(", ")))
+    }
+
+    fn location(&mut self, req: &HttpRequest, path: &str) -> &mut Self {
+        self.insert_header((LOCATION, resource_url(req, path)))
+    }
+}
@@ -0,0 +1,104 @@
+// Tracing setup and the X-Request-Id echo.
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::filter::FilterExt;
+
+use crate::*;
+
+fn log_filter(config: &Config) -> tracing_subscriber::EnvFilter {
+    tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(&config.log_level))
+}
+
+// Sends spans over OTLP/HTTP when OTEL_EXPORTER_OTLP_ENDPOINT (or
+// OTEL_EXPORTER_OTLP_TRACES_ENDPOINT) is set, as `OTEL_SERVICE_NAME`
+// (default `rest_api`). OTEL_TRACES_SAMPLER and OTEL_TRACES_SAMPLER_ARG
+// pick what is sampled; by default everything, unless the caller's
+// traceparent says it wasn't sampled there.
+fn tracer_provider() -> Option<SdkTracerProvider> {
+    let configured = |name| env::var(name).is_ok_and(|value: String| !value.trim().is_empty());
+    let endpoints = ["OTEL_EXPORTER_OTLP_ENDPOINT", "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"];
+    if !endpoints.into_iter().any(configured) {
+        return None;
+    }
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .unwrap_or_else(|err| panic!("Invalid OTLP exporter settings: {}", err));
+    let service_name = env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "rest_api".to_string());
+    let resource = Resource::builder()
+        .with_service_name(service_name)
+        .with_attribute(opentelemetry::KeyValue::new(
+            "service.version",
+            env!("CARGO_PKG_VERSION"),
+        ))
+        .build();
+    Some(SdkTracerProvider::builder().with_batch_exporter(exporter).with_resource(resource).build())
+}
+
+// Spans as OpenTelemetry spans of `provider`. A request's span continues
+// the trace in its traceparent header, which TracingLogger reads.
+pub fn otel_layer<S>(
+    provider: &SdkTracerProvider,
+) -> tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_CRATE_NAME")))
+}
+
+// Flushes the spans not yet exported when dropped, which run() and the
+// CLI commands do as they return.
+#[must_use]
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take()
+            && let Err(err) = provider.shutdown()
+        {
+            eprintln!("Could not export the last spans: {}", err);
+        }
+    }
+}
+
+// Events from `log` users (sqlx, redis) are forwarded into tracing too.
+// RUST_LOG, when set, still wins over LOG_LEVEL for per-module filters.
+pub fn init_tracing(config: &Config) -> Telemetry {
+    let filter = log_filter(config);
+    // Closing a span logs it with its timings: one line per request at info,
+    // plus one per query function at debug.
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE);
+    // Each JSON line carries the fields of the spans it happened in,
+    // request_id included.
+    let fmt = if config.log_format == "json" {
+        fmt.json().boxed()
+    } else {
+        fmt.boxed()
+    };
+    // The query spans are exported whatever LOG_LEVEL is, like their
+    // timings.
+    let provider = tracer_provider();
+    let otel = provider.as_ref().map(|provider| {
+        let queries = tracing_subscriber::filter::filter_fn(is_query_span);
+        otel_layer(provider).with_filter(log_filter(config).or(queries))
+    });
+    // The query timings have their own filter, so /metrics has them
+    // whatever LOG_LEVEL is; the slow-query warnings go through `filter`.
+    tracing_subscriber::registry()
+        .with(fmt.with_filter(filter))
+        .with(
+            QueryTimings::new(config)
+                .with_filter(tracing_subscriber::filter::filter_fn(is_query_span)),
+        )
+        .with(otel)
+        .init();
+    Telemetry { provider }
+}
@@ -0,0 +1,258 @@
+// Prometheus metrics for GET /metrics.
+
+use crate::*;
+
+// Prometheus figures served at GET /metrics. One set per process, shared by
+// every worker; request labels use the matched route pattern, never the
+// raw path, so ids don't multiply the series.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    query_duration: HistogramVec,
+    pool_connections: IntGaugeVec,
+    pool_max_connections: IntGauge,
+    pool_timeouts: IntCounter,
+}
+
+pub(crate) static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests answered"),
+            &["method", "route", "status"],
+        )
+        .expect("valid metric");
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time to answer HTTP requests"),
+            &["method", "route", "status"],
+        )
+        .expect("valid metric");
+        let query_duration = HistogramVec::new(
+            HistogramOpts::new("db_query_duration_seconds", "Time spent in query functions"),
+            &["query"],
+        )
+        .expect("valid metric");
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database connections by state"),
+            &["state"],
+        )
+        .expect("valid metric");
+        let pool_max_connections =
+            IntGauge::new("db_pool_max_connections", "Size limit of the database pool")
+                .expect("valid metric");
+        let pool_timeouts = IntCounter::new(
+            "db_pool_acquire_timeouts_total",
+            "Queries that found no free connection within DB_ACQUIRE_TIMEOUT_SECS",
+        )
+        .expect("valid metric");
+        let registry = Registry::new();
+        for collector in [
+            Box::new(requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(request_duration.clone()),
+            Box::new(query_duration.clone()),
+            Box::new(pool_connections.clone()),
+            Box::new(pool_max_connections.clone()),
+            Box::new(pool_timeouts.clone()),
+        ] {
+            registry.register(collector).expect("metric registered once");
+        }
+        Metrics {
+            registry,
+            requests,
+            request_duration,
+            query_duration,
+            pool_connections,
+            pool_max_connections,
+            pool_timeouts,
+        }
+    }
+
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.requests.with_label_values(&labels).inc();
+        self.request_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_query(&self, query: &str, elapsed: Duration) {
+        self.query_duration
+            .with_label_values(&[query])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_pool_timeout(&self) {
+        self.pool_timeouts.inc();
+    }
+
+    pub fn pool_timeouts(&self) -> u64 {
+        self.pool_timeouts.get()
+    }
+
+    // The pool gauges are read when scraped rather than kept up to date.
+    pub fn render(&self, pool: &PgPool) -> String {
+        let idle = pool.num_idle() as i64;
+        self.pool_connections.with_label_values(&["idle"]).set(idle);
+        self.pool_connections
+            .with_label_values(&["in_use"])
+            .set(pool.size() as i64 - idle);
+        self.pool_max_connections
+            .set(pool.options().get_max_connections() as i64);
+        self.encode()
+    }
+
+    pub fn encode(&self) -> String {
+        let mut text = String::new();
+        TextEncoder::new()
+            .encode_utf8(&self.registry.gather(), &mut text)
+            .expect("text encoding doesn't fail");
+        text
+    }
+}
+
+// Counts and times every response. Requests that matched no route are
+// all labelled `unmatched`.
+pub fn record_request<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>> + use<S, B>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let method = req.method().to_string();
+    let started = Instant::now();
+    let fut = srv.call(req);
+    async move {
+        let res = fut.await?;
+        let route = res.request().match_pattern();
+        metrics().observe_request(
+            &method,
+            route.as_deref().unwrap_or("unmatched"),
+            res.status().as_u16(),
+            started.elapsed(),
+        );
+        Ok(res)
+    }
+}
+
+// The spans opened by the `#[tracing::instrument]`ed query functions in
+// the SQLX section.
+pub(crate) fn is_query_span(meta: &tracing::Metadata<'_>) -> bool {
+    meta.is_span()
+        && meta.target().starts_with(env!("CARGO_CRATE_NAME"))
+        && *meta.level() == tracing::Level::DEBUG
+}
+
+// Times each query span from creation to close, so the time spent waiting
+// for a connection counts too. Spans over `slow` are also logged at warn,
+// with their fields. The query functions skip their arguments and record
+// only ids, filters and limits, so no post content or password gets into
+// the log.
+pub(crate) struct QueryTimings {
+    pub slow: Option<Duration>,
+}
+
+impl QueryTimings {
+    // DB_SLOW_QUERY_MS, where 0 logs no query.
+    pub fn new(config: &Config) -> Self {
+        QueryTimings {
+            slow: (config.slow_query_ms > 0).then(|| Duration::from_millis(config.slow_query_ms)),
+        }
+    }
+}
+
+// When a query span opened, and its fields so far as `tenant=1 limit=20`.
+struct QueryStart {
+    started: Instant,
+    params: String,
+}
+
+struct ParamSummary<'a>(&'a mut String);
+
+impl tracing::field::Visit for ParamSummary<'_> {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        self.0.push_str(&format!("{}={:?}", field.name(), value));
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for QueryTimings
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        if let Some(span) = ctx.span(id) {
+            let mut params = String::new();
+            attrs.record(&mut ParamSummary(&mut params));
+            span.extensions_mut().insert(QueryStart {
+                started: Instant::now(),
+                params,
+            });
+        }
+    }
+
+    fn on_record(
+        &self,
+        id: &tracing::span::Id,
+        values: &tracing::span::Record<'_>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        if let Some(span) = ctx.span(id)
+            && let Some(start) = span.extensions_mut().get_mut::<QueryStart>()
+        {
+            values.record(&mut ParamSummary(&mut start.params));
+        }
+    }
+
+    fn on_close(&self, id: tracing::span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        // Out of the extensions before logging, which looks at spans too.
+        let Some((elapsed, params)) = span
+            .extensions_mut()
+            .remove::<QueryStart>()
+            .map(|start| (start.started.elapsed(), start.params))
+        else {
+            return;
+        };
+        metrics().observe_query(span.name(), elapsed);
+        if self.slow.is_some_and(|slow| elapsed >= slow) {
+            tracing::warn!(
+                query = span.name(),
+                duration_ms = elapsed.as_millis() as u64,
+                params = %params,
+                "Slow query",
+            );
+        }
+    }
+}
+
+// Echoes the id TracingLogger gave the request (it is on every log line of
+// the request's span) as X-Request-Id, so a client report can be matched
+// to the server logs.
+pub fn echo_request_id<B>(mut res: ServiceResponse<B>) -> ServiceResponse<B> {
+    let value = res
+        .request()
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| HeaderValue::from_str(&id.to_string()).ok());
+    if let Some(value) = value {
+        res.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+    }
+    res
+}
@@ -0,0 +1,1530 @@
+// Request and response types, and the validation and paging rules that
+// go with them.
+
+use crate::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow, ToSchema, SimpleObject)]
+pub struct BlogPost {
+    pub id: i32,
+    // The public key; a UUID v7, so it sorts by creation time. With
+    // ID_STRATEGY=uuid it is the only one the routes take.
+    pub uuid: Uuid,
+    // The key ID_STRATEGY=snowflake routes by. Posts from before snowflake
+    // ids came in have none.
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub snowflake_id: Option<i64>,
+    pub title: String,
+    // Made from the title on create and unique; GET /blog/slug/{slug}.
+    pub slug: String,
+    pub content: String,
+    pub user_id: i32,
+    // Username of the owning user, joined in by every post query.
+    pub author: String,
+    // Sorted tag names.
+    pub tags: Vec<String>,
+    // Starts at 1 and goes up by one with every update; PUT and PATCH must
+    // send the version they were based on.
+    pub version: i32,
+    // One of PostStatus's names.
+    pub status: String,
+    // How many readers like it. Not part of the ETag, so liking a post
+    // doesn't make a client's If-Match stale.
+    pub like_count: i32,
+    // Lags by up to VIEW_FLUSH_SECS; also left out of the ETag.
+    pub view_count: i64,
+    // When a scheduled draft is to be published; only drafts have one.
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub publish_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    // Moves with every change to the version.
+    pub updated_at: DateTime<Utc>,
+}
+
+// Only published posts are public; the others are visible to their author
+// and admins.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[derive(async_graphql::Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum PostStatus {
+    Draft,
+    #[default]
+    Published,
+    Archived,
+}
+
+impl PostStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PostStatus::Draft => "draft",
+            PostStatus::Published => "published",
+            PostStatus::Archived => "archived",
+        }
+    }
+}
+
+// The author is always the authenticated caller, so it isn't part of the
+// body; an `author` sent by older clients lands in `unknown_fields`.
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+pub struct NewBlogPost {
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = "MAX_TITLE_CHARS", message = "must be at most 200 characters")
+    )]
+    #[schema(min_length = 1, max_length = 200)]
+    pub title: String,
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = "MAX_CONTENT_CHARS", message = "must be at most 100000 characters")
+    )]
+    #[schema(min_length = 1, max_length = 100000)]
+    pub content: String,
+    // Replaces the post's tags when present; leaving it out of an update
+    // keeps the current ones.
+    pub tags: Option<Vec<String>>,
+    // Required by PUT, where it must be the stored version; ignored when
+    // creating.
+    pub version: Option<i32>,
+    // Defaults to published when creating; PUT keeps the current status.
+    pub status: Option<PostStatus>,
+    // Creates the post as a draft to be published at this time. PUT keeps
+    // the current schedule.
+    pub publish_at: Option<DateTime<Utc>>,
+    // Anything else the client sent. Never stored; only inspected when
+    // STRICT_FIELDS=1 so unknown fields can be rejected.
+    #[serde(flatten, skip_serializing)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
+}
+
+// Body of PATCH /blog/{id}: only the fields present are changed, with the
+// same limits as NewBlogPost.
+#[derive(Deserialize, Debug, ToSchema, Validate)]
+pub struct UpdateBlogPost {
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = "MAX_TITLE_CHARS", message = "must be at most 200 characters")
+    )]
+    pub title: Option<String>,
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = "MAX_CONTENT_CHARS", message = "must be at most 100000 characters")
+    )]
+    pub content: Option<String>,
+    pub tags: Option<Vec<String>>,
+    // Required; see NewBlogPost::version.
+    pub version: Option<i32>,
+    pub status: Option<PostStatus>,
+    // A time schedules the draft, `null` takes it off the schedule.
+    #[serde(default, deserialize_with = "present")]
+    #[schema(value_type = Option<DateTime<Utc>>)]
+    pub publish_at: Option<Option<DateTime<Utc>>>,
+    #[serde(flatten)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
+}
+
+// Tells a field sent as null, Some(None), from one left out, None.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+const ONLY_DRAFTS_SCHEDULED: &str = "Only drafts can have a publish_at";
+
+impl NewBlogPost {
+    // What the post is created as: scheduled posts start as drafts.
+    pub fn initial_status(&self) -> Result<PostStatus, ApiError> {
+        match (self.publish_at, self.status) {
+            (None, status) => Ok(status.unwrap_or_default()),
+            (Some(_), None | Some(PostStatus::Draft)) => Ok(PostStatus::Draft),
+            (Some(_), Some(_)) => {
+                Err(ApiError::UnprocessableEntity(ONLY_DRAFTS_SCHEDULED.to_string()))
+            }
+        }
+    }
+}
+
+impl UpdateBlogPost {
+    // The publish_at a post in `status` has after this patch, for stores
+    // that keep schedules. Moving a post out of draft takes it off the
+    // schedule; only a draft can be put on it.
+    pub fn scheduled(
+        &self,
+        status: &str,
+        publish_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<DateTime<Utc>>, ApiError> {
+        let is_draft = match self.status {
+            Some(status) => status == PostStatus::Draft,
+            None => status == PostStatus::Draft.as_str(),
+        };
+        match self.publish_at {
+            Some(Some(_)) if !is_draft => {
+                Err(ApiError::UnprocessableEntity(ONLY_DRAFTS_SCHEDULED.to_string()))
+            }
+            Some(publish_at) => Ok(publish_at),
+            None => Ok(publish_at.filter(|_| is_draft)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow, ToSchema)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    // One of Role's names.
+    pub role: String,
+    // For notifications; only set by the user.
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub email: Option<String>,
+    pub tenant_id: i32,
+    // The public profile, set with PUT /users/me/profile.
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub bio: Option<String>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub avatar_url: Option<String>,
+}
+
+// Ordered by how much they may do.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Reads, and comments.
+    #[default]
+    Reader,
+    // Also writes posts, and changes their own.
+    Editor,
+    // Changes anything, and manages roles.
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Reader => "reader",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+
+    // The column is CHECKed, so anything else is a bug and gets the least
+    // access.
+    pub fn from_db(role: &str) -> Self {
+        match role {
+            "admin" => Role::Admin,
+            "editor" => Role::Editor,
+            _ => Role::Reader,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct RoleUpdate {
+    pub role: Role,
+}
+
+// What a refused request would have needed.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Requirement {
+    Editor,
+    Admin,
+    // The author of the post or comment, or an admin.
+    AuthorOrAdmin,
+    // A read_write API key, or a bearer token.
+    ReadWrite,
+}
+
+impl Requirement {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Requirement::Editor => "editor",
+            Requirement::Admin => "admin",
+            Requirement::AuthorOrAdmin => "author_or_admin",
+            Requirement::ReadWrite => "read_write",
+        }
+    }
+}
+
+// Body of a 403.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PermissionDenied {
+    pub message: String,
+    pub required: Requirement,
+    // The caller's role.
+    pub role: Role,
+}
+
+impl fmt::Display for PermissionDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyScope {
+    // GET requests only.
+    #[default]
+    Read,
+    ReadWrite,
+}
+
+impl KeyScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KeyScope::Read => "read",
+            KeyScope::ReadWrite => "read_write",
+        }
+    }
+}
+
+// A key acts as its user, with the user's current role.
+#[derive(Serialize, Debug, Clone, FromRow, ToSchema)]
+pub struct ApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub username: String,
+    pub name: String,
+    // The start of the key, to tell keys apart.
+    pub prefix: String,
+    // One of KeyScope's names.
+    pub scope: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewApiKey {
+    pub name: String,
+    #[serde(default)]
+    pub scope: KeyScope,
+    // Defaults to the admin creating the key.
+    pub user_id: Option<i32>,
+}
+
+// The only time the key itself is shown.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CreatedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
+
+#[derive(Serialize, Debug, Clone, FromRow, ToSchema)]
+pub struct Webhook {
+    pub id: i32,
+    pub user_id: i32,
+    pub url: String,
+    // Which of post.created, post.updated and post.deleted are sent.
+    pub events: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewWebhook {
+    pub url: String,
+    // Every event when left out.
+    pub events: Option<Vec<String>>,
+}
+
+// The only time the secret is shown.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CreatedWebhook {
+    pub secret: String,
+    #[serde(flatten)]
+    pub webhook: Webhook,
+}
+
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    // pending until delivered, or failed once its job gives up.
+    pub status: String,
+    pub attempts: i32,
+    // From the last try; none when it got no response at all.
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub response_status: Option<i32>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub error: Option<String>,
+    pub payload: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveriesQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    // Where notifications go; none are sent without one.
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+// Body of PUT /users/me/email; null removes the address.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct UserEmail {
+    pub email: Option<String>,
+}
+
+// Body of PUT /users/me/profile. It replaces the whole profile: a field
+// left out or null is cleared.
+#[derive(Deserialize, Debug, ToSchema, Validate)]
+pub struct UserProfile {
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = 64, message = "must be at most 64 characters")
+    )]
+    #[schema(min_length = 1, max_length = 64)]
+    pub display_name: Option<String>,
+    #[validate(length(max = 1000, message = "must be at most 1000 characters"))]
+    #[schema(max_length = 1000)]
+    pub bio: Option<String>,
+    // An absolute http(s) URL.
+    #[validate(custom(function = "http_url"))]
+    pub avatar_url: Option<String>,
+}
+
+// Someone's public profile, as GET /authors shows it.
+#[derive(Serialize, Debug, Clone, FromRow, ToSchema)]
+pub struct Author {
+    pub id: i32,
+    pub username: String,
+    // The username when none was set.
+    pub display_name: String,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub bio: Option<String>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub avatar_url: Option<String>,
+    // Published posts only.
+    pub post_count: i64,
+}
+
+pub(crate) const MAX_TAGS_PER_POST: usize = 20;
+// Counted in characters, not bytes.
+pub(crate) const MAX_TITLE_CHARS: u64 = 200;
+pub(crate) const MAX_CONTENT_CHARS: u64 = 100_000;
+
+pub(crate) fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(ValidationError::new("blank").with_message("must not be empty".into()));
+    }
+    Ok(())
+}
+
+pub(crate) fn http_url(value: &str) -> Result<(), ValidationError> {
+    let ok = reqwest::Url::parse(value)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some());
+    if !ok {
+        return Err(ValidationError::new("url").with_message("must be an http(s) URL".into()));
+    }
+    Ok(())
+}
+
+// Trims and lowercases tag names, dropping duplicates, so `Rust` and
+// ` rust` are the same tag.
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>, ApiError> {
+    let mut normalized: Vec<String> = tags.iter().map(|tag| normalize_tag(tag)).collect();
+    normalized.sort();
+    normalized.dedup();
+    if let Some(tag) = normalized.iter().find(|tag| {
+        !(1..=32).contains(&tag.chars().count())
+            || !tag
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '+' | '#'))
+    }) {
+        return Err(ApiError::UnprocessableEntity(format!(
+            "Invalid tag `{}`: use 1-32 letters, digits or `-_.+#`",
+            tag
+        )));
+    }
+    if normalized.len() > MAX_TAGS_PER_POST {
+        return Err(ApiError::UnprocessableEntity(format!(
+            "A post can have at most {} tags",
+            MAX_TAGS_PER_POST
+        )));
+    }
+    Ok(normalized)
+}
+
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+// NULL_HANDLING controls how empty optional fields are serialized:
+// `include` (default) writes `null`, `omit` leaves the key out for clients
+// that can't cope with explicit nulls. Optional response fields opt in with
+// `#[serde(skip_serializing_if = "omit_if_null")]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NullHandling {
+    Include,
+    Omit,
+}
+
+pub(crate) static NULL_HANDLING: OnceLock<NullHandling> = OnceLock::new();
+
+// Lets a test serialize in one mode without changing it for the others
+// running in the same process.
+#[cfg(test)]
+thread_local! {
+    pub(crate) static NULL_HANDLING_OVERRIDE: std::cell::Cell<Option<NullHandling>> =
+        const { std::cell::Cell::new(None) };
+}
+
+pub fn null_handling() -> NullHandling {
+    #[cfg(test)]
+    if let Some(mode) = NULL_HANDLING_OVERRIDE.get() {
+        return mode;
+    }
+    *NULL_HANDLING.get_or_init(|| match env::var("NULL_HANDLING").as_deref() {
+        Ok("include") | Err(_) => NullHandling::Include,
+        Ok("omit") => NullHandling::Omit,
+        Ok(other) => panic!("Unknown NULL_HANDLING `{}`", other),
+    })
+}
+
+pub(crate) fn omit_if_null<T>(value: &Option<T>) -> bool {
+    value.is_none() && null_handling() == NullHandling::Omit
+}
+
+// STRICT_FIELDS=1 rejects request bodies carrying fields the model doesn't
+// know about. Off by default so existing clients keep working.
+pub(crate) fn strict_fields_enabled() -> bool {
+    env_flag("STRICT_FIELDS", false)
+}
+
+pub(crate) fn check_unknown_fields(
+    unknown_fields: &BTreeMap<String, serde_json::Value>,
+) -> Result<(), ApiError> {
+    if !strict_fields_enabled() {
+        return Ok(());
+    }
+    match unknown_fields.keys().next() {
+        Some(field) => Err(ApiError::UnprocessableEntity(format!(
+            "Unexpected field `{}`",
+            field
+        ))),
+        None => Ok(()),
+    }
+}
+
+// Both checks a post body goes through before it is stored; the bulk
+// endpoints run them per item, the single-post ones via ValidatedJson.
+pub(crate) fn check_new_post(post: &NewBlogPost) -> Result<(), ApiError> {
+    check_unknown_fields(&post.unknown_fields)?;
+    post.validate()?;
+    Ok(())
+}
+
+// `web::Json` that also runs the body's `Validate` rules, answering 422
+// with the problems per field instead of reaching the handler.
+#[derive(Debug)]
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> std::ops::Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidatedJson<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let value = json.await?.into_inner();
+            value.validate().map_err(ApiError::from)?;
+            Ok(ValidatedJson(value))
+        })
+    }
+}
+
+// Body of a 422 from ValidatedJson.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ValidationFailure {
+    pub message: String,
+    // Field name to what is wrong with it.
+    pub errors: BTreeMap<String, Vec<String>>,
+}
+
+impl fmt::Display for ValidationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for (index, (field, problems)) in self.errors.iter().enumerate() {
+            let separator = if index == 0 { ": " } else { "; " };
+            write!(f, "{}{} {}", separator, field, problems.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        let errors = errors
+            .field_errors()
+            .into_iter()
+            .map(|(field, problems)| {
+                let problems = problems
+                    .iter()
+                    .map(|problem| match &problem.message {
+                        Some(message) => message.to_string(),
+                        None => problem.code.to_string(),
+                    })
+                    .collect();
+                (field.to_string(), problems)
+            })
+            .collect();
+        ApiError::Validation(ValidationFailure {
+            message: "Validation failed".to_string(),
+            errors,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PostQuery {
+    #[serde(default)]
+    pub anchors: bool,
+    pub lang: Option<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Paragraph {
+    pub anchor: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct AnchoredBlogPost {
+    pub id: i32,
+    pub uuid: Uuid,
+    pub title: String,
+    pub author: String,
+    pub paragraphs: Vec<Paragraph>,
+    pub version: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromRow, ToSchema)]
+pub struct Translation {
+    pub id: i32,
+    pub post_id: i32,
+    pub lang: String,
+    pub title: String,
+    pub content: String,
+}
+
+// An image uploaded to a post. The bytes are in the attachment store; they
+// are downloaded from GET /blog/{post_id}/images/{id}.
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct Attachment {
+    pub id: i32,
+    pub post_id: i32,
+    pub user_id: i32,
+    // Name the uploader gave the file, without any directories.
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    #[serde(skip_serializing)]
+    pub storage_key: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// An image the client uploads to the attachment store itself.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewDirectUpload {
+    pub filename: Option<String>,
+    // image/png, image/jpeg, image/gif or image/webp.
+    pub content_type: String,
+    pub size_bytes: i64,
+}
+
+// The attachment, and the request that puts its file in the store.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DirectUpload {
+    pub attachment: Attachment,
+    pub upload: PresignedUpload,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromRow, ToSchema)]
+pub struct Comment {
+    pub id: i32,
+    pub post_id: i32,
+    pub user_id: i32,
+    // Username of the commenter, joined in like BlogPost::author.
+    pub author: String,
+    pub body: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewComment {
+    pub body: String,
+}
+
+// Someone who likes a post, in GET /blog/{id}/likes.
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct Like {
+    pub user_id: i32,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// The post's count after a like or an unlike.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct LikeCount {
+    pub post_id: i32,
+    pub like_count: i32,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct TranslateRequest {
+    pub lang: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CountMode {
+    Exact,
+    Estimate,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, async_graphql::Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum SortColumn {
+    Id,
+    Title,
+    Author,
+    #[serde(rename = "created_at")]
+    CreatedAt,
+    #[serde(rename = "updated_at")]
+    UpdatedAt,
+}
+
+impl SortColumn {
+    // Only these fixed names ever reach the ORDER BY clause.
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            SortColumn::Id => "p.id",
+            SortColumn::Title => "p.title",
+            SortColumn::Author => "u.username",
+            SortColumn::CreatedAt => "p.created_at",
+            SortColumn::UpdatedAt => "p.updated_at",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, async_graphql::Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    pub count: Option<CountMode>,
+    pub sort: Option<SortColumn>,
+    pub order: Option<SortOrder>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    pub tag: Option<String>,
+    // Username of the post's author, matched exactly.
+    pub author: Option<String>,
+    // Adds each post's rendered first paragraph as `excerpt`.
+    #[serde(default)]
+    pub excerpt: bool,
+    // Drafts and archived posts only show up for their author or an admin.
+    pub status: Option<PostStatus>,
+    // Inclusive bounds, as RFC 3339 timestamps.
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub updated_from: Option<DateTime<Utc>>,
+    pub updated_to: Option<DateTime<Utc>>,
+    // Keyset paging instead of `page`: the `next_cursor` of the previous
+    // page, or none for the first. Either one lists newest first, `limit`
+    // posts at a time.
+    pub after: Option<String>,
+    pub limit: Option<i64>,
+}
+
+// Which live posts a listing covers. Every field is bound as a parameter;
+// None leaves that filter off.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PostFilter<'a> {
+    // Already normalized with `normalize_tag`.
+    pub tag: Option<&'a str>,
+    pub author: Option<&'a str>,
+    pub status: Option<PostStatus>,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub updated_from: Option<DateTime<Utc>>,
+    pub updated_to: Option<DateTime<Utc>>,
+    // Whose unpublished posts are included besides the published ones;
+    // `see_all` includes everyone's.
+    pub viewer: Option<i32>,
+    pub see_all: bool,
+    // Whose posts these are. Always set from the request's Tenant: the
+    // default 0 is no tenant and matches nothing.
+    pub tenant: i32,
+    // Only posts listed after this one, newest first. Listings only: counts
+    // leave it out.
+    pub after: Option<PostCursor>,
+}
+
+// Where a keyset listing left off: the last post it returned. Clients get
+// it as an opaque base64 string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PostCursor {
+    pub created_at: DateTime<Utc>,
+    pub id: i32,
+}
+
+impl PostCursor {
+    pub fn of(post: &BlogPost) -> Self {
+        PostCursor {
+            created_at: post.created_at,
+            id: post.id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let time = self.created_at.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        URL_SAFE_NO_PAD.encode(format!("{},{}", self.id, time))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, ApiError> {
+        let invalid = || ApiError::BadRequest(format!("Invalid cursor `{}`", cursor));
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let text = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let (id, time) = text.split_once(',').ok_or_else(invalid)?;
+        Ok(PostCursor {
+            created_at: DateTime::parse_from_rfc3339(time).map_err(|_| invalid())?.to_utc(),
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+
+    // Whether `post` comes after the cursor, newest first with ties broken
+    // by id like the ORDER BY.
+    pub fn precedes(&self, post: &BlogPost) -> bool {
+        post.created_at < self.created_at
+            || (post.created_at == self.created_at && post.id > self.id)
+    }
+}
+
+// Whether `viewer` may read `post`, going by its status.
+pub fn post_visible(post: &BlogPost, viewer: Option<&AuthUser>) -> bool {
+    post.status == PostStatus::Published.as_str()
+        || viewer.is_some_and(|user| user.id == post.user_id || user.role == Role::Admin)
+}
+
+impl PostFilter<'_> {
+    pub fn viewed_by(self, viewer: Option<&AuthUser>) -> Self {
+        PostFilter {
+            viewer: viewer.map(|user| user.id),
+            see_all: viewer.is_some_and(|user| user.role == Role::Admin),
+            ..self
+        }
+    }
+
+    // What LIST_POSTS_SQL checks, for stores that filter in Rust.
+    pub fn matches(&self, post: &BlogPost) -> bool {
+        self.tag.is_none_or(|tag| post.tags.iter().any(|t| t == tag))
+            && self.author.is_none_or(|author| post.author == author)
+            && self.status.is_none_or(|status| post.status == status.as_str())
+            && (post.status == PostStatus::Published.as_str()
+                || self.viewer == Some(post.user_id)
+                || self.see_all)
+            && self.created_from.is_none_or(|from| post.created_at >= from)
+            && self.created_to.is_none_or(|to| post.created_at <= to)
+            && self.updated_from.is_none_or(|from| post.updated_at >= from)
+            && self.updated_to.is_none_or(|to| post.updated_at <= to)
+            && self.after.is_none_or(|cursor| cursor.precedes(post))
+    }
+}
+
+pub(crate) const DEFAULT_PER_PAGE: i64 = 20;
+pub(crate) const MAX_PER_PAGE: i64 = 100;
+
+// Out of range values are clamped rather than rejected.
+pub fn clamp_paging(page: Option<i64>, per_page: Option<i64>) -> (i64, i64) {
+    (
+        page.unwrap_or(1).max(1),
+        per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE),
+    )
+}
+
+impl ListQuery {
+    pub fn page(&self) -> i64 {
+        clamp_paging(self.page, self.per_page).0
+    }
+
+    pub fn per_page(&self) -> i64 {
+        clamp_paging(self.page, self.per_page).1
+    }
+
+    // The cursor and page size when this asks for keyset paging. It always
+    // goes newest first, so it can't be mixed with page numbers, sorting
+    // or counts.
+    pub fn keyset(&self) -> Result<Option<(Option<PostCursor>, i64)>, ApiError> {
+        if self.after.is_none() && self.limit.is_none() {
+            return Ok(None);
+        }
+        if self.page.is_some()
+            || self.per_page.is_some()
+            || self.sort.is_some()
+            || self.order.is_some()
+            || self.count.is_some()
+        {
+            return Err(ApiError::BadRequest(
+                "after and limit can't be combined with page, per_page, sort, order or count"
+                    .to_string(),
+            ));
+        }
+        let cursor = self.after.as_deref().map(PostCursor::decode).transpose()?;
+        let limit = self.limit.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+        Ok(Some((cursor, limit)))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    // A header row, then one row per post; tags are joined with `;`.
+    Csv,
+    // One BlogPost JSON object per line.
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    pub fn header(&self) -> Option<&'static str> {
+        match self {
+            ExportFormat::Csv => {
+                Some("id,title,content,user_id,author,tags,version,created_at,updated_at\r\n")
+            }
+            ExportFormat::Ndjson => None,
+        }
+    }
+
+    pub fn encode(&self, post: &BlogPost) -> String {
+        match self {
+            ExportFormat::Csv => format!(
+                "{},{},{},{},{},{},{},{},{}\r\n",
+                post.id,
+                csv_field(&post.title),
+                csv_field(&post.content),
+                post.user_id,
+                csv_field(&post.author),
+                csv_field(&post.tags.join(";")),
+                post.version,
+                post.created_at.to_rfc3339(),
+                post.updated_at.to_rfc3339(),
+            ),
+            ExportFormat::Ndjson => {
+                let mut line = serde_json::to_string(post).unwrap_or_default();
+                line.push('\n');
+                line
+            }
+        }
+    }
+}
+
+// RFC 4180: quoted when it holds a comma, quote or line break, with inner
+// quotes doubled.
+pub(crate) fn csv_field(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    pub format: ExportFormat,
+}
+
+// The filters and sorting of ListQuery, without paging: a stream has every
+// match.
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamQuery {
+    pub sort: Option<SortColumn>,
+    pub order: Option<SortOrder>,
+    pub tag: Option<String>,
+    pub author: Option<String>,
+    pub status: Option<PostStatus>,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub updated_from: Option<DateTime<Utc>>,
+    pub updated_to: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PopularQuery {
+    // 1 to 50, default 10.
+    pub limit: Option<i64>,
+}
+
+impl PopularQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(10).clamp(1, 50)
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RelatedQuery {
+    // 1 to 20, default 5.
+    pub limit: Option<i64>,
+}
+
+impl RelatedQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(5).clamp(1, 20)
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    pub q: String,
+    // How much more a title match counts than a content match.
+    pub title_boost: Option<f32>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+pub(crate) const DEFAULT_TITLE_BOOST: f32 = 2.5;
+
+impl SearchQuery {
+    // ts_rank weights for the D, C, B and A labels. Postgres caps each
+    // weight at 1.0, so a boost above 1 scales the others down instead;
+    // only the ratio matters for ordering.
+    pub fn rank_weights(&self) -> Vec<f32> {
+        let boost = self
+            .title_boost
+            .filter(|b| b.is_finite())
+            .unwrap_or(DEFAULT_TITLE_BOOST)
+            .clamp(0.1, 10.0);
+        let content = 0.4;
+        let scale = (boost * content).max(1.0);
+        vec![0.1 / scale, 0.2 / scale, content / scale, boost * content / scale]
+    }
+}
+
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct SearchHit {
+    pub id: i32,
+    pub uuid: Uuid,
+    pub title: String,
+    pub slug: String,
+    pub user_id: i32,
+    pub author: String,
+    pub tags: Vec<String>,
+    pub version: i32,
+    pub rank: f32,
+    // Content excerpt with matches wrapped in <mark>. The surrounding text
+    // is the raw post content and is not HTML-escaped.
+    pub snippet: String,
+}
+
+// A post in the trash, as listed by GET /blog/trash.
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct TrashedPost {
+    pub id: i32,
+    pub uuid: Uuid,
+    pub title: String,
+    pub user_id: i32,
+    pub author: String,
+    pub deleted_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    // True when `total` is the planner's estimate (?count=estimate).
+    pub total_estimated: bool,
+    pub total_pages: i64,
+}
+
+impl<T> Page<T> {
+    pub fn new(
+        data: Vec<T>,
+        page: i64,
+        per_page: i64,
+        total: i64,
+        total_estimated: bool,
+    ) -> Self {
+        Page {
+            data,
+            page,
+            per_page,
+            total,
+            total_estimated,
+            total_pages: (total.max(0) + per_page - 1) / per_page,
+        }
+    }
+
+    // The same page with every item passed through `f`.
+    pub fn map<U>(&self, f: impl FnMut(&T) -> U) -> Page<U> {
+        Page {
+            data: self.data.iter().map(f).collect(),
+            page: self.page,
+            per_page: self.per_page,
+            total: self.total,
+            total_estimated: self.total_estimated,
+            total_pages: self.total_pages,
+        }
+    }
+}
+
+// A keyset page. `next_cursor` is None on the last page.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct CursorPage<T> {
+    pub data: Vec<T>,
+    pub limit: i64,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub next_cursor: Option<String>,
+}
+
+impl<T> CursorPage<T> {
+    pub fn map<U>(&self, f: impl FnMut(&T) -> U) -> CursorPage<U> {
+        CursorPage {
+            data: self.data.iter().map(f).collect(),
+            limit: self.limit,
+            next_cursor: self.next_cursor.clone(),
+        }
+    }
+}
+
+// Deployment-wide defaults for GET /blog, read from DEFAULT_LIST_PROFILE as
+// JSON, e.g. {"sort": "title", "order": "desc"}. Anything the client passes
+// in the query string wins.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ListProfile {
+    pub sort: Option<SortColumn>,
+    pub order: Option<SortOrder>,
+}
+
+impl ListProfile {
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("DEFAULT_LIST_PROFILE") {
+            Ok(raw) if !raw.trim().is_empty() => serde_json::from_str(&raw)
+                .map_err(|err| format!("Invalid DEFAULT_LIST_PROFILE: {}", err)),
+            _ => Ok(ListProfile::default()),
+        }
+    }
+
+    pub fn resolve(&self, query: &ListQuery) -> (SortColumn, SortOrder) {
+        self.resolve_with(query.sort, query.order)
+    }
+
+    pub fn resolve_with(
+        &self,
+        sort: Option<SortColumn>,
+        order: Option<SortOrder>,
+    ) -> (SortColumn, SortOrder) {
+        (
+            sort.or(self.sort).unwrap_or(SortColumn::Id),
+            order.or(self.order).unwrap_or(SortOrder::Asc),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkMode {
+    // One transaction; the first bad row fails the whole batch.
+    #[default]
+    AllOrNothing,
+    // Every row gets its own savepoint; bad rows are reported and skipped.
+    BestEffort,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BulkQuery {
+    #[serde(default)]
+    pub mode: BulkMode,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UpsertQuery {
+    // Create the post under the id in the path if there is none.
+    #[serde(default)]
+    pub upsert: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct BulkFailure {
+    pub index: usize,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default, ToSchema)]
+pub struct BulkResult {
+    pub created: Vec<BlogPost>,
+    pub failed: Vec<BulkFailure>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct BulkDeleteFailure {
+    pub id: i32,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default, ToSchema)]
+pub struct BulkDeleteResult {
+    pub deleted: Vec<i32>,
+    pub failed: Vec<BulkDeleteFailure>,
+}
+
+pub(crate) const MAX_BATCH_IDS: usize = 100;
+pub(crate) const MAX_BATCH_SLUGS: usize = 100;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BySlugsQuery {
+    // Comma-separated, e.g. `?slugs=hello,hello-2`.
+    pub slugs: String,
+}
+
+impl BySlugsQuery {
+    // In the order asked, blanks and repeats left out.
+    pub fn slugs(&self) -> Vec<String> {
+        let mut slugs: Vec<String> = Vec::new();
+        for slug in self.slugs.split(',').map(str::trim).filter(|slug| !slug.is_empty()) {
+            if !slugs.iter().any(|seen| seen == slug) {
+                slugs.push(slug.to_string());
+            }
+        }
+        slugs
+    }
+}
+
+// The answer to POST /blog/batch-get. Drafts the caller can't see count as
+// not found, as with GET /blog/{id}.
+#[derive(Serialize, Debug, Default, ToSchema)]
+pub struct BatchGetResult {
+    // By id, as a string since JSON keys are.
+    pub posts: BTreeMap<i32, BlogPost>,
+    // The requested ids missing from `posts`, in order.
+    pub not_found: Vec<i32>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub mode: BulkMode,
+    // ?async=true queues the import and returns 202 with a job id.
+    #[serde(default, rename = "async")]
+    pub run_async: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ImportSummary {
+    pub created: usize,
+    pub failed: Vec<BulkFailure>,
+}
+
+impl From<BulkResult> for ImportSummary {
+    fn from(result: BulkResult) -> Self {
+        ImportSummary {
+            created: result.created.len(),
+            failed: result.failed,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, FromRow, ToSchema)]
+pub struct Job {
+    pub id: i32,
+    pub kind: String,
+    pub status: String,
+    // Runs so far, failed ones included.
+    pub attempts: i32,
+    pub max_attempts: i32,
+    // When a pending job may run; after a failure, when it is retried.
+    pub run_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JobsQuery {
+    pub status: Option<JobStatus>,
+    pub kind: Option<String>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+// How many jobs are in each status, for GET /admin/jobs.
+#[derive(Serialize, Debug, Default, ToSchema)]
+pub struct JobCounts {
+    pub pending: i64,
+    pub running: i64,
+    pub done: i64,
+    pub failed: i64,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RebuildSlugsQuery {
+    // Only posts with an empty slug, instead of all of them.
+    #[serde(default)]
+    pub missing_only: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RebuildSlugsResult {
+    // Posts whose slug changed.
+    pub updated: usize,
+}
+
+// What GET /admin/stats reports about a tenant's live posts.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct AdminStats {
+    pub total_posts: i64,
+    pub total_comments: i64,
+    // Most posts first.
+    pub posts_per_author: Vec<AuthorPostCount>,
+    // The last 30 days up to today (UTC), oldest first, including days
+    // without posts.
+    pub posts_per_day: Vec<DailyPostCount>,
+    pub most_viewed: Vec<ViewedPost>,
+}
+
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct AuthorPostCount {
+    pub user_id: i32,
+    pub username: String,
+    pub posts: i64,
+}
+
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct DailyPostCount {
+    pub day: chrono::NaiveDate,
+    pub posts: i64,
+}
+
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct ViewedPost {
+    pub id: i32,
+    pub title: String,
+    pub slug: String,
+    pub view_count: i64,
+}
+
+// A year of GET /blog/archive: how many posts were published in it, and
+// in each of its months that has any, newest first.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ArchiveYear {
+    pub year: i32,
+    pub posts: i64,
+    pub months: Vec<ArchiveMonth>,
+}
+
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct ArchiveMonth {
+    pub year: i32,
+    // 1 to 12.
+    pub month: i32,
+    pub posts: i64,
+}
+
+impl ArchiveYear {
+    // Groups months, already newest first, by year.
+    pub fn group(months: Vec<ArchiveMonth>) -> Vec<ArchiveYear> {
+        let mut years: Vec<ArchiveYear> = Vec::new();
+        for month in months {
+            match years.last_mut() {
+                Some(year) if year.year == month.year => {
+                    year.posts += month.posts;
+                    year.months.push(month);
+                }
+                _ => years.push(ArchiveYear {
+                    year: month.year,
+                    posts: month.posts,
+                    months: vec![month],
+                }),
+            }
+        }
+        years
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct JobList {
+    pub counts: JobCounts,
+    #[serde(flatten)]
+    pub jobs: Page<Job>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditEntity {
+    Post,
+    Comment,
+    // Role changes.
+    User,
+}
+
+impl AuditEntity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEntity::Post => "post",
+            AuditEntity::Comment => "comment",
+            AuditEntity::User => "user",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    // Moved to the trash.
+    Delete,
+    Restore,
+    // Removed for good; the content was recorded by the earlier delete.
+    Purge,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+            AuditAction::Restore => "restore",
+            AuditAction::Purge => "purge",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    // None once the acting user has been deleted.
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub user_id: Option<i32>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub username: Option<String>,
+    pub entity: String,
+    pub entity_id: i32,
+    pub action: String,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub old_value: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub new_value: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    pub entity: Option<AuditEntity>,
+    pub entity_id: Option<i32>,
+    // Inclusive bounds, as RFC 3339 timestamps.
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PostPreview {
+    pub title: String,
+    pub html: String,
+    pub word_count: usize,
+    pub reading_time_minutes: usize,
+}
+
+// A listed post with ?excerpt=true.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PostWithExcerpt {
+    #[serde(flatten)]
+    pub post: BlogPost,
+    // The first paragraph, shortened and rendered like GET /blog/{id}/html.
+    pub excerpt: String,
+}
@@ -0,0 +1,298 @@
+// Content moderation: the Moderator backends that review new posts and
+// comments, and the queue of flagged content admins go through.
+
+use crate::*;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentKind {
+    Post,
+    Comment,
+}
+
+impl ContentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentKind::Post => "post",
+            ContentKind::Comment => "comment",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    // Saved as usual, and queued for an admin to look at.
+    Flag(String),
+    // Refused with 422.
+    Reject(String),
+}
+
+// Reviews content before it is saved. Selected at startup via MODERATOR.
+// A backend that can't reach a decision should flag rather than fail, so
+// an outage neither blocks writing nor lets content through unseen.
+#[async_trait]
+pub trait Moderator: Send + Sync {
+    async fn review(&self, kind: ContentKind, text: &str) -> Verdict;
+}
+
+// Lets everything through. The default.
+pub struct AllowAll;
+
+#[async_trait]
+impl Moderator for AllowAll {
+    async fn review(&self, _kind: ContentKind, _text: &str) -> Verdict {
+        Verdict::Allow
+    }
+}
+
+// What a moderator's hit turns into, from MODERATION_ACTION.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationAction {
+    #[default]
+    Flag,
+    Reject,
+}
+
+impl ModerationAction {
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("MODERATION_ACTION").as_deref() {
+            Ok("flag") | Err(_) => Ok(ModerationAction::Flag),
+            Ok("reject") => Ok(ModerationAction::Reject),
+            Ok(other) => Err(format!("Unknown MODERATION_ACTION `{}`", other)),
+        }
+    }
+
+    fn verdict(self, reason: String) -> Verdict {
+        match self {
+            ModerationAction::Flag => Verdict::Flag(reason),
+            ModerationAction::Reject => Verdict::Reject(reason),
+        }
+    }
+}
+
+// Lowercased runs of letters and digits.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// Catches listed words and phrases, matched as whole words regardless of
+// case and punctuation: `spam` catches "SPAM!" but not "spammer".
+pub struct WordlistModerator {
+    phrases: Vec<Vec<String>>,
+    action: ModerationAction,
+}
+
+impl WordlistModerator {
+    pub fn new<S: AsRef<str>>(
+        entries: impl IntoIterator<Item = S>,
+        action: ModerationAction,
+    ) -> Self {
+        let phrases = entries
+            .into_iter()
+            .map(|entry| words(entry.as_ref()))
+            .filter(|phrase| !phrase.is_empty())
+            .collect();
+        WordlistModerator { phrases, action }
+    }
+
+    // One word or phrase per line; blank lines and `#` comments are skipped.
+    pub fn from_file(path: &str, action: ModerationAction) -> Result<Self, String> {
+        let list = std::fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read MODERATION_WORDLIST {}: {}", path, err))?;
+        let entries = list.lines().map(str::trim).filter(|line| !line.starts_with('#'));
+        Ok(WordlistModerator::new(entries, action))
+    }
+}
+
+#[async_trait]
+impl Moderator for WordlistModerator {
+    async fn review(&self, _kind: ContentKind, text: &str) -> Verdict {
+        let text = words(text);
+        let hit = self
+            .phrases
+            .iter()
+            .find(|phrase| text.windows(phrase.len()).any(|window| window == phrase.as_slice()));
+        match hit {
+            Some(phrase) => self.action.verdict(format!("Contains `{}`", phrase.join(" "))),
+            None => Verdict::Allow,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ReviewRequest<'a> {
+    kind: ContentKind,
+    text: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ReviewAction {
+    Allow,
+    Flag,
+    Reject,
+}
+
+#[derive(Deserialize)]
+struct ReviewAnswer {
+    action: ReviewAction,
+    reason: Option<String>,
+}
+
+// Asks an external service: POSTs `{"kind": "post", "text": "..."}` and
+// expects `{"action": "allow" | "flag" | "reject", "reason": "..."}`.
+// No answer within MODERATION_TIMEOUT_SECS, or a malformed one, flags.
+pub struct HttpModerator {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpModerator {
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, String> {
+        reqwest::Url::parse(url).map_err(|err| format!("Invalid MODERATION_URL: {}", err))?;
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .user_agent(concat!("rest_api-moderation/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|err| format!("Could not set up the moderation client: {}", err))?;
+        Ok(HttpModerator {
+            client,
+            url: url.to_string(),
+        })
+    }
+
+    async fn ask(&self, kind: ContentKind, text: &str) -> Result<ReviewAnswer, String> {
+        let body = serde_json::to_vec(&ReviewRequest { kind, text }).map_err(|e| e.to_string())?;
+        let answer = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| err.to_string())?
+            .bytes()
+            .await
+            .map_err(|err| err.to_string())?;
+        serde_json::from_slice(&answer).map_err(|err| format!("Malformed answer: {}", err))
+    }
+}
+
+#[async_trait]
+impl Moderator for HttpModerator {
+    async fn review(&self, kind: ContentKind, text: &str) -> Verdict {
+        let answer = match self.ask(kind, text).await {
+            Ok(answer) => answer,
+            Err(err) => {
+                tracing::warn!("Moderation service failed: {}", err);
+                return Verdict::Flag("The moderation service could not be reached".to_string());
+            }
+        };
+        let reason = answer.reason.unwrap_or_else(|| "Flagged by the moderation service".into());
+        match answer.action {
+            ReviewAction::Allow => Verdict::Allow,
+            ReviewAction::Flag => Verdict::Flag(reason),
+            ReviewAction::Reject => Verdict::Reject(reason),
+        }
+    }
+}
+
+// MODERATOR=none (the default), wordlist, which checks against the file at
+// MODERATION_WORDLIST, or http, which asks MODERATION_URL.
+pub fn build_moderator() -> Result<Arc<dyn Moderator>, String> {
+    match env::var("MODERATOR").as_deref() {
+        Ok("none") | Err(_) => Ok(Arc::new(AllowAll)),
+        Ok("wordlist") => {
+            let path = env::var("MODERATION_WORDLIST")
+                .map_err(|_| "MODERATOR=wordlist needs MODERATION_WORDLIST")?;
+            Ok(Arc::new(WordlistModerator::from_file(&path, ModerationAction::from_env()?)?))
+        }
+        Ok("http") => {
+            let url =
+                env::var("MODERATION_URL").map_err(|_| "MODERATOR=http needs MODERATION_URL")?;
+            let timeout = env::var("MODERATION_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5);
+            Ok(Arc::new(HttpModerator::new(&url, Duration::from_secs(timeout))?))
+        }
+        Ok(other) => Err(format!("Unknown MODERATOR `{}`", other)),
+    }
+}
+
+impl NewBlogPost {
+    // What a moderator gets to see of a post: title, tags and content.
+    pub fn moderated_text(&self) -> String {
+        let tags = self.tags.as_deref().unwrap_or_default().join(" ");
+        format!("{}\n\n{}\n\n{}", self.title, tags, self.content)
+    }
+}
+
+// Reviews content about to be saved: an error if it is rejected, else the
+// reason to flag it for, if any.
+pub async fn moderate(
+    moderator: &dyn Moderator,
+    kind: ContentKind,
+    text: &str,
+) -> Result<Option<String>, ApiError> {
+    match moderator.review(kind, text).await {
+        Verdict::Allow => Ok(None),
+        Verdict::Flag(reason) => Ok(Some(reason)),
+        Verdict::Reject(reason) => Err(ApiError::UnprocessableEntity(format!(
+            "Rejected by moderation: {}",
+            reason
+        ))),
+    }
+}
+
+// Queues a post saved through the PostRepository, which commits on its
+// own, for review. The post is there either way, so a failure is only
+// logged. Writes that run in one transaction queue with
+// queue_for_moderation instead.
+pub async fn flag_for_review(
+    pool: &PgPool,
+    tenant: i32,
+    kind: ContentKind,
+    content_id: i32,
+    user_id: i32,
+    reason: Option<String>,
+) {
+    let Some(reason) = reason else {
+        return;
+    };
+    if let Err(err) = queue_for_moderation(pool, tenant, kind, content_id, user_id, &reason).await {
+        tracing::warn!("Could not queue {} {} for moderation: {}", kind.as_str(), content_id, err);
+    }
+}
+
+#[derive(Serialize, Debug, Clone, FromRow, ToSchema)]
+pub struct ModerationItem {
+    pub id: i32,
+    // `post` or `comment`.
+    pub content_kind: String,
+    pub content_id: i32,
+    // Who wrote it.
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub user_id: Option<i32>,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub resolved_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub resolved_by: Option<i32>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ModerationQuery {
+    // Resolved items instead of the open ones.
+    #[serde(default)]
+    pub resolved: bool,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
@@ -0,0 +1,396 @@
+// Logging in with GitHub or Google (OAuth2 authorization code flow).
+// /auth/{provider}/login sends the browser to the provider, and
+// /auth/{provider}/callback trades the code it comes back with for the
+// provider account and logs in as the local user linked to it, making one
+// the first time.
+
+use actix_web::cookie::{time, Cookie, SameSite};
+
+use crate::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OAuthKind {
+    GitHub,
+    Google,
+}
+
+// One provider and the app registered with it. The endpoints are the
+// provider's own unless a test points them elsewhere.
+#[derive(Clone)]
+pub struct OAuthProvider {
+    pub kind: OAuthKind,
+    pub client_id: String,
+    pub client_secret: String,
+    pub authorize_url: String,
+    pub token_url: String,
+    // Who the access token belongs to; GitHub lists their emails under
+    // `{userinfo_url}/emails`.
+    pub userinfo_url: String,
+}
+
+impl OAuthProvider {
+    pub fn github(client_id: &str, client_secret: &str) -> Self {
+        OAuthProvider {
+            kind: OAuthKind::GitHub,
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            authorize_url: "https://github.com/login/oauth/authorize".to_string(),
+            token_url: "https://github.com/login/oauth/access_token".to_string(),
+            userinfo_url: "https://api.github.com/user".to_string(),
+        }
+    }
+
+    pub fn google(client_id: &str, client_secret: &str) -> Self {
+        OAuthProvider {
+            kind: OAuthKind::Google,
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            authorize_url: "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
+            token_url: "https://oauth2.googleapis.com/token".to_string(),
+            userinfo_url: "https://openidconnect.googleapis.com/v1/userinfo".to_string(),
+        }
+    }
+
+    // As it appears in the routes and in user_identities.
+    pub fn name(&self) -> &'static str {
+        match self.kind {
+            OAuthKind::GitHub => "github",
+            OAuthKind::Google => "google",
+        }
+    }
+
+    fn scope(&self) -> &'static str {
+        match self.kind {
+            OAuthKind::GitHub => "read:user user:email",
+            OAuthKind::Google => "openid email profile",
+        }
+    }
+}
+
+// The provider account a login came back with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderAccount {
+    // The provider's id for it, which doesn't change with its name.
+    pub subject: String,
+    // What to base a new local username on.
+    pub login: String,
+    // Only an address the provider has verified.
+    pub email: Option<String>,
+}
+
+// OAUTH_GITHUB_CLIENT_ID and OAUTH_GITHUB_CLIENT_SECRET turn on GitHub,
+// OAUTH_GOOGLE_CLIENT_ID and OAUTH_GOOGLE_CLIENT_SECRET Google. Each
+// deployment registers its own app, with
+// `{API_BASE_URL}/api/v1/auth/{provider}/callback` as the redirect URL.
+// Providers without both answer 404.
+pub struct OAuthConfig {
+    providers: Vec<OAuthProvider>,
+    client: reqwest::Client,
+}
+
+// What the `state` parameter carries to the provider and back, signed
+// with JWT_SECRET so it can't be made up.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct OAuthState {
+    pub(crate) provider: String,
+    pub(crate) tenant: i32,
+    // Also in the OAUTH_COOKIE, so a callback only works in the browser
+    // that started the login.
+    pub(crate) nonce: String,
+    // The user who was signed in when the login started, to link the
+    // provider account to.
+    pub(crate) link: Option<i32>,
+    pub(crate) exp: u64,
+}
+
+pub(crate) const OAUTH_COOKIE: &str = "oauth_nonce";
+
+// How long a login may take at the provider.
+const STATE_TTL_SECS: u64 = 600;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OAuthCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    // Set instead of `code` when the user said no, for one.
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenAnswer {
+    access_token: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitHubUser {
+    id: i64,
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct GitHubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+#[derive(Deserialize)]
+struct GoogleUser {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+}
+
+impl OAuthConfig {
+    pub fn new(providers: Vec<OAuthProvider>) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .user_agent(concat!("rest_api-oauth/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|err| format!("Could not set up the OAuth client: {}", err))?;
+        Ok(OAuthConfig { providers, client })
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: String| env::var(name).ok().filter(|value| !value.trim().is_empty());
+        let mut providers = Vec::new();
+        let kinds = [("OAUTH_GITHUB", OAuthKind::GitHub), ("OAUTH_GOOGLE", OAuthKind::Google)];
+        for (prefix, kind) in kinds {
+            let id = var(format!("{}_CLIENT_ID", prefix));
+            let secret = var(format!("{}_CLIENT_SECRET", prefix));
+            match (id, secret) {
+                (Some(id), Some(secret)) => providers.push(match kind {
+                    OAuthKind::GitHub => OAuthProvider::github(&id, &secret),
+                    OAuthKind::Google => OAuthProvider::google(&id, &secret),
+                }),
+                (None, None) => {}
+                _ => return Err(format!("{0}_CLIENT_ID needs {0}_CLIENT_SECRET and back", prefix)),
+            }
+        }
+        OAuthConfig::new(providers)
+    }
+
+    pub fn provider(&self, name: &str) -> Result<&OAuthProvider, ApiError> {
+        self.providers
+            .iter()
+            .find(|provider| provider.name() == name)
+            .ok_or_else(|| ApiError::NotFound(format!("No OAuth provider `{}`", name)))
+    }
+
+    // Where to send the browser, and the nonce for the OAUTH_COOKIE.
+    pub(crate) fn authorize_url(
+        &self,
+        auth: &AuthConfig,
+        provider: &OAuthProvider,
+        redirect_uri: &str,
+        tenant: i32,
+        link: Option<i32>,
+    ) -> Result<(String, String), ApiError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let nonce = hex::encode(rand::random::<[u8; 16]>());
+        let state = auth.sign(&OAuthState {
+            provider: provider.name().to_string(),
+            tenant,
+            nonce: nonce.clone(),
+            link,
+            exp: now + STATE_TTL_SECS,
+        })?;
+        let query = form(&[
+            ("response_type", "code"),
+            ("client_id", &provider.client_id),
+            ("redirect_uri", redirect_uri),
+            ("scope", provider.scope()),
+            ("state", &state),
+        ]);
+        Ok((format!("{}?{}", provider.authorize_url, query), nonce))
+    }
+
+    // 400 unless `state` is one authorize_url made for this provider, in
+    // the last STATE_TTL_SECS, for the browser that sent `nonce`.
+    pub(crate) fn check_state(
+        &self,
+        auth: &AuthConfig,
+        provider: &OAuthProvider,
+        state: Option<&str>,
+        nonce: Option<&str>,
+    ) -> Result<OAuthState, ApiError> {
+        state
+            .and_then(|state| auth.verify::<OAuthState>(state))
+            .filter(|state| state.provider == provider.name())
+            .filter(|state| nonce == Some(state.nonce.as_str()))
+            .ok_or_else(|| {
+                ApiError::BadRequest(
+                    "Missing or invalid OAuth state; start the login again".to_string(),
+                )
+            })
+    }
+
+    // The account behind `code`. 401 if the provider won't take the code,
+    // 503 if it can't be reached.
+    pub async fn account(
+        &self,
+        provider: &OAuthProvider,
+        code: &str,
+        redirect_uri: &str,
+    ) -> Result<ProviderAccount, ApiError> {
+        let unreachable = |err: String| {
+            tracing::warn!(provider = provider.name(), "OAuth provider failed: {}", err);
+            ApiError::ServiceUnavailable(format!("Could not reach {}", provider.name()))
+        };
+        let body = form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", &provider.client_id),
+            ("client_secret", &provider.client_secret),
+        ]);
+        let answer = self
+            .client
+            .post(&provider.token_url)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| unreachable(err.to_string()))?;
+        let status = answer.status();
+        let bytes = answer.bytes().await.map_err(|err| unreachable(err.to_string()))?;
+        let token: TokenAnswer = serde_json::from_slice(&bytes)
+            .map_err(|_| unreachable(format!("token endpoint answered {}", status)))?;
+        let token = match token.access_token {
+            Some(token) if status.is_success() => token,
+            _ => {
+                let reason = token.error.unwrap_or_else(|| status.to_string());
+                return Err(ApiError::Unauthorized(format!(
+                    "{} did not accept the authorization code: {}",
+                    provider.name(),
+                    reason
+                )));
+            }
+        };
+        match provider.kind {
+            OAuthKind::GitHub => {
+                let user: GitHubUser =
+                    self.get(&provider.userinfo_url, &token).await.map_err(unreachable)?;
+                // Without the user:email scope there are none to be had.
+                let emails: Vec<GitHubEmail> = self
+                    .get(&format!("{}/emails", provider.userinfo_url), &token)
+                    .await
+                    .unwrap_or_default();
+                let email = emails.into_iter().find(|email| email.primary && email.verified);
+                Ok(ProviderAccount {
+                    subject: user.id.to_string(),
+                    login: user.login,
+                    email: email.map(|email| email.email),
+                })
+            }
+            OAuthKind::Google => {
+                let user: GoogleUser =
+                    self.get(&provider.userinfo_url, &token).await.map_err(unreachable)?;
+                let email = user.email.filter(|_| user.email_verified);
+                let local_part = email.as_deref().and_then(|email| email.split('@').next());
+                Ok(ProviderAccount {
+                    subject: user.sub,
+                    login: local_part.unwrap_or("user").to_string(),
+                    email,
+                })
+            }
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str, token: &str) -> Result<T, String> {
+        let bytes = self
+            .client
+            .get(url)
+            .bearer_auth(token)
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| err.to_string())?
+            .bytes()
+            .await
+            .map_err(|err| err.to_string())?;
+        serde_json::from_slice(&bytes).map_err(|err| format!("Malformed answer: {}", err))
+    }
+}
+
+fn form(pairs: &[(&str, &str)]) -> String {
+    let pairs = pairs.iter().map(|(name, value)| format!("{}={}", name, uri_encode(value, true)));
+    pairs.collect::<Vec<_>>().join("&")
+}
+
+// The cookie a login sets for its callback, at `path`, or with no value
+// the one that removes it again.
+pub(crate) fn oauth_cookie<'c>(
+    req: &HttpRequest,
+    path: String,
+    nonce: Option<String>,
+) -> Cookie<'c> {
+    let secure = req.connection_info().scheme() == "https";
+    let mut cookie = Cookie::build(OAUTH_COOKIE, nonce.clone().unwrap_or_default())
+        .path(path)
+        .http_only(true)
+        .secure(secure)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::seconds(STATE_TTL_SECS as i64))
+        .finish();
+    if nonce.is_none() {
+        cookie.make_removal();
+    }
+    cookie
+}
+
+// The local user for a provider account. One already linked logs in as
+// its user; otherwise it is linked to `link`, the user who started the
+// login signed in, or to a new user named after the account (with a
+// number added if that's taken) who can only log in this way. Accounts
+// are never linked by email, as local addresses aren't verified.
+pub async fn oauth_user(
+    pool: &PgPool,
+    tenant: i32,
+    provider: &str,
+    account: &ProviderAccount,
+    link: Option<i32>,
+) -> Result<User, ApiError> {
+    if let Some(user) = get_identity_user(pool, tenant, provider, &account.subject).await? {
+        return match link {
+            Some(id) if id != user.id => Err(ApiError::Conflict(format!(
+                "This {} account is linked to another user",
+                provider
+            ))),
+            _ => Ok(user),
+        };
+    }
+    if let Some(id) = link {
+        let user = get_user(pool, id).await?;
+        link_identity(pool, tenant, provider, &account.subject, user.id).await?;
+        return Ok(user);
+    }
+    let mut tx = pool.begin().await?;
+    let base = username_base(&account.login);
+    let mut username = base.clone();
+    for n in 2.. {
+        if get_user_by_username(&mut *tx, tenant, &username).await?.is_none() {
+            break;
+        }
+        username = format!("{}-{}", base, n);
+    }
+    // '!' is no password hash, as for the accounts migrated from authors.
+    let email = account.email.as_deref();
+    let user = create_user(&mut *tx, tenant, &username, "!", email).await?;
+    link_identity(&mut *tx, tenant, provider, &account.subject, user.id).await?;
+    tx.commit().await?;
+    Ok(user)
+}
+
+// `name` with only the characters usernames may have, short enough for
+// a `-n` suffix.
+fn username_base(name: &str) -> String {
+    let allowed = |c: &char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.');
+    let base: String = name.chars().filter(allowed).take(26).collect();
+    if base.len() < 3 { format!("user{}", base) } else { base }
+}
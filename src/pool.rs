@@ -0,0 +1,120 @@
+// Connection pool diagnostics for GET /admin/db-pool. sqlx doesn't say how
+// long queries wait for a connection, so a background probe takes one
+// every DB_POOL_PROBE_SECS and times that; with the pool busy it waits in
+// line like any query would.
+
+use crate::*;
+
+// Five minutes of samples at the default interval.
+const WAIT_SAMPLES: usize = 60;
+
+pub struct PoolMonitor {
+    statement_timeout_ms: u64,
+    waits: std::sync::Mutex<std::collections::VecDeque<Duration>>,
+    failed_probes: AtomicU64,
+}
+
+impl PoolMonitor {
+    pub fn new(config: &Config) -> Self {
+        PoolMonitor {
+            statement_timeout_ms: config.statement_timeout_ms,
+            waits: std::sync::Mutex::new(std::collections::VecDeque::with_capacity(WAIT_SAMPLES)),
+            failed_probes: AtomicU64::new(0),
+        }
+    }
+
+    // Takes a connection, and gives it straight back, only to time the wait.
+    pub async fn probe(&self, pool: &PgPool) {
+        let started = Instant::now();
+        match pool.acquire().await {
+            Ok(_) => {
+                let mut waits = self.waits.lock().unwrap_or_else(|err| err.into_inner());
+                if waits.len() == WAIT_SAMPLES {
+                    waits.pop_front();
+                }
+                waits.push_back(started.elapsed());
+            }
+            Err(err) => {
+                self.failed_probes.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!("Pool probe could not get a connection: {}", err);
+            }
+        }
+    }
+
+    pub fn report(&self, pool: &PgPool) -> PoolReport {
+        let options = pool.options();
+        let waits: Vec<Duration> =
+            self.waits.lock().unwrap_or_else(|err| err.into_inner()).iter().copied().collect();
+        let ms = |wait: Duration| wait.as_secs_f64() * 1000.0;
+        let idle = pool.num_idle();
+        PoolReport {
+            size: pool.size(),
+            idle,
+            in_use: (pool.size() as usize).saturating_sub(idle),
+            max_connections: options.get_max_connections(),
+            acquire_timeout_secs: options.get_acquire_timeout().as_secs(),
+            idle_timeout_secs: options.get_idle_timeout().map(|timeout| timeout.as_secs()),
+            statement_timeout_ms: (self.statement_timeout_ms > 0)
+                .then_some(self.statement_timeout_ms),
+            acquire_timeouts: metrics().pool_timeouts(),
+            wait: AcquireWaits {
+                samples: waits.len(),
+                last_ms: waits.last().copied().map(ms),
+                avg_ms: (!waits.is_empty())
+                    .then(|| ms(waits.iter().sum::<Duration>()) / waits.len() as f64),
+                max_ms: waits.iter().max().copied().map(ms),
+                failed_probes: self.failed_probes.load(Ordering::Relaxed),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PoolReport {
+    // Open connections, idle ones included.
+    pub size: u32,
+    pub idle: usize,
+    pub in_use: usize,
+    pub max_connections: u32,
+    pub acquire_timeout_secs: u64,
+    // Null when idle connections are kept open.
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub idle_timeout_secs: Option<u64>,
+    // Null when statements may run for as long as they like.
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub statement_timeout_ms: Option<u64>,
+    // Queries that gave up waiting for a connection since the start.
+    pub acquire_timeouts: u64,
+    pub wait: AcquireWaits,
+}
+
+// How long the recent probes waited for a connection. The figures are null
+// until the first probe.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct AcquireWaits {
+    pub samples: usize,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub last_ms: Option<f64>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub avg_ms: Option<f64>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub max_ms: Option<f64>,
+    // Probes that timed out or found the database unreachable.
+    pub failed_probes: u64,
+}
+
+pub(crate) fn pool_probe_secs() -> u64 {
+    env::var("DB_POOL_PROBE_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5)
+        .max(1)
+}
+
+pub async fn monitor_pool(pool: PgPool, monitor: web::Data<PoolMonitor>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(pool_probe_secs()));
+    loop {
+        interval.tick().await;
+        monitor.probe(&pool).await;
+    }
+}
@@ -0,0 +1,227 @@
+// How routes name posts. Every post has an integer id and a UUID v7, and
+// posts made since snowflake ids came in have one of those too; the
+// `{id}` in `/blog/{id}` may be one or the other as ID_STRATEGY says, so
+// clients can't count posts or guess their URLs. The integer stays the
+// key everything else refers to.
+
+use crate::*;
+
+// ID_STRATEGY: `serial` (the default; an id or a UUID), `uuid` (only the
+// UUID) or `snowflake` (the snowflake id or, for older posts, the UUID).
+// POST_IDS is its old name and is read when ID_STRATEGY isn't set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IdStrategy {
+    #[default]
+    SerialI32,
+    Uuid,
+    Snowflake,
+}
+
+impl IdStrategy {
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("ID_STRATEGY").or_else(|_| env::var("POST_IDS")).as_deref() {
+            Ok("serial") | Err(_) => Ok(IdStrategy::SerialI32),
+            Ok("uuid") => Ok(IdStrategy::Uuid),
+            Ok("snowflake") => Ok(IdStrategy::Snowflake),
+            Ok(other) => Err(format!("Unknown ID_STRATEGY `{}`", other)),
+        }
+    }
+
+    // Where the post is, for Location headers and links.
+    pub fn path(self, post: &BlogPost) -> String {
+        match (self, post.snowflake_id) {
+            (IdStrategy::SerialI32, _) => format!("/blog/{}", post.id),
+            (IdStrategy::Snowflake, Some(snowflake)) => format!("/blog/{}", snowflake),
+            (IdStrategy::Uuid | IdStrategy::Snowflake, _) => format!("/blog/{}", post.uuid),
+        }
+    }
+
+    fn of(req: &HttpRequest) -> Self {
+        req.app_data::<web::Data<IdStrategy>>()
+            .map_or_else(IdStrategy::default, |ids| *ids.get_ref())
+    }
+}
+
+// `IdStrategy::path` with the strategy of the app the request came in on.
+pub fn post_path(req: &HttpRequest, post: &BlogPost) -> String {
+    IdStrategy::of(req).path(post)
+}
+
+// Milliseconds since this in the top 41 bits of a snowflake id (good for
+// about 69 years), the machine id in the next 10 and a sequence number
+// within the millisecond in the last 12. Ids from one machine never
+// repeat and sort by when they were made; across machines they sort to
+// within clock skew.
+pub(crate) const SNOWFLAKE_EPOCH_MS: i64 = 1_767_225_600_000; // 2026-01-01T00:00:00Z
+const SNOWFLAKE_MACHINE_BITS: u32 = 10;
+const SNOWFLAKE_SEQUENCE_BITS: u32 = 12;
+
+pub(crate) static SNOWFLAKES: OnceLock<Snowflakes> = OnceLock::new();
+
+// Hands out the snowflake ids of new posts. Every store gives one to
+// every post it creates, whatever ID_STRATEGY is, so a later switch to
+// `snowflake` finds the newer posts ready.
+#[derive(Debug, Default)]
+pub struct Snowflakes {
+    machine: i64,
+    // The millisecond and sequence number of the last id.
+    last: std::sync::Mutex<(i64, i64)>,
+}
+
+impl Snowflakes {
+    // SNOWFLAKE_MACHINE_ID: 0 (the default) to 1023. Instances sharing a
+    // database need different ones, or their ids can collide.
+    pub fn from_env() -> Result<Self, String> {
+        let machine = match env::var("SNOWFLAKE_MACHINE_ID") {
+            Ok(value) => value
+                .parse::<i64>()
+                .ok()
+                .filter(|machine| (0..1 << SNOWFLAKE_MACHINE_BITS).contains(machine))
+                .ok_or_else(|| format!("SNOWFLAKE_MACHINE_ID must be 0 to 1023, not `{}`", value))?,
+            Err(_) => 0,
+        };
+        Ok(Snowflakes::new(machine))
+    }
+
+    pub fn new(machine: i64) -> Self {
+        Snowflakes {
+            machine: machine & ((1 << SNOWFLAKE_MACHINE_BITS) - 1),
+            last: std::sync::Mutex::new((i64::MIN, 0)),
+        }
+    }
+
+    // Checks the machine id at startup rather than on the first post.
+    pub fn init() -> Result<(), String> {
+        let snowflakes = Snowflakes::from_env()?;
+        let _ = SNOWFLAKES.set(snowflakes);
+        Ok(())
+    }
+
+    // The millisecond only goes forward: when the clock steps back, or a
+    // millisecond's 4096 ids are used up, ids carry on from the next one
+    // rather than waiting for the clock.
+    pub fn next(&self) -> i64 {
+        let now = Utc::now().timestamp_millis() - SNOWFLAKE_EPOCH_MS;
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        let (ms, sequence) = match *last {
+            (ms, sequence) if now <= ms && sequence + 1 < 1 << SNOWFLAKE_SEQUENCE_BITS => {
+                (ms, sequence + 1)
+            }
+            (ms, _) if now <= ms => (ms + 1, 0),
+            _ => (now, 0),
+        };
+        *last = (ms, sequence);
+        ms << (SNOWFLAKE_MACHINE_BITS + SNOWFLAKE_SEQUENCE_BITS)
+            | self.machine << SNOWFLAKE_SEQUENCE_BITS
+            | sequence
+    }
+}
+
+// The snowflake id for a post being created.
+pub fn next_snowflake_id() -> i64 {
+    SNOWFLAKES.get_or_init(Snowflakes::default).next()
+}
+
+// The `{id}` of a post route as the client sent it, before it is looked up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostKey {
+    Id(i32),
+    Uuid(Uuid),
+    Snowflake(i64),
+}
+
+impl fmt::Display for PostKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PostKey::Id(id) => write!(f, "{}", id),
+            PostKey::Uuid(uuid) => write!(f, "{}", uuid),
+            PostKey::Snowflake(snowflake) => write!(f, "{}", snowflake),
+        }
+    }
+}
+
+impl PostKey {
+    // 400 for something that is no key; integers are 404, as if there
+    // were no such post, under ID_STRATEGY=uuid, and snowflake ids under
+    // ID_STRATEGY=snowflake.
+    pub fn parse(segment: &str, ids: IdStrategy) -> Result<Self, ApiError> {
+        if let Ok(number) = segment.parse::<i64>() {
+            return match (ids, i32::try_from(number)) {
+                (IdStrategy::SerialI32, Ok(id)) => Ok(PostKey::Id(id)),
+                (IdStrategy::SerialI32, Err(_)) => {
+                    Err(ApiError::BadRequest(format!("`{}` is not a post id", segment)))
+                }
+                (IdStrategy::Uuid, _) => {
+                    Err(ApiError::NotFound(format!("Post {} not found", segment)))
+                }
+                (IdStrategy::Snowflake, _) => Ok(PostKey::Snowflake(number)),
+            };
+        }
+        match Uuid::try_parse(segment) {
+            Ok(uuid) => Ok(PostKey::Uuid(uuid)),
+            Err(_) => Err(ApiError::BadRequest(format!("`{}` is not a post id", segment))),
+        }
+    }
+
+    // The post's integer id; NotFound when no post of `tenant` has the
+    // UUID or snowflake id. Integer ids are taken as they are and checked
+    // by whatever uses them.
+    pub async fn resolve(self, repo: &dyn PostRepository, tenant: i32) -> Result<i32, ApiError> {
+        match self {
+            PostKey::Id(id) => Ok(id),
+            PostKey::Uuid(uuid) => repo.id_by_uuid(tenant, uuid).await,
+            PostKey::Snowflake(snowflake) => repo.id_by_snowflake(tenant, snowflake).await,
+        }
+    }
+}
+
+impl FromRequest for PostKey {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let segment = req.match_info().get("id").unwrap_or_default();
+        ready(PostKey::parse(segment, IdStrategy::of(req)))
+    }
+}
+
+// The `{id}` of the post routes, for the API docs, which can't see it
+// through PostId.
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct PostPath {
+    pub id: String,
+}
+
+// In place of `web::Path<i32>` on the post routes: the integer id of the
+// post the route names, by whichever key it takes, in the request's
+// tenant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PostId(pub i32);
+
+impl PostId {
+    pub fn into_inner(self) -> i32 {
+        self.0
+    }
+}
+
+impl FromRequest for PostId {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let key = PostKey::from_request(req, payload).into_inner();
+        let req = req.clone();
+        Box::pin(async move {
+            let key = key?;
+            if let PostKey::Id(id) = key {
+                return Ok(PostId(id));
+            }
+            let Some(repo) = req.app_data::<web::Data<dyn PostRepository>>() else {
+                return Err(ApiError::NotFound(format!("Post {} not found", key)));
+            };
+            let tenant = Tenant::extract(&req).await?;
+            Ok(PostId(key.resolve(repo.get_ref(), tenant.id).await?))
+        })
+    }
+}
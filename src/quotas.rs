@@ -0,0 +1,128 @@
+// Per-user write quotas: how many posts and comments one account may
+// create in a rolling window, so a single account can't flood the blog.
+
+use actix_web::HttpResponseBuilder;
+
+use crate::*;
+
+// QUOTA_POSTS posts and QUOTA_COMMENTS comments (0, the default, is no
+// limit) per QUOTA_WINDOW_SECS (default 86400, a day) per user. Admins
+// have none. What counts is kept in write_quota_usage, so the limit holds
+// across instances and restarts; deleting what was written doesn't lower
+// it. Two writes at the same moment may both get the last slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteQuotas {
+    pub posts: u32,
+    pub comments: u32,
+    pub window: Duration,
+}
+
+impl Default for WriteQuotas {
+    fn default() -> Self {
+        WriteQuotas {
+            posts: 0,
+            comments: 0,
+            window: Duration::from_secs(86400),
+        }
+    }
+}
+
+// Where a user stands against a quota, counting the write being made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub kind: ContentKind,
+    pub limit: u32,
+    pub used: i64,
+    // Seconds until the oldest counted write leaves the window.
+    pub reset_secs: u64,
+}
+
+impl QuotaUsage {
+    pub fn remaining(&self) -> i64 {
+        (i64::from(self.limit) - self.used).max(0)
+    }
+}
+
+impl WriteQuotas {
+    pub fn from_env() -> Self {
+        let default = WriteQuotas::default();
+        let limit = |name: &str| env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(0);
+        let window = env::var("QUOTA_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&secs: &u64| secs > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(default.window);
+        WriteQuotas {
+            posts: limit("QUOTA_POSTS"),
+            comments: limit("QUOTA_COMMENTS"),
+            window,
+        }
+    }
+
+    fn limit(&self, kind: ContentKind) -> u32 {
+        match kind {
+            ContentKind::Post => self.posts,
+            ContentKind::Comment => self.comments,
+        }
+    }
+
+    // Whether `user` may create `count` more of `kind`: QuotaExceeded if
+    // not, else their usage with those included. None when there is no
+    // quota for them.
+    pub async fn check(
+        &self,
+        pool: &PgPool,
+        user: &AuthUser,
+        kind: ContentKind,
+        count: usize,
+    ) -> Result<Option<QuotaUsage>, ApiError> {
+        let limit = self.limit(kind);
+        if limit == 0 || user.role == Role::Admin {
+            return Ok(None);
+        }
+        let (used, oldest) = quota_usage(pool, user.id, kind, self.window).await?;
+        let reset_secs = oldest
+            .map(|oldest| (oldest + self.window - Utc::now()).num_seconds().max(1) as u64)
+            .unwrap_or(self.window.as_secs());
+        let usage = QuotaUsage {
+            kind,
+            limit,
+            used: used + count as i64,
+            reset_secs,
+        };
+        if usage.used > i64::from(limit) {
+            return Err(ApiError::QuotaExceeded(QuotaUsage { used, ..usage }));
+        }
+        Ok(Some(usage))
+    }
+
+    // Counts `count` writes of `kind` that went through. They are saved
+    // already, so a failure is only logged.
+    pub async fn record(&self, pool: &PgPool, user: &AuthUser, kind: ContentKind, count: usize) {
+        if self.limit(kind) == 0 || user.role == Role::Admin || count == 0 {
+            return;
+        }
+        if let Err(err) = record_quota_usage(pool, user.id, kind, count, self.window).await {
+            let kind = kind.as_str();
+            tracing::warn!("Could not count {} {}(s) of user {}: {}", count, kind, user.id, err);
+        }
+    }
+}
+
+// X-Quota-Limit, X-Quota-Remaining and X-Quota-Reset (seconds) on a
+// response to a write with a quota.
+pub trait QuotaHeaders {
+    fn quota(&mut self, usage: Option<QuotaUsage>) -> &mut Self;
+}
+
+impl QuotaHeaders for HttpResponseBuilder {
+    fn quota(&mut self, usage: Option<QuotaUsage>) -> &mut Self {
+        if let Some(usage) = usage {
+            self.insert_header(("X-Quota-Limit", usage.limit.to_string()))
+                .insert_header(("X-Quota-Remaining", usage.remaining().to_string()))
+                .insert_header(("X-Quota-Reset", usage.reset_secs.to_string()));
+        }
+        self
+    }
+}
@@ -0,0 +1,254 @@
+// Per-client request rate limiting.
+
+use crate::*;
+
+// Token bucket per client IP: RATE_LIMIT_REQUESTS requests (0, the
+// default, turns limiting off) refilled evenly over RATE_LIMIT_WINDOW_SECS
+// (default 60), so short bursts up to the limit are allowed. Buckets live
+// in process memory unless RATE_LIMIT_REDIS_URL is set, in which case all
+// instances share them through Redis. Over the limit a request gets 429
+// with Retry-After.
+#[derive(Debug, Clone, Copy)]
+pub struct RateDecision {
+    pub allowed: bool,
+    pub retry_after: Duration,
+}
+
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    // Takes one token from `key`'s bucket if there is one.
+    async fn hit(&self, key: &str) -> Result<RateDecision, String>;
+}
+
+pub(crate) struct Bucket {
+    tokens: f64,
+    updated: std::time::Instant,
+}
+
+pub struct MemoryRateLimitStore {
+    capacity: f64,
+    refill_per_sec: f64,
+    // Idle buckets are full again after one window, so they can go.
+    buckets: Cache<String, Arc<std::sync::Mutex<Bucket>>>,
+}
+
+impl MemoryRateLimitStore {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        MemoryRateLimitStore {
+            capacity: limit as f64,
+            refill_per_sec: limit as f64 / window.as_secs_f64(),
+            buckets: Cache::builder()
+                .time_to_idle(window)
+                .max_capacity(100_000)
+                .build(),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn hit(&self, key: &str) -> Result<RateDecision, String> {
+        let now = std::time::Instant::now();
+        let bucket = self.buckets.get_with(key.to_string(), || {
+            Arc::new(std::sync::Mutex::new(Bucket {
+                tokens: self.capacity,
+                updated: now,
+            }))
+        });
+        let mut bucket = bucket.lock().map_err(|_| "rate limit bucket poisoned".to_string())?;
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(RateDecision { allowed: true, retry_after: Duration::ZERO });
+        }
+        let wait = (1.0 - bucket.tokens) / self.refill_per_sec;
+        Ok(RateDecision {
+            allowed: false,
+            retry_after: Duration::from_secs_f64(wait),
+        })
+    }
+}
+
+// The same bucket as MemoryRateLimitStore, updated atomically in a Lua
+// script and timed by the Redis clock so instances don't need synced
+// clocks. Keys expire once the bucket would be full again.
+pub(crate) const REDIS_TOKEN_BUCKET: &str = r#"
+local capacity = tonumber(ARGV[1])
+local refill_per_ms = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or capacity
+local updated = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated) * refill_per_ms)
+local allowed = 0
+local wait_ms = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+else
+    wait_ms = math.ceil((1 - tokens) / refill_per_ms)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / refill_per_ms))
+return {allowed, wait_ms}
+"#;
+
+pub struct RedisRateLimitStore {
+    connection: redis::aio::ConnectionManager,
+    script: redis::Script,
+    capacity: f64,
+    refill_per_ms: f64,
+}
+
+impl RedisRateLimitStore {
+    pub async fn connect(url: &str, limit: u32, window: Duration) -> Result<Self, String> {
+        Ok(RedisRateLimitStore {
+            connection: connect_redis(url).await?,
+            script: redis::Script::new(REDIS_TOKEN_BUCKET),
+            capacity: limit as f64,
+            refill_per_ms: limit as f64 / window.as_millis() as f64,
+        })
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for RedisRateLimitStore {
+    async fn hit(&self, key: &str) -> Result<RateDecision, String> {
+        let mut connection = self.connection.clone();
+        let (allowed, wait_ms): (i64, i64) = self
+            .script
+            .key(format!("rate_limit:{}", key))
+            .arg(self.capacity)
+            .arg(self.refill_per_ms)
+            .invoke_async(&mut connection)
+            .await
+            .map_err(|err| err.to_string())?;
+        Ok(RateDecision {
+            allowed: allowed == 1,
+            retry_after: Duration::from_millis(wait_ms.max(0) as u64),
+        })
+    }
+}
+
+// The middleware. Health and readiness checks are never limited, so probes
+// keep working under load. If the store fails (e.g. Redis is down) the
+// request is let through: losing the limit briefly beats refusing all
+// traffic.
+#[derive(Clone)]
+pub struct RateLimit {
+    store: Option<Arc<dyn RateLimitStore>>,
+    // RATE_LIMIT_TRUST_PROXY=1 keys on X-Forwarded-For / Forwarded, which
+    // is only safe behind a proxy that sets them.
+    trust_proxy: bool,
+}
+
+impl RateLimit {
+    // Without a store nothing is limited.
+    pub fn new(store: Option<Arc<dyn RateLimitStore>>, trust_proxy: bool) -> Self {
+        RateLimit { store, trust_proxy }
+    }
+
+    pub async fn from_env() -> Result<Self, String> {
+        let limit: u32 = env::var("RATE_LIMIT_REQUESTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let window = Duration::from_secs(
+            env::var("RATE_LIMIT_WINDOW_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&secs: &u64| secs > 0)
+                .unwrap_or(60),
+        );
+        let trust_proxy = env_flag("RATE_LIMIT_TRUST_PROXY", false);
+        if limit == 0 {
+            return Ok(RateLimit::new(None, trust_proxy));
+        }
+
+        let store: Arc<dyn RateLimitStore> = match env::var("RATE_LIMIT_REDIS_URL") {
+            Ok(url) if !url.is_empty() => Arc::new(
+                RedisRateLimitStore::connect(&url, limit, window)
+                    .await
+                    .map_err(|err| format!("Cannot connect to RATE_LIMIT_REDIS_URL: {}", err))?,
+            ),
+            _ => Arc::new(MemoryRateLimitStore::new(limit, window)),
+        };
+        Ok(RateLimit::new(Some(store), trust_proxy))
+    }
+
+    fn client_key(&self, req: &ServiceRequest) -> Option<String> {
+        if req.path().starts_with("/health") || req.path() == "/readyz" {
+            return None;
+        }
+        let ip = if self.trust_proxy {
+            req.connection_info().realip_remote_addr().map(str::to_string)
+        } else {
+            req.peer_addr().map(|addr| addr.ip().to_string())
+        };
+        ip.map(|ip| format!("ip:{}", ip))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limit: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limit: RateLimit,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let store = self.limit.store.clone();
+        let key = self.limit.client_key(&req);
+
+        Box::pin(async move {
+            if let (Some(store), Some(key)) = (store, key) {
+                match store.hit(&key).await {
+                    Ok(decision) if !decision.allowed => {
+                        let secs = decision.retry_after.as_secs_f64().ceil().max(1.0);
+                        let response = ApiError::TooManyRequests(secs as u64).error_response();
+                        return Ok(req.into_response(response).map_into_right_body());
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        tracing::warn!("Rate limit store failed, allowing request: {}", err)
+                    }
+                }
+            }
+            service.call(req).await.map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
@@ -0,0 +1,103 @@
+// Markdown rendering, excerpts and the anchored paragraph view.
+
+use crate::*;
+
+// -------------------- Anchors --------------------
+
+// Paragraphs are separated by blank lines. The anchor is derived from the
+// paragraph text only, so the same paragraph always gets the same id no
+// matter where it moves in the post. Repeated paragraphs get a -2, -3...
+// suffix to keep ids unique within the post.
+pub(crate) fn paragraph_anchor(text: &str) -> String {
+    let digest = Sha256::digest(text.as_bytes());
+    format!("para-{}", hex::encode(&digest[..4]))
+}
+
+pub fn anchor_post(post: BlogPost) -> AnchoredBlogPost {
+    let normalized = post.content.replace("\r\n", "\n");
+    let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+    let mut paragraphs = Vec::new();
+
+    for text in normalized.split("\n\n").map(str::trim) {
+        if text.is_empty() {
+            continue;
+        }
+        let base = paragraph_anchor(text);
+        let count = seen.entry(base.clone()).or_insert(0);
+        *count += 1;
+        let anchor = match *count {
+            1 => base,
+            n => format!("{}-{}", base, n),
+        };
+        paragraphs.push(Paragraph {
+            anchor,
+            text: text.to_string(),
+        });
+    }
+
+    AnchoredBlogPost {
+        id: post.id,
+        uuid: post.uuid,
+        title: post.title,
+        author: post.author,
+        paragraphs,
+        version: post.version,
+    }
+}
+
+// -------------------- Rendering --------------------
+
+pub(crate) const WORDS_PER_MINUTE: usize = 200;
+
+// Markdown to HTML, then through ammonia's default allowlist so scripts,
+// event handlers and javascript: links never reach a browser.
+pub fn render_markdown(markdown: &str) -> String {
+    let parser = pulldown_cmark::Parser::new_ext(markdown, pulldown_cmark::Options::all());
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    ammonia::clean(&html)
+}
+
+// Counted in characters of Markdown, before rendering.
+pub(crate) const EXCERPT_MAX_CHARS: usize = 300;
+
+// The first paragraph rendered, cut at a word boundary with an ellipsis
+// when it is longer than EXCERPT_MAX_CHARS. Markup left open by the cut is
+// closed by the renderer.
+pub fn render_excerpt(markdown: &str) -> String {
+    let normalized = markdown.replace("\r\n", "\n");
+    let first = normalized
+        .split("\n\n")
+        .map(str::trim)
+        .find(|paragraph| !paragraph.is_empty())
+        .unwrap_or("");
+    if first.chars().count() <= EXCERPT_MAX_CHARS {
+        return render_markdown(first);
+    }
+    let cut: String = first.chars().take(EXCERPT_MAX_CHARS).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(end) => &cut[..end],
+        None => &cut,
+    };
+    render_markdown(&format!("{}…", cut.trim_end()))
+}
+
+pub fn word_count(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+pub fn reading_time_minutes(words: usize) -> usize {
+    words.div_ceil(WORDS_PER_MINUTE)
+}
+
+// Renders the content as it would be saved, sanitized first.
+pub fn preview_post(post: &NewBlogPost) -> PostPreview {
+    let content = sanitize_content(&post.content);
+    let words = word_count(&content);
+    PostPreview {
+        title: post.title.clone(),
+        html: render_markdown(&content),
+        word_count: words,
+        reading_time_minutes: reading_time_minutes(words),
+    }
+}
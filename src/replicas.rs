@@ -0,0 +1,99 @@
+// Read replicas: queries that only read go to one of them in turn, and
+// to the primary while none can be reached.
+
+use crate::*;
+
+// How long a replica may take to hand out a connection, at most.
+pub(crate) const REPLICA_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(1);
+// How long a replica that couldn't be reached is left out before a read
+// tries it again.
+pub(crate) const REPLICA_BACKOFF: Duration = Duration::from_secs(10);
+
+struct Replica {
+    pool: PgPool,
+    down_until: std::sync::Mutex<Option<Instant>>,
+}
+
+impl Replica {
+    fn is_up(&self) -> bool {
+        let down_until = self.down_until.lock().unwrap_or_else(|err| err.into_inner());
+        down_until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    fn mark_down(&self) {
+        let mut down_until = self.down_until.lock().unwrap_or_else(|err| err.into_inner());
+        *down_until = Some(Instant::now() + REPLICA_BACKOFF);
+    }
+}
+
+// The primary pool and the replicas, if any. Cheap to clone; clones share
+// the rotation and which replicas are down. Replicas may lag behind the
+// primary, so a read right after a write may not see it yet.
+#[derive(Clone)]
+pub struct ReadPools {
+    primary: PgPool,
+    replicas: Arc<Vec<Replica>>,
+    next: Arc<AtomicU64>,
+}
+
+impl ReadPools {
+    // Everything on `primary`.
+    pub fn primary(primary: PgPool) -> Self {
+        ReadPools::new(primary, Vec::new())
+    }
+
+    pub fn new(primary: PgPool, replicas: Vec<PgPool>) -> Self {
+        let replicas = replicas
+            .into_iter()
+            .map(|pool| Replica {
+                pool,
+                down_until: std::sync::Mutex::new(None),
+            })
+            .collect();
+        ReadPools {
+            primary,
+            replicas: Arc::new(replicas),
+            next: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    // The pools for `config.replica_urls` next to `primary`.
+    pub fn connect(config: &Config, primary: PgPool) -> Result<Self, sqlx::Error> {
+        Ok(ReadPools::new(primary, establish_replica_connections(config)?))
+    }
+
+    pub fn primary_pool(&self) -> &PgPool {
+        &self.primary
+    }
+
+    // The next replica in turn that isn't left out.
+    fn replica(&self) -> Option<&Replica> {
+        let count = self.replicas.len();
+        if count == 0 {
+            return None;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed) as usize;
+        (0..count).map(|i| &self.replicas[(start + i) % count]).find(|replica| replica.is_up())
+    }
+
+    // Runs the read `op` on a replica, or on the primary when there is no
+    // replica up or the one picked turns out to be unreachable; that one
+    // is then left out for REPLICA_BACKOFF. Other errors are the query's
+    // and are returned as they are.
+    pub async fn read<'a, T, F, Fut>(&'a self, mut op: F) -> Result<T, ApiError>
+    where
+        F: FnMut(&'a PgPool) -> Fut,
+        Fut: Future<Output = Result<T, ApiError>>,
+    {
+        if let Some(replica) = self.replica() {
+            match op(&replica.pool).await {
+                Err(ApiError::DatabaseUnavailable(_)) => {
+                    tracing::warn!("Read replica unreachable; reading from the primary");
+                    replica.mark_down();
+                }
+                result => return result,
+            }
+        }
+        op(&self.primary).await
+    }
+}
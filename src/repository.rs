@@ -0,0 +1,593 @@
+// PostRepository and the Postgres and in-memory stores behind it.
+
+use crate::*;
+
+// Storage for the post CRUD handlers, injected as `web::Data<dyn
+// PostRepository>` like the translator. Handlers only see this, so they can
+// be tested against a stand-in and another database only needs an
+// implementation. Errors use the same ApiError variants as the query
+// functions: NotFound for a missing post, PreconditionFailed for a stale
+// If-Match and Conflict for a stale version. Everything is per tenant; a
+// post of another tenant is NotFound. New posts go to the author's.
+#[async_trait]
+pub trait PostRepository: Send + Sync {
+    async fn create(&self, post: &NewBlogPost, author: &AuthUser) -> Result<BlogPost, ApiError>;
+    async fn get(&self, tenant: i32, id: i32) -> Result<BlogPost, ApiError>;
+    async fn get_by_slug(&self, tenant: i32, slug: &str) -> Result<BlogPost, ApiError>;
+    // The live posts among `ids`, in id order, leaving out the ones that
+    // aren't. One query, not one per id.
+    async fn get_many(&self, tenant: i32, ids: &[i32]) -> Result<Vec<BlogPost>, ApiError>;
+    async fn list(
+        &self,
+        filter: PostFilter<'_>,
+        sort: SortColumn,
+        order: SortOrder,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<BlogPost>, ApiError>;
+    // Backends without a planner estimate may answer Estimate exactly.
+    async fn count(&self, filter: PostFilter<'_>, mode: CountMode) -> Result<i64, ApiError>;
+    // The author's id, for trashed posts too.
+    async fn owner(&self, tenant: i32, id: i32) -> Result<i32, ApiError>;
+    // The id of the post with this UUID, for trashed posts too.
+    async fn id_by_uuid(&self, tenant: i32, uuid: Uuid) -> Result<i32, ApiError>;
+    // The same for a snowflake id.
+    async fn id_by_snowflake(&self, tenant: i32, snowflake: i64) -> Result<i32, ApiError>;
+    async fn update(
+        &self,
+        tenant: i32,
+        id: i32,
+        post: &NewBlogPost,
+        if_match: Option<&IfMatch>,
+        user_id: i32,
+    ) -> Result<BlogPost, ApiError>;
+    async fn patch(
+        &self,
+        tenant: i32,
+        id: i32,
+        patch: &UpdateBlogPost,
+        if_match: Option<&IfMatch>,
+        user_id: i32,
+    ) -> Result<BlogPost, ApiError>;
+    // Moves the post to the trash.
+    async fn delete(
+        &self,
+        tenant: i32,
+        id: i32,
+        if_match: Option<&IfMatch>,
+        user_id: i32,
+    ) -> Result<(), ApiError>;
+    // PUT with `?upsert=true`: creates the post under `key`, an id, a UUID
+    // or a snowflake id, if there is none, otherwise updates it. `owner` is who the caller found owning it (the
+    // caller, for a new one). True when it was created. Only Postgres lets
+    // clients choose ids.
+    async fn upsert(
+        &self,
+        _tenant: i32,
+        _key: PostKey,
+        _post: &NewBlogPost,
+        _if_match: Option<&IfMatch>,
+        _owner: i32,
+        _user_id: i32,
+    ) -> Result<(BlogPost, bool), ApiError> {
+        Err(ApiError::UnprocessableEntity("Upserts need POST_STORE=postgres".to_string()))
+    }
+
+    // Languages the post has been translated into. Backends that don't
+    // keep translations serve every post in the original language.
+    async fn translation_langs(&self, _id: i32) -> Result<Vec<String>, ApiError> {
+        Ok(Vec::new())
+    }
+
+    async fn translation(&self, _id: i32, _lang: &str) -> Result<Option<Translation>, ApiError> {
+        Ok(None)
+    }
+
+    // The live posts among `slugs`, in any order, leaving out the ones no
+    // post has. Backends that can should answer in one query.
+    async fn get_many_by_slug(
+        &self,
+        tenant: i32,
+        slugs: &[String],
+    ) -> Result<Vec<BlogPost>, ApiError> {
+        let mut posts = Vec::new();
+        for slug in slugs {
+            match self.get_by_slug(tenant, slug).await {
+                Ok(post) => posts.push(post),
+                Err(ApiError::NotFound(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(posts)
+    }
+
+    // The published posts for GET /sitemap.xml, most recently updated
+    // first. Backends that can should answer without loading the posts.
+    async fn sitemap(&self, tenant: i32, limit: i64) -> Result<Vec<SitemapEntry>, ApiError> {
+        let filter = PostFilter {
+            status: Some(PostStatus::Published),
+            tenant,
+            ..PostFilter::default()
+        };
+        let posts = self.list(filter, SortColumn::UpdatedAt, SortOrder::Desc, limit, 0).await?;
+        Ok(posts
+            .into_iter()
+            .map(|post| SitemapEntry {
+                slug: post.slug,
+                updated_at: post.updated_at,
+            })
+            .collect())
+    }
+}
+
+// Where the post CRUD handlers keep posts, from POST_STORE: `postgres`
+// (the default), `memory`, or `sqlite` in a build with `--features sqlite`,
+// at SQLITE_URL (default `sqlite://posts.db`). With the others, Postgres is
+// only needed by the routes that don't go through PostRepository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostStore {
+    Postgres,
+    Memory,
+    Sqlite,
+}
+
+impl PostStore {
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("POST_STORE").as_deref() {
+            Ok("postgres") | Err(_) => Ok(PostStore::Postgres),
+            Ok("memory") => Ok(PostStore::Memory),
+            Ok("sqlite") => Ok(PostStore::Sqlite),
+            Ok(other) => Err(format!("Unknown POST_STORE `{}`", other)),
+        }
+    }
+
+    pub async fn open(self, reads: &ReadPools) -> Result<Arc<dyn PostRepository>, String> {
+        match self {
+            PostStore::Postgres => Ok(Arc::new(PgPostRepository::with_reads(reads.clone()))),
+            PostStore::Memory => Ok(Arc::new(InMemoryPostRepository::default())),
+            #[cfg(feature = "sqlite")]
+            PostStore::Sqlite => {
+                let url =
+                    env::var("SQLITE_URL").unwrap_or_else(|_| "sqlite://posts.db".to_string());
+                Ok(Arc::new(sqlite::SqlitePostRepository::connect(&url).await?))
+            }
+            #[cfg(not(feature = "sqlite"))]
+            PostStore::Sqlite => {
+                Err("POST_STORE=sqlite needs a build with `--features sqlite`".to_string())
+            }
+        }
+    }
+}
+
+// The query functions below, over a pool. Reads are retried while the
+// database is unreachable; writes aren't, as one may have gone through.
+// Gets, lists and counts go to the read replicas, if there are any;
+// `owner`, which writes are checked against, stays on the primary.
+pub struct PgPostRepository {
+    pool: PgPool,
+    reads: ReadPools,
+    retry: RetryPolicy,
+}
+
+impl PgPostRepository {
+    pub fn new(pool: PgPool) -> Self {
+        PgPostRepository::with_reads(ReadPools::primary(pool))
+    }
+
+    pub fn with_reads(reads: ReadPools) -> Self {
+        PgPostRepository {
+            pool: reads.primary_pool().clone(),
+            reads,
+            retry: RetryPolicy::from_env(),
+        }
+    }
+}
+
+#[async_trait]
+impl PostRepository for PgPostRepository {
+    async fn create(&self, post: &NewBlogPost, author: &AuthUser) -> Result<BlogPost, ApiError> {
+        let mut tx = self.pool.begin().await?;
+        let post = create_post(&mut tx, author.tenant_id, post, author.id).await?;
+        tx.commit().await?;
+        Ok(post)
+    }
+
+    async fn get(&self, tenant: i32, id: i32) -> Result<BlogPost, ApiError> {
+        retry(&self.retry, || self.reads.read(|pool| get_post(pool, tenant, id))).await
+    }
+
+    async fn get_by_slug(&self, tenant: i32, slug: &str) -> Result<BlogPost, ApiError> {
+        retry(&self.retry, || self.reads.read(|pool| get_post_by_slug(pool, tenant, slug))).await
+    }
+
+    async fn get_many(&self, tenant: i32, ids: &[i32]) -> Result<Vec<BlogPost>, ApiError> {
+        retry(&self.retry, || self.reads.read(|pool| get_posts_by_ids(pool, tenant, ids))).await
+    }
+
+    async fn get_many_by_slug(
+        &self,
+        tenant: i32,
+        slugs: &[String],
+    ) -> Result<Vec<BlogPost>, ApiError> {
+        retry(&self.retry, || self.reads.read(|pool| get_posts_by_slugs(pool, tenant, slugs)))
+            .await
+    }
+
+    async fn list(
+        &self,
+        filter: PostFilter<'_>,
+        sort: SortColumn,
+        order: SortOrder,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<BlogPost>, ApiError> {
+        retry(&self.retry, || {
+            self.reads.read(|pool| get_all_posts(pool, filter, sort, order, limit, offset))
+        })
+        .await
+    }
+
+    async fn count(&self, filter: PostFilter<'_>, mode: CountMode) -> Result<i64, ApiError> {
+        retry(&self.retry, || {
+            self.reads.read(|pool| async move {
+                match mode {
+                    CountMode::Exact => count_posts(pool, filter).await,
+                    CountMode::Estimate => estimate_count(pool, LIST_POSTS_SQL, filter).await,
+                }
+            })
+        })
+        .await
+    }
+
+    async fn owner(&self, tenant: i32, id: i32) -> Result<i32, ApiError> {
+        retry(&self.retry, || post_owner(&self.pool, tenant, id)).await
+    }
+
+    async fn id_by_uuid(&self, tenant: i32, uuid: Uuid) -> Result<i32, ApiError> {
+        retry(&self.retry, || post_id_by_uuid(&self.pool, tenant, uuid)).await
+    }
+
+    async fn id_by_snowflake(&self, tenant: i32, snowflake: i64) -> Result<i32, ApiError> {
+        retry(&self.retry, || post_id_by_snowflake(&self.pool, tenant, snowflake)).await
+    }
+
+    async fn update(
+        &self,
+        tenant: i32,
+        id: i32,
+        post: &NewBlogPost,
+        if_match: Option<&IfMatch>,
+        user_id: i32,
+    ) -> Result<BlogPost, ApiError> {
+        let mut tx = self.pool.begin().await?;
+        let post = update_post(&mut tx, tenant, id, post, if_match, user_id).await?;
+        tx.commit().await?;
+        Ok(post)
+    }
+
+    async fn patch(
+        &self,
+        tenant: i32,
+        id: i32,
+        patch: &UpdateBlogPost,
+        if_match: Option<&IfMatch>,
+        user_id: i32,
+    ) -> Result<BlogPost, ApiError> {
+        let mut tx = self.pool.begin().await?;
+        let post = patch_post(&mut tx, tenant, id, patch, if_match, user_id).await?;
+        tx.commit().await?;
+        Ok(post)
+    }
+
+    async fn delete(
+        &self,
+        tenant: i32,
+        id: i32,
+        if_match: Option<&IfMatch>,
+        user_id: i32,
+    ) -> Result<(), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        delete_post(&mut tx, tenant, id, if_match, user_id).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn upsert(
+        &self,
+        tenant: i32,
+        key: PostKey,
+        post: &NewBlogPost,
+        if_match: Option<&IfMatch>,
+        owner: i32,
+        user_id: i32,
+    ) -> Result<(BlogPost, bool), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        let saved = upsert_post(&mut tx, tenant, key, post, if_match, owner, user_id).await?;
+        tx.commit().await?;
+        Ok(saved)
+    }
+
+    async fn translation_langs(&self, id: i32) -> Result<Vec<String>, ApiError> {
+        retry(&self.retry, || list_translation_langs(&self.pool, id)).await
+    }
+
+    async fn translation(&self, id: i32, lang: &str) -> Result<Option<Translation>, ApiError> {
+        retry(&self.retry, || get_translation(&self.pool, id, lang)).await
+    }
+
+    async fn sitemap(&self, tenant: i32, limit: i64) -> Result<Vec<SitemapEntry>, ApiError> {
+        retry(&self.retry, || self.reads.read(|pool| get_sitemap_entries(pool, tenant, limit)))
+            .await
+    }
+}
+
+// Posts in a map, for POST_STORE=memory: demos, CI smoke tests and handler
+// tests. Nothing survives a restart, and like the SQLite store it keeps no
+// translations or audit entries. The lock is never held across an await.
+#[derive(Default)]
+pub struct InMemoryPostRepository {
+    state: std::sync::RwLock<MemoryPosts>,
+}
+
+#[derive(Default)]
+pub(crate) struct MemoryPosts {
+    last_id: i32,
+    // Trashed posts stay, so their slugs and owners are still known.
+    posts: HashMap<i32, MemoryPost>,
+}
+
+pub(crate) struct MemoryPost {
+    post: BlogPost,
+    tenant: i32,
+    deleted: bool,
+}
+
+impl MemoryPosts {
+    // Trashed or not.
+    fn any(&self, tenant: i32, id: i32) -> Result<&MemoryPost, ApiError> {
+        match self.posts.get(&id) {
+            Some(entry) if entry.tenant == tenant => Ok(entry),
+            _ => Err(ApiError::NotFound(format!("Post {} not found", id))),
+        }
+    }
+
+    fn live(&self, tenant: i32, id: i32) -> Result<&BlogPost, ApiError> {
+        match self.any(tenant, id)? {
+            MemoryPost { post, deleted: false, .. } => Ok(post),
+            _ => Err(ApiError::NotFound(format!("Post {} not found", id))),
+        }
+    }
+
+    // The live posts of `tenant`.
+    fn listed(&self, tenant: i32) -> impl Iterator<Item = &BlogPost> {
+        self.posts
+            .values()
+            .filter(move |entry| entry.tenant == tenant && !entry.deleted)
+            .map(|entry| &entry.post)
+    }
+
+    // The live post about to be changed, checked like the other stores do,
+    // with its version and updated_at already moved on.
+    fn change(
+        &mut self,
+        tenant: i32,
+        id: i32,
+        if_match: Option<&IfMatch>,
+        version: Option<i32>,
+    ) -> Result<&mut BlogPost, ApiError> {
+        let current = self.live(tenant, id)?;
+        if let Some(if_match) = if_match {
+            require_etag(current, if_match)?;
+        }
+        if let Some(version) = version
+            && version != current.version
+        {
+            return Err(stale_version(id, version, Some(current.version)));
+        }
+        let post = &mut self.posts.get_mut(&id).expect("checked above").post;
+        post.version += 1;
+        post.updated_at = Utc::now();
+        Ok(post)
+    }
+}
+
+impl InMemoryPostRepository {
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, MemoryPosts> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, MemoryPosts> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl PostRepository for InMemoryPostRepository {
+    async fn create(&self, post: &NewBlogPost, author: &AuthUser) -> Result<BlogPost, ApiError> {
+        let tags = post.tags.as_deref().map(normalize_tags).transpose()?;
+        no_schedule(post.publish_at)?;
+        let mut state = self.write();
+        let base = slugify(&post.title);
+        let taken: Vec<String> = state
+            .posts
+            .values()
+            .filter(|entry| entry.tenant == author.tenant_id)
+            .map(|entry| entry.post.slug.clone())
+            .filter(|slug| slug.starts_with(&base))
+            .collect();
+        state.last_id += 1;
+        let now = Utc::now();
+        let created = BlogPost {
+            id: state.last_id,
+            uuid: Uuid::now_v7(),
+            snowflake_id: Some(next_snowflake_id()),
+            title: post.title.clone(),
+            slug: first_free_slug(&base, &taken),
+            content: sanitize_content(&post.content).into_owned(),
+            user_id: author.id,
+            author: author.username.clone(),
+            tags: tags.unwrap_or_default(),
+            version: 1,
+            status: post.status.unwrap_or_default().as_str().to_string(),
+            like_count: 0,
+            view_count: 0,
+            publish_at: None,
+            created_at: now,
+            updated_at: now,
+        };
+        let entry = MemoryPost {
+            post: created.clone(),
+            tenant: author.tenant_id,
+            deleted: false,
+        };
+        state.posts.insert(created.id, entry);
+        Ok(created)
+    }
+
+    async fn get(&self, tenant: i32, id: i32) -> Result<BlogPost, ApiError> {
+        self.read().live(tenant, id).cloned()
+    }
+
+    async fn get_by_slug(&self, tenant: i32, slug: &str) -> Result<BlogPost, ApiError> {
+        self.read()
+            .listed(tenant)
+            .find(|post| post.slug == slug)
+            .cloned()
+            .ok_or_else(|| ApiError::NotFound("Record not found".to_string()))
+    }
+
+    async fn get_many(&self, tenant: i32, ids: &[i32]) -> Result<Vec<BlogPost>, ApiError> {
+        let mut ids = ids.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+        let state = self.read();
+        Ok(ids.into_iter().filter_map(|id| state.live(tenant, id).ok().cloned()).collect())
+    }
+
+    async fn list(
+        &self,
+        filter: PostFilter<'_>,
+        sort: SortColumn,
+        order: SortOrder,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<BlogPost>, ApiError> {
+        let state = self.read();
+        let mut posts: Vec<&BlogPost> =
+            state.listed(filter.tenant).filter(|post| filter.matches(post)).collect();
+        posts.sort_by(|a, b| {
+            let primary = match sort {
+                SortColumn::Id => a.id.cmp(&b.id),
+                SortColumn::Title => a.title.cmp(&b.title),
+                SortColumn::Author => a.author.cmp(&b.author),
+                SortColumn::CreatedAt => a.created_at.cmp(&b.created_at),
+                SortColumn::UpdatedAt => a.updated_at.cmp(&b.updated_at),
+            };
+            let primary = match order {
+                SortOrder::Asc => primary,
+                SortOrder::Desc => primary.reverse(),
+            };
+            primary.then(a.id.cmp(&b.id))
+        });
+        Ok(posts
+            .into_iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn count(&self, filter: PostFilter<'_>, _mode: CountMode) -> Result<i64, ApiError> {
+        let state = self.read();
+        let live = state.listed(filter.tenant).filter(|post| filter.matches(post));
+        Ok(live.count() as i64)
+    }
+
+    async fn owner(&self, tenant: i32, id: i32) -> Result<i32, ApiError> {
+        Ok(self.read().any(tenant, id)?.post.user_id)
+    }
+
+    async fn id_by_uuid(&self, tenant: i32, uuid: Uuid) -> Result<i32, ApiError> {
+        self.read()
+            .posts
+            .values()
+            .find(|entry| entry.tenant == tenant && entry.post.uuid == uuid)
+            .map(|entry| entry.post.id)
+            .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", uuid)))
+    }
+
+    async fn id_by_snowflake(&self, tenant: i32, snowflake: i64) -> Result<i32, ApiError> {
+        self.read()
+            .posts
+            .values()
+            .find(|entry| entry.tenant == tenant && entry.post.snowflake_id == Some(snowflake))
+            .map(|entry| entry.post.id)
+            .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", snowflake)))
+    }
+
+    async fn update(
+        &self,
+        tenant: i32,
+        id: i32,
+        post: &NewBlogPost,
+        if_match: Option<&IfMatch>,
+        _user_id: i32,
+    ) -> Result<BlogPost, ApiError> {
+        let tags = post.tags.as_deref().map(normalize_tags).transpose()?;
+        let version = expected_version(post.version)?;
+        let mut state = self.write();
+        let updated = state.change(tenant, id, if_match, Some(version))?;
+        updated.title = post.title.clone();
+        updated.content = sanitize_content(&post.content).into_owned();
+        if let Some(tags) = tags {
+            updated.tags = tags;
+        }
+        Ok(updated.clone())
+    }
+
+    async fn patch(
+        &self,
+        tenant: i32,
+        id: i32,
+        patch: &UpdateBlogPost,
+        if_match: Option<&IfMatch>,
+        _user_id: i32,
+    ) -> Result<BlogPost, ApiError> {
+        let tags = patch.tags.as_deref().map(normalize_tags).transpose()?;
+        let version = expected_version(patch.version)?;
+        no_schedule(patch.publish_at.flatten())?;
+        let mut state = self.write();
+        let updated = state.change(tenant, id, if_match, Some(version))?;
+        if let Some(title) = &patch.title {
+            updated.title = title.clone();
+        }
+        if let Some(content) = &patch.content {
+            updated.content = sanitize_content(content).into_owned();
+        }
+        if let Some(status) = patch.status {
+            updated.status = status.as_str().to_string();
+        }
+        if let Some(tags) = tags {
+            updated.tags = tags;
+        }
+        Ok(updated.clone())
+    }
+
+    async fn delete(
+        &self,
+        tenant: i32,
+        id: i32,
+        if_match: Option<&IfMatch>,
+        _user_id: i32,
+    ) -> Result<(), ApiError> {
+        let mut state = self.write();
+        let current = state.live(tenant, id)?;
+        if let Some(if_match) = if_match {
+            require_etag(current, if_match)?;
+        }
+        if let Some(entry) = state.posts.get_mut(&id) {
+            entry.deleted = true;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+// Riding out a database that is briefly unreachable: reads are retried
+// with backoff, and when it stays down a circuit breaker answers 503 at
+// once instead of letting every request wait out its own timeout.
+
+use std::collections::VecDeque;
+
+use crate::*;
+
+// No retry starts after this long, so one that already waited out
+// DB_ACQUIRE_TIMEOUT_SECS isn't made to wait again.
+const RETRY_BUDGET: Duration = Duration::from_secs(2);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// How often to try a call that failed with DatabaseUnavailable:
+// DB_RETRY_ATTEMPTS tries in all (default 3, 1 for none), waiting
+// DB_RETRY_BASE_MS (default 50) before the second and doubling from there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub base: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            base: Duration::from_millis(50),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> Self {
+        let default = RetryPolicy::default();
+        let attempts = env::var("DB_RETRY_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&attempts: &u32| attempts > 0)
+            .unwrap_or(default.attempts);
+        let base = env::var("DB_RETRY_BASE_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(default.base);
+        RetryPolicy { attempts, base }
+    }
+
+    // The wait before try `attempt + 1`, between half and all of the
+    // doubled delay so callers that failed together don't retry together.
+    fn delay(&self, attempt: u32) -> Duration {
+        let doubled = self.base.saturating_mul(2u32.saturating_pow(attempt - 1));
+        doubled.min(MAX_RETRY_DELAY).mul_f64(0.5 + rand::random::<f64>() / 2.0)
+    }
+}
+
+// Runs `op` until it succeeds, fails with anything but DatabaseUnavailable,
+// or the policy gives up. Only for reads: a write that failed may still
+// have been applied.
+pub async fn retry<T, F, Fut>(policy: &RetryPolicy, mut op: F) -> Result<T, ApiError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ApiError>>,
+{
+    let started = Instant::now();
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(ApiError::DatabaseUnavailable(_))
+                if attempt < policy.attempts && started.elapsed() < RETRY_BUDGET =>
+            {
+                tokio::time::sleep(policy.delay(attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Counts requests answered with database_unavailable. DB_BREAKER_FAILURES
+// of them (default 5, 0 turns the breaker off) within DB_BREAKER_WINDOW_SECS
+// (default 10) open it: for DB_BREAKER_OPEN_SECS (default 10) requests get
+// 503 with Retry-After straight away. Then one request is let through to
+// try the database; it closes the breaker again unless it fails too.
+// Health checks and metrics always go through.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    breaker: Option<Arc<Breaker>>,
+}
+
+struct Breaker {
+    failures: usize,
+    window: Duration,
+    open_for: Duration,
+    state: std::sync::Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    // When recent failures happened, oldest first.
+    failed_at: VecDeque<Instant>,
+    open_until: Option<Instant>,
+    // When the request trying the database after opening went out. A new
+    // one may go if that one never finished, e.g. because its client left.
+    trial_since: Option<Instant>,
+}
+
+enum Admission {
+    Pass,
+    Trial,
+    // Seconds until the breaker lets a request through again.
+    Refuse(u64),
+}
+
+impl CircuitBreaker {
+    pub fn new(failures: usize, window: Duration, open_for: Duration) -> Self {
+        let breaker = (failures > 0).then(|| {
+            Arc::new(Breaker {
+                failures,
+                window,
+                open_for,
+                state: Default::default(),
+            })
+        });
+        CircuitBreaker { breaker }
+    }
+
+    pub fn from_env() -> Self {
+        let secs = |name: &str, default: u64| {
+            let secs = env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+            Duration::from_secs(secs)
+        };
+        let failures = env::var("DB_BREAKER_FAILURES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5);
+        CircuitBreaker::new(
+            failures,
+            secs("DB_BREAKER_WINDOW_SECS", 10),
+            secs("DB_BREAKER_OPEN_SECS", 10).max(Duration::from_secs(1)),
+        )
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.breaker.as_ref().is_some_and(|breaker| breaker.state().open_until.is_some())
+    }
+}
+
+impl Breaker {
+    fn state(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn admit(&self) -> Admission {
+        let now = Instant::now();
+        let mut state = self.state();
+        let Some(open_until) = state.open_until else {
+            return Admission::Pass;
+        };
+        if now < open_until {
+            let secs = (open_until - now).as_secs_f64().ceil().max(1.0);
+            return Admission::Refuse(secs as u64);
+        }
+        match state.trial_since {
+            Some(since) if now - since < self.open_for => Admission::Refuse(1),
+            _ => {
+                state.trial_since = Some(now);
+                Admission::Trial
+            }
+        }
+    }
+
+    fn record(&self, trial: bool, failed: bool) {
+        let now = Instant::now();
+        let mut state = self.state();
+        if trial {
+            state.trial_since = None;
+            if failed {
+                state.open_until = Some(now + self.open_for);
+                tracing::warn!("Database still unavailable; circuit breaker stays open");
+            } else {
+                state.open_until = None;
+                state.failed_at.clear();
+                tracing::info!("Database reachable again; circuit breaker closed");
+            }
+            return;
+        }
+        if !failed || state.open_until.is_some() {
+            return;
+        }
+        state.failed_at.push_back(now);
+        while state.failed_at.front().is_some_and(|&at| now - at > self.window) {
+            state.failed_at.pop_front();
+        }
+        if state.failed_at.len() >= self.failures {
+            state.open_until = Some(now + self.open_for);
+            tracing::warn!(
+                "{} database failures in {:?}; circuit breaker open for {:?}",
+                state.failed_at.len(),
+                self.window,
+                self.open_for
+            );
+        }
+    }
+}
+
+fn bypasses_breaker(req: &ServiceRequest) -> bool {
+    req.path().starts_with("/health") || req.path() == "/readyz" || req.path() == "/metrics"
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CircuitBreaker
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = CircuitBreakerMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CircuitBreakerMiddleware {
+            service: Rc::new(service),
+            breaker: self.breaker.clone(),
+        }))
+    }
+}
+
+pub struct CircuitBreakerMiddleware<S> {
+    service: Rc<S>,
+    breaker: Option<Arc<Breaker>>,
+}
+
+impl<S, B> Service<ServiceRequest> for CircuitBreakerMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let breaker = self.breaker.clone().filter(|_| !bypasses_breaker(&req));
+
+        Box::pin(async move {
+            let Some(breaker) = breaker else {
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
+            };
+            let trial = match breaker.admit() {
+                Admission::Pass => false,
+                Admission::Trial => true,
+                Admission::Refuse(secs) => {
+                    let response = ApiError::DatabaseUnavailable(secs).error_response();
+                    return Ok(req.into_response(response).map_into_right_body());
+                }
+            };
+            let result = service.call(req).await;
+            let failed = result.as_ref().is_ok_and(|res| {
+                res.response()
+                    .extensions()
+                    .get::<Problem>()
+                    .is_some_and(|problem| problem.code == ErrorCode::DatabaseUnavailable)
+            });
+            breaker.record(trial, failed);
+            result.map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
@@ -0,0 +1,104 @@
+// Post revision history: every update keeps what the post looked like
+// before it in post_revisions, so older versions can be listed, compared
+// and restored. Postgres only, like the trash.
+
+use similar::{ChangeTag, TextDiff};
+
+use crate::*;
+
+// A post as it was at one version.
+#[derive(Serialize, Debug, Clone, FromRow, ToSchema)]
+pub struct PostRevision {
+    pub post_id: i32,
+    // The post's version at the time.
+    pub rev: i32,
+    pub title: String,
+    pub content: String,
+    pub tags: Vec<String>,
+    pub status: String,
+    // When this version was written.
+    pub edited_at: DateTime<Utc>,
+    // When the update that replaced it was made, and by whom; None once
+    // that account is gone.
+    pub replaced_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub replaced_by: Option<i32>,
+}
+
+// What the update that replaced a revision changed, going from it to the
+// next revision or, for the newest, the post as it is now.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+pub struct RevisionChanges {
+    pub title: bool,
+    pub tags: bool,
+    pub status: bool,
+    // Lines of content added and removed.
+    pub lines_added: usize,
+    pub lines_removed: usize,
+}
+
+// One entry of GET /blog/{id}/revisions: the revision without its content,
+// and what replacing it changed.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct RevisionSummary {
+    pub rev: i32,
+    pub title: String,
+    pub status: String,
+    pub edited_at: DateTime<Utc>,
+    pub replaced_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub replaced_by: Option<i32>,
+    pub changes: RevisionChanges,
+}
+
+// A revision as list_revisions reads it, next to the version that came
+// after it.
+#[derive(Debug, FromRow)]
+pub struct RevisionRow {
+    pub rev: i32,
+    pub title: String,
+    pub content: String,
+    pub tags: Vec<String>,
+    pub status: String,
+    pub edited_at: DateTime<Utc>,
+    pub replaced_at: DateTime<Utc>,
+    pub replaced_by: Option<i32>,
+    pub next_title: String,
+    pub next_content: String,
+    pub next_tags: Vec<String>,
+    pub next_status: String,
+}
+
+impl RevisionRow {
+    fn changes(&self) -> RevisionChanges {
+        let mut changes = RevisionChanges {
+            title: self.title != self.next_title,
+            tags: self.tags != self.next_tags,
+            status: self.status != self.next_status,
+            ..Default::default()
+        };
+        let diff = TextDiff::from_lines(&self.content, &self.next_content);
+        for change in diff.iter_all_changes() {
+            match change.tag() {
+                ChangeTag::Insert => changes.lines_added += 1,
+                ChangeTag::Delete => changes.lines_removed += 1,
+                ChangeTag::Equal => {}
+            }
+        }
+        changes
+    }
+}
+
+impl From<RevisionRow> for RevisionSummary {
+    fn from(row: RevisionRow) -> Self {
+        RevisionSummary {
+            changes: row.changes(),
+            rev: row.rev,
+            title: row.title,
+            status: row.status,
+            edited_at: row.edited_at,
+            replaced_at: row.replaced_at,
+            replaced_by: row.replaced_by,
+        }
+    }
+}
@@ -0,0 +1,299 @@
+// An S3-compatible attachment store (AWS S3, MinIO and the like). Requests
+// are signed with AWS Signature Version 4, and pre-signed URLs let clients
+// move large files to and from the bucket without the API in between.
+
+use hmac::{Hmac, Mac};
+
+use crate::*;
+
+// ATTACHMENT_STORE=s3 reads S3_BUCKET (required), S3_REGION (default
+// us-east-1), S3_ACCESS_KEY_ID and S3_SECRET_ACCESS_KEY (required), and
+// S3_ENDPOINT for anything but AWS, e.g. `http://minio:9000` (default
+// `https://s3.{region}.amazonaws.com`). S3_PATH_STYLE, on by default with
+// S3_ENDPOINT, addresses objects as `{endpoint}/{bucket}/{key}` rather
+// than on the `{bucket}.` subdomain. Pre-signed URLs work for
+// S3_PRESIGN_EXPIRES_SECS (default 900, at most 604800, a week).
+#[derive(Clone)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub endpoint: String,
+    pub path_style: bool,
+    pub presign_expires: Duration,
+}
+
+// The longest S3 accepts.
+const MAX_PRESIGN_EXPIRES_SECS: u64 = 604_800;
+
+impl S3Config {
+    pub fn from_env() -> Result<Self, String> {
+        let required = |name: &str| {
+            env::var(name)
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+                .ok_or_else(|| format!("ATTACHMENT_STORE=s3 needs {}", name))
+        };
+        let region = env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        let endpoint = env::var("S3_ENDPOINT").ok().filter(|url| !url.trim().is_empty());
+        let path_style = match env::var("S3_PATH_STYLE") {
+            Ok(_) => env_flag("S3_PATH_STYLE", false),
+            Err(_) => endpoint.is_some(),
+        };
+        let endpoint = endpoint
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region))
+            .trim_end_matches('/')
+            .to_string();
+        if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+            return Err(format!("S3_ENDPOINT must be an http(s):// URL, not `{}`", endpoint));
+        }
+        let expires = env::var("S3_PRESIGN_EXPIRES_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| (1..=MAX_PRESIGN_EXPIRES_SECS).contains(secs))
+            .unwrap_or(900);
+        Ok(S3Config {
+            bucket: required("S3_BUCKET")?,
+            region,
+            access_key_id: required("S3_ACCESS_KEY_ID")?,
+            secret_access_key: required("S3_SECRET_ACCESS_KEY")?,
+            endpoint,
+            path_style,
+            presign_expires: Duration::from_secs(expires),
+        })
+    }
+}
+
+pub struct S3Store {
+    config: S3Config,
+    client: reqwest::Client,
+}
+
+// The X-Amz-Date format, and the date alone for the credential scope.
+const AMZ_DATE: &str = "%Y%m%dT%H%M%SZ";
+const SCOPE_DATE: &str = "%Y%m%d";
+
+impl S3Store {
+    pub fn new(config: S3Config) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(60))
+            .build()
+            .map_err(|err| format!("Could not set up the S3 client: {}", err))?;
+        Ok(S3Store { config, client })
+    }
+
+    // The object's host and canonical (encoded) path.
+    fn locate(&self, key: &str) -> (String, String) {
+        let authority = self.config.endpoint.split_once("://").map_or("", |(_, rest)| rest);
+        let key = uri_encode(key, false);
+        if self.config.path_style {
+            (authority.to_string(), format!("/{}/{}", self.config.bucket, key))
+        } else {
+            (format!("{}.{}", self.config.bucket, authority), format!("/{}", key))
+        }
+    }
+
+    fn url(&self, host: &str, path: &str, query: &str) -> String {
+        let scheme = self.config.endpoint.split("://").next().unwrap_or("https");
+        match query {
+            "" => format!("{}://{}{}", scheme, host, path),
+            _ => format!("{}://{}{}?{}", scheme, host, path, query),
+        }
+    }
+
+    fn scope(&self, now: DateTime<Utc>) -> String {
+        format!("{}/{}/s3/aws4_request", now.format(SCOPE_DATE), self.config.region)
+    }
+
+    // The signature of `canonical_request` made at `now`.
+    fn sign(&self, now: DateTime<Utc>, canonical_request: &str) -> String {
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            now.format(AMZ_DATE),
+            self.scope(now),
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let secret = format!("AWS4{}", self.config.secret_access_key);
+        let date = hmac(secret.as_bytes(), now.format(SCOPE_DATE).to_string().as_bytes());
+        let region = hmac(&date, self.config.region.as_bytes());
+        let service = hmac(&region, b"s3");
+        let key = hmac(&service, b"aws4_request");
+        hex::encode(hmac(&key, string_to_sign.as_bytes()))
+    }
+
+    // A URL that lets whoever has it make this one request until
+    // `expires` after `now`. `headers` (with `host`) are signed, so the
+    // request must send them with these values; `query` is extra
+    // parameters such as `response-content-type`.
+    pub(crate) fn presign(
+        &self,
+        method: &str,
+        key: &str,
+        headers: &[(&str, String)],
+        query: &[(&str, String)],
+        expires: Duration,
+        now: DateTime<Utc>,
+    ) -> String {
+        let (host, path) = self.locate(key);
+        let mut headers: Vec<(String, String)> = headers
+            .iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+            .chain([("host".to_string(), host.clone())])
+            .collect();
+        headers.sort();
+        let signed_headers = headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        let credential = format!("{}/{}", self.config.access_key_id, self.scope(now));
+        let mut params: Vec<(String, String)> = [
+            ("X-Amz-Algorithm", "AWS4-HMAC-SHA256".to_string()),
+            ("X-Amz-Credential", credential),
+            ("X-Amz-Date", now.format(AMZ_DATE).to_string()),
+            ("X-Amz-Expires", expires.as_secs().to_string()),
+            ("X-Amz-SignedHeaders", signed_headers.join(";")),
+        ]
+        .iter()
+        .chain(query)
+        .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+        .collect();
+        params.sort();
+        let query = params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+        let canonical_headers: String =
+            headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect();
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\nUNSIGNED-PAYLOAD",
+            method,
+            path,
+            query,
+            canonical_headers,
+            signed_headers.join(";")
+        );
+        let signature = self.sign(now, &canonical_request);
+        self.url(&host, &path, &format!("{}&X-Amz-Signature={}", query, signature))
+    }
+
+    // One request of the server's own, signed in the Authorization header.
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        body: Option<(&[u8], &str)>,
+    ) -> std::io::Result<reqwest::Response> {
+        let now = Utc::now();
+        let (host, path) = self.locate(key);
+        let payload = body.map_or(&[][..], |(bytes, _)| bytes);
+        let payload_hash = hex::encode(Sha256::digest(payload));
+        let amz_date = now.format(AMZ_DATE).to_string();
+        let mut headers = vec![
+            ("host", host.clone()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date),
+        ];
+        if let Some((_, content_type)) = body {
+            headers.push(("content-type", content_type.to_string()));
+        }
+        headers.sort();
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        let canonical_headers: String =
+            headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect();
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method, path, canonical_headers, signed_headers, payload_hash
+        );
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key_id,
+            self.scope(now),
+            signed_headers,
+            self.sign(now, &canonical_request)
+        );
+        let mut request = self
+            .client
+            .request(method, self.url(&host, &path, ""))
+            .header("Authorization", authorization);
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name, value);
+        }
+        if let Some((bytes, _)) = body {
+            request = request.body(bytes.to_vec());
+        }
+        let response = request.send().await.map_err(std::io::Error::other)?;
+        match response.status() {
+            status if status.is_success() => Ok(response),
+            reqwest::StatusCode::NOT_FOUND => Err(std::io::ErrorKind::NotFound.into()),
+            status => Err(std::io::Error::other(format!("S3 answered {}", status))),
+        }
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Percent-encoding as SigV4 wants it: everything but unreserved characters,
+// and `/` too unless it separates path segments.
+pub(crate) fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[async_trait]
+impl AttachmentStore for S3Store {
+    async fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        let content_type = sniff_image_type(bytes).map_or("application/octet-stream", |t| t.0);
+        self.send(reqwest::Method::PUT, key, Some((bytes, content_type))).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        let response = self.send(reqwest::Method::GET, key, None).await?;
+        let bytes = response.bytes().await.map_err(std::io::Error::other)?;
+        Ok(bytes.to_vec())
+    }
+
+    // S3 answers 204 for keys that aren't there too.
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        self.send(reqwest::Method::DELETE, key, None).await?;
+        Ok(())
+    }
+
+    fn download_url(&self, key: &str, content_type: &str, filename: &str) -> Option<String> {
+        let query = [
+            ("response-content-type", content_type.to_string()),
+            ("response-content-disposition", format!("inline; filename=\"{}\"", filename)),
+        ];
+        let expires = self.config.presign_expires;
+        Some(self.presign("GET", key, &[], &query, expires, Utc::now()))
+    }
+
+    fn upload_url(&self, key: &str, content_type: &str, size: i64) -> Option<PresignedUpload> {
+        let now = Utc::now();
+        let expires = self.config.presign_expires;
+        let headers = [
+            ("content-length", size.to_string()),
+            ("content-type", content_type.to_string()),
+        ];
+        let url = self.presign("PUT", key, &headers, &[], expires, now);
+        let headers = headers.into_iter().map(|(name, value)| (name.to_string(), value));
+        Some(PresignedUpload {
+            method: "PUT",
+            url,
+            headers: headers.collect(),
+            expires_at: now + expires,
+        })
+    }
+}
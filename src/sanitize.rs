@@ -0,0 +1,194 @@
+// Sanitizing post content as it is written, so what the API echoes back
+// is safe for a frontend that puts it in a page as HTML. Rendering
+// (`render_markdown`) cleans its own output either way.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use crate::*;
+
+pub(crate) static CONTENT_SANITIZER: OnceLock<ContentSanitizer> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanitizeMode {
+    // Stored as written.
+    Off,
+    // Tags and attributes outside the allowlist are removed.
+    Html,
+    // Every tag is removed and only the text kept.
+    Text,
+}
+
+// CONTENT_SANITIZER is `html` (the default), `text` or `off`.
+// CONTENT_ALLOWED_TAGS (comma-separated) replaces ammonia's default tags,
+// and CONTENT_ALLOWED_ATTRIBUTES replaces its attributes: `title` for any
+// tag, `a:href` for one. Link schemes stay ammonia's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentSanitizer {
+    pub mode: SanitizeMode,
+    pub tags: Option<HashSet<String>>,
+    pub attributes: Option<HashSet<String>>,
+}
+
+impl Default for ContentSanitizer {
+    fn default() -> Self {
+        ContentSanitizer {
+            mode: SanitizeMode::Html,
+            tags: None,
+            attributes: None,
+        }
+    }
+}
+
+// Their content goes with them, so they can't be allowed.
+const CONTENT_TAGS: [&str; 2] = ["script", "style"];
+
+fn list(name: &str) -> Option<HashSet<String>> {
+    let value = env::var(name).ok()?;
+    let items = value.split(',').map(|item| item.trim().to_lowercase());
+    Some(items.filter(|item| !item.is_empty()).collect())
+}
+
+impl ContentSanitizer {
+    pub fn from_env() -> Result<Self, String> {
+        let mode = match env::var("CONTENT_SANITIZER").as_deref() {
+            Ok("html") | Err(_) => SanitizeMode::Html,
+            Ok("text") => SanitizeMode::Text,
+            Ok("off") => SanitizeMode::Off,
+            Ok(other) => return Err(format!("Unknown CONTENT_SANITIZER `{}`", other)),
+        };
+        let tags = list("CONTENT_ALLOWED_TAGS");
+        if let Some(tag) = tags.iter().flatten().find(|tag| CONTENT_TAGS.contains(&tag.as_str())) {
+            return Err(format!("CONTENT_ALLOWED_TAGS cannot allow `{}`", tag));
+        }
+        Ok(ContentSanitizer {
+            mode,
+            tags,
+            attributes: list("CONTENT_ALLOWED_ATTRIBUTES"),
+        })
+    }
+
+    // Reads the configuration once at startup, so a bad one stops the
+    // server rather than the first write.
+    pub fn init() -> Result<(), String> {
+        let sanitizer = ContentSanitizer::from_env()?;
+        let _ = CONTENT_SANITIZER.set(sanitizer);
+        Ok(())
+    }
+
+    fn builder(&self) -> ammonia::Builder<'_> {
+        let mut builder = match self.mode {
+            SanitizeMode::Text => {
+                let mut builder = ammonia::Builder::empty();
+                builder.clean_content_tags(CONTENT_TAGS.into());
+                builder
+            }
+            _ => ammonia::Builder::default(),
+        };
+        let allowlist = self.mode == SanitizeMode::Html;
+        if let Some(tags) = self.tags.as_ref().filter(|_| allowlist) {
+            builder.tags(tags.iter().map(String::as_str).collect());
+        }
+        if let Some(attributes) = self.attributes.as_ref().filter(|_| allowlist) {
+            let mut generic = HashSet::new();
+            let mut by_tag: HashMap<&str, HashSet<&str>> = HashMap::new();
+            for attribute in attributes {
+                match attribute.split_once(':') {
+                    Some((tag, attribute)) => {
+                        by_tag.entry(tag).or_default().insert(attribute);
+                    }
+                    None => {
+                        generic.insert(attribute.as_str());
+                    }
+                }
+            }
+            builder.generic_attributes(generic).tag_attributes(by_tag);
+        }
+        builder
+    }
+
+    // `content` with what the mode doesn't allow taken out. Markdown comes
+    // through as it was: only `<` that may start a tag is touched, and
+    // autolinks like `<https://example.com>` are kept.
+    pub fn clean<'a>(&self, content: &'a str) -> Cow<'a, str> {
+        if self.mode == SanitizeMode::Off || !content.contains('<') {
+            return Cow::Borrowed(content);
+        }
+        let mut escaped = String::with_capacity(content.len());
+        for (i, part) in content.split('<').enumerate() {
+            if i > 0 {
+                escaped.push_str(if is_autolink(part) { "&lt;" } else { "<" });
+            }
+            escaped.push_str(part);
+        }
+        let cleaned = self.builder().clean(&escaped).to_string();
+        Cow::Owned(unescape_text(&cleaned))
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    pub(crate) static CONTENT_SANITIZER_OVERRIDE: std::cell::RefCell<Option<ContentSanitizer>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+// What this server writes post content through.
+pub fn sanitize_content(content: &str) -> Cow<'_, str> {
+    #[cfg(test)]
+    if let Some(sanitizer) = CONTENT_SANITIZER_OVERRIDE.with_borrow(Clone::clone) {
+        return Cow::Owned(sanitizer.clean(content).into_owned());
+    }
+    CONTENT_SANITIZER.get_or_init(ContentSanitizer::default).clean(content)
+}
+
+// After a `<`: `scheme:...>` or `user@host>`, a Markdown autolink. The
+// `>` may be escaped, as it is in ammonia's output.
+fn is_autolink(rest: &str) -> bool {
+    let Some(end) = rest.find('>').into_iter().chain(rest.find("&gt;")).min() else {
+        return false;
+    };
+    let link = &rest[..end];
+    if link.is_empty() || link.contains(|c: char| c.is_whitespace() || c == '<') {
+        return false;
+    }
+    match link.split_once(':') {
+        Some((scheme, _)) => {
+            scheme.len() >= 2
+                && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c))
+        }
+        None => link.contains('@') && !link.contains('/'),
+    }
+}
+
+// Undoes the escaping ammonia's serializer does to text, except for a `<`
+// that would start a tag (or an autolink, put back here).
+fn unescape_text(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(at) = rest.find('&') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let (text, len) = if rest.starts_with("&amp;") {
+            ("&", 5)
+        } else if rest.starts_with("&gt;") {
+            (">", 4)
+        } else if rest.starts_with("&nbsp;") {
+            ("\u{a0}", 6)
+        } else if let Some(after) = rest.strip_prefix("&lt;") {
+            let opens_tag =
+                after.starts_with(|c: char| c.is_ascii_alphabetic() || "/!?".contains(c));
+            if opens_tag && !is_autolink(after) {
+                ("&lt;", 4)
+            } else {
+                ("<", 4)
+            }
+        } else {
+            ("&", 1)
+        };
+        out.push_str(text);
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+    out
+}
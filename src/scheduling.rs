@@ -0,0 +1,44 @@
+// Scheduled publishing: drafts with a publish_at are published by a
+// background task that looks for due ones every PUBLISH_CHECK_SECS, so a
+// post goes out up to that long after its time.
+
+use crate::*;
+
+pub(crate) fn publish_check_secs() -> u64 {
+    env::var("PUBLISH_CHECK_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30)
+        .max(1)
+}
+
+// One look for due posts; returns how many went out.
+pub async fn publish_scheduled_posts(
+    pool: &PgPool,
+    cache: &PostCache,
+    feed: &ChangeFeed,
+) -> Result<usize, ApiError> {
+    let published = publish_due_posts(pool).await?;
+    for (tenant, post) in &published {
+        cache.invalidate(*tenant, Some(post.id)).await;
+        // As with a manual publish, subscribers see the post only now.
+        feed.publish(PostEventKind::Created, *tenant, post.id, Some(post.clone()));
+    }
+    Ok(published.len())
+}
+
+pub async fn publish_scheduled(
+    pool: PgPool,
+    cache: web::Data<PostCache>,
+    feed: web::Data<ChangeFeed>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(publish_check_secs()));
+    loop {
+        interval.tick().await;
+        match publish_scheduled_posts(&pool, &cache, &feed).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Published {} scheduled posts", n),
+            Err(err) => tracing::warn!("Could not publish scheduled posts: {}", err),
+        }
+    }
+}
@@ -0,0 +1,404 @@
+// Builds the App and runs the server.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::ServiceFactory;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::*;
+
+// Everything an App is built from, created once at startup and cloned into
+// each worker.
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub reads: web::Data<ReadPools>,
+    pub repo: web::Data<dyn PostRepository>,
+    pub post_ids: web::Data<IdStrategy>,
+    pub translator: web::Data<dyn Translator>,
+    pub moderator: web::Data<dyn Moderator>,
+    pub cache: web::Data<PostCache>,
+    pub feed: web::Data<ChangeFeed>,
+    pub profile: web::Data<ListProfile>,
+    pub storage: web::Data<StorageGuard>,
+    pub limiter: web::Data<HeavyQueryLimiter>,
+    pub quotas: web::Data<WriteQuotas>,
+    pub limits: web::Data<LimitsConfig>,
+    pub cache_control: web::Data<CacheControlConfig>,
+    pub auth: web::Data<AuthConfig>,
+    pub oauth: web::Data<OAuthConfig>,
+    pub schema: web::Data<BlogSchema>,
+    pub uploads: web::Data<Uploads>,
+    pub site: web::Data<SiteConfig>,
+    pub sitemaps: web::Data<Sitemaps>,
+    pub jobs: web::Data<JobQueue>,
+    pub views: web::Data<ViewCounter>,
+    pub tenants: web::Data<Tenants>,
+    pub catalogs: web::Data<Catalogs>,
+    pub pool_monitor: web::Data<PoolMonitor>,
+    pub rate_limit: RateLimit,
+    pub breaker: CircuitBreaker,
+    pub cors: CorsConfig,
+    pub compression: CompressionConfig,
+    pub environment: Environment,
+    pub legacy: Option<LegacyRoutes>,
+    pub openapi: utoipa::openapi::OpenApi,
+}
+
+impl AppState {
+    // The rest of the state, each part from its own env knobs. `reads` is
+    // the primary pool and its replicas.
+    pub async fn from_env(
+        config: &Config,
+        reads: ReadPools,
+        repo: Arc<dyn PostRepository>,
+    ) -> Result<Self, String> {
+        let pool = reads.primary_pool().clone();
+        let profile = web::Data::new(ListProfile::from_env()?);
+        let cache = web::Data::new(PostCache::from_env().await?);
+        let feed = web::Data::new(ChangeFeed::new(256));
+        let storage = web::Data::new(StorageGuard::from_env());
+        let limiter = web::Data::new(HeavyQueryLimiter::from_env());
+        let quotas = web::Data::new(WriteQuotas::from_env());
+        let site = web::Data::new(SiteConfig::from_env());
+        let moderator = web::Data::from(build_moderator()?);
+        let mut jobs = JobQueue::new(pool.clone(), JobSettings::from_env());
+        jobs.register(
+            "import",
+            Arc::new(ImportJob {
+                pool: pool.clone(),
+                cache: cache.clone(),
+                feed: feed.clone(),
+                limiter: limiter.clone(),
+                moderator: moderator.clone(),
+            }),
+        );
+        jobs.register(
+            "comment_email",
+            Arc::new(CommentEmailJob {
+                pool: pool.clone(),
+                mailer: build_mailer()?,
+                site: site.clone(),
+            }),
+        );
+        jobs.register("webhook_delivery", Arc::new(WebhookDeliveryJob::from_env(pool.clone())?));
+        ContentSanitizer::init()?;
+        Snowflakes::init()?;
+        let schema = web::Data::new(build_schema(
+            pool.clone(),
+            cache.clone(),
+            feed.clone(),
+            storage.clone(),
+            profile.clone(),
+            moderator.clone(),
+            quotas.clone(),
+        ));
+        Ok(AppState {
+            repo: web::Data::from(repo),
+            post_ids: web::Data::new(IdStrategy::from_env()?),
+            translator: web::Data::from(build_translator()),
+            moderator,
+            cache,
+            feed,
+            profile,
+            storage,
+            limiter,
+            quotas,
+            limits: web::Data::new(config.limits),
+            cache_control: web::Data::new(config.cache_control.clone()),
+            auth: web::Data::new(AuthConfig::from_env()),
+            oauth: web::Data::new(OAuthConfig::from_env()?),
+            schema,
+            uploads: web::Data::new(Uploads::from_env()),
+            site,
+            sitemaps: web::Data::new(Sitemaps::from_env()),
+            jobs: web::Data::new(jobs),
+            views: web::Data::new(ViewCounter::default()),
+            tenants: web::Data::new(Tenants::from_env(pool.clone())),
+            catalogs: web::Data::new(Catalogs::from_env()?),
+            pool_monitor: web::Data::new(PoolMonitor::new(config)),
+            rate_limit: RateLimit::from_env().await?,
+            breaker: CircuitBreaker::from_env(),
+            cors: config.cors.clone(),
+            compression: config.compression.clone(),
+            environment: config.environment,
+            legacy: LegacyRoutes::from_env(),
+            openapi: docs::ApiDoc::openapi(),
+            reads: web::Data::new(reads),
+            pool,
+        })
+    }
+}
+
+// The whole API as an App: shared state, middleware and every route. run()
+// serves one per worker; an embedding server can mount it the same way.
+pub fn app(
+    state: AppState,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let AppState { openapi, legacy, compression, .. } = state.clone();
+    let app = App::new()
+        .app_data(web::Data::new(state.pool))
+        .app_data(state.reads)
+        .app_data(state.translator)
+        .app_data(state.moderator)
+        .app_data(state.repo)
+        .app_data(state.post_ids)
+        .app_data(state.cache)
+        .app_data(state.feed)
+        .app_data(state.profile)
+        .app_data(state.storage)
+        .app_data(state.limiter)
+        .app_data(state.quotas)
+        .app_data(state.limits.clone())
+        .app_data(state.cache_control)
+        .app_data(state.auth)
+        .app_data(state.oauth)
+        .app_data(state.schema)
+        .app_data(state.uploads)
+        .app_data(state.site)
+        .app_data(state.sitemaps)
+        .app_data(state.jobs)
+        .app_data(state.views)
+        .app_data(state.tenants)
+        .app_data(state.catalogs)
+        .app_data(state.pool_monitor)
+        // Innermost, so only what the routes answered gets a policy.
+        .wrap_fn(apply_cache_control)
+        // Inside the rate limit, so limited requests don't count as tries.
+        .wrap(state.breaker)
+        .wrap(state.rate_limit)
+        // Outside the rate limit too, so 429s are translated.
+        .wrap_fn(|req, srv| {
+            let fut = srv.call(req);
+            async move { fut.await.map(localize_problem) }
+        })
+        // Outside the rate limit, so preflights aren't counted and
+        // 429s still carry the CORS headers browsers need to read them.
+        .wrap(state.cors.middleware(state.environment))
+        .wrap_fn(|req, srv| {
+            let fut = srv.call(req);
+            async move { fut.await.map(echo_request_id) }
+        })
+        .wrap(TracingLogger::default())
+        .wrap_fn(record_request)
+        .configure(move |cfg| configure_routes(cfg, &openapi, legacy.as_ref(), &state.limits));
+    // Outermost, so everything above sees the body uncompressed.
+    with_compression(app, compression)
+}
+
+pub async fn run() -> std::io::Result<()> {
+    serve(None).await
+}
+
+// run(), loading the fixtures at `fixtures`, or else at FIXTURES, into
+// Postgres once its schema is up to date.
+pub async fn serve(fixtures: Option<PathBuf>) -> std::io::Result<()> {
+    dotenv().ok();
+    let fixtures = fixtures.or_else(|| env::var_os("FIXTURES").map(PathBuf::from));
+    let config = Config::load().unwrap_or_else(|err| panic!("{}", err));
+    let _telemetry = init_tracing(&config);
+
+    // Resolve once up front so a bad value fails at startup, not mid-request.
+    null_handling();
+    default_language();
+    idempotency_ttl_secs();
+    let tls = config
+        .tls
+        .enabled()
+        .then(|| load_rustls_config(&config.tls).unwrap_or_else(|err| panic!("{}", err)));
+
+    let store = PostStore::from_env().unwrap_or_else(|err| panic!("{}", err));
+    let pool = match store {
+        PostStore::Postgres => establish_connection(&config).await,
+        _ => establish_lazy_connection(&config),
+    }
+    .expect("Failed to connect to database");
+
+    // Deployments that run `rest_api migrate` as a separate step can set
+    // MIGRATE_ON_STARTUP=0 so the server itself never changes the schema.
+    // Other stores migrate their own schema when opened.
+    if store == PostStore::Postgres && env_flag("MIGRATE_ON_STARTUP", true) {
+        MIGRATOR
+            .run(&pool)
+            .await
+            .unwrap_or_else(|err| panic!("Failed to run migrations: {}", err));
+        tracing::info!("Database schema is up to date");
+    }
+    if let Some(path) = fixtures {
+        let fixtures = Fixtures::from_file(&path).unwrap_or_else(|err| panic!("{}", err));
+        let report = load_fixtures(&pool, &fixtures)
+            .await
+            .unwrap_or_else(|err| panic!("Failed to load fixtures: {}", err));
+        tracing::info!("{}", report);
+    }
+    let reads = ReadPools::connect(&config, pool.clone()).expect("Invalid replica URL");
+    let repo = store.open(&reads).await.unwrap_or_else(|err| panic!("{}", err));
+    let state = AppState::from_env(&config, reads, repo)
+        .await
+        .unwrap_or_else(|err| panic!("{}", err));
+    // The job queue is a Postgres table too, and webhooks live there.
+    if store == PostStore::Postgres {
+        actix_web::rt::spawn(watch_storage(pool.clone(), state.storage.clone()));
+        spawn_job_workers(state.jobs.clone());
+        spawn_webhook_dispatcher(pool.clone(), state.feed.clone(), state.jobs.clone());
+        actix_web::rt::spawn(flush_views(pool.clone(), state.views.clone()));
+        actix_web::rt::spawn(monitor_pool(pool.clone(), state.pool_monitor.clone()));
+        actix_web::rt::spawn(publish_scheduled(
+            pool.clone(),
+            state.cache.clone(),
+            state.feed.clone(),
+        ));
+    }
+
+    // SHUTDOWN_TIMEOUT_SECS: how long in-flight requests get to finish after
+    // SIGTERM/SIGINT before their connections are dropped.
+    let shutdown_timeout: u64 = env::var("SHUTDOWN_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30);
+    let in_flight = InFlight::default();
+    let shutdown_feed = state.feed.clone();
+    let shutdown_jobs = state.jobs.clone();
+    let views = state.views.clone();
+    let state_for_grpc = state.clone();
+    let app_in_flight = in_flight.clone();
+
+    let server = HttpServer::new(move || {
+        let in_flight = app_in_flight.clone();
+        app(state.clone()).wrap_fn(move |req, srv| {
+            let guard = in_flight.enter();
+            let fut = srv.call(req);
+            async move {
+                let res = fut.await;
+                drop(guard);
+                res
+            }
+        })
+    })
+    .shutdown_timeout(shutdown_timeout)
+    .disable_signals();
+    let server = match tls {
+        Some(tls) => server.bind_rustls_0_23((config.host.as_str(), config.port), tls)?,
+        None => server.bind((config.host.as_str(), config.port))?,
+    }
+    .run();
+
+    // TLS_REDIRECT_PORT: plain HTTP on another port, answering every
+    // request with a redirect to the HTTPS listener.
+    let redirect = match config.tls.redirect_port {
+        Some(redirect_port) => {
+            let target = web::Data::new(HttpsTarget {
+                host: config.host.clone(),
+                port: config.port,
+            });
+            let redirect = HttpServer::new(move || redirect_app(target.clone()))
+                .shutdown_timeout(shutdown_timeout)
+                .disable_signals()
+                .bind((config.host.as_str(), redirect_port))?
+                .run();
+            tracing::info!("Redirecting http://{}:{} to HTTPS", config.host, redirect_port);
+            Some(redirect)
+        }
+        None => None,
+    };
+
+    let grpc = spawn_grpc(&state_for_grpc, &config.host)?;
+    let (grpc_stop, grpc_served) = grpc.unzip();
+
+    let handle = server.handle();
+    let redirect_handle = redirect.as_ref().map(|redirect| redirect.handle());
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        shutdown_feed.close();
+        shutdown_jobs.close();
+        if let Some(grpc_stop) = grpc_stop {
+            let _ = grpc_stop.send(());
+        }
+        tracing::info!(
+            "Shutting down with {} request(s) in flight; waiting up to {}s for them",
+            in_flight.count(),
+            shutdown_timeout
+        );
+        if let Some(redirect_handle) = redirect_handle {
+            redirect_handle.stop(true).await;
+        }
+        handle.stop(true).await;
+    });
+
+    match redirect {
+        Some(redirect) => {
+            let (served, redirected) = futures_util::join!(server, redirect);
+            served?;
+            redirected?;
+        }
+        None => server.await?,
+    }
+    if let Some(grpc_served) = grpc_served {
+        let _ = grpc_served.await;
+    }
+    // Counted since the last flush; lost if this fails.
+    if store == PostStore::Postgres
+        && let Err(err) = views.flush(&pool).await
+    {
+        tracing::warn!("Could not write view counts: {}", err);
+    }
+    // Connections still checked out by abandoned queries would make close()
+    // wait for the database; don't let them hold up the exit.
+    match tokio::time::timeout(Duration::from_secs(5), pool.close()).await {
+        Ok(()) => tracing::info!("Database pool closed"),
+        Err(_) => tracing::warn!("Timed out closing the database pool; exiting anyway"),
+    }
+    Ok(())
+}
+
+// GRPC_PORT: the post CRUD over gRPC as well, on the HTTP server's host.
+// Gives what stops it and the task that finishes once it has.
+#[cfg(feature = "grpc")]
+fn spawn_grpc(
+    state: &AppState,
+    host: &str,
+) -> std::io::Result<Option<(oneshot::Sender<()>, JoinHandle<()>)>> {
+    use std::net::ToSocketAddrs;
+
+    let Some(port) = grpc::grpc_port().map_err(std::io::Error::other)? else {
+        return Ok(None);
+    };
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::other(format!("Cannot resolve {}", host)))?;
+    let incoming = tonic::transport::server::TcpIncoming::bind(addr)?;
+    let (stop, stopped) = oneshot::channel();
+    let state = state.clone();
+    let served = actix_web::rt::spawn(async move {
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        if let Err(err) = grpc::serve_grpc(&state, incoming, shutdown).await {
+            tracing::error!("{}", err);
+        }
+    });
+    tracing::info!("Serving gRPC on {}", addr);
+    Ok(Some((stop, served)))
+}
+
+#[cfg(not(feature = "grpc"))]
+fn spawn_grpc(
+    _state: &AppState,
+    _host: &str,
+) -> std::io::Result<Option<(oneshot::Sender<()>, JoinHandle<()>)>> {
+    match env::var("GRPC_PORT") {
+        Ok(port) if !port.trim().is_empty() => Err(std::io::Error::other(
+            "GRPC_PORT is set, but this build has no gRPC server; build with --features grpc",
+        )),
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,71 @@
+// GET /sitemap.xml: the public URL of every published post, for search
+// engines. Built on the first request and then kept for a short while,
+// so crawlers don't put the post list under load.
+
+use crate::*;
+
+// What one sitemap file may list, by the protocol.
+pub const SITEMAP_MAX_URLS: i64 = 50_000;
+
+#[derive(Debug, Clone, FromRow)]
+pub struct SitemapEntry {
+    pub slug: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Posts link to `{SITE_BASE_URL}/blog/{slug}`, as in the feeds, with
+// their last update as `lastmod`.
+pub fn render_sitemap(site: &SiteConfig, entries: &[SitemapEntry]) -> String {
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
+    for entry in entries {
+        xml.push_str(&format!(
+            "<url><loc>{}</loc><lastmod>{}</lastmod></url>",
+            xml_escape(&site.slug_url(&entry.slug)),
+            entry.updated_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        ));
+    }
+    xml.push_str("</urlset>");
+    xml
+}
+
+// Rendered sitemaps by tenant. SITEMAP_CACHE_SECS (default 300) is how
+// long one is served before the next request builds it again, and its
+// Cache-Control max-age; a new or changed post shows up within that.
+pub struct Sitemaps {
+    cache: Cache<i32, Arc<String>>,
+    pub max_age_secs: u64,
+}
+
+impl Sitemaps {
+    pub fn new(ttl: Duration) -> Self {
+        Sitemaps {
+            cache: Cache::builder().max_capacity(1_000).time_to_live(ttl).build(),
+            max_age_secs: ttl.as_secs(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let secs = env::var("SITEMAP_CACHE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300);
+        Sitemaps::new(Duration::from_secs(secs))
+    }
+
+    // The most recently updated SITEMAP_MAX_URLS published posts.
+    pub async fn get(
+        &self,
+        repo: &dyn PostRepository,
+        site: &SiteConfig,
+        tenant: i32,
+    ) -> Result<Arc<String>, ApiError> {
+        if let Some(xml) = self.cache.get(&tenant) {
+            return Ok(xml);
+        }
+        let entries = repo.sitemap(tenant, SITEMAP_MAX_URLS).await?;
+        let xml = Arc::new(render_sitemap(site, &entries));
+        self.cache.insert(tenant, xml.clone());
+        Ok(xml)
+    }
+}
@@ -0,0 +1,422 @@
+// Post storage in SQLite for POST_STORE=sqlite, built with `--features
+// sqlite`. The schema is migrations_sqlite/, which notes how it differs
+// from the Postgres one. The pool holds a single connection, which is as
+// many writers as SQLite allows anyway, so a transaction here sees no
+// concurrent writes and needs no row locks. Changes are not audited; the
+// audit log lives in Postgres.
+
+use std::str::FromStr;
+
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::{Sqlite, SqliteConnection, SqliteExecutor};
+use uuid::fmt::Hyphenated;
+
+use crate::*;
+
+static SQLITE_MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations_sqlite");
+
+const POST_COLUMNS: &str = "id, uuid, snowflake_id, title, slug, content, user_id, author, tags, \
+     version, status, created_at, updated_at";
+
+// The same filter as LIST_POSTS_SQL, bound the same way through ?1..?10.
+const FILTER_SQL: &str = "deleted_at IS NULL \
+     AND (?1 IS NULL OR EXISTS (SELECT 1 FROM json_each(tags) WHERE value = ?1)) \
+     AND (?2 IS NULL OR author = ?2) \
+     AND (?3 IS NULL OR status = ?3) \
+     AND (status = 'published' OR user_id = ?4 OR ?5) \
+     AND (?6 IS NULL OR created_at >= ?6) \
+     AND (?7 IS NULL OR created_at <= ?7) \
+     AND (?8 IS NULL OR updated_at >= ?8) \
+     AND (?9 IS NULL OR updated_at <= ?9) \
+     AND tenant_id = ?10";
+
+macro_rules! bind_filter {
+    ($query:expr, $filter:expr) => {
+        $query
+            .bind($filter.tag)
+            .bind($filter.author)
+            .bind($filter.status.map(PostStatus::as_str))
+            .bind($filter.viewer)
+            .bind($filter.see_all)
+            .bind($filter.created_from.map(timestamp))
+            .bind($filter.created_to.map(timestamp))
+            .bind($filter.updated_from.map(timestamp))
+            .bind($filter.updated_to.map(timestamp))
+            .bind($filter.tenant)
+    };
+}
+
+// Fixed width, so comparing the text compares the times.
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn tags_json(tags: &[String]) -> String {
+    serde_json::to_string(tags).unwrap_or_else(|_| "[]".to_string())
+}
+
+fn sort_column(sort: SortColumn) -> &'static str {
+    match sort {
+        SortColumn::Id => "id",
+        SortColumn::Title => "title",
+        SortColumn::Author => "author",
+        SortColumn::CreatedAt => "created_at",
+        SortColumn::UpdatedAt => "updated_at",
+    }
+}
+
+#[derive(FromRow)]
+struct PostRow {
+    id: i32,
+    uuid: Hyphenated,
+    snowflake_id: Option<i64>,
+    title: String,
+    slug: String,
+    content: String,
+    user_id: i32,
+    author: String,
+    tags: String,
+    version: i32,
+    status: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<PostRow> for BlogPost {
+    fn from(row: PostRow) -> Self {
+        BlogPost {
+            id: row.id,
+            uuid: row.uuid.into_uuid(),
+            snowflake_id: row.snowflake_id,
+            title: row.title,
+            slug: row.slug,
+            content: row.content,
+            user_id: row.user_id,
+            author: row.author,
+            tags: serde_json::from_str(&row.tags).unwrap_or_default(),
+            version: row.version,
+            status: row.status,
+            // Likes, views and schedules are kept by the Postgres store only.
+            like_count: 0,
+            view_count: 0,
+            publish_at: None,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+async fn find_post<'e, E: SqliteExecutor<'e>>(
+    executor: E,
+    tenant: i32,
+    id: i32,
+) -> Result<Option<BlogPost>, ApiError> {
+    let sql = format!(
+        "SELECT {} FROM blog_posts WHERE id = ?1 AND tenant_id = ?2 AND deleted_at IS NULL",
+        POST_COLUMNS
+    );
+    let row = sqlx::query_as::<_, PostRow>(&sql)
+        .bind(id)
+        .bind(tenant)
+        .fetch_optional(executor)
+        .await?;
+    Ok(row.map(BlogPost::from))
+}
+
+// The live post about to be changed, checked against If-Match and the
+// version the client sent.
+async fn post_to_change(
+    conn: &mut SqliteConnection,
+    tenant: i32,
+    id: i32,
+    if_match: Option<&IfMatch>,
+    version: Option<i32>,
+) -> Result<BlogPost, ApiError> {
+    let current = find_post(&mut *conn, tenant, id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", id)))?;
+    if let Some(if_match) = if_match {
+        require_etag(&current, if_match)?;
+    }
+    if let Some(version) = version
+        && version != current.version
+    {
+        return Err(stale_version(id, version, Some(current.version)));
+    }
+    Ok(current)
+}
+
+// After the write itself: 404 if it found no live post after all.
+fn changed(id: i32, rows_affected: u64) -> Result<(), ApiError> {
+    match rows_affected {
+        0 => Err(ApiError::NotFound(format!("Post {} not found", id))),
+        _ => Ok(()),
+    }
+}
+
+async fn unique_slug(
+    conn: &mut SqliteConnection,
+    tenant: i32,
+    title: &str,
+) -> Result<String, ApiError> {
+    let base = slugify(title);
+    let taken = sqlx::query_scalar::<_, String>(
+        "SELECT slug FROM blog_posts WHERE tenant_id = ?2 AND (slug = ?1 OR slug LIKE ?1 || '-%')",
+    )
+    .bind(&base)
+    .bind(tenant)
+    .fetch_all(conn)
+    .await?;
+    Ok(first_free_slug(&base, &taken))
+}
+
+pub struct SqlitePostRepository {
+    pool: SqlitePool,
+}
+
+impl SqlitePostRepository {
+    // Creates the database file if needed and brings its schema up to date.
+    // `sqlite::memory:` gives a fresh database that lasts as long as this.
+    pub async fn connect(url: &str) -> Result<Self, String> {
+        let options = SqliteConnectOptions::from_str(url)
+            .map_err(|err| format!("Invalid SQLITE_URL `{}`: {}", url, err))?
+            .create_if_missing(true);
+        // Never closed for being idle: that would lose an in-memory database.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await
+            .map_err(|err| format!("Cannot open SQLITE_URL `{}`: {}", url, err))?;
+        SQLITE_MIGRATOR
+            .run(&pool)
+            .await
+            .map_err(|err| format!("Failed to run SQLite migrations: {}", err))?;
+        Ok(SqlitePostRepository { pool })
+    }
+}
+
+#[async_trait]
+impl PostRepository for SqlitePostRepository {
+    async fn create(&self, post: &NewBlogPost, author: &AuthUser) -> Result<BlogPost, ApiError> {
+        let tags = post.tags.as_deref().map(normalize_tags).transpose()?;
+        no_schedule(post.publish_at)?;
+        let mut tx = self.pool.begin().await?;
+        let slug = unique_slug(&mut tx, author.tenant_id, &post.title).await?;
+        let now = timestamp(Utc::now());
+        let id = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO blog_posts \
+             (title, slug, content, user_id, author, tags, status, created_at, updated_at, \
+             tenant_id, uuid, snowflake_id) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8, ?9, ?10, ?11) RETURNING id",
+        )
+        .bind(&post.title)
+        .bind(&slug)
+        .bind(sanitize_content(&post.content))
+        .bind(author.id)
+        .bind(&author.username)
+        .bind(tags_json(&tags.unwrap_or_default()))
+        .bind(post.status.unwrap_or_default().as_str())
+        .bind(&now)
+        .bind(author.tenant_id)
+        .bind(Uuid::now_v7().hyphenated())
+        .bind(next_snowflake_id())
+        .fetch_one(&mut *tx)
+        .await?;
+        let created = find_post(&mut *tx, author.tenant_id, id)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+        tx.commit().await?;
+        Ok(created)
+    }
+
+    async fn get(&self, tenant: i32, id: i32) -> Result<BlogPost, ApiError> {
+        Ok(find_post(&self.pool, tenant, id).await?.ok_or(sqlx::Error::RowNotFound)?)
+    }
+
+    async fn get_by_slug(&self, tenant: i32, slug: &str) -> Result<BlogPost, ApiError> {
+        let sql = format!(
+            "SELECT {} FROM blog_posts WHERE slug = ?1 AND tenant_id = ?2 AND deleted_at IS NULL",
+            POST_COLUMNS
+        );
+        let row = sqlx::query_as::<_, PostRow>(&sql)
+            .bind(slug)
+            .bind(tenant)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.into())
+    }
+
+    async fn get_many(&self, tenant: i32, ids: &[i32]) -> Result<Vec<BlogPost>, ApiError> {
+        // The ids go in as one JSON array, so the statement is the same
+        // however many there are.
+        let sql = format!(
+            "SELECT {} FROM blog_posts WHERE id IN (SELECT value FROM json_each(?1)) \
+             AND tenant_id = ?2 AND deleted_at IS NULL ORDER BY id",
+            POST_COLUMNS
+        );
+        let ids = serde_json::to_string(ids).unwrap_or_else(|_| "[]".to_string());
+        let rows = sqlx::query_as::<_, PostRow>(&sql)
+            .bind(ids)
+            .bind(tenant)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(BlogPost::from).collect())
+    }
+
+    async fn list(
+        &self,
+        filter: PostFilter<'_>,
+        sort: SortColumn,
+        order: SortOrder,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<BlogPost>, ApiError> {
+        let sql = format!(
+            "SELECT {} FROM blog_posts WHERE {} \
+             AND (?13 IS NULL OR created_at < ?13 OR (created_at = ?13 AND id > ?14)) \
+             ORDER BY {} {}, id LIMIT ?11 OFFSET ?12",
+            POST_COLUMNS,
+            FILTER_SQL,
+            sort_column(sort),
+            order.as_sql()
+        );
+        let rows = bind_filter!(sqlx::query_as::<_, PostRow>(&sql), filter)
+            .bind(limit)
+            .bind(offset)
+            .bind(filter.after.map(|cursor| timestamp(cursor.created_at)))
+            .bind(filter.after.map(|cursor| cursor.id))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(BlogPost::from).collect())
+    }
+
+    // SQLite has no row estimates, so both modes count.
+    async fn count(&self, filter: PostFilter<'_>, _mode: CountMode) -> Result<i64, ApiError> {
+        let sql = format!("SELECT COUNT(*) FROM blog_posts WHERE {}", FILTER_SQL);
+        Ok(bind_filter!(sqlx::query_scalar::<_, i64>(&sql), filter)
+            .fetch_one(&self.pool)
+            .await?)
+    }
+
+    async fn owner(&self, tenant: i32, id: i32) -> Result<i32, ApiError> {
+        sqlx::query_scalar::<_, i32>(
+            "SELECT user_id FROM blog_posts WHERE id = ?1 AND tenant_id = ?2",
+        )
+        .bind(id)
+        .bind(tenant)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", id)))
+    }
+
+    async fn id_by_uuid(&self, tenant: i32, uuid: Uuid) -> Result<i32, ApiError> {
+        sqlx::query_scalar::<_, i32>("SELECT id FROM blog_posts WHERE uuid = ?1 AND tenant_id = ?2")
+            .bind(uuid.hyphenated())
+            .bind(tenant)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", uuid)))
+    }
+
+    async fn id_by_snowflake(&self, tenant: i32, snowflake: i64) -> Result<i32, ApiError> {
+        let sql = "SELECT id FROM blog_posts WHERE snowflake_id = ?1 AND tenant_id = ?2";
+        sqlx::query_scalar::<_, i32>(sql)
+            .bind(snowflake)
+            .bind(tenant)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", snowflake)))
+    }
+
+    async fn update(
+        &self,
+        tenant: i32,
+        id: i32,
+        post: &NewBlogPost,
+        if_match: Option<&IfMatch>,
+        _user_id: i32,
+    ) -> Result<BlogPost, ApiError> {
+        let tags = post.tags.as_deref().map(normalize_tags).transpose()?;
+        let version = expected_version(post.version)?;
+        let mut tx = self.pool.begin().await?;
+        post_to_change(&mut tx, tenant, id, if_match, Some(version)).await?;
+        let result = sqlx::query(
+            "UPDATE blog_posts SET title = ?1, content = ?2, tags = COALESCE(?3, tags), \
+             version = version + 1, updated_at = ?4 WHERE id = ?5 AND deleted_at IS NULL",
+        )
+        .bind(&post.title)
+        .bind(sanitize_content(&post.content))
+        .bind(tags.as_deref().map(tags_json))
+        .bind(timestamp(Utc::now()))
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        changed(id, result.rows_affected())?;
+        let updated = find_post(&mut *tx, tenant, id).await?.ok_or(sqlx::Error::RowNotFound)?;
+        tx.commit().await?;
+        Ok(updated)
+    }
+
+    async fn patch(
+        &self,
+        tenant: i32,
+        id: i32,
+        patch: &UpdateBlogPost,
+        if_match: Option<&IfMatch>,
+        _user_id: i32,
+    ) -> Result<BlogPost, ApiError> {
+        let tags = patch.tags.as_deref().map(normalize_tags).transpose()?;
+        let version = expected_version(patch.version)?;
+        no_schedule(patch.publish_at.flatten())?;
+        let mut tx = self.pool.begin().await?;
+        post_to_change(&mut tx, tenant, id, if_match, Some(version)).await?;
+
+        let mut builder = QueryBuilder::<Sqlite>::new("UPDATE blog_posts SET ");
+        let mut fields = builder.separated(", ");
+        fields.push("version = version + 1");
+        fields
+            .push("updated_at = ")
+            .push_bind_unseparated(timestamp(Utc::now()));
+        if let Some(title) = &patch.title {
+            fields.push("title = ").push_bind_unseparated(title);
+        }
+        if let Some(content) = &patch.content {
+            fields.push("content = ").push_bind_unseparated(sanitize_content(content).into_owned());
+        }
+        if let Some(status) = patch.status {
+            fields.push("status = ").push_bind_unseparated(status.as_str());
+        }
+        if let Some(tags) = &tags {
+            fields.push("tags = ").push_bind_unseparated(tags_json(tags));
+        }
+        builder.push(" WHERE deleted_at IS NULL AND id = ").push_bind(id);
+        let result = builder.build().execute(&mut *tx).await?;
+        changed(id, result.rows_affected())?;
+        let updated = find_post(&mut *tx, tenant, id).await?.ok_or(sqlx::Error::RowNotFound)?;
+        tx.commit().await?;
+        Ok(updated)
+    }
+
+    async fn delete(
+        &self,
+        tenant: i32,
+        id: i32,
+        if_match: Option<&IfMatch>,
+        _user_id: i32,
+    ) -> Result<(), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        post_to_change(&mut tx, tenant, id, if_match, None).await?;
+        let result = sqlx::query(
+            "UPDATE blog_posts SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+        )
+        .bind(timestamp(Utc::now()))
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        changed(id, result.rows_affected())?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
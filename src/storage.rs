@@ -0,0 +1,133 @@
+// Refuses writes while the database is close to full.
+
+use crate::*;
+
+// Stops writes before the database fills up. With DB_SIZE_LIMIT_MB set, a
+// background task checks pg_database_size every DB_SIZE_CHECK_SECS (default
+// 60) and write endpoints answer 507 while the size is at or over the
+// limit. Reads and deletes keep working so operators can free space.
+pub struct StorageGuard {
+    limit_bytes: Option<u64>,
+    used_bytes: AtomicU64,
+    blocked: AtomicBool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct StorageStatus {
+    pub used_bytes: u64,
+    #[serde(skip_serializing_if = "omit_if_null")]
+    pub limit_bytes: Option<u64>,
+    pub writes_blocked: bool,
+}
+
+impl StorageGuard {
+    pub fn from_env() -> Self {
+        let limit_bytes = env::var("DB_SIZE_LIMIT_MB")
+            .ok()
+            .map(|v| v.parse::<u64>().expect("DB_SIZE_LIMIT_MB must be a number"))
+            .map(|mb| mb * 1024 * 1024);
+        StorageGuard::new(limit_bytes)
+    }
+
+    pub fn new(limit_bytes: Option<u64>) -> Self {
+        StorageGuard {
+            limit_bytes,
+            used_bytes: AtomicU64::new(0),
+            blocked: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record(&self, used_bytes: u64) {
+        self.used_bytes.store(used_bytes, Ordering::Relaxed);
+        let over = self.limit_bytes.is_some_and(|limit| used_bytes >= limit);
+        if over != self.blocked.swap(over, Ordering::Relaxed) {
+            if over {
+                tracing::warn!(
+                    "Database size {} bytes reached the limit; blocking writes",
+                    used_bytes
+                );
+            } else {
+                tracing::info!("Database size back under the limit; accepting writes");
+            }
+        }
+    }
+
+    pub fn check_writable(&self) -> Result<(), ApiError> {
+        if self.blocked.load(Ordering::Relaxed) {
+            return Err(ApiError::InsufficientStorage(
+                "The database is near capacity; writes are disabled until space is freed"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn status(&self) -> StorageStatus {
+        StorageStatus {
+            used_bytes: self.used_bytes.load(Ordering::Relaxed),
+            limit_bytes: self.limit_bytes,
+            writes_blocked: self.blocked.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn database_size(pool: &PgPool) -> Result<i64, ApiError> {
+    sqlx::query_scalar::<_, i64>("SELECT pg_database_size(current_database())")
+        .fetch_one(pool)
+        .await
+        .map_err(ApiError::from)
+}
+
+pub async fn watch_storage(pool: PgPool, guard: web::Data<StorageGuard>) {
+    let secs = env::var("DB_SIZE_CHECK_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60);
+    let mut interval = tokio::time::interval(Duration::from_secs(secs));
+    loop {
+        interval.tick().await;
+        match database_size(&pool).await {
+            Ok(size) => guard.record(size.max(0) as u64),
+            Err(err) => tracing::warn!("Could not read database size: {}", err),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Health {
+    pub status: &'static str,
+    pub storage: StorageStatus,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Liveness {
+    pub status: &'static str,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Readiness {
+    // "ready" or "unavailable".
+    pub status: &'static str,
+    // "ok", or why the database check failed.
+    pub database: String,
+    pub pool: PoolState,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PoolState {
+    pub size: u32,
+    pub idle: usize,
+    pub max_connections: u32,
+}
+
+// The readiness probe: a round trip through the pool, bounded so a hung
+// database makes the probe fail rather than time out on the prober's side.
+pub async fn check_database(pool: &PgPool) -> Result<(), String> {
+    let ping = sqlx::query("SELECT 1").execute(pool);
+    match tokio::time::timeout(Duration::from_secs(2), ping).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err("timed out after 2s".to_string()),
+    }
+}
@@ -0,0 +1,152 @@
+// RSS and Atom feeds of the latest published posts.
+
+use crate::*;
+
+// Site metadata for GET /feed.rss and /feed.atom. SITE_TITLE (default
+// "Blog") and SITE_DESCRIPTION name the feed; SITE_BASE_URL (default
+// http://localhost:8081) is the public site, and each post links to
+// `{SITE_BASE_URL}/blog/{slug}`. FEED_MAX_ITEMS (default 20, at most 100)
+// caps the entries and FEED_MAX_AGE_SECS (default 300) goes out in
+// Cache-Control.
+#[derive(Debug, Clone)]
+pub struct SiteConfig {
+    pub title: String,
+    pub description: String,
+    pub base_url: String,
+    pub max_items: i64,
+    pub max_age_secs: u64,
+}
+
+impl SiteConfig {
+    pub fn from_env() -> Self {
+        let title = env::var("SITE_TITLE").unwrap_or_else(|_| "Blog".to_string());
+        SiteConfig {
+            description: env::var("SITE_DESCRIPTION")
+                .unwrap_or_else(|_| format!("Latest posts from {}", title)),
+            title,
+            base_url: env::var("SITE_BASE_URL")
+                .unwrap_or_else(|_| "http://localhost:8081".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            max_items: env::var("FEED_MAX_ITEMS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(20)
+                .clamp(1, MAX_PER_PAGE),
+            max_age_secs: env::var("FEED_MAX_AGE_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(300),
+        }
+    }
+
+    pub fn post_url(&self, post: &BlogPost) -> String {
+        self.slug_url(&post.slug)
+    }
+
+    pub fn slug_url(&self, slug: &str) -> String {
+        format!("{}/blog/{}", self.base_url, slug)
+    }
+}
+
+// Escapes text for element content and attribute values alike.
+pub fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// RSS 2.0, newest first. Descriptions carry the rendered HTML, escaped.
+pub fn render_rss(site: &SiteConfig, posts: &[BlogPost]) -> String {
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(concat!(
+        r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom""#,
+        r#" xmlns:dc="http://purl.org/dc/elements/1.1/"><channel>"#
+    ));
+    xml.push_str(&format!(
+        "<title>{}</title><link>{}/</link><description>{}</description>",
+        xml_escape(&site.title),
+        xml_escape(&site.base_url),
+        xml_escape(&site.description),
+    ));
+    xml.push_str(&format!(
+        r#"<atom:link href="{}/feed.rss" rel="self" type="application/rss+xml"/>"#,
+        xml_escape(&site.base_url)
+    ));
+    if let Some(updated) = posts.iter().map(|post| post.updated_at).max() {
+        xml.push_str(&format!("<lastBuildDate>{}</lastBuildDate>", updated.to_rfc2822()));
+    }
+    for post in posts {
+        let url = xml_escape(&site.post_url(post));
+        xml.push_str(&format!(
+            concat!(
+                "<item><title>{}</title><link>{}</link>",
+                r#"<guid isPermaLink="true">{}</guid><pubDate>{}</pubDate>"#,
+                "<dc:creator>{}</dc:creator><description>{}</description></item>"
+            ),
+            xml_escape(&post.title),
+            url,
+            url,
+            post.created_at.to_rfc2822(),
+            xml_escape(&post.author),
+            xml_escape(&render_markdown(&post.content)),
+        ));
+    }
+    xml.push_str("</channel></rss>");
+    xml
+}
+
+// Atom 1.0, newest first. With no posts the feed is dated now, since
+// Atom requires an <updated>.
+pub fn render_atom(site: &SiteConfig, posts: &[BlogPost]) -> String {
+    let base = xml_escape(&site.base_url);
+    let updated = posts
+        .iter()
+        .map(|post| post.updated_at)
+        .max()
+        .unwrap_or_else(Utc::now);
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+    xml.push_str(&format!(
+        concat!(
+            "<id>{}/</id><title>{}</title><subtitle>{}</subtitle><updated>{}</updated>",
+            r#"<link href="{}/"/><link href="{}/feed.atom" rel="self""#,
+            r#" type="application/atom+xml"/>"#
+        ),
+        base,
+        xml_escape(&site.title),
+        xml_escape(&site.description),
+        updated.to_rfc3339(),
+        base,
+        base,
+    ));
+    for post in posts {
+        let url = xml_escape(&site.post_url(post));
+        xml.push_str(&format!(
+            concat!(
+                "<entry><id>{}</id><title>{}</title><link href=\"{}\"/>",
+                "<published>{}</published><updated>{}</updated>",
+                "<author><name>{}</name></author>",
+                r#"<content type="html">{}</content></entry>"#
+            ),
+            url,
+            xml_escape(&post.title),
+            url,
+            post.created_at.to_rfc3339(),
+            post.updated_at.to_rfc3339(),
+            xml_escape(&post.author),
+            xml_escape(&render_markdown(&post.content)),
+        ));
+    }
+    xml.push_str("</feed>");
+    xml
+}
@@ -0,0 +1,145 @@
+// Tenants: several blogs in one deployment, each request scoped to the one
+// named by its X-Tenant-Id header or its subdomain.
+
+use crate::*;
+
+pub const DEFAULT_TENANT: i32 = 1;
+
+pub(crate) fn default_tenant() -> i32 {
+    DEFAULT_TENANT
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, FromRow, ToSchema)]
+pub struct Tenant {
+    pub id: i32,
+    pub slug: String,
+    pub name: String,
+}
+
+impl Default for Tenant {
+    // What the migration creates; everything from before tenants is in it.
+    fn default() -> Self {
+        Tenant {
+            id: DEFAULT_TENANT,
+            slug: "default".to_string(),
+            name: "Default".to_string(),
+        }
+    }
+}
+
+// A DNS label, so every slug also works as a subdomain.
+pub fn validate_tenant_slug(slug: &str) -> Result<(), ApiError> {
+    let ok = (1..=63).contains(&slug.len())
+        && slug.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && !slug.bytes().all(|b| b.is_ascii_digit());
+    if !ok {
+        return Err(ApiError::UnprocessableEntity(format!(
+            "Tenant slug `{}` must be 1-63 lowercase letters, digits or inner dashes, \
+             and not only digits",
+            slug
+        )));
+    }
+    Ok(())
+}
+
+// The tenant a Host header names: the label in front of `base_domain`,
+// e.g. `acme` for acme.blog.example with base blog.example. None for the
+// base domain itself and for hosts outside it.
+pub fn tenant_slug_from_host(host: &str, base_domain: &str) -> Option<String> {
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.parse::<u16>().is_ok() => name,
+        _ => host,
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let base = base_domain.trim_matches('.').to_ascii_lowercase();
+    let label = host.strip_suffix(&base)?.strip_suffix('.')?;
+    (!label.is_empty() && !label.contains('.')).then(|| label.to_string())
+}
+
+// Looks tenants up for requests. X-Tenant-Id, a slug or a numeric id, wins
+// over the subdomain of TENANT_BASE_DOMAIN; a request with neither is for
+// the default tenant. Found tenants are cached for a minute, so a renamed
+// slug takes that long to move.
+pub struct Tenants {
+    pool: PgPool,
+    base_domain: Option<String>,
+    cache: Cache<String, Tenant>,
+}
+
+impl Tenants {
+    pub fn new(pool: PgPool, base_domain: Option<String>) -> Self {
+        Tenants {
+            pool,
+            base_domain: base_domain.filter(|domain| !domain.is_empty()),
+            cache: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(Duration::from_secs(60))
+                .build(),
+        }
+    }
+
+    pub fn from_env(pool: PgPool) -> Self {
+        Tenants::new(pool, env::var("TENANT_BASE_DOMAIN").ok())
+    }
+
+    // What the request names, before it is looked up.
+    fn requested(&self, req: &HttpRequest) -> Result<Option<String>, ApiError> {
+        if let Some(value) = req.headers().get("X-Tenant-Id") {
+            let value = value
+                .to_str()
+                .map_err(|_| ApiError::BadRequest("Invalid X-Tenant-Id header".to_string()))?;
+            return Ok(Some(value.trim().to_ascii_lowercase()));
+        }
+        let Some(base) = &self.base_domain else {
+            return Ok(None);
+        };
+        Ok(tenant_slug_from_host(req.connection_info().host(), base))
+    }
+
+    pub async fn resolve(&self, req: &HttpRequest) -> Result<Tenant, ApiError> {
+        match self.requested(req)? {
+            Some(key) => self.lookup(key).await,
+            None => Ok(Tenant::default()),
+        }
+    }
+
+    // The tenant with this slug or numeric id, as X-Tenant-Id names it.
+    pub async fn lookup(&self, key: String) -> Result<Tenant, ApiError> {
+        if let Some(tenant) = self.cache.get(&key) {
+            return Ok(tenant);
+        }
+        let tenant = match key.parse::<i32>() {
+            Ok(id) => get_tenant(&self.pool, id).await?,
+            Err(_) => get_tenant_by_slug(&self.pool, &key).await?,
+        }
+        .ok_or_else(|| ApiError::NotFound(format!("No tenant `{}`", key)))?;
+        self.cache.insert(key, tenant.clone());
+        Ok(tenant)
+    }
+}
+
+// The request's tenant. Resolved once per request and kept in its
+// extensions; an unknown tenant answers 404. Apps without a `Tenants`
+// registry serve the default tenant only.
+impl FromRequest for Tenant {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if let Some(tenant) = req.extensions().get::<Tenant>() {
+            let tenant = tenant.clone();
+            return Box::pin(async move { Ok(tenant) });
+        }
+        let req = req.clone();
+        Box::pin(async move {
+            let Some(tenants) = req.app_data::<web::Data<Tenants>>() else {
+                return Ok(Tenant::default());
+            };
+            let tenant = tenants.resolve(&req).await?;
+            req.extensions_mut().insert(tenant.clone());
+            Ok(tenant)
+        })
+    }
+}
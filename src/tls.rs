@@ -0,0 +1,81 @@
+// HTTPS with rustls when TLS_CERT and TLS_KEY are set, and the plain HTTP
+// listener that sends clients over to it.
+
+use actix_web::dev::ServiceFactory;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+use crate::*;
+
+// Read once at startup; renewed certificates take effect on restart.
+pub fn load_rustls_config(tls: &TlsConfig) -> Result<rustls::ServerConfig, String> {
+    let (Some(cert_path), Some(key_path)) = (&tls.cert, &tls.key) else {
+        return Err("HTTPS needs TLS_CERT and TLS_KEY".to_string());
+    };
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("Cannot read TLS_CERT {}: {}", cert_path.display(), err))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates in TLS_CERT {}", cert_path.display()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|err| format!("Cannot read TLS_KEY {}: {}", key_path.display(), err))?;
+    rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| format!("TLS_CERT and TLS_KEY don't go together: {}", err))
+}
+
+// Where a plain HTTP request belongs: the same host and path over HTTPS,
+// with the port left out when it is the default 443.
+pub fn https_location(host: &str, path_and_query: &str, https_port: u16) -> String {
+    // The Host header may carry the HTTP port, or be a bracketed IPv6
+    // address, which has colons of its own.
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') && port.parse::<u16>().is_ok() => name,
+        _ => host,
+    };
+    if https_port == 443 {
+        format!("https://{}{}", host, path_and_query)
+    } else {
+        format!("https://{}:{}{}", host, https_port, path_and_query)
+    }
+}
+
+// 308 so a redirected POST is repeated as a POST, body and all. Requests
+// without a Host header go to the configured HOST.
+pub(crate) async fn redirect_to_https(
+    req: HttpRequest,
+    target: web::Data<HttpsTarget>,
+) -> HttpResponse {
+    let host = req.connection_info().host().to_string();
+    let host = if host.is_empty() { target.host.clone() } else { host };
+    let path_and_query = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    HttpResponse::PermanentRedirect()
+        .insert_header(("Location", https_location(&host, path_and_query, target.port)))
+        .finish()
+}
+
+pub struct HttpsTarget {
+    pub host: String,
+    pub port: u16,
+}
+
+// The whole app the redirect listener serves.
+pub fn redirect_app(
+    target: web::Data<HttpsTarget>,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .app_data(target)
+        .default_service(web::to(redirect_to_https))
+}
@@ -0,0 +1,113 @@
+// Translating posts and choosing which translation to serve.
+
+use crate::*;
+
+// -------------------- Translation --------------------
+
+// Backend used by POST /blog/{id}/translate. Selected at startup via
+// TRANSLATOR; an HTTP-backed implementation only needs to implement this.
+#[async_trait]
+pub trait Translator: Send + Sync {
+    async fn translate(&self, text: &str, lang: &str) -> Result<String, ApiError>;
+}
+
+// Returns the text unchanged. Useful as a default and for wiring up
+// translations by hand before a real backend is configured.
+pub struct EchoTranslator;
+
+#[async_trait]
+impl Translator for EchoTranslator {
+    async fn translate(&self, text: &str, _lang: &str) -> Result<String, ApiError> {
+        Ok(text.to_string())
+    }
+}
+
+pub fn build_translator() -> Arc<dyn Translator> {
+    match env::var("TRANSLATOR").as_deref() {
+        Ok("echo") | Err(_) => Arc::new(EchoTranslator),
+        Ok(other) => panic!("Unknown TRANSLATOR `{}`", other),
+    }
+}
+
+// Accepts BCP 47 style codes such as `fr` or `pt-br`, normalized to lowercase.
+pub fn normalize_lang(lang: &str) -> Result<String, ApiError> {
+    let lang = lang.trim().to_ascii_lowercase();
+    let valid = (2..=16).contains(&lang.len())
+        && lang.split('-').all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    if !valid {
+        return Err(ApiError::UnprocessableEntity(format!(
+            "Invalid language code `{}`",
+            lang
+        )));
+    }
+    Ok(lang)
+}
+
+// -------------------- Language negotiation --------------------
+
+pub(crate) static DEFAULT_LANGUAGE: OnceLock<String> = OnceLock::new();
+
+// Language posts are written in, from DEFAULT_LANGUAGE (default `en`).
+// Served as Content-Language whenever no translation is used.
+pub fn default_language() -> &'static str {
+    DEFAULT_LANGUAGE.get_or_init(|| {
+        let raw = env::var("DEFAULT_LANGUAGE").unwrap_or_else(|_| "en".to_string());
+        normalize_lang(&raw).unwrap_or_else(|err| panic!("DEFAULT_LANGUAGE: {}", err))
+    })
+}
+
+// Accept-Language entries ordered by preference. Entries with q=0 are
+// dropped, as are ones that aren't valid tags; `*` is kept as is.
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut entries: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let tag = parts.next()?.to_ascii_lowercase();
+            let mut quality = 1.0;
+            for param in parts {
+                if let Some(q) = param.strip_prefix("q=") {
+                    quality = q.trim().parse().ok()?;
+                }
+            }
+            let valid = tag == "*" || normalize_lang(&tag).is_ok();
+            (valid && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+
+    // Stable, so equal q values keep the order the client sent them in.
+    entries.sort_by(|a, b| b.1.total_cmp(&a.1));
+    entries.into_iter().map(|(tag, _)| tag).collect()
+}
+
+pub(crate) fn primary_subtag(tag: &str) -> &str {
+    tag.split('-').next().unwrap_or(tag)
+}
+
+// Picks the language to serve for the client's preferences: an exact tag
+// match first, then one sharing the primary subtag (`fr-ca` -> `fr`).
+// `None` means none of the preferences can be served.
+pub fn negotiate_language(
+    preferences: &[String],
+    original: &str,
+    available: &[String],
+) -> Option<String> {
+    let mut candidates: Vec<&str> = vec![original];
+    candidates.extend(available.iter().map(String::as_str));
+
+    for preference in preferences {
+        if preference == "*" {
+            return Some(original.to_string());
+        }
+        if let Some(exact) = candidates.iter().find(|c| *c == preference) {
+            return Some(exact.to_string());
+        }
+        let primary = primary_subtag(preference);
+        if let Some(close) = candidates.iter().find(|c| primary_subtag(c) == primary) {
+            return Some(close.to_string());
+        }
+    }
+    None
+}
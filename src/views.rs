@@ -0,0 +1,57 @@
+// View counts for GET /blog/{id}. Reads only bump a number in memory; a
+// background task adds the totals to the posts in one UPDATE every
+// VIEW_FLUSH_SECS. Each instance counts its own views, and the sums add up.
+
+use crate::*;
+
+#[derive(Default)]
+pub struct ViewCounter {
+    pending: std::sync::Mutex<HashMap<i32, i64>>,
+}
+
+impl ViewCounter {
+    // A panic elsewhere while holding the lock leaves the counts usable.
+    fn pending(&self) -> std::sync::MutexGuard<'_, HashMap<i32, i64>> {
+        self.pending.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn record(&self, post_id: i32) {
+        *self.pending().entry(post_id).or_insert(0) += 1;
+    }
+
+    // Writes what was counted since the last flush and returns how many
+    // posts it touched. On failure the counts are kept for the next try.
+    pub async fn flush(&self, pool: &PgPool) -> Result<usize, ApiError> {
+        let batch = std::mem::take(&mut *self.pending());
+        if batch.is_empty() {
+            return Ok(0);
+        }
+        let (ids, counts): (Vec<i32>, Vec<i64>) = batch.iter().map(|(&id, &n)| (id, n)).unzip();
+        if let Err(err) = add_post_views(pool, &ids, &counts).await {
+            let mut pending = self.pending();
+            for (id, n) in batch {
+                *pending.entry(id).or_insert(0) += n;
+            }
+            return Err(err);
+        }
+        Ok(ids.len())
+    }
+}
+
+pub(crate) fn view_flush_secs() -> u64 {
+    env::var("VIEW_FLUSH_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10)
+        .max(1)
+}
+
+pub async fn flush_views(pool: PgPool, views: web::Data<ViewCounter>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(view_flush_secs()));
+    loop {
+        interval.tick().await;
+        if let Err(err) = views.flush(&pool).await {
+            tracing::warn!("Could not write view counts: {}", err);
+        }
+    }
+}
@@ -0,0 +1,289 @@
+// Outgoing webhooks: post changes POSTed as signed JSON to the URLs users
+// register, each delivery a job so failures are retried with backoff.
+
+use std::net::{IpAddr, SocketAddr};
+
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::*;
+
+pub(crate) const WEBHOOK_EVENTS: [&str; 3] = ["post.created", "post.updated", "post.deleted"];
+
+pub fn generate_webhook_secret() -> String {
+    format!("whsec_{}", hex::encode(rand::random::<[u8; 32]>()))
+}
+
+pub(crate) static WEBHOOK_ALLOWED_HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+
+// Hosts webhooks may reach even on a private network, from
+// WEBHOOK_ALLOWED_HOSTS (comma-separated names or addresses, none by
+// default). Meant for tests and receivers on the same network.
+pub fn webhook_allowed_hosts() -> &'static [String] {
+    WEBHOOK_ALLOWED_HOSTS.get_or_init(|| {
+        env::var("WEBHOOK_ALLOWED_HOSTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|host| host.trim().trim_matches(['[', ']']).to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect()
+    })
+}
+
+// Whether a webhook may be sent to `ip`: anything but loopback, private,
+// link-local and unspecified addresses, so deliveries can't be aimed at
+// this host or the services next to it.
+pub fn public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => public_address(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+// An absolute http(s) URL that doesn't name a private address. Names are
+// checked once resolved, when a delivery is sent.
+fn check_webhook_url(url: &str) -> Result<reqwest::Url, String> {
+    let url = reqwest::Url::parse(url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some())
+        .ok_or_else(|| format!("Webhook URL must be an absolute http(s) URL, not `{}`", url))?;
+    let host = url.host_str().unwrap_or_default().trim_matches(['[', ']']).to_ascii_lowercase();
+    if webhook_allowed_hosts().contains(&host) {
+        return Ok(url);
+    }
+    let private = match host.parse() {
+        Ok(ip) => !public_address(ip),
+        Err(_) => host == "localhost" || host.ends_with(".localhost"),
+    };
+    if private {
+        return Err(format!("Webhook URL must not point at a private address, not `{}`", url));
+    }
+    Ok(url)
+}
+
+// Resolves webhook hosts to their public addresses only, unless the host
+// is allowed. Done as each delivery connects, so a name can't be pointed
+// at a private address after it was checked.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_ascii_lowercase();
+        Box::pin(async move {
+            let allowed = webhook_allowed_hosts().contains(&host);
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| allowed || public_address(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// An absolute http(s) URL, and the events it asked for, deduplicated;
+// every event when none were given.
+pub fn validate_webhook(webhook: &NewWebhook) -> Result<Vec<String>, ApiError> {
+    check_webhook_url(&webhook.url).map_err(ApiError::UnprocessableEntity)?;
+    let Some(events) = &webhook.events else {
+        return Ok(WEBHOOK_EVENTS.iter().map(|event| event.to_string()).collect());
+    };
+    if let Some(unknown) = events.iter().find(|event| !WEBHOOK_EVENTS.contains(&event.as_str())) {
+        return Err(ApiError::UnprocessableEntity(format!(
+            "Unknown webhook event `{}`; expected one of {}",
+            unknown,
+            WEBHOOK_EVENTS.join(", ")
+        )));
+    }
+    if events.is_empty() {
+        return Err(ApiError::UnprocessableEntity(
+            "A webhook needs at least one event".to_string(),
+        ));
+    }
+    let mut events = events.clone();
+    events.sort();
+    events.dedup();
+    Ok(events)
+}
+
+// Sent as `X-Webhook-Signature: t=<timestamp>,v1=<signature>`, where the
+// signature is the hex HMAC-SHA256 of `<timestamp>.<body>` under the
+// webhook's secret. Receivers recompute it, and reject old timestamps to
+// stop replays.
+pub fn sign_webhook(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("t={},v1={}", timestamp, hex::encode(mac.finalize().into_bytes()))
+}
+
+// A delivery row for each webhook that wants the event, each with its job,
+// in one transaction. Only the author's and admins' webhooks match, and
+// they may see drafts, so events go out as they are.
+pub async fn queue_webhook_deliveries(
+    pool: &PgPool,
+    jobs: &JobQueue,
+    event: &PostEvent,
+) -> Result<usize, ApiError> {
+    let name = serde_json::to_value(event.kind)
+        .ok()
+        .and_then(|name| name.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let owner = match &event.post {
+        Some(post) => Some(post.user_id),
+        None => match post_owner(pool, event.tenant, event.id).await {
+            Ok(owner) => Some(owner),
+            Err(ApiError::NotFound(_)) => None,
+            Err(err) => return Err(err),
+        },
+    };
+    let webhooks = webhooks_for_event(pool, event.tenant, owner, &name).await?;
+    if webhooks.is_empty() {
+        return Ok(0);
+    }
+
+    let payload = serde_json::json!({
+        "event": name,
+        "post_id": event.id,
+        "post": event.post,
+        "occurred_at": Utc::now(),
+    });
+    let mut tx = pool.begin().await?;
+    for &webhook_id in &webhooks {
+        let delivery_id = create_webhook_delivery(&mut tx, webhook_id, &name, &payload).await?;
+        let payload = WebhookDeliveryPayload { delivery_id };
+        let job = jobs.enqueue_in(&mut tx, event.tenant, "webhook_delivery", &payload).await?;
+        set_delivery_job(&mut tx, delivery_id, job.id).await?;
+    }
+    tx.commit().await?;
+    jobs.wake();
+    Ok(webhooks.len())
+}
+
+// Turns this instance's post changes into deliveries until the feed closes
+// on shutdown. The feed is the one WebSocket clients get, so webhooks see
+// what they see: a post leaving `published` arrives as post.deleted.
+pub fn spawn_webhook_dispatcher(
+    pool: PgPool,
+    feed: web::Data<ChangeFeed>,
+    jobs: web::Data<JobQueue>,
+) {
+    let mut events = feed.subscribe();
+    actix_web::rt::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    if let Err(err) = queue_webhook_deliveries(&pool, &jobs, &event).await {
+                        tracing::error!("Could not queue webhooks for post {}: {}", event.id, err);
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Webhooks missed {} post event(s) under load", missed);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+pub struct DeliveryTarget {
+    pub url: String,
+    pub secret: String,
+    pub event: String,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WebhookDeliveryPayload {
+    pub delivery_id: i32,
+}
+
+// Sends one delivery. Anything but a 2xx answer is retried.
+pub struct WebhookDeliveryJob {
+    pub pool: PgPool,
+    pub client: reqwest::Client,
+}
+
+impl WebhookDeliveryJob {
+    // WEBHOOK_TIMEOUT_SECS (default 10) bounds each request. Redirects are
+    // not followed: they could lead anywhere.
+    pub fn from_env(pool: PgPool) -> Result<Self, String> {
+        let timeout = env::var("WEBHOOK_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10);
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(timeout))
+            .user_agent(concat!("rest_api-webhooks/", env!("CARGO_PKG_VERSION")))
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()
+            .map_err(|err| format!("Could not set up the webhook client: {}", err))?;
+        Ok(WebhookDeliveryJob { pool, client })
+    }
+}
+
+#[async_trait]
+impl JobHandler for WebhookDeliveryJob {
+    async fn run(&self, payload: serde_json::Value) -> Result<Option<serde_json::Value>, JobError> {
+        let payload: WebhookDeliveryPayload = serde_json::from_value(payload)
+            .map_err(|err| JobError::Fail(format!("Malformed webhook_delivery job: {}", err)))?;
+        let id = payload.delivery_id;
+        let Some(target) = delivery_target(&self.pool, id).await? else {
+            return Ok(Some(serde_json::json!({ "skipped": "webhook deleted" })));
+        };
+
+        // Checked again as it's sent, in case the rules changed since.
+        let url = match check_webhook_url(&target.url) {
+            Ok(url) => url,
+            Err(error) => {
+                record_delivery_attempt(&self.pool, id, None, Some(&error)).await?;
+                return Err(JobError::Fail(error));
+            }
+        };
+        let body =
+            serde_json::to_vec(&target.payload).map_err(|err| JobError::Fail(err.to_string()))?;
+        let signature = sign_webhook(&target.secret, Utc::now().timestamp(), &body);
+        let sent = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Event", &target.event)
+            .header("X-Webhook-Delivery", id.to_string())
+            .header("X-Webhook-Signature", signature)
+            .body(body)
+            .send()
+            .await;
+        let (status, error) = match sent {
+            Ok(res) if res.status().is_success() => (Some(res.status().as_u16()), None),
+            Ok(res) => (Some(res.status().as_u16()), Some(format!("HTTP {}", res.status()))),
+            Err(err) => (None, Some(err.without_url().to_string())),
+        };
+        let response_status = status.map(i32::from);
+        record_delivery_attempt(&self.pool, id, response_status, error.as_deref()).await?;
+        match error {
+            None => Ok(Some(serde_json::json!({ "status": status }))),
+            Some(error) => Err(JobError::Retry(error)),
+        }
+    }
+}